@@ -0,0 +1,13 @@
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::AgentContext;
+
+use crate::agent::KafkaAgent;
+
+mod reassign;
+
+pub use self::reassign::Reassign;
+
+/// Register Kafka specific actions.
+pub fn register(agent: &KafkaAgent, context: &AgentContext) {
+    ACTIONS::register(Reassign::new(context.clone(), agent.jmx(), agent.zoo()));
+}
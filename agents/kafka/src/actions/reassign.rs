@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use failure::ResultExt;
+use opentracingrust::Span;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::ActionValidityError;
+use replicante_agent::actions::CachedResponse;
+use replicante_agent::AgentContext;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::Transaction;
+
+use crate::agent::KafkaJmx;
+use crate::agent::KafkaZoo;
+use crate::agent::ReassignPlan;
+use crate::error::ErrorKind;
+
+/// Move partition replicas between brokers by submitting a reassignment plan to the controller.
+///
+/// The action must be invoked on the controller broker and the plan uses the same format
+/// as the `kafka-reassign-partitions.sh` tool.
+/// Once submitted, the action stays running and tracks the reassignment progress in its
+/// state payload until all partitions in the plan are reassigned.
+pub struct Reassign {
+    context: AgentContext,
+    jmx: Arc<KafkaJmx>,
+    zoo: Arc<KafkaZoo>,
+}
+
+impl Reassign {
+    pub fn new(context: AgentContext, jmx: Arc<KafkaJmx>, zoo: Arc<KafkaZoo>) -> Reassign {
+        Reassign { context, jmx, zoo }
+    }
+}
+
+impl Action for Reassign {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "replicante.kafka/reassign".into(),
+            description: "Reassign partition replicas according to the given plan".into(),
+        }
+    }
+
+    fn idempotent(&self) -> bool {
+        false
+    }
+
+    fn invalidates(&self) -> Vec<CachedResponse> {
+        // Reassignments move partition replicas and leaders between brokers.
+        vec![CachedResponse::Shards]
+    }
+
+    fn singleton(&self) -> bool {
+        // Kafka runs only one partition reassignment at a time across the cluster.
+        true
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let plan = parse_plan(record.args()).with_context(|_| BaseKind::ActionDecode)?;
+        let parent = span.map(|span| span.context().clone());
+        let mut span = self.context.tracer.span("kafka.reassign").auto_finish();
+        if let Some(parent) = parent.clone() {
+            span.child_of(parent);
+        }
+        match record.state() {
+            ActionState::New => {
+                let broker = self.jmx.broker_id(&mut span)?;
+                let controller = self.zoo.controller(&mut span)?;
+                ensure_controller(broker, controller)?;
+                self.zoo.reassign(&plan, &mut span)?;
+                let payload = progress(&plan, Some(&plan));
+                tx.action()
+                    .transition(record, ActionState::Running, payload, parent)
+            }
+            _ => {
+                let pending = self.zoo.reassignments(&mut span)?;
+                let payload = progress(&plan, pending.as_ref());
+                if payload["progress"] == 100 {
+                    return tx
+                        .action()
+                        .transition(record, ActionState::Done, payload, parent);
+                }
+                if record.state_payload().as_ref() == Some(&payload) {
+                    return Ok(());
+                }
+                tx.action()
+                    .transition(record, ActionState::Running, payload, parent)
+            }
+        }
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        parse_plan(args).map(|_| ())
+    }
+}
+
+/// Decode and check a reassignment plan from the action arguments.
+fn parse_plan(args: &Json) -> ActionValidity<ReassignPlan> {
+    let plan: ReassignPlan = validate_action_args(args.clone())?;
+    let invalid = |field: String, reason: &str| ActionValidityError::InvalidField {
+        field,
+        reason: reason.into(),
+    };
+    if plan.version != 1 {
+        return Err(invalid(
+            "version".into(),
+            "only version 1 plans are supported",
+        ));
+    }
+    if plan.partitions.is_empty() {
+        return Err(invalid(
+            "partitions".into(),
+            "at least one partition is required",
+        ));
+    }
+    let mut seen = HashSet::new();
+    for (index, partition) in plan.partitions.iter().enumerate() {
+        let field = |name: &str| format!("partitions.{}.{}", index, name);
+        if partition.topic.is_empty() {
+            return Err(invalid(field("topic"), "topic must not be empty"));
+        }
+        if partition.partition < 0 {
+            return Err(invalid(
+                field("partition"),
+                "partition must not be negative",
+            ));
+        }
+        if !seen.insert((&partition.topic, partition.partition)) {
+            return Err(invalid(
+                field("partition"),
+                "partition is listed more than once",
+            ));
+        }
+        if partition.replicas.is_empty() {
+            return Err(invalid(
+                field("replicas"),
+                "at least one replica is required",
+            ));
+        }
+        if partition.replicas.iter().any(|replica| *replica < 0) {
+            return Err(invalid(
+                field("replicas"),
+                "broker ids must not be negative",
+            ));
+        }
+        let replicas: HashSet<i32> = partition.replicas.iter().cloned().collect();
+        if replicas.len() != partition.replicas.len() {
+            return Err(invalid(
+                field("replicas"),
+                "brokers can only hold one replica of a partition",
+            ));
+        }
+    }
+    Ok(plan)
+}
+
+/// Ensure the broker the agent manages is the cluster controller.
+fn ensure_controller(broker: i32, controller: i32) -> Result<()> {
+    if broker != controller {
+        return Err(ErrorKind::NotController(broker, controller).into());
+    }
+    Ok(())
+}
+
+/// Build the action payload reporting how many partitions in the plan were reassigned.
+///
+/// Partitions from the plan still listed in the `pending` reassignments are not done yet.
+fn progress(plan: &ReassignPlan, pending: Option<&ReassignPlan>) -> Json {
+    let total = plan.partitions.len();
+    let pending = match pending {
+        None => 0,
+        Some(pending) => plan
+            .partitions
+            .iter()
+            .filter(|partition| {
+                pending.partitions.iter().any(|other| {
+                    other.topic == partition.topic && other.partition == partition.partition
+                })
+            })
+            .count(),
+    };
+    json!({
+        "partitions": total,
+        "pending": pending,
+        "progress": (total - pending) * 100 / total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Fail;
+    use serde_json::json;
+
+    use replicante_agent::actions::ActionValidityError;
+
+    use super::ensure_controller;
+    use super::parse_plan;
+    use super::progress;
+
+    fn assert_invalid(args: serde_json::Value, expected: &str) {
+        match parse_plan(&args) {
+            Err(ActionValidityError::InvalidField { field, .. }) => assert_eq!(field, expected),
+            other => panic!("expected invalid field {}, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn malformed_plans() {
+        assert_invalid(json!({"partitions": []}), "version");
+        assert_invalid(json!({"version": 2, "partitions": []}), "version");
+        assert_invalid(json!({"version": 1, "partitions": []}), "partitions");
+        assert_invalid(
+            json!({"version": 1, "partitions": [{"topic": "", "partition": 0, "replicas": [1]}]}),
+            "partitions.0.topic",
+        );
+        assert_invalid(
+            json!({"version": 1, "partitions": [{"topic": "t", "partition": -1, "replicas": [1]}]}),
+            "partitions.0.partition",
+        );
+        assert_invalid(
+            json!({"version": 1, "partitions": [{"topic": "t", "partition": 0, "replicas": []}]}),
+            "partitions.0.replicas",
+        );
+        assert_invalid(
+            json!({"version": 1, "partitions": [{"topic": "t", "partition": 0, "replicas": [1, 1]}]}),
+            "partitions.0.replicas",
+        );
+        assert_invalid(
+            json!({"version": 1, "partitions": [
+                {"topic": "t", "partition": 0, "replicas": [1, 2]},
+                {"topic": "t", "partition": 0, "replicas": [2, 3]},
+            ]}),
+            "partitions.1.partition",
+        );
+    }
+
+    #[test]
+    fn valid_plan() {
+        let args = json!({"version": 1, "partitions": [
+            {"topic": "t", "partition": 0, "replicas": [1, 2]},
+            {"topic": "t", "partition": 1, "replicas": [2, 3]},
+        ]});
+        let plan = parse_plan(&args).unwrap();
+        assert_eq!(plan.partitions.len(), 2);
+        assert_eq!(plan.partitions[1].replicas, vec![2, 3]);
+    }
+
+    #[test]
+    fn controller_only() {
+        let error = ensure_controller(1, 2).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_controller(2, 2).unwrap();
+    }
+
+    #[test]
+    fn progress_tracks_pending_partitions() {
+        let plan = json!({"version": 1, "partitions": [
+            {"topic": "t", "partition": 0, "replicas": [1, 2]},
+            {"topic": "t", "partition": 1, "replicas": [2, 3]},
+        ]});
+        let plan = parse_plan(&plan).unwrap();
+        let mut pending = plan.clone();
+        pending.partitions.remove(0);
+        assert_eq!(
+            progress(&plan, Some(&plan)),
+            json!({"partitions": 2, "pending": 2, "progress": 0})
+        );
+        assert_eq!(
+            progress(&plan, Some(&pending)),
+            json!({"partitions": 2, "pending": 1, "progress": 50})
+        );
+        assert_eq!(
+            progress(&plan, None),
+            json!({"partitions": 2, "pending": 0, "progress": 100})
+        );
+    }
+}
@@ -113,6 +113,15 @@ impl KafkaJmx {
         Err(ErrorKind::BrokerIdFormat(name.clone()).into())
     }
 
+    /// Fetch the ID of the broker as a number.
+    pub fn broker_id(&self, parent: &mut Span) -> Result<i32> {
+        let name = self.broker_name(parent)?;
+        let id = name
+            .parse::<i32>()
+            .with_context(|_| ErrorKind::BrokerIdFormat(name))?;
+        Ok(id)
+    }
+
     /// Fetch the version of the broker.
     pub fn broker_version(&self, parent: &mut Span) -> Result<String> {
         let mut span = self.context.tracer.span("brokerVersion").auto_finish();
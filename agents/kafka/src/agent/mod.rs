@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
@@ -11,6 +12,7 @@ use opentracingrust::Span;
 
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::RequestBudget;
 use replicante_agent::Result;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::AgentVersion;
@@ -20,6 +22,7 @@ use replicante_models_agent::info::Shard;
 use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
 
+use super::config::Kafka;
 use super::error::ErrorKind;
 use super::metrics::OPS_COUNT;
 use super::metrics::OPS_DURATION;
@@ -29,8 +32,10 @@ use super::Config;
 mod jmx;
 mod zk;
 
-use self::jmx::KafkaJmx;
-use self::zk::KafkaZoo;
+pub use self::jmx::KafkaJmx;
+pub use self::zk::KafkaZoo;
+pub use self::zk::PartitionReassignment;
+pub use self::zk::ReassignPlan;
 
 lazy_static! {
     pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
@@ -42,33 +47,42 @@ lazy_static! {
 
 /// Kafka 1.0+ agent.
 pub struct KafkaAgent {
-    jmx: KafkaJmx,
+    config: Kafka,
+    jmx: Arc<KafkaJmx>,
     kafka: Mutex<KafkaClient>,
-    zoo: KafkaZoo,
+    zoo: Arc<KafkaZoo>,
 }
 
 impl KafkaAgent {
     pub fn with_config(config: Config, context: AgentContext) -> Result<KafkaAgent> {
-        let jmx = KafkaJmx::with_context(context.clone(), config.kafka.target.jmx)?;
+        config.kafka.validate_shard_id_template()?;
+        let jmx = KafkaJmx::with_context(context.clone(), config.kafka.target.jmx.clone())?;
         let kafka_timeout = Duration::from_secs(config.kafka.target.broker.timeout);
-        let mut kafka = KafkaClient::new(vec![config.kafka.target.broker.uri]);
+        let mut kafka = KafkaClient::new(vec![config.kafka.target.broker.uri.clone()]);
         kafka.set_client_id("replicante-kafka-agent".into());
         kafka
             .set_fetch_max_wait_time(kafka_timeout)
             .map_err(SyncFailure::new)
             .with_context(|_| ErrorKind::ConfigOption("kafka.target.broker.timeout"))?;
         kafka.set_connection_idle_timeout(kafka_timeout);
-        let zoo = KafkaZoo::connect(
-            context,
-            config.kafka.target.zookeeper.uri,
-            config.kafka.target.zookeeper.timeout,
-        )?;
+        let zoo = KafkaZoo::connect(context, &config.kafka.target.zookeeper)?;
         Ok(KafkaAgent {
-            jmx,
+            config: config.kafka,
+            jmx: Arc::new(jmx),
             kafka: Mutex::new(kafka),
-            zoo,
+            zoo: Arc::new(zoo),
         })
     }
+
+    /// JMX client shared with actions that need broker details.
+    pub fn jmx(&self) -> Arc<KafkaJmx> {
+        Arc::clone(&self.jmx)
+    }
+
+    /// Zookeeper client shared with actions that operate on the cluster.
+    pub fn zoo(&self) -> Arc<KafkaZoo> {
+        Arc::clone(&self.zoo)
+    }
 }
 
 impl KafkaAgent {
@@ -89,7 +103,7 @@ impl KafkaAgent {
             } else {
                 ShardRole::Secondary
             };
-            let id = format!("{}/{}", topic, meta.partition);
+            let id = self.config.shard_id(topic, meta.partition);
             let commit = if primary {
                 offsets
                     .get(&meta.partition)
@@ -157,13 +171,14 @@ impl Agent for KafkaAgent {
     }
 
     fn shards(&self, span: &mut Span) -> Result<Shards> {
-        let name = self.jmx.broker_name(span)?;
-        let broker_id: i32 = name
-            .parse::<i32>()
-            .with_context(|_| ErrorKind::BrokerIdFormat(name))?;
+        let broker_id = self.jmx.broker_id(span)?;
         let mut shards = Vec::new();
         let topics = self.zoo.topics(span)?;
         for topic in topics {
+            // Each topic takes several operations so they are accounted for together.
+            if !RequestBudget::spend() {
+                break;
+            }
             self.push_shard(&mut shards, broker_id, &topic, span)?;
         }
         Ok(Shards::new(shards))
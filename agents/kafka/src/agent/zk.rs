@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -17,6 +18,11 @@ use slog::Logger;
 use opentracingrust::Log;
 use opentracingrust::Span;
 
+use zookeeper::Acl;
+use zookeeper::CreateMode;
+use zookeeper::KeeperState;
+use zookeeper::WatchedEvent;
+use zookeeper::ZkError;
 use zookeeper::ZkState;
 use zookeeper::ZooKeeper;
 
@@ -24,6 +30,7 @@ use replicante_agent::fail_span;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
 
+use super::super::config::ZookeeperTarget;
 use super::super::error::ErrorKind;
 use super::super::metrics::OPS_COUNT;
 use super::super::metrics::OPS_DURATION;
@@ -31,6 +38,8 @@ use super::super::metrics::OP_ERRORS_COUNT;
 use super::super::metrics::RECONNECT_COUNT;
 
 const CLUSTER_ID_PATH: &str = "/cluster/id";
+const CONTROLLER_PATH: &str = "/controller";
+const REASSIGN_PATH: &str = "/admin/reassign_partitions";
 const TOPICS_PATH: &str = "/brokers/topics";
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
@@ -42,23 +51,37 @@ struct ClusterId {
     pub version: String,
 }
 
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+struct Controller {
+    /// ID of the broker acting as the cluster controller.
+    pub brokerid: i32,
+}
+
 /// Kafka specifics that rely on Zookeeper.
 pub struct KafkaZoo {
     context: AgentContext,
+    connect_timeout: Duration,
     session: Mutex<ZookeeperSession>,
+    session_timeout: Duration,
     target: String,
-    timeout: Duration,
 }
 
 impl KafkaZoo {
-    pub fn connect(context: AgentContext, target: String, timeout: u64) -> Result<KafkaZoo> {
-        let timeout = Duration::from_secs(timeout);
-        let session = ZookeeperSession::connect(&target, timeout, context.logger.clone())?;
+    pub fn connect(context: AgentContext, target: &ZookeeperTarget) -> Result<KafkaZoo> {
+        let connect_timeout = Duration::from_secs(target.connect_timeout());
+        let session_timeout = Duration::from_secs(target.session_timeout());
+        let session = ZookeeperSession::connect(
+            &target.uri,
+            connect_timeout,
+            session_timeout,
+            context.logger.clone(),
+        )?;
         Ok(KafkaZoo {
             context,
+            connect_timeout,
             session: Mutex::new(session),
-            target,
-            timeout,
+            session_timeout,
+            target: target.uri.clone(),
         })
     }
 
@@ -91,6 +114,35 @@ impl KafkaZoo {
         Ok(id.id)
     }
 
+    /// Fetch the ID of the broker acting as the cluster controller.
+    pub fn controller(&self, parent: &mut Span) -> Result<i32> {
+        let mut span = self.context.tracer.span("controller").auto_finish();
+        span.child_of(parent.context().clone());
+        span.tag("service", "zookeeper");
+        span.log(Log::new().log("span.kind", "client-send"));
+        let keeper = self
+            .keeper(&mut span)
+            .map_err(|error| fail_span(error, &mut *span))?;
+        OPS_COUNT.with_label_values(&["zookeeper", "getData"]).inc();
+        let timer = OPS_DURATION
+            .with_label_values(&["zookeeper", "getData"])
+            .start_timer();
+        let (controller, _) = keeper
+            .get_data(CONTROLLER_PATH, false)
+            .map_err(|error| {
+                OP_ERRORS_COUNT
+                    .with_label_values(&["zookeeper", "getData"])
+                    .inc();
+                fail_span(error, &mut *span)
+            })
+            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.controller"))?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        let controller: Controller = serde_json::from_slice(&controller)
+            .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.controller"))?;
+        Ok(controller.brokerid)
+    }
+
     /// Fetch partitions metadata for the topic that are on the given broker.
     pub fn partitions(
         &self,
@@ -121,26 +173,83 @@ impl KafkaZoo {
             .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.partitions"))?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
-        let mut partitions = Vec::new();
         let meta: PartitionsMap = serde_json::from_slice(&meta)
             .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.partitions"))?;
-        for (partition, brokers) in meta.partitions {
-            if !brokers.contains(&broker) {
-                continue;
+        meta.on_broker(broker)
+    }
+
+    /// Submit a partition reassignment plan for the controller to execute.
+    ///
+    /// Fails if a reassignment is already in progress.
+    pub fn reassign(&self, plan: &ReassignPlan, parent: &mut Span) -> Result<()> {
+        let mut span = self.context.tracer.span("reassign").auto_finish();
+        span.child_of(parent.context().clone());
+        span.tag("service", "zookeeper");
+        span.log(Log::new().log("span.kind", "client-send"));
+        let plan = serde_json::to_vec(plan)
+            .with_context(|_| ErrorKind::JsonEncode("<zookeeper>.reassign"))?;
+        let keeper = self
+            .keeper(&mut span)
+            .map_err(|error| fail_span(error, &mut *span))?;
+        OPS_COUNT.with_label_values(&["zookeeper", "create"]).inc();
+        let timer = OPS_DURATION
+            .with_label_values(&["zookeeper", "create"])
+            .start_timer();
+        let created = keeper.create(
+            REASSIGN_PATH,
+            plan,
+            Acl::open_unsafe().clone(),
+            CreateMode::Persistent,
+        );
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        match created {
+            Err(ZkError::NodeExists) => Err(ErrorKind::ReassignInProgress.into()),
+            created => {
+                created
+                    .map_err(|error| {
+                        OP_ERRORS_COUNT
+                            .with_label_values(&["zookeeper", "create"])
+                            .inc();
+                        fail_span(error, &mut *span)
+                    })
+                    .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.reassign"))?;
+                Ok(())
             }
-            let leader = *(brokers
-                .first()
-                .ok_or_else(|| ErrorKind::PartitionNoBrokers(partition.clone()))?);
-            let partition = partition
-                .parse::<i32>()
-                .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.partitions"))?;
-            partitions.push(PartitionMeta {
-                leader,
-                partition,
-                replicas: brokers,
-            });
         }
-        Ok(partitions)
+    }
+
+    /// Fetch the partition reassignments still in progress, if any.
+    pub fn reassignments(&self, parent: &mut Span) -> Result<Option<ReassignPlan>> {
+        let mut span = self.context.tracer.span("reassignments").auto_finish();
+        span.child_of(parent.context().clone());
+        span.tag("service", "zookeeper");
+        span.log(Log::new().log("span.kind", "client-send"));
+        let keeper = self
+            .keeper(&mut span)
+            .map_err(|error| fail_span(error, &mut *span))?;
+        OPS_COUNT.with_label_values(&["zookeeper", "getData"]).inc();
+        let timer = OPS_DURATION
+            .with_label_values(&["zookeeper", "getData"])
+            .start_timer();
+        let plan = keeper.get_data(REASSIGN_PATH, false);
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        let (plan, _) = match plan {
+            // The controller deletes the plan once all partitions are reassigned.
+            Err(ZkError::NoNode) => return Ok(None),
+            plan => plan
+                .map_err(|error| {
+                    OP_ERRORS_COUNT
+                        .with_label_values(&["zookeeper", "getData"])
+                        .inc();
+                    fail_span(error, &mut *span)
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.reassignments"))?,
+        };
+        let plan = serde_json::from_slice(&plan)
+            .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.reassignments"))?;
+        Ok(Some(plan))
     }
 
     /// Fetch a list of topics in the cluster.
@@ -169,6 +278,9 @@ impl KafkaZoo {
             .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.topics"))?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
+        // Zookeeper does not guarantee the order of children so sort topics for stable shards.
+        let mut topics = topics;
+        topics.sort();
         Ok(topics)
     }
 }
@@ -184,8 +296,12 @@ impl KafkaZoo {
             debug!(self.context.logger, "Creating new zookeeper session");
             span.log(Log::new().log("action", "zookeeper.connect"));
             RECONNECT_COUNT.with_label_values(&["zookeeper"]).inc();
-            let new_session =
-                ZookeeperSession::connect(&self.target, self.timeout, self.context.logger.clone())?;
+            let new_session = ZookeeperSession::connect(
+                &self.target,
+                self.connect_timeout,
+                self.session_timeout,
+                self.context.logger.clone(),
+            )?;
             *session = new_session;
             info!(self.context.logger, "New zookeeper session ready");
         }
@@ -205,6 +321,29 @@ pub struct PartitionMeta {
     pub replicas: Vec<i32>,
 }
 
+/// Target replicas for a partition in a reassignment plan.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct PartitionReassignment {
+    /// ID of the partition to reassign.
+    pub partition: i32,
+
+    /// IDs of the brokers that should hold a replica of the partition, preferred leader first.
+    pub replicas: Vec<i32>,
+
+    /// Topic the partition belongs to.
+    pub topic: String,
+}
+
+/// Partition reassignment plan, in the format the Kafka controller expects.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ReassignPlan {
+    /// Partitions to reassign.
+    pub partitions: Vec<PartitionReassignment>,
+
+    /// Plan format version, expected to be 1.
+    pub version: i32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct PartitionsMap {
     /// Map of partitions to brokers.
@@ -214,6 +353,31 @@ struct PartitionsMap {
     pub version: i32,
 }
 
+impl PartitionsMap {
+    /// Partitions with a replica on the given broker, sorted by partition ID.
+    fn on_broker(self, broker: i32) -> Result<Vec<PartitionMeta>> {
+        let mut partitions = Vec::new();
+        for (partition, brokers) in self.partitions {
+            if !brokers.contains(&broker) {
+                continue;
+            }
+            let leader = *(brokers
+                .first()
+                .ok_or_else(|| ErrorKind::PartitionNoBrokers(partition.clone()))?);
+            let partition = partition
+                .parse::<i32>()
+                .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.partitions"))?;
+            partitions.push(PartitionMeta {
+                leader,
+                partition,
+                replicas: brokers,
+            });
+        }
+        partitions.sort_by_key(|meta| meta.partition);
+        Ok(partitions)
+    }
+}
+
 /// Container for a zookeeper session.
 struct ZookeeperSession {
     active: Arc<AtomicBool>,
@@ -222,13 +386,32 @@ struct ZookeeperSession {
 
 impl ZookeeperSession {
     /// Create a new zookeeper session.
+    ///
+    /// Fails if the connection to the ensamble is not established within `connect_timeout`.
     pub fn connect(
         connection: &str,
-        timeout: Duration,
+        connect_timeout: Duration,
+        session_timeout: Duration,
         logger: Logger,
     ) -> Result<ZookeeperSession> {
-        let client = ZooKeeper::connect(connection, timeout, |_| {})
-            .with_context(|_| ErrorKind::ZookeeperConnection(connection.to_string()))?;
+        let (connected, wait_connected) = mpsc::channel();
+        let client = ZooKeeper::connect(connection, session_timeout, move |event: WatchedEvent| {
+            if event.keeper_state == KeeperState::SyncConnected {
+                // The receiver is dropped once connected so errors can be ignored.
+                let _ = connected.send(());
+            }
+        })
+        .with_context(|_| ErrorKind::ZookeeperConnection(connection.to_string()))?;
+        if wait_connected.recv_timeout(connect_timeout).is_err() {
+            warn!(
+                logger,
+                "Timeout connecting to zookeeper";
+                "connection" => connection,
+                "connect_timeout" => ?connect_timeout,
+            );
+            let _ = client.close();
+            return Err(ErrorKind::ZookeeperConnection(connection.to_string()).into());
+        }
         let active = Arc::new(AtomicBool::new(true));
         let notify_close = Arc::clone(&active);
         client.add_listener(move |state| {
@@ -283,3 +466,35 @@ impl ZookeeperSession {
         Arc::clone(&self.client)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PartitionsMap;
+    use crate::config::Kafka;
+
+    fn shard_ids(config: &Kafka, meta: &str) -> Vec<String> {
+        let meta: PartitionsMap = serde_json::from_str(meta).unwrap();
+        meta.on_broker(1)
+            .unwrap()
+            .iter()
+            .map(|meta| config.shard_id("events", meta.partition))
+            .collect()
+    }
+
+    #[test]
+    fn shard_ids_are_stable() {
+        let partitions: Vec<String> = (0..32)
+            .map(|partition| format!(r#""{}": [{}, {}]"#, partition, partition % 3, 1))
+            .collect();
+        let meta = format!(
+            r#"{{"version": 1, "partitions": {{{}}}}}"#,
+            partitions.join(", ")
+        );
+        let config = Kafka::default();
+        let first = shard_ids(&config, &meta);
+        let second = shard_ids(&config, &meta);
+        assert_eq!(first.len(), 32);
+        assert_eq!(first[..3], ["events/0", "events/1", "events/2"]);
+        assert_eq!(first, second);
+    }
+}
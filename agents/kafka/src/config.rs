@@ -60,13 +60,50 @@ impl Config {
 }
 
 /// Kafka related options.
-#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Kafka {
+    /// Template for shard IDs, with `{topic}` and `{partition}` placeholders.
+    ///
+    /// IDs must be stable across restarts so both placeholders are required.
+    #[serde(default = "Kafka::default_shard_id_template")]
+    pub shard_id_template: String,
+
     /// Addresses used to locate the kafka services.
     #[serde(default)]
     pub target: KafkaTarget,
 }
 
+impl Kafka {
+    fn default_shard_id_template() -> String {
+        "{topic}/{partition}".into()
+    }
+
+    /// Render the shard ID of a topic partition.
+    pub fn shard_id(&self, topic: &str, partition: i32) -> String {
+        self.shard_id_template
+            .replace("{topic}", topic)
+            .replace("{partition}", &partition.to_string())
+    }
+
+    /// Check the shard ID template can generate unique IDs for all partitions.
+    pub fn validate_shard_id_template(&self) -> Result<()> {
+        let template = &self.shard_id_template;
+        if !template.contains("{topic}") || !template.contains("{partition}") {
+            return Err(ErrorKind::ConfigOption("kafka.shard_id_template").into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Kafka {
+    fn default() -> Self {
+        Kafka {
+            shard_id_template: Kafka::default_shard_id_template(),
+            target: KafkaTarget::default(),
+        }
+    }
+}
+
 /// Kafka server listening locations.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct KafkaTarget {
@@ -136,9 +173,19 @@ pub struct ZookeeperTarget {
     #[serde(default = "ZookeeperTarget::default_uri")]
     pub uri: String,
 
-    /// Zookeeper session timeout.
-    #[serde(default = "ZookeeperTarget::default_timeout")]
-    pub timeout: u64,
+    /// Seconds to wait for a connection to the ensamble to be established.
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+
+    /// Zookeeper session timeout in seconds.
+    #[serde(default)]
+    pub session_timeout: Option<u64>,
+
+    /// DEPRECATED: use `connect_timeout` and `session_timeout` instead.
+    ///
+    /// When set, it is used for both timeouts unless they are set explicitly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
 }
 
 impl ZookeeperTarget {
@@ -148,13 +195,29 @@ impl ZookeeperTarget {
     fn default_timeout() -> u64 {
         10
     }
+
+    /// Seconds to wait for a connection to the ensamble to be established.
+    pub fn connect_timeout(&self) -> u64 {
+        self.connect_timeout
+            .or(self.timeout)
+            .unwrap_or_else(ZookeeperTarget::default_timeout)
+    }
+
+    /// Zookeeper session timeout in seconds.
+    pub fn session_timeout(&self) -> u64 {
+        self.session_timeout
+            .or(self.timeout)
+            .unwrap_or_else(ZookeeperTarget::default_timeout)
+    }
 }
 
 impl Default for ZookeeperTarget {
     fn default() -> Self {
         ZookeeperTarget {
             uri: ZookeeperTarget::default_uri(),
-            timeout: ZookeeperTarget::default_timeout(),
+            connect_timeout: None,
+            session_timeout: None,
+            timeout: None,
         }
     }
 }
@@ -177,4 +240,59 @@ mod tests {
         let cursor = Cursor::new("{agent: {db: test}, kafka: {cluster: test}}");
         Config::from_reader(cursor).unwrap();
     }
+
+    #[test]
+    fn shard_id_template() {
+        let cursor = Cursor::new(
+            "{agent: {db: test}, kafka: {shard_id_template: 'kafka.{topic}.p{partition}'}}",
+        );
+        let config = Config::from_reader(cursor).unwrap();
+        config.kafka.validate_shard_id_template().unwrap();
+        assert_eq!(config.kafka.shard_id("events", 3), "kafka.events.p3");
+    }
+
+    #[test]
+    fn shard_id_template_default() {
+        let cursor = Cursor::new("{agent: {db: test}}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert_eq!(config.kafka.shard_id("events", 3), "events/3");
+    }
+
+    #[test]
+    fn shard_id_template_requires_partition() {
+        let cursor = Cursor::new("{agent: {db: test}, kafka: {shard_id_template: '{topic}'}}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert!(config.kafka.validate_shard_id_template().is_err());
+    }
+
+    #[test]
+    fn zookeeper_timeouts() {
+        let cursor = Cursor::new(
+            "{agent: {db: test}, kafka: {target: {zookeeper: {connect_timeout: 5, session_timeout: 30}}}}",
+        );
+        let config = Config::from_reader(cursor).unwrap();
+        let zookeeper = config.kafka.target.zookeeper;
+        assert_eq!(zookeeper.connect_timeout(), 5);
+        assert_eq!(zookeeper.session_timeout(), 30);
+    }
+
+    #[test]
+    fn zookeeper_timeouts_default() {
+        let cursor = Cursor::new("{agent: {db: test}}");
+        let config = Config::from_reader(cursor).unwrap();
+        let zookeeper = config.kafka.target.zookeeper;
+        assert_eq!(zookeeper.connect_timeout(), 10);
+        assert_eq!(zookeeper.session_timeout(), 10);
+    }
+
+    #[test]
+    fn zookeeper_timeouts_legacy() {
+        let cursor = Cursor::new(
+            "{agent: {db: test}, kafka: {target: {zookeeper: {timeout: 20, session_timeout: 30}}}}",
+        );
+        let config = Config::from_reader(cursor).unwrap();
+        let zookeeper = config.kafka.target.zookeeper;
+        assert_eq!(zookeeper.connect_timeout(), 20);
+        assert_eq!(zookeeper.session_timeout(), 30);
+    }
 }
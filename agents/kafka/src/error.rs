@@ -33,9 +33,18 @@ pub enum ErrorKind {
     /// JSON specifc `ResponseDecode`.
     JsonDecode(&'static str),
 
+    /// JSON specifc `FreeForm` for requests that can't be encoded.
+    JsonEncode(&'static str),
+
+    /// `InvalidStoreState` caused by an operation that requires the broker to be the controller.
+    NotController(i32, i32),
+
     /// `InvalidStoreState` wrapper for partitions without brokers.
     PartitionNoBrokers(String),
 
+    /// `InvalidStoreState` caused by a partition reassignment already in progress.
+    ReassignInProgress,
+
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
@@ -71,9 +80,19 @@ impl From<ErrorKind> for BaseKind {
             ErrorKind::Io(path) => BaseKind::Io(path),
             ErrorKind::JmxConnection(address) => BaseKind::Connection("jmx server", address),
             ErrorKind::JsonDecode(op) => BaseKind::ResponseDecode("json", op),
+            ErrorKind::JsonEncode(op) => {
+                BaseKind::FreeForm(format!("unable to encode JSON request for {}", op))
+            }
+            ErrorKind::NotController(broker, controller) => BaseKind::InvalidStoreState(format!(
+                "operation requires the controller but broker {} is not (the controller is broker {})",
+                broker, controller
+            )),
             ErrorKind::PartitionNoBrokers(partition) => {
                 BaseKind::InvalidStoreState(format!("partition {} has no brokers", partition))
             }
+            ErrorKind::ReassignInProgress => BaseKind::InvalidStoreState(
+                "a partition reassignment is already in progress".into(),
+            ),
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
             ErrorKind::TopicNoOffsets(topic) => {
                 BaseKind::FreeForm(format!("unable to find offsets for topic {}", topic))
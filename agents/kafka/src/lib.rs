@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use replicante_agent::Result;
 use replicante_agent::SemVersion;
 
+mod actions;
 mod agent;
 mod config;
 mod error;
@@ -50,6 +51,7 @@ pub fn run() -> Result<bool> {
     replicante_agent::process::run(agent_conf, "repliagent-kafka", release, |context, _| {
         metrics::register_metrics(context);
         let agent = KafkaAgent::with_config(config, context.clone())?;
+        actions::register(&agent, context);
         replicante_agent::process::update_checker(CURRENT_VERSION.clone(), UPDATE_META, context)?;
         Ok(agent)
     })
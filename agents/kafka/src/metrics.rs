@@ -1,42 +1,45 @@
 use lazy_static::lazy_static;
 use prometheus::CounterVec;
-use prometheus::HistogramOpts;
 use prometheus::HistogramVec;
-use prometheus::Opts;
-use slog::debug;
 
+use replicante_agent::register_collector;
 use replicante_agent::AgentContext;
+use replicante_agent::MetricOpts;
 
 lazy_static! {
     pub static ref OP_ERRORS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_kafka_operation_errors",
             "Number of Kafka/JMX/Zookeeper operations failed"
-        ),
+        )
+        .opts(),
         &["service", "operation"]
     )
     .expect("Failed to create OP_ERRORS_COUNT counter");
     pub static ref OPS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_kafka_operations",
             "Number of Kafka/JMX/Zookeeper operations issued"
-        ),
+        )
+        .opts(),
         &["service", "operation"]
     )
     .expect("Failed to create OPS_COUNT counter");
     pub static ref OPS_DURATION: HistogramVec = HistogramVec::new(
-        HistogramOpts::new(
+        MetricOpts::new(
             "repliagent_kafka_operations_duration",
             "Duration (in seconds) of Kafka/JMX/Zookeeper operations"
-        ),
+        )
+        .histogram_opts(),
         &["service", "operation"]
     )
     .expect("Failed to create OPS_DURATION histogram");
     pub static ref RECONNECT_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_kafka_reconnect",
             "Number of Kafka/JMX/Zookeeper reconnect operations"
-        ),
+        )
+        .opts(),
         &["service"]
     )
     .expect("Failed to create RECONNECT_COUNT counter");
@@ -48,16 +51,23 @@ lazy_static! {
 pub fn register_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let registry = &context.metrics;
-    if let Err(error) = registry.register(Box::new(OPS_COUNT.clone())) {
-        debug!(logger, "Failed to register OPS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(OP_ERRORS_COUNT.clone())) {
-        debug!(logger, "Failed to register OP_ERRORS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(OPS_DURATION.clone())) {
-        debug!(logger, "Failed to register OPS_DURATION"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(RECONNECT_COUNT.clone())) {
-        debug!(logger, "Failed to register RECONNECT_COUNT"; "error" => ?error);
-    }
+    register_collector(logger, registry, "OPS_COUNT", Box::new(OPS_COUNT.clone()));
+    register_collector(
+        logger,
+        registry,
+        "OP_ERRORS_COUNT",
+        Box::new(OP_ERRORS_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "OPS_DURATION",
+        Box::new(OPS_DURATION.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "RECONNECT_COUNT",
+        Box::new(RECONNECT_COUNT.clone()),
+    );
 }
@@ -0,0 +1,192 @@
+use std::fs;
+use std::path::Path;
+
+use bson::Document;
+use failure::ResultExt;
+use mongodb::sync::Client;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::Transaction;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::version::prepare_command;
+
+const KIND: &str = "replicante.mongodb/diagnostics";
+
+/// Commands included in the diagnostics bundle, in the order they are collected.
+const SECTIONS: &[&str] = &[
+    "serverStatus",
+    "replSetGetStatus",
+    "getCmdLineOpts",
+    "hostInfo",
+];
+
+/// Collect a read-only diagnostics bundle for support escalations.
+///
+/// The output of each command in `SECTIONS` is stored in the action's `state_payload`
+/// and, if `mongo.diagnostics.path` is set, written to `<path>/<action id>.json`.
+/// Commands that fail are reported in the bundle instead of failing the action.
+pub struct Diagnostics {
+    client: Client,
+    config: MongoDB,
+}
+
+impl Diagnostics {
+    pub fn new(client: Client, config: MongoDB) -> Diagnostics {
+        Diagnostics { client, config }
+    }
+
+    /// Write the bundle to the configured directory, if any.
+    fn export(&self, record: &dyn ActionRecordView, bundle: &Json) -> Result<()> {
+        let dir = match self.config.diagnostics.path.as_ref() {
+            None => return Ok(()),
+            Some(dir) => dir,
+        };
+        let path = Path::new(dir).join(format!("{}.json", ActionRecordView::id(record)));
+        let path = path.to_string_lossy().to_string();
+        let bundle = serde_json::to_vec_pretty(bundle).with_context(|_| BaseKind::ActionEncode)?;
+        fs::write(&path, bundle).with_context(|_| ErrorKind::Io(path))?;
+        Ok(())
+    }
+}
+
+impl Action for Diagnostics {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: KIND.into(),
+            description: "Collect a diagnostics bundle for support escalations".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        validate_action_args::<DiagnosticsArgs>(record.args().clone())
+            .with_context(|_| BaseKind::ActionDecode)?;
+        let admin = self.client.database("admin");
+        let responses = SECTIONS
+            .iter()
+            .map(|command| {
+                let mut request = Document::new();
+                request.insert(*command, 1);
+                let response = prepare_command(&self.config, request)
+                    .map_err(|error| error.to_string())
+                    .and_then(|request| {
+                        admin
+                            .run_command(request, None)
+                            .map_err(|error| error.to_string())
+                    });
+                (*command, response)
+            })
+            .collect();
+        let bundle = bundle(responses, self.config.diagnostics.max_size);
+        self.export(record, &bundle)?;
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            bundle,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<DiagnosticsArgs>(args.clone()).map(|_| ())
+    }
+}
+
+/// Arguments accepted by the `Diagnostics` action.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiagnosticsArgs {}
+
+/// Assemble command responses into a bundle of at most (about) `max_size` bytes.
+///
+/// Sections that would exceed the limit are replaced by a note with their size.
+fn bundle(responses: Vec<(&str, std::result::Result<Document, String>)>, max_size: usize) -> Json {
+    let mut bundle = serde_json::Map::new();
+    let mut size = 0;
+    for (command, response) in responses {
+        let section = match response.map(|response| serde_json::to_value(&response)) {
+            Ok(Ok(section)) => section,
+            Ok(Err(error)) => json!({ "error": error.to_string() }),
+            Err(error) => json!({ "error": error }),
+        };
+        let section_size = section.to_string().len();
+        let section = if size + section_size > max_size {
+            json!({
+                "omitted": "section exceeds the diagnostics bundle size limit",
+                "size": section_size,
+            })
+        } else {
+            size += section_size;
+            section
+        };
+        bundle.insert(command.to_string(), section);
+    }
+    Json::Object(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use serde_json::json;
+
+    use super::bundle;
+
+    fn responses() -> Vec<(&'static str, Result<bson::Document, String>)> {
+        vec![
+            (
+                "serverStatus",
+                Ok(doc! {"host": "node1", "uptime": 42, "ok": 1}),
+            ),
+            (
+                "replSetGetStatus",
+                Ok(doc! {"set": "rs0", "myState": 1, "ok": 1}),
+            ),
+            ("getCmdLineOpts", Ok(doc! {"argv": ["mongod"], "ok": 1})),
+            ("hostInfo", Err("command failed".to_string())),
+        ]
+    }
+
+    #[test]
+    fn bundle_contains_all_sections() {
+        let bundle = bundle(responses(), 1024 * 1024);
+        let mut sections: Vec<&String> = bundle.as_object().unwrap().keys().collect();
+        sections.sort();
+        assert_eq!(
+            sections,
+            vec![
+                "getCmdLineOpts",
+                "hostInfo",
+                "replSetGetStatus",
+                "serverStatus"
+            ]
+        );
+        assert_eq!(bundle["serverStatus"]["host"], json!("node1"));
+        assert_eq!(bundle["replSetGetStatus"]["set"], json!("rs0"));
+        assert_eq!(bundle["hostInfo"], json!({"error": "command failed"}));
+    }
+
+    #[test]
+    fn bundle_size_is_bounded() {
+        let bundle = bundle(responses(), 60);
+        assert_eq!(bundle["serverStatus"]["host"], json!("node1"));
+        assert!(bundle["replSetGetStatus"].get("omitted").is_some());
+        assert!(bundle["getCmdLineOpts"].get("omitted").is_some());
+    }
+}
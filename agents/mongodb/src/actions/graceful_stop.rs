@@ -13,14 +13,18 @@ use replicante_agent::actions::ActionValidity;
 use replicante_agent::Result;
 use replicante_agent::Transaction;
 
+use crate::config::MongoDB;
+use crate::version::prepare_command;
+
 /// Request graceful server stop by issuing a `shutdown` command.
 pub struct GracefulStop {
     client: Client,
+    config: MongoDB,
 }
 
 impl GracefulStop {
-    pub fn new(client: Client) -> GracefulStop {
-        GracefulStop { client }
+    pub fn new(client: Client, config: MongoDB) -> GracefulStop {
+        GracefulStop { client, config }
     }
 }
 
@@ -35,7 +39,7 @@ impl Action for GracefulStop {
         record: &dyn ActionRecordView,
         span: Option<&mut Span>,
     ) -> Result<()> {
-        let shutdown = doc! {"shutdown": 1};
+        let shutdown = prepare_command(&self.config, doc! {"shutdown": 1})?;
         // This will fail even on success as the server will not respond.
         let result = self.client.database("admin").run_command(shutdown, None);
         let message = format!("{:?}", result);
@@ -0,0 +1,188 @@
+use bson::doc;
+use bson::Document;
+use failure::ResultExt;
+use mongodb::sync::Client;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::CachedResponse;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::Transaction;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::version::member_role;
+use crate::version::prepare_command;
+
+/// Enter or leave maintenance mode with `replSetMaintenance`.
+///
+/// Members in maintenance mode enter the RECOVERING state and stop serving reads.
+/// Maintenance mode can only be entered by secondaries and only left by non-primaries.
+pub struct Maintenance {
+    client: Client,
+    config: MongoDB,
+}
+
+impl Maintenance {
+    pub fn new(client: Client, config: MongoDB) -> Maintenance {
+        Maintenance { client, config }
+    }
+}
+
+impl Action for Maintenance {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "replicante.mongodb/maintenance".into(),
+            description: "Enter or leave maintenance mode on a secondary".into(),
+        }
+    }
+
+    fn idempotent(&self) -> bool {
+        // mongod counts maintenance requests so a replayed enable needs an extra disable.
+        false
+    }
+
+    fn invalidates(&self) -> Vec<CachedResponse> {
+        // Members in maintenance mode report the RECOVERING role.
+        vec![CachedResponse::Shards]
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: MaintenanceArgs =
+            validate_action_args(record.args().clone()).with_context(|_| BaseKind::ActionDecode)?;
+        let admin = self.client.database("admin");
+        let is_master = prepare_command(&self.config, doc! {"isMaster": 1})?;
+        let is_master = admin
+            .run_command(is_master, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("isMaster"))?;
+        ensure_secondary(&is_master, args.enable)?;
+        let maintenance = prepare_command(&self.config, doc! {"replSetMaintenance": args.enable})?;
+        admin
+            .run_command(maintenance, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("replSetMaintenance"))?;
+        let status = prepare_command(&self.config, doc! {"replSetGetStatus": 1})?;
+        let status = admin
+            .run_command(status, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
+        let state = status
+            .get_i32("myState")
+            .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
+        let payload = json!({
+            "maintenance": args.enable,
+            "role": member_role(state)?,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<MaintenanceArgs>(args.clone()).map(|_| ())
+    }
+}
+
+/// Arguments accepted by the `Maintenance` action.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaintenanceArgs {
+    /// Enter maintenance mode if true, leave it if false.
+    enable: bool,
+}
+
+/// Ensure the `isMaster` response allows the maintenance mode change.
+///
+/// Only secondaries can enter maintenance mode while members already in maintenance
+/// are RECOVERING and so are not reported as secondaries.
+fn ensure_secondary(is_master: &Document, enable: bool) -> Result<()> {
+    let primary = is_master
+        .get_bool("ismaster")
+        .with_context(|_| ErrorKind::BsonDecode("isMaster"))?;
+    let secondary = is_master.get_bool("secondary").unwrap_or(false);
+    if primary || (enable && !secondary) {
+        return Err(ErrorKind::NotSecondary.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use failure::Fail;
+    use mongodb::sync::Client;
+    use serde_json::json;
+
+    use replicante_agent::actions::utils::validate_action_args;
+    use replicante_agent::actions::Action;
+    use replicante_agent::actions::ActionValidityError;
+    use replicante_agent::actions::CachedResponse;
+
+    use super::ensure_secondary;
+    use super::Maintenance;
+    use super::MaintenanceArgs;
+    use crate::config::MongoDB;
+
+    #[test]
+    fn args_enable_must_be_boolean() {
+        let args = validate_action_args::<MaintenanceArgs>(json!({"enable": "yes"}));
+        match args {
+            Err(ActionValidityError::InvalidField { field, .. }) => assert_eq!(field, "enable"),
+            other => panic!("unexpected value: {:?}", other),
+        };
+    }
+
+    #[test]
+    fn args_enable_required() {
+        let args = validate_action_args::<MaintenanceArgs>(json!({}));
+        match args {
+            Err(ActionValidityError::InvalidField { field, .. }) => assert_eq!(field, "enable"),
+            other => panic!("unexpected value: {:?}", other),
+        };
+    }
+
+    #[test]
+    fn args_valid() {
+        let args = validate_action_args::<MaintenanceArgs>(json!({"enable": true})).unwrap();
+        assert!(args.enable);
+    }
+
+    #[test]
+    fn invalidates_shards() {
+        // The client connects lazily so no server is needed.
+        let client = Client::with_uri_str("mongodb://localhost:27017").unwrap();
+        let action = Maintenance::new(client, MongoDB::default());
+        assert_eq!(action.invalidates(), vec![CachedResponse::Shards]);
+    }
+
+    #[test]
+    fn enter_secondary_only() {
+        let error = ensure_secondary(&doc! {"ismaster": true}, true).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        let error = ensure_secondary(&doc! {"ismaster": false}, true).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_secondary(&doc! {"ismaster": false, "secondary": true}, true).unwrap();
+    }
+
+    #[test]
+    fn leave_not_on_primary() {
+        let error = ensure_secondary(&doc! {"ismaster": true}, false).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_secondary(&doc! {"ismaster": false, "secondary": false}, false).unwrap();
+    }
+}
@@ -1,3 +1,36 @@
+use mongodb::sync::Client;
+
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::Result;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+
+mod diagnostics;
 mod graceful_stop;
+mod maintenance;
+mod resync;
+mod set_priority;
 
+pub use self::diagnostics::Diagnostics;
 pub use self::graceful_stop::GracefulStop;
+pub use self::maintenance::Maintenance;
+pub use self::resync::Resync;
+pub use self::set_priority::SetPriority;
+
+/// Register MongoDB specific actions.
+///
+/// The resync action is only registered when a `resync_command` is configured.
+pub fn register(client: &Client, config: &MongoDB) -> Result<()> {
+    ACTIONS::register(Diagnostics::new(client.clone(), config.clone()));
+    ACTIONS::register(Maintenance::new(client.clone(), config.clone()));
+    ACTIONS::register(SetPriority::new(client.clone(), config.clone()));
+    if let Some(command) = config.resync_command.as_ref() {
+        if command.is_empty() {
+            let message = "empty command for mongo.resync_command".into();
+            return Err(ErrorKind::Initialisation(message).into());
+        }
+        ACTIONS::register(Resync::new(client.clone(), config.clone(), command.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::process::Command;
+
+use bson::doc;
+use bson::Document;
+use failure::ResultExt;
+use mongodb::sync::Client;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::ActionValidityError;
+use replicante_agent::actions::CachedResponse;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::Transaction;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::version::prepare_command;
+
+const KIND: &str = "replicante.mongodb/resync";
+
+/// Wipe a secondary's data and restart it so it performs an initial sync.
+///
+/// Removing the data and restarting the process is delegated to the `mongo.resync_command`
+/// configured by the operator. The action is destructive and only runs on secondaries:
+///
+///   1. When first invoked, the node is checked to be a secondary and the action
+///      enters the `resync` phase.
+///   2. On the next invocation the node is checked again, the command is executed
+///      and the action completes.
+pub struct Resync {
+    client: Client,
+    command: Vec<String>,
+    config: MongoDB,
+}
+
+impl Resync {
+    pub fn new(client: Client, config: MongoDB, command: Vec<String>) -> Resync {
+        Resync {
+            client,
+            command,
+            config,
+        }
+    }
+
+    /// Check the node is (still) a secondary.
+    fn check_secondary(&self) -> Result<()> {
+        let is_master = prepare_command(&self.config, doc! {"isMaster": 1})?;
+        let is_master = self
+            .client
+            .database("admin")
+            .run_command(is_master, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("isMaster"))?;
+        ensure_secondary(&is_master)
+    }
+
+    /// Run the resync command and capture its output.
+    fn exec(&self, record: &dyn ActionRecordView) -> Result<Json> {
+        let action_id = ActionRecordView::id(record);
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .output()
+            .with_context(|_| BaseKind::ExternalActionStart(KIND.into(), action_id))?;
+        let stdout =
+            String::from_utf8(output.stdout).unwrap_or_else(|_| "{binary blob}".to_string());
+        let stderr =
+            String::from_utf8(output.stderr).unwrap_or_else(|_| "{binary blob}".to_string());
+        if !output.status.success() {
+            let error = BaseKind::ExternalActionExec(action_id, stdout, stderr);
+            return Err(error.into());
+        }
+        Ok(json!({"phase": "done", "stdout": stdout}))
+    }
+}
+
+impl Action for Resync {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: KIND.into(),
+            description: "Wipe a secondary's data and restart it to trigger an initial sync".into(),
+        }
+    }
+
+    fn destructive(&self) -> bool {
+        true
+    }
+
+    fn idempotent(&self) -> bool {
+        false
+    }
+
+    fn invalidates(&self) -> Vec<CachedResponse> {
+        // The member restarts without data and goes through an initial sync.
+        vec![CachedResponse::Shards]
+    }
+
+    fn singleton(&self) -> bool {
+        // Resyncing several members at once could leave the set without a majority.
+        true
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        ResyncArgs::parse(record.args()).with_context(|_| BaseKind::ActionDecode)?;
+        let span = span.map(|span| span.context().clone());
+        match record.state() {
+            ActionState::New => {
+                self.check_secondary()?;
+                tx.action().phase(record, "resync", span)
+            }
+            _ => {
+                // The node may have been elected between invocations: never wipe a primary.
+                self.check_secondary()?;
+                let payload = self.exec(record)?;
+                tx.action()
+                    .transition(record, ActionState::Done, payload, span)
+            }
+        }
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        ResyncArgs::parse(args).map(|_| ())
+    }
+}
+
+/// Arguments accepted by the `Resync` action.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResyncArgs {
+    /// Must be set to true to confirm the node's data should be deleted.
+    confirm: bool,
+}
+
+impl ResyncArgs {
+    fn parse(args: &Json) -> ActionValidity<ResyncArgs> {
+        let args: ResyncArgs = validate_action_args(args.clone())?;
+        if !args.confirm {
+            return Err(ActionValidityError::InvalidField {
+                field: "confirm".into(),
+                reason: "resyncing deletes all data on the node and must be confirmed".into(),
+            });
+        }
+        Ok(args)
+    }
+}
+
+/// Ensure the `isMaster` response comes from a secondary node.
+fn ensure_secondary(is_master: &Document) -> Result<()> {
+    let secondary = is_master.get_bool("secondary").unwrap_or(false);
+    if !secondary {
+        return Err(ErrorKind::NotSecondary.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use failure::Fail;
+    use serde_json::json;
+
+    use replicante_agent::actions::ActionValidityError;
+
+    use super::ensure_secondary;
+    use super::ResyncArgs;
+
+    #[test]
+    fn confirm_required() {
+        match ResyncArgs::parse(&json!({})) {
+            Err(ActionValidityError::InvalidField { field, .. }) => assert_eq!(field, "confirm"),
+            other => panic!("unexpected value: {:?}", other),
+        };
+    }
+
+    #[test]
+    fn confirm_must_be_true() {
+        match ResyncArgs::parse(&json!({"confirm": false})) {
+            Err(ActionValidityError::InvalidField { field, .. }) => assert_eq!(field, "confirm"),
+            other => panic!("unexpected value: {:?}", other),
+        };
+        assert!(ResyncArgs::parse(&json!({"confirm": true})).is_ok());
+    }
+
+    #[test]
+    fn secondary_only() {
+        let error = ensure_secondary(&doc! {"ismaster": true, "secondary": false}).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        let error = ensure_secondary(&doc! {"ismaster": false}).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_secondary(&doc! {"ismaster": false, "secondary": true}).unwrap();
+    }
+}
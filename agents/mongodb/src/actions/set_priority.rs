@@ -0,0 +1,308 @@
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use failure::ResultExt;
+use mongodb::sync::Client;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::ActionValidityError;
+use replicante_agent::actions::CachedResponse;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::Transaction;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::version::prepare_command;
+
+/// Adjust the `priority` and `votes` of a replica set member with `replSetReconfig`.
+///
+/// The action must be invoked on the primary node as reconfigurations are only
+/// accepted there and the new configuration version is derived from the current one.
+pub struct SetPriority {
+    client: Client,
+    config: MongoDB,
+}
+
+impl SetPriority {
+    pub fn new(client: Client, config: MongoDB) -> SetPriority {
+        SetPriority { client, config }
+    }
+}
+
+impl Action for SetPriority {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "replicante.mongodb/set_priority".into(),
+            description: "Set the priority and votes of a replica set member".into(),
+        }
+    }
+
+    fn invalidates(&self) -> Vec<CachedResponse> {
+        // Priority changes can trigger an election.
+        vec![CachedResponse::Shards]
+    }
+
+    fn requires_primary(&self) -> bool {
+        // Reconfigurations are only accepted by the primary.
+        true
+    }
+
+    fn singleton(&self) -> bool {
+        // Concurrent reconfigs from different members would overwrite each other.
+        true
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args =
+            SetPriorityArgs::parse(record.args()).with_context(|_| BaseKind::ActionDecode)?;
+        let admin = self.client.database("admin");
+        let is_master = prepare_command(&self.config, doc! {"isMaster": 1})?;
+        let is_master = admin
+            .run_command(is_master, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("isMaster"))?;
+        ensure_primary(&is_master)?;
+        let config = prepare_command(&self.config, doc! {"replSetGetConfig": 1})?;
+        let config = admin
+            .run_command(config, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetConfig"))?;
+        let config = config
+            .get_document("config")
+            .with_context(|_| ErrorKind::BsonDecode("replSetGetConfig"))?
+            .clone();
+        let (config, payload) = reconfigure(config, &args)?;
+        let reconfig = prepare_command(&self.config, doc! {"replSetReconfig": config})?;
+        admin
+            .run_command(reconfig, None)
+            .with_context(|_| ErrorKind::StoreOpFailed("replSetReconfig"))?;
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        SetPriorityArgs::parse(args).map(|_| ())
+    }
+}
+
+/// Arguments accepted by the `SetPriority` action.
+#[derive(Debug, Deserialize)]
+struct SetPriorityArgs {
+    /// Address (`host:port`) of the replica set member to update, as it appears in the config.
+    member: String,
+
+    /// New priority for the member, between 0 and 1000.
+    #[serde(default)]
+    priority: Option<f64>,
+
+    /// New number of votes for the member, 0 or 1.
+    #[serde(default)]
+    votes: Option<i32>,
+}
+
+impl SetPriorityArgs {
+    fn parse(args: &Json) -> ActionValidity<SetPriorityArgs> {
+        let args: SetPriorityArgs = serde_json::from_value(args.clone())
+            .map_err(|error| ActionValidityError::InvalidArgs(error.to_string()))?;
+        if args.priority.is_none() && args.votes.is_none() {
+            let message = "at least one of priority or votes must be set".into();
+            return Err(ActionValidityError::InvalidArgs(message));
+        }
+        if let Some(priority) = args.priority {
+            if !(0.0..=1000.0).contains(&priority) {
+                let message = format!("priority must be between 0 and 1000, got {}", priority);
+                return Err(ActionValidityError::InvalidArgs(message));
+            }
+        }
+        if let Some(votes) = args.votes {
+            if votes != 0 && votes != 1 {
+                let message = format!("votes must be 0 or 1, got {}", votes);
+                return Err(ActionValidityError::InvalidArgs(message));
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Ensure the `isMaster` response comes from a primary node.
+fn ensure_primary(is_master: &Document) -> Result<()> {
+    let primary = is_master
+        .get_bool("ismaster")
+        .with_context(|_| ErrorKind::BsonDecode("isMaster"))?;
+    if !primary {
+        return Err(ErrorKind::NotPrimary.into());
+    }
+    Ok(())
+}
+
+/// Read a BSON number as a float, regardless of how it is stored.
+fn number(value: Option<&Bson>) -> Option<f64> {
+    match value {
+        Some(Bson::FloatingPoint(value)) => Some(*value),
+        Some(Bson::I32(value)) => Some(f64::from(*value)),
+        Some(Bson::I64(value)) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+/// Apply the requested member changes to a replica set config and bump its version.
+///
+/// Returns the new config and the action payload recording old and new values.
+fn reconfigure(mut config: Document, args: &SetPriorityArgs) -> Result<(Document, Json)> {
+    let version = match config.get("version") {
+        Some(Bson::I32(version)) => i64::from(*version),
+        Some(Bson::I64(version)) => *version,
+        _ => return Err(ErrorKind::BsonDecode("replSetGetConfig").into()),
+    };
+    let new_version = version + 1;
+    match config.get("version") {
+        Some(Bson::I32(_)) => config.insert("version", new_version as i32),
+        _ => config.insert("version", new_version),
+    };
+
+    let members = match config.get_mut("members") {
+        Some(Bson::Array(members)) => members,
+        _ => return Err(ErrorKind::BsonDecode("replSetGetConfig").into()),
+    };
+    let member = members
+        .iter_mut()
+        .filter_map(|member| match member {
+            Bson::Document(member) => Some(member),
+            _ => None,
+        })
+        .find(|member| member.get_str("host").ok() == Some(args.member.as_str()))
+        .ok_or_else(|| ErrorKind::MemberNotFound(args.member.clone()))?;
+
+    // Members without explicit priority and votes use the MongoDB defaults.
+    let old_priority = number(member.get("priority")).unwrap_or(1.0);
+    let old_votes = number(member.get("votes"))
+        .map(|votes| votes as i32)
+        .unwrap_or(1);
+    let priority = args.priority.unwrap_or(old_priority);
+    let votes = args.votes.unwrap_or(old_votes);
+    member.insert("priority", priority);
+    member.insert("votes", votes);
+
+    let payload = json!({
+        "member": args.member,
+        "old": {"priority": old_priority, "votes": old_votes, "version": version},
+        "new": {"priority": priority, "votes": votes, "version": new_version},
+    });
+    Ok((config, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use bson::Bson;
+    use failure::Fail;
+    use serde_json::json;
+
+    use replicante_agent::actions::ActionValidityError;
+
+    use super::ensure_primary;
+    use super::reconfigure;
+    use super::SetPriorityArgs;
+
+    fn make_config() -> bson::Document {
+        doc! {
+            "_id": "test-rs",
+            "version": 3,
+            "members": [{
+                "_id": 0,
+                "host": "host0:27017",
+                "priority": 1.0,
+                "votes": 1,
+            }, {
+                "_id": 1,
+                "host": "host1:27017",
+            }],
+        }
+    }
+
+    #[test]
+    fn invalid_priority_too_high() {
+        match SetPriorityArgs::parse(&json!({"member": "host0:27017", "priority": 1001})) {
+            Err(ActionValidityError::InvalidArgs(message)) => {
+                assert_eq!(message, "priority must be between 0 and 1000, got 1001");
+            }
+            Ok(_) => panic!("expected invalid priority"),
+        };
+    }
+
+    #[test]
+    fn invalid_priority_negative() {
+        let args = json!({"member": "host0:27017", "priority": -1});
+        assert!(SetPriorityArgs::parse(&args).is_err());
+    }
+
+    #[test]
+    fn invalid_votes() {
+        let args = json!({"member": "host0:27017", "votes": 2});
+        assert!(SetPriorityArgs::parse(&args).is_err());
+    }
+
+    #[test]
+    fn invalid_without_changes() {
+        let args = json!({"member": "host0:27017"});
+        assert!(SetPriorityArgs::parse(&args).is_err());
+    }
+
+    #[test]
+    fn valid_args() {
+        let args = json!({"member": "host0:27017", "priority": 0.5, "votes": 0});
+        let args = SetPriorityArgs::parse(&args).unwrap();
+        assert_eq!(args.priority, Some(0.5));
+        assert_eq!(args.votes, Some(0));
+    }
+
+    #[test]
+    fn primary_only() {
+        let error = ensure_primary(&doc! {"ismaster": false, "secondary": true}).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_primary(&doc! {"ismaster": true}).unwrap();
+    }
+
+    #[test]
+    fn reconfigure_bumps_version() {
+        let args =
+            SetPriorityArgs::parse(&json!({"member": "host1:27017", "priority": 0})).unwrap();
+        let (config, payload) = reconfigure(make_config(), &args).unwrap();
+        assert_eq!(config.get("version"), Some(&Bson::I32(4)));
+        let members = config.get_array("members").unwrap();
+        let member = members[1].as_document().unwrap();
+        assert_eq!(member.get("priority"), Some(&Bson::FloatingPoint(0.0)));
+        assert_eq!(member.get("votes"), Some(&Bson::I32(1)));
+        assert_eq!(
+            payload,
+            json!({
+                "member": "host1:27017",
+                "old": {"priority": 1.0, "votes": 1, "version": 3},
+                "new": {"priority": 0.0, "votes": 1, "version": 4},
+            })
+        );
+    }
+
+    #[test]
+    fn reconfigure_unknown_member() {
+        let args = SetPriorityArgs::parse(&json!({"member": "host9:27017", "votes": 0})).unwrap();
+        let error = reconfigure(make_config(), &args).unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+    }
+}
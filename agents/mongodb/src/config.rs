@@ -71,41 +71,251 @@ impl Config {
 /// MongoDB related options.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct MongoDB {
+    /// Commands the agent is allowed to issue to MongoDB (optional).
+    ///
+    /// When set, commands not in the list are refused by the agent. All commands are allowed
+    /// when the option is not set.
+    #[serde(default)]
+    pub command_allowlist: Option<Vec<String>>,
+
+    /// Options for the `replicante.mongodb/diagnostics` action.
+    #[serde(default)]
+    pub diagnostics: Diagnostics,
+
+    /// Enrich datastore information with details queried from MongoDB.
+    ///
+    /// Server parameters are fetched once and cached until the agent restarts while
+    /// server status details are refreshed every `expensive_metrics_interval`.
+    #[serde(default = "MongoDB::default_enrichment")]
+    pub enrichment: bool,
+
+    /// Interval (in seconds) between collections of expensive MongoDB metrics.
+    ///
+    /// Results of expensive commands (such as serverStatus) are cached in between.
+    #[serde(default = "MongoDB::default_expensive_metrics_interval")]
+    pub expensive_metrics_interval: u64,
+
+    /// Compatibility module to use regardless of the detected MongoDB version (optional).
+    ///
+    /// Intended as an escape hatch for versions the agent misdetects or does not support yet.
+    #[serde(default)]
+    pub force_compat: Option<CompatModule>,
+
     /// Timeout (in milliseconds) for selecting an appropriate server for operations.
     #[serde(default = "MongoDB::default_host_select_timeout")]
     pub host_select_timeout: u64,
 
+    /// Maximum size (in bytes) of replSetGetStatus responses the agent will decode.
+    ///
+    /// Larger responses are rejected to avoid memory spikes. Set to null to disable the check.
+    #[serde(default = "MongoDB::default_max_response_size")]
+    pub max_response_size: Option<usize>,
+
+    /// Minimum number of connections to keep open to MongoDB (optional).
+    ///
+    /// When the agent warmup is enabled these connections are opened at startup.
+    #[serde(default)]
+    pub min_pool_size: Option<u32>,
+
+    /// Node name to report when it can't be determined from MongoDB (optional).
+    ///
+    /// Defaults to the hostname of the agent's host when not set.
+    #[serde(default)]
+    pub node_name_fallback: Option<String>,
+
+    /// Time (in seconds) to keep reporting the last known role and lag when the primary is lost.
+    ///
+    /// Smooths out flapping caused by elections. Set to 0 to disable.
+    #[serde(default)]
+    pub primary_loss_grace: u64,
+
+    /// Read concern for auxiliary reads of cluster state (such as chunk distributions).
+    ///
+    /// Server commands like replSetGetStatus do not support read concerns and are not affected.
+    #[serde(default)]
+    pub read_concern: ReadConcern,
+
+    /// Command that deletes the node's data and restarts MongoDB (optional).
+    ///
+    /// Enables the `replicante.mongodb/resync` action when set.
+    #[serde(default)]
+    pub resync_command: Option<Vec<String>>,
+
+    /// Interval (in seconds) between checks for the node entering or leaving ROLLBACK.
+    #[serde(default = "MongoDB::default_rollback_check_interval")]
+    pub rollback_check_interval: u64,
+
+    /// Dotted `serverStatus` paths to export as gauges (such as `metrics.document.inserted`).
+    ///
+    /// Values are refreshed, regardless of `enrichment`, at most once every
+    /// `expensive_metrics_interval` as datastore information is requested.
+    #[serde(default)]
+    pub server_status_metrics: Vec<String>,
+
+    /// Tenant the agent's commands are attributed to in shared MongoDB deployments (optional).
+    ///
+    /// Commands issued to collect datastore information carry a `comment` naming the tenant
+    /// so they can be attributed in MongoDB logs and the profiler.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+
     /// MongoDB connection URI.
+    ///
+    /// Client options set in the URI take precedence over the equivalent options above.
+    /// Setting an option both in the URI and explicitly in this section is an error.
     #[serde(default = "MongoDB::default_uri")]
     pub uri: String,
 
     /// Configure MongoDB sharding mode.
     #[serde(default)]
     pub sharding: Option<Sharding>,
+
+    /// Configure TLS server certificate verification for the MongoDB client.
+    #[serde(default)]
+    pub tls: Option<Tls>,
 }
 
 impl Default for MongoDB {
     fn default() -> Self {
         MongoDB {
+            command_allowlist: None,
+            diagnostics: Diagnostics::default(),
+            enrichment: Self::default_enrichment(),
+            expensive_metrics_interval: Self::default_expensive_metrics_interval(),
+            force_compat: None,
             host_select_timeout: Self::default_host_select_timeout(),
+            max_response_size: Self::default_max_response_size(),
+            min_pool_size: None,
+            node_name_fallback: None,
+            primary_loss_grace: 0,
+            read_concern: ReadConcern::default(),
+            resync_command: None,
+            rollback_check_interval: Self::default_rollback_check_interval(),
+            server_status_metrics: Vec::new(),
+            tenant_id: None,
             uri: Self::default_uri(),
             sharding: None,
+            tls: None,
         }
     }
 }
 
 impl MongoDB {
+    /// Default value for `enrichment` used by serde.
+    fn default_enrichment() -> bool {
+        true
+    }
+
+    /// Default value for `expensive_metrics_interval` used by serde.
+    fn default_expensive_metrics_interval() -> u64 {
+        30
+    }
+
+    /// Default value for `max_response_size` used by serde.
+    fn default_max_response_size() -> Option<usize> {
+        Some(4 * 1024 * 1024)
+    }
+
+    /// Default value for `rollback_check_interval` used by serde.
+    fn default_rollback_check_interval() -> u64 {
+        10
+    }
+
     /// Default value for `uri` used by serde.
     fn default_uri() -> String {
         String::from("mongodb://localhost:27017")
     }
 
     /// Default value for `host_select_timeout` used by serde.
-    fn default_host_select_timeout() -> u64 {
+    pub(crate) fn default_host_select_timeout() -> u64 {
         1000
     }
 }
 
+/// MongoDB version compatibility modules the agent can be forced to use.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum CompatModule {
+    #[serde(rename = "v3_0")]
+    V3_0,
+
+    #[serde(rename = "v3_2")]
+    V3_2,
+}
+
+impl CompatModule {
+    /// Name of the module as used in the configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            CompatModule::V3_0 => "v3_0",
+            CompatModule::V3_2 => "v3_2",
+        }
+    }
+}
+
+/// Options for the `replicante.mongodb/diagnostics` action.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Diagnostics {
+    /// Maximum size (in bytes) of diagnostics bundles.
+    ///
+    /// Command responses that would grow the bundle past this size are omitted.
+    #[serde(default = "Diagnostics::default_max_size")]
+    pub max_size: usize,
+
+    /// Directory to also write diagnostics bundles to, as `<action id>.json` (optional).
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Diagnostics {
+            max_size: Self::default_max_size(),
+            path: None,
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Default value for `max_size` used by serde.
+    fn default_max_size() -> usize {
+        1024 * 1024
+    }
+}
+
+/// Read concern levels supported for agent reads.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum ReadConcern {
+    #[serde(rename = "available")]
+    Available,
+
+    #[serde(rename = "linearizable")]
+    Linearizable,
+
+    #[serde(rename = "local")]
+    Local,
+
+    #[serde(rename = "majority")]
+    Majority,
+}
+
+impl ReadConcern {
+    /// Read concern level as expected by MongoDB commands.
+    pub fn level(self) -> &'static str {
+        match self {
+            ReadConcern::Available => "available",
+            ReadConcern::Linearizable => "linearizable",
+            ReadConcern::Local => "local",
+            ReadConcern::Majority => "majority",
+        }
+    }
+}
+
+impl Default for ReadConcern {
+    fn default() -> ReadConcern {
+        ReadConcern::Local
+    }
+}
+
 /// Configure the agent to operate in sharded cluster mode.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Sharding {
@@ -131,11 +341,48 @@ impl Sharding {
     }
 }
 
+/// MongoDB client TLS options.
+///
+/// Certificates and keys are configured with the `tlsCAFile` and `tlsCertificateKeyFile`
+/// connection URI options, this section controls how the server certificate is verified.
+///
+/// The MongoDB client checks hostnames as part of certificate verification and can't
+/// verify the CA alone, so there are no `ca_only` or `allow_invalid_hostnames` options:
+/// configurations using them are rejected when loaded instead of being ignored.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// Verification mode for the server certificate.
+    #[serde(default)]
+    pub verify: TlsVerify,
+}
+
+/// Verification modes for MongoDB server certificates.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum TlsVerify {
+    /// Verify the certificate is signed by a trusted CA and matches the server hostname.
+    #[serde(rename = "full")]
+    Full,
+
+    /// Do not verify the server certificate at all.
+    #[serde(rename = "none")]
+    None,
+}
+
+impl Default for TlsVerify {
+    fn default() -> TlsVerify {
+        TlsVerify::Full
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
+    use super::CompatModule;
     use super::Config;
+    use super::ReadConcern;
+    use super::TlsVerify;
 
     #[test]
     #[should_panic(expected = "invalid type: string")]
@@ -144,6 +391,57 @@ mod tests {
         Config::from_reader(cursor).unwrap();
     }
 
+    #[test]
+    #[should_panic(expected = "unknown variant `eventual`")]
+    fn read_concern_invalid() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {read_concern: eventual}");
+        Config::from_reader(cursor).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown variant `v2_6`")]
+    fn force_compat_invalid() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {force_compat: v2_6}");
+        Config::from_reader(cursor).unwrap();
+    }
+
+    #[test]
+    fn force_compat_module() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {force_compat: v3_0}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert_eq!(config.mongo.force_compat, Some(CompatModule::V3_0));
+    }
+
+    #[test]
+    fn read_concern_majority() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {read_concern: majority}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert_eq!(config.mongo.read_concern, ReadConcern::Majority);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown field `allow_invalid_hostnames`")]
+    fn tls_allow_invalid_hostnames_rejected() {
+        let cursor = Cursor::new(
+            "agent: {db: 'test.db'}\nmongo: {tls: {allow_invalid_hostnames: true}}",
+        );
+        Config::from_reader(cursor).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown variant `ca_only`")]
+    fn tls_verify_ca_only_rejected() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {tls: {verify: ca_only}}");
+        Config::from_reader(cursor).unwrap();
+    }
+
+    #[test]
+    fn tls_verify_none() {
+        let cursor = Cursor::new("agent: {db: 'test.db'}\nmongo: {tls: {verify: none}}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert_eq!(config.mongo.tls.unwrap().verify, TlsVerify::None);
+    }
+
     #[test]
     fn from_reader_ok() {
         let cursor = Cursor::new("agent: {db: 'test.db'}");
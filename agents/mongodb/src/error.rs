@@ -9,6 +9,12 @@ pub enum ErrorKind {
     /// BSON specifc `ResponseDecode`.
     BsonDecode(&'static str),
 
+    /// `FreeForm` caused by a command not in the `mongo.command_allowlist`.
+    CommandNotAllowed(String),
+
+    /// Alias for `ConfigClash`.
+    ConfigClash(&'static str),
+
     /// Alias for `ConfigLoad`.
     ConfigLoad,
 
@@ -24,12 +30,24 @@ pub enum ErrorKind {
     /// Alias for `Io`.
     Io(String),
 
+    /// `InvalidStoreState` caused by the inability to find a member in the replica set config.
+    MemberNotFound(String),
+
     /// `InvalidStoreState` caused by the inability to find a primary.
     MembersNoPrimary,
 
     /// `InvalidStoreState` caused by the inability to find self in the replica set.
     MembersNoSelf,
 
+    /// `InvalidStoreState` caused by an operation that requires the node to be primary.
+    NotPrimary,
+
+    /// `InvalidStoreState` caused by an operation that requires the node to be secondary.
+    NotSecondary,
+
+    /// `ResponseDecode` caused by a response exceeding the maximum allowed size.
+    ResponseTooLarge(&'static str),
+
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
@@ -47,17 +65,32 @@ impl From<ErrorKind> for BaseKind {
     fn from(error: ErrorKind) -> BaseKind {
         match error {
             ErrorKind::BsonDecode(operation) => BaseKind::ResponseDecode("bson", operation),
+            ErrorKind::CommandNotAllowed(command) => BaseKind::FreeForm(format!(
+                "MongoDB command '{}' is not in the mongo.command_allowlist",
+                command
+            )),
+            ErrorKind::ConfigClash(message) => BaseKind::ConfigClash(message),
             ErrorKind::ConfigLoad => BaseKind::ConfigLoad,
             ErrorKind::ConfigOption(option) => BaseKind::ConfigOption(option),
             ErrorKind::Connection(system, address) => BaseKind::Connection(system, address),
             ErrorKind::Initialisation(message) => BaseKind::Initialisation(message),
             ErrorKind::Io(path) => BaseKind::Io(path),
+            ErrorKind::MemberNotFound(member) => {
+                BaseKind::InvalidStoreState(format!("member {} not in replica set config", member))
+            }
             ErrorKind::MembersNoPrimary => {
                 BaseKind::InvalidStoreState("primary node not in members list".into())
             }
             ErrorKind::MembersNoSelf => {
                 BaseKind::InvalidStoreState("self not in members list".into())
             }
+            ErrorKind::NotPrimary => {
+                BaseKind::InvalidStoreState("operation requires a primary node".into())
+            }
+            ErrorKind::NotSecondary => {
+                BaseKind::InvalidStoreState("operation requires a secondary node".into())
+            }
+            ErrorKind::ResponseTooLarge(operation) => BaseKind::ResponseDecode("bson", operation),
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
             ErrorKind::UnsupportedSateId(state) => {
                 BaseKind::InvalidStoreState(format!("unsupported node state {}", state))
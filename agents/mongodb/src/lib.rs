@@ -8,6 +8,7 @@ mod actions;
 mod config;
 mod error;
 mod metrics;
+mod rollback;
 mod version;
 
 use config::Config;
@@ -49,11 +50,23 @@ pub fn run() -> Result<bool> {
     // Run the agent using the provided default helper.
     let agent_conf = config.agent.clone();
     let release = RELEASE.as_str();
-    replicante_agent::process::run(agent_conf, "repliagent-mongodb", release, |context, _| {
-        metrics::register_metrics(context);
-        let factory = MongoDBFactory::with_config(config, context.clone())?;
-        let agent = VersionedAgent::new(context.clone(), factory);
-        replicante_agent::process::update_checker(CURRENT_VERSION.clone(), UPDATE_META, context)?;
-        Ok(agent)
-    })
+    replicante_agent::process::run(
+        agent_conf,
+        "repliagent-mongodb",
+        release,
+        |context, upkeep| {
+            metrics::register_metrics(context);
+            let mongo = config.mongo.clone();
+            let factory = MongoDBFactory::with_config(config, context.clone())?;
+            actions::register(&factory.client(), &mongo)?;
+            rollback::spawn(&mongo, &factory, context.clone(), upkeep)?;
+            let agent = VersionedAgent::new(context.clone(), factory);
+            replicante_agent::process::update_checker(
+                CURRENT_VERSION.clone(),
+                UPDATE_META,
+                context,
+            )?;
+            Ok(agent)
+        },
+    )
 }
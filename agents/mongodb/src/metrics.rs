@@ -1,37 +1,99 @@
 use lazy_static::lazy_static;
+use prometheus::Counter;
 use prometheus::CounterVec;
-use prometheus::HistogramOpts;
+use prometheus::Gauge;
+use prometheus::GaugeVec;
 use prometheus::HistogramVec;
-use prometheus::Opts;
-use slog::debug;
 
+use replicante_agent::register_collector;
 use replicante_agent::AgentContext;
+use replicante_agent::MetricOpts;
 
 lazy_static! {
+    pub static ref MONGODB_ELECTIONS_COUNT: Counter = Counter::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_elections_total",
+            "Number of election term changes observed by the agent"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_ELECTIONS_COUNT counter");
     pub static ref MONGODB_OP_ERRORS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_mongodb_operation_errors",
             "Number of MongoDB operations failed"
-        ),
+        )
+        .opts(),
         &["operation"]
     )
     .expect("Failed to create MONGODB_OP_ERRORS_COUNT counter");
     pub static ref MONGODB_OPS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_mongodb_operations",
             "Number of MongoDB operations issued"
-        ),
+        )
+        .opts(),
         &["operation"]
     )
     .expect("Failed to create MONGODB_OPS_COUNT counter");
     pub static ref MONGODB_OPS_DURATION: HistogramVec = HistogramVec::new(
-        HistogramOpts::new(
+        MetricOpts::new(
             "repliagent_mongodb_operations_duration",
             "Duration (in seconds) of MongoDB operations"
-        ),
+        )
+        .histogram_opts(),
         &["operation"]
     )
     .expect("Failed to create MONGODB_OPS_DURATION histogram");
+    pub static ref MONGODB_ROLLBACK_COUNT: Counter = Counter::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_rollback_total",
+            "Number of times the MongoDB node was observed entering the ROLLBACK state"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_ROLLBACK_COUNT counter");
+    pub static ref MONGODB_SERVER_STATUS: GaugeVec = GaugeVec::new(
+        MetricOpts::new(
+            "repliagent_mongodb_server_status",
+            "Values of the serverStatus paths listed in mongo.server_status_metrics"
+        )
+        .opts(),
+        &["path"]
+    )
+    .expect("Failed to create MONGODB_SERVER_STATUS gauge");
+    pub static ref MONGODB_WT_CACHE_BYTES: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_wt_cache_bytes",
+            "Bytes currently in the WiredTiger cache"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_WT_CACHE_BYTES gauge");
+    pub static ref MONGODB_WT_CACHE_DIRTY_RATIO: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_wt_cache_dirty_ratio",
+            "Fraction of the configured WiredTiger cache holding dirty data"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_WT_CACHE_DIRTY_RATIO gauge");
+    pub static ref MONGODB_WT_CACHE_MAX_BYTES: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_wt_cache_max_bytes",
+            "Maximum bytes configured for the WiredTiger cache"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_WT_CACHE_MAX_BYTES gauge");
+    pub static ref MONGODB_WT_CACHE_USED_RATIO: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_mongodb_wt_cache_used_ratio",
+            "Fraction of the configured WiredTiger cache in use"
+        )
+        .opts()
+    )
+    .expect("Failed to create MONGODB_WT_CACHE_USED_RATIO gauge");
 }
 
 /// Attemps to register metrics with the Repositoy.
@@ -40,13 +102,64 @@ lazy_static! {
 pub fn register_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let registry = &context.metrics;
-    if let Err(error) = registry.register(Box::new(MONGODB_OPS_COUNT.clone())) {
-        debug!(logger, "Failed to register MONGODB_OPS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(MONGODB_OP_ERRORS_COUNT.clone())) {
-        debug!(logger, "Failed to register MONGODB_OP_ERRORS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(MONGODB_OPS_DURATION.clone())) {
-        debug!(logger, "Failed to register MONGODB_OPS_DURATION"; "error" => ?error);
-    }
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_ELECTIONS_COUNT",
+        Box::new(MONGODB_ELECTIONS_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_OPS_COUNT",
+        Box::new(MONGODB_OPS_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_OP_ERRORS_COUNT",
+        Box::new(MONGODB_OP_ERRORS_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_OPS_DURATION",
+        Box::new(MONGODB_OPS_DURATION.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_ROLLBACK_COUNT",
+        Box::new(MONGODB_ROLLBACK_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_SERVER_STATUS",
+        Box::new(MONGODB_SERVER_STATUS.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_WT_CACHE_BYTES",
+        Box::new(MONGODB_WT_CACHE_BYTES.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_WT_CACHE_DIRTY_RATIO",
+        Box::new(MONGODB_WT_CACHE_DIRTY_RATIO.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_WT_CACHE_MAX_BYTES",
+        Box::new(MONGODB_WT_CACHE_MAX_BYTES.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "MONGODB_WT_CACHE_USED_RATIO",
+        Box::new(MONGODB_WT_CACHE_USED_RATIO.clone()),
+    );
 }
@@ -0,0 +1,180 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use bson::Document;
+use failure::ResultExt;
+use humthreads::Builder;
+use mongodb::sync::Client;
+use serde_json::json;
+use slog::debug;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+
+use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::HealthGate;
+use replicante_agent::Result;
+use replicante_models_agent::info::ShardRole;
+use replicante_util_failure::failure_info;
+use replicante_util_upkeep::Upkeep;
+
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::metrics::MONGODB_ROLLBACK_COUNT;
+use crate::version::member_role;
+use crate::version::repl_set_get_status;
+use crate::version::MongoDBFactory;
+
+/// Track the node entering and leaving the ROLLBACK replica set state.
+pub struct RollbackTracker {
+    in_progress: AtomicBool,
+    logger: Logger,
+}
+
+impl RollbackTracker {
+    pub fn new(logger: Logger) -> RollbackTracker {
+        RollbackTracker {
+            in_progress: AtomicBool::new(false),
+            logger,
+        }
+    }
+
+    /// Datastore info extras flagging rollbacks in progress.
+    pub fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        if self.in_progress() {
+            extras.insert("rollback".into(), json!(true));
+        }
+        extras
+    }
+
+    /// Check if the node was last observed in the ROLLBACK state.
+    pub fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::Relaxed)
+    }
+
+    /// Record the latest observed role of the node and report state transitions.
+    pub fn observe(&self, role: &ShardRole) {
+        let rollback = match role {
+            ShardRole::Unknown(state) => state == "ROLLBACK",
+            _ => false,
+        };
+        let was_rollback = self.in_progress.swap(rollback, Ordering::Relaxed);
+        if rollback && !was_rollback {
+            MONGODB_ROLLBACK_COUNT.inc();
+            warn!(self.logger, "MongoDB node entered the ROLLBACK state");
+        }
+        if !rollback && was_rollback {
+            info!(
+                self.logger,
+                "MongoDB node left the ROLLBACK state";
+                "role" => ?role,
+            );
+        }
+    }
+}
+
+/// Start a background thread to periodically check the node for rollbacks.
+///
+/// No thread is started for `mongos` instances as they are not replica set members.
+/// Checks are paused while the datastore is known to be unhealthy (see `HealthGate`).
+pub fn spawn(
+    config: &MongoDB,
+    factory: &MongoDBFactory,
+    context: AgentContext,
+    upkeep: &mut Upkeep,
+) -> Result<()> {
+    let mongos = config
+        .sharding
+        .as_ref()
+        .map(|sharding| sharding.enable && sharding.mongos_node_name.is_some())
+        .unwrap_or(false);
+    if mongos {
+        return Ok(());
+    }
+    let client = factory.client();
+    let config = config.clone();
+    let interval = config.rollback_check_interval;
+    let tracker = factory.rollback();
+    let gate = HealthGate::new("mongodb rollback tracker", &context);
+    let thread = Builder::new("r:m:rollback")
+        .full_name("replicante:mongodb:rollback")
+        .spawn(move |scope| {
+            let interval = context.config.jitter.apply(Duration::from_secs(interval));
+            context.tasks.register("mongodb.rollback", interval);
+            scope.activity("waiting to check for rollbacks");
+            while !scope.should_shutdown() {
+                if gate.open() {
+                    let _activity = scope.scoped_activity("checking for rollbacks");
+                    let role = node_role(&client, &config, &context);
+                    context.tasks.record("mongodb.rollback", &role);
+                    match role {
+                        Ok(role) => tracker.observe(&role),
+                        Err(error) => debug!(
+                            context.logger,
+                            "Unable to check MongoDB for rollbacks";
+                            failure_info(&error),
+                        ),
+                    };
+                }
+                thread::sleep(interval);
+            }
+        })
+        .with_context(|_| BaseKind::ThreadSpawn("mongodb rollback tracker"))?;
+    upkeep.register_thread(thread);
+    Ok(())
+}
+
+/// Fetch the node's role in the Replica Set with the replSetGetStatus command.
+fn node_role(client: &Client, config: &MongoDB, context: &AgentContext) -> Result<ShardRole> {
+    let mut span = context.tracer.span("mongodb.rollback").auto_finish();
+    let status: Document = repl_set_get_status(client, config, context, &mut span)?;
+    let state = status
+        .get_i32("myState")
+        .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
+    member_role(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use serde_json::json;
+
+    use replicante_agent::AgentContext;
+    use replicante_models_agent::info::ShardRole;
+
+    use super::RollbackTracker;
+    use crate::metrics::MONGODB_ROLLBACK_COUNT;
+
+    #[test]
+    fn counter_increments_entering_rollback() {
+        let context = AgentContext::mock();
+        let tracker = RollbackTracker::new(context.logger);
+        let rollback = ShardRole::Unknown("ROLLBACK".into());
+        let before = MONGODB_ROLLBACK_COUNT.get();
+        tracker.observe(&ShardRole::Secondary);
+        assert_eq!(MONGODB_ROLLBACK_COUNT.get(), before);
+        tracker.observe(&rollback);
+        tracker.observe(&rollback);
+        assert_eq!(MONGODB_ROLLBACK_COUNT.get(), before + 1.0);
+        assert!(tracker.in_progress());
+        tracker.observe(&ShardRole::Secondary);
+        tracker.observe(&rollback);
+        assert_eq!(MONGODB_ROLLBACK_COUNT.get(), before + 2.0);
+    }
+
+    #[test]
+    fn extras_flag_rollback_in_progress() {
+        let context = AgentContext::mock();
+        let tracker = RollbackTracker::new(context.logger);
+        assert!(tracker.extras().is_empty());
+        // Set the flag directly to avoid racing with the counter test.
+        tracker.in_progress.store(true, Ordering::Relaxed);
+        assert_eq!(tracker.extras().get("rollback"), Some(&json!(true)));
+    }
+}
@@ -1,6 +1,45 @@
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use bson::doc;
+use bson::Bson;
+use bson::Document;
+use failure::Fail;
+use failure::ResultExt;
 use lazy_static::lazy_static;
+use mongodb::sync::Client;
+use opentracingrust::utils::FailSpan;
+use opentracingrust::Log;
+use opentracingrust::Span;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use slog::warn;
 
+use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
+use replicante_agent::Error;
+use replicante_agent::Result;
 use replicante_models_agent::info::AgentVersion;
+use replicante_models_agent::info::DatastoreInfo;
+use replicante_models_agent::info::ShardRole;
+
+use crate::config::MongoDB;
+use crate::config::ReadConcern;
+use crate::error::ErrorKind;
+use crate::metrics::MONGODB_OPS_COUNT;
+use crate::metrics::MONGODB_OPS_DURATION;
+use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+
+/// Health probes supported by the MongoDB agent, cheapest first.
+pub const HEALTH_PROBES: &[&str] = &["ping", "isMaster"];
+
+/// Placeholder for cluster names that could not be determined.
+const UNKNOWN_CLUSTER: &str = "<unknown>";
 
 lazy_static! {
     pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
@@ -9,3 +48,585 @@ lazy_static! {
         env!("GIT_BUILD_TAINT"),
     );
 }
+
+/// Decode a command response, rejecting responses larger than `max_size` bytes.
+///
+/// The driver only hands over complete replies so the limit can't stop it from reading
+/// a response (MongoDB caps replies at 16MB anyway). The size is measured without
+/// copying the response and measuring stops as soon as the limit is exceeded, so
+/// oversized responses are dropped before they are decoded into the agent models.
+pub fn decode_response<T>(
+    context: &AgentContext,
+    op: &'static str,
+    response: Document,
+    max_size: Option<usize>,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    if let Some(max_size) = max_size {
+        let mut guard = SizeGuard::new(max_size);
+        if bson::encode_document(&mut guard, &response).is_err() {
+            warn!(
+                context.logger,
+                "MongoDB response exceeds the maximum allowed size";
+                "operation" => op,
+                "max_response_size" => max_size,
+            );
+            return Err(ErrorKind::ResponseTooLarge(op).into());
+        }
+    }
+    let response =
+        bson::from_bson(Bson::Document(response)).with_context(|_| ErrorKind::BsonDecode(op))?;
+    Ok(response)
+}
+
+/// Node name to report when it can't be determined from the replica set status.
+///
+/// The configured `node_name_fallback` is preferred, otherwise the hostname is used.
+/// Returns `None` if no name is configured and the hostname lookup fails.
+pub fn fallback_node_name(config: &MongoDB, context: &AgentContext) -> Option<String> {
+    if let Some(name) = config.node_name_fallback.as_ref() {
+        return Some(name.clone());
+    }
+    match hostname::get() {
+        Ok(name) => Some(name.to_string_lossy().into_owned()),
+        Err(error) => {
+            warn!(
+                context.logger,
+                "Failed to look up hostname to use as fallback node name";
+                "error" => %error,
+            );
+            None
+        }
+    }
+}
+
+/// Replica set and node names of a member, with details on how they were determined.
+pub struct MemberNames {
+    /// Error that prevented the names from being determined from the replica set status.
+    pub cause: Option<Error>,
+    pub node_name: String,
+    /// The node name is a fallback rather than the name known to the replica set.
+    pub provisional: bool,
+    pub set: Option<String>,
+}
+
+impl MemberNames {
+    /// Determine a member's names from its replica set name and node name lookups.
+    ///
+    /// Nodes that can't report their replica set status (for example because the agent user
+    /// lacks the privileges for replSetGetStatus or during early startup) report the
+    /// `fallback` node name instead. Without a fallback the node can't be told apart from
+    /// other nodes so the lookup error is returned.
+    pub fn resolve(
+        names: Result<(String, Result<String>)>,
+        fallback: Option<&str>,
+    ) -> Result<MemberNames> {
+        let (set, node_name) = match names {
+            Ok((set, node_name)) => (Some(set), node_name),
+            Err(error) => (None, Err(error)),
+        };
+        let (node_name, cause) = match (node_name, fallback) {
+            (Ok(node_name), _) => (node_name, None),
+            (Err(error), None) => return Err(error),
+            (Err(error), Some(fallback)) => (fallback.to_string(), Some(error)),
+        };
+        Ok(MemberNames {
+            provisional: cause.is_some(),
+            cause,
+            node_name,
+            set,
+        })
+    }
+
+    /// Report the `fallback` node name without looking up the replica set status.
+    pub fn fallback(fallback: &str) -> MemberNames {
+        MemberNames {
+            cause: None,
+            node_name: fallback.to_string(),
+            provisional: true,
+            set: None,
+        }
+    }
+
+    /// Datastore info extras flagging incomplete information and provisional node names.
+    pub fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        if self.set.is_none() || self.provisional {
+            extras.insert("info_incomplete".into(), json!(true));
+        }
+        if self.provisional {
+            extras.insert("node_name_provisional".into(), json!(true));
+        }
+        extras
+    }
+
+    /// Build the member's datastore information.
+    ///
+    /// The cluster name is taken from the replica set status unless `cluster` is given.
+    pub fn info(&self, cluster: Option<String>, version: String) -> DatastoreInfo {
+        let cluster = cluster
+            .or_else(|| self.set.clone())
+            .unwrap_or_else(|| UNKNOWN_CLUSTER.to_string());
+        DatastoreInfo::new(cluster, "MongoDB", self.node_name.clone(), version, None)
+    }
+}
+
+/// Prepare a command document to send to MongoDB.
+///
+/// Every command the agent issues goes through here so commands that are not in the
+/// configured `command_allowlist` are refused, and allowed ones are tagged with the
+/// configured `tenant_id`, wherever they come from.
+/// The command is named by the first key of the document, as MongoDB does.
+pub fn prepare_command(config: &MongoDB, command: Document) -> Result<Document> {
+    let name = command.keys().next().map(String::as_str).unwrap_or("");
+    ensure_command_allowed(config, name)?;
+    Ok(with_tenant_comment(command, config.tenant_id.as_deref()))
+}
+
+/// Refuse commands that are not in the configured `command_allowlist`, if any.
+fn ensure_command_allowed(config: &MongoDB, command: &str) -> Result<()> {
+    let allowed = config
+        .command_allowlist
+        .as_ref()
+        .map(|allowlist| allowlist.iter().any(|allowed| allowed == command))
+        .unwrap_or(true);
+    if !allowed {
+        return Err(ErrorKind::CommandNotAllowed(command.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Attach a read concern to a read command.
+pub fn with_read_concern(mut command: Document, read_concern: ReadConcern) -> Document {
+    command.insert("readConcern", doc! {"level": read_concern.level()});
+    command
+}
+
+/// Tag a command with the tenant it is issued for, if any.
+///
+/// The tag is attached as the command's `comment` so it shows up in MongoDB logs and profiler.
+fn with_tenant_comment(mut command: Document, tenant_id: Option<&str>) -> Document {
+    if let Some(tenant_id) = tenant_id {
+        command.insert("comment", format!("repliagent tenant:{}", tenant_id));
+    }
+    command
+}
+
+/// Executes the configured health probe command against the DB.
+pub fn health_probe(
+    client: &Client,
+    config: &MongoDB,
+    context: &AgentContext,
+    parent: &mut Span,
+) -> Result<()> {
+    let probe = context.config.health.probe(HEALTH_PROBES)?;
+    let command = prepare_command(config, probe_command(probe))?;
+    let mut span = context.tracer.span(probe).auto_finish();
+    span.child_of(parent.context().clone());
+    span.log(Log::new().log("span.kind", "client-send"));
+    MONGODB_OPS_COUNT.with_label_values(&[probe]).inc();
+    let timer = MONGODB_OPS_DURATION
+        .with_label_values(&[probe])
+        .start_timer();
+    client
+        .database("admin")
+        .run_command(command, None)
+        .fail_span(&mut span)
+        .map_err(|error| {
+            MONGODB_OP_ERRORS_COUNT.with_label_values(&[probe]).inc();
+            error
+        })
+        .with_context(|_| ErrorKind::StoreOpFailed(probe))?;
+    timer.observe_duration();
+    span.log(Log::new().log("span.kind", "client-receive"));
+    Ok(())
+}
+
+/// Executes the replSetGetStatus command against the DB and decodes the response.
+pub fn repl_set_get_status<T>(
+    client: &Client,
+    config: &MongoDB,
+    context: &AgentContext,
+    parent: &mut Span,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let command = prepare_command(config, doc! {"replSetGetStatus" => 1})?;
+    let mut span = context.tracer.span("replSetGetStatus").auto_finish();
+    span.child_of(parent.context().clone());
+    span.log(Log::new().log("span.kind", "client-send"));
+    MONGODB_OPS_COUNT
+        .with_label_values(&["replSetGetStatus"])
+        .inc();
+    let timer = MONGODB_OPS_DURATION
+        .with_label_values(&["replSetGetStatus"])
+        .start_timer();
+    let status = client
+        .database("admin")
+        .run_command(command, None)
+        .fail_span(&mut span)
+        .map_err(|error| {
+            MONGODB_OP_ERRORS_COUNT
+                .with_label_values(&["replSetGetStatus"])
+                .inc();
+            error
+        })
+        .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
+    timer.observe_duration();
+    span.log(Log::new().log("span.kind", "client-receive"));
+    decode_response(context, "replSetGetStatus", status, config.max_response_size)
+}
+
+/// Open up to `connections` pooled connections to the node and probe it once.
+///
+/// Probes are issued concurrently so the driver needs a separate connection for each.
+pub fn warmup(
+    client: &Client,
+    config: &MongoDB,
+    context: &AgentContext,
+    connections: u32,
+    parent: &mut Span,
+) -> Result<()> {
+    let probe = context.config.health.probe(HEALTH_PROBES)?;
+    let command = prepare_command(config, probe_command(probe))?;
+    let probes: Vec<_> = (1..connections)
+        .map(|_| {
+            let client = client.clone();
+            let command = command.clone();
+            thread::spawn(move || client.database("admin").run_command(command, None))
+        })
+        .collect();
+    health_probe(client, config, context, parent)?;
+    for handle in probes {
+        join_probe(handle, probe)?;
+    }
+    Ok(())
+}
+
+/// Wait for a warmup probe thread, failing the warmup if the probe failed or panicked.
+fn join_probe<T, E>(
+    handle: JoinHandle<std::result::Result<T, E>>,
+    probe: &'static str,
+) -> Result<()>
+where
+    E: Fail,
+{
+    let result = match handle.join() {
+        Ok(result) => result,
+        Err(_) => {
+            let error = failure::err_msg("MongoDB warmup probe panicked")
+                .context(ErrorKind::StoreOpFailed(probe));
+            return Err(error.into());
+        }
+    };
+    result.with_context(|_| ErrorKind::StoreOpFailed(probe))?;
+    Ok(())
+}
+
+/// Map a replica set member state (`myState`) to the node's role in the Replica Set.
+pub fn member_role(state: i32) -> Result<ShardRole> {
+    match state {
+        0 => Ok(ShardRole::Unknown(String::from("STARTUP"))),
+        1 => Ok(ShardRole::Primary),
+        2 => Ok(ShardRole::Secondary),
+        3 => Ok(ShardRole::Unknown(String::from("RECOVERING"))),
+        5 => Ok(ShardRole::Unknown(String::from("STARTUP2"))),
+        6 => Ok(ShardRole::Unknown(String::from("UNKNOWN"))),
+        7 => Ok(ShardRole::Unknown(String::from("ARBITER"))),
+        8 => Ok(ShardRole::Unknown(String::from("DOWN"))),
+        9 => Ok(ShardRole::Unknown(String::from("ROLLBACK"))),
+        10 => Ok(ShardRole::Unknown(String::from("REMOVED"))),
+        state => Err(ErrorKind::UnsupportedSateId(state).into()),
+    }
+}
+
+/// Command document to issue for the given health probe.
+fn probe_command(probe: &str) -> Document {
+    let mut command = Document::new();
+    command.insert(probe, 1);
+    command
+}
+
+/// Cache for the results of expensive operations, refreshed at most once per interval.
+///
+/// Failed collections are not cached so the next request tries again.
+pub struct Sampled<T> {
+    interval: Duration,
+    sample: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> Sampled<T> {
+    pub fn new(interval: Duration) -> Sampled<T> {
+        Sampled {
+            interval,
+            sample: Mutex::new(None),
+        }
+    }
+
+    /// Return the cached sample if still fresh, otherwise collect and cache a new one.
+    pub fn get<F>(&self, collect: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let mut sample = self.sample.lock().expect("Sampled lock poisoned");
+        if let Some((collected, value)) = sample.as_ref() {
+            if collected.elapsed() < self.interval {
+                return Ok(value.clone());
+            }
+        }
+        let value = collect()?;
+        *sample = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+}
+
+/// Byte counting `Write` sink that fails as soon as a size limit is exceeded.
+///
+/// Encoding documents into the guard measures their size without buffering them.
+struct SizeGuard {
+    limit: usize,
+    size: usize,
+}
+
+impl SizeGuard {
+    fn new(limit: usize) -> SizeGuard {
+        SizeGuard { limit, size: 0 }
+    }
+}
+
+impl Write for SizeGuard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.size += buf.len();
+        if self.size > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "response size limit exceeded",
+            ));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::thread;
+    use std::time::Duration;
+
+    use bson::doc;
+    use bson::Bson;
+    use bson::Document;
+    use failure::Fail;
+
+    use replicante_agent::config::HealthConfig;
+    use replicante_agent::AgentContext;
+
+    use super::decode_response;
+    use super::fallback_node_name;
+    use super::join_probe;
+    use super::prepare_command;
+    use super::probe_command;
+    use super::with_read_concern;
+    use super::with_tenant_comment;
+    use super::Sampled;
+    use super::HEALTH_PROBES;
+    use crate::config::MongoDB;
+    use crate::config::ReadConcern;
+    use crate::error::ErrorKind;
+    use crate::version::v3_2::BuildInfo;
+
+    fn oversized_document() -> Document {
+        let members: Vec<Bson> = (0..1000)
+            .map(|id| Bson::Document(doc! {"_id": id, "name": format!("host{}:27017", id)}))
+            .collect();
+        doc! {"version": "3.6.0", "members": members}
+    }
+
+    #[test]
+    fn warmup_probe_panic_is_an_error() {
+        let handle = thread::spawn(|| -> Result<(), io::Error> { panic!("probe failed") });
+        let error = join_probe(handle, "ping").unwrap_err();
+        assert_eq!(error.name().unwrap(), "StoreOpFailed");
+    }
+
+    #[test]
+    fn warmup_probe_error_is_returned() {
+        let handle = thread::spawn(|| Err(io::Error::new(io::ErrorKind::Other, "refused")));
+        let error = join_probe::<(), _>(handle, "ping").unwrap_err();
+        assert_eq!(error.name().unwrap(), "StoreOpFailed");
+        let handle = thread::spawn(|| Ok::<_, io::Error>(()));
+        join_probe(handle, "ping").unwrap();
+    }
+
+    #[test]
+    fn decode_response_within_limit() {
+        let context = AgentContext::mock();
+        let response = doc! {"version": "3.6.0"};
+        let info: BuildInfo = decode_response(&context, "buildInfo", response, Some(1024)).unwrap();
+        assert_eq!(info.version, "3.6.0");
+    }
+
+    #[test]
+    fn decode_response_without_limit() {
+        let context = AgentContext::mock();
+        let response = oversized_document();
+        let info: BuildInfo = decode_response(&context, "buildInfo", response, None).unwrap();
+        assert_eq!(info.version, "3.6.0");
+    }
+
+    #[test]
+    fn decode_response_too_large() {
+        let context = AgentContext::mock();
+        let response = oversized_document();
+        let result: replicante_agent::Result<BuildInfo> =
+            decode_response(&context, "buildInfo", response, Some(1024));
+        match result {
+            Ok(_) => panic!("expected oversized response to fail"),
+            Err(error) => assert_eq!(error.name().unwrap(), "ResponseDecode"),
+        };
+    }
+
+    #[test]
+    fn fallback_node_name_from_config() {
+        let context = AgentContext::mock();
+        let config = MongoDB {
+            node_name_fallback: Some("mongo-0.example.com".into()),
+            ..MongoDB::default()
+        };
+        let name = fallback_node_name(&config, &context);
+        assert_eq!(name, Some("mongo-0.example.com".into()));
+    }
+
+    #[test]
+    fn fallback_node_name_from_hostname() {
+        let context = AgentContext::mock();
+        let hostname = hostname::get().unwrap().to_string_lossy().into_owned();
+        let name = fallback_node_name(&MongoDB::default(), &context);
+        assert_eq!(name, Some(hostname));
+    }
+
+    #[test]
+    fn command_allowlist_absent_allows_all() {
+        let config = MongoDB::default();
+        let command = prepare_command(&config, doc! {"replSetGetStatus": 1}).unwrap();
+        assert_eq!(command, doc! {"replSetGetStatus": 1});
+    }
+
+    #[test]
+    fn command_allowlist_refuses_other_commands() {
+        let config = MongoDB {
+            command_allowlist: Some(vec!["buildInfo".into()]),
+            ..MongoDB::default()
+        };
+        assert!(prepare_command(&config, doc! {"buildInfo": 1}).is_ok());
+        let error = prepare_command(&config, doc! {"replSetGetStatus": 1}).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "MongoDB command 'replSetGetStatus' is not in the mongo.command_allowlist"
+        );
+    }
+
+    #[test]
+    fn read_concern_attached_to_command() {
+        let command = with_read_concern(doc! {"aggregate": "chunks"}, ReadConcern::Majority);
+        let expected = doc! {"aggregate": "chunks", "readConcern": {"level": "majority"}};
+        assert_eq!(command, expected);
+    }
+
+    #[test]
+    fn tenant_comment_attached() {
+        let command = with_tenant_comment(doc! {"buildInfo": 1}, Some("team-a"));
+        let expected = doc! {"buildInfo": 1, "comment": "repliagent tenant:team-a"};
+        assert_eq!(command, expected);
+    }
+
+    #[test]
+    fn tenant_comment_attached_to_prepared_commands() {
+        let config = MongoDB {
+            tenant_id: Some("team-a".into()),
+            ..MongoDB::default()
+        };
+        let command = prepare_command(&config, doc! {"shutdown": 1}).unwrap();
+        let expected = doc! {"shutdown": 1, "comment": "repliagent tenant:team-a"};
+        assert_eq!(command, expected);
+    }
+
+    #[test]
+    fn tenant_comment_absent_by_default() {
+        let config = MongoDB::default();
+        let command = with_tenant_comment(doc! {"buildInfo": 1}, config.tenant_id.as_deref());
+        assert_eq!(command, doc! {"buildInfo": 1});
+    }
+
+    #[test]
+    fn sampled_collects_once_per_interval() {
+        let sampled = Sampled::new(Duration::from_secs(30));
+        let mut collections = 0;
+        for _ in 0..5 {
+            let value = sampled
+                .get(|| {
+                    collections += 1;
+                    Ok(collections)
+                })
+                .unwrap();
+            assert_eq!(value, 1);
+        }
+        assert_eq!(collections, 1);
+    }
+
+    #[test]
+    fn sampled_refreshes_expired_samples() {
+        let sampled = Sampled::new(Duration::from_secs(0));
+        let mut collections = 0;
+        for _ in 0..3 {
+            sampled
+                .get(|| {
+                    collections += 1;
+                    Ok(collections)
+                })
+                .unwrap();
+        }
+        assert_eq!(collections, 3);
+    }
+
+    #[test]
+    fn sampled_does_not_cache_errors() {
+        let sampled: Sampled<i32> = Sampled::new(Duration::from_secs(30));
+        let error = sampled.get(|| Err(ErrorKind::MembersNoSelf.into()));
+        assert!(error.is_err());
+        assert_eq!(sampled.get(|| Ok(42)).unwrap(), 42);
+    }
+
+    #[test]
+    fn probe_configured_is_issued() {
+        let config = HealthConfig {
+            probe: Some("isMaster".into()),
+            ..HealthConfig::default()
+        };
+        let probe = config.probe(HEALTH_PROBES).unwrap();
+        assert_eq!(probe_command(probe), doc! {"isMaster": 1});
+    }
+
+    #[test]
+    fn probe_default_is_ping() {
+        let config = HealthConfig::default();
+        let probe = config.probe(HEALTH_PROBES).unwrap();
+        assert_eq!(probe_command(probe), doc! {"ping": 1});
+    }
+
+    #[test]
+    fn probe_unsupported() {
+        let config = HealthConfig {
+            probe: Some("serverStatus".into()),
+            ..HealthConfig::default()
+        };
+        assert!(config.probe(HEALTH_PROBES).is_err());
+    }
+}
@@ -5,6 +5,7 @@ use replicante_agent::Result;
 use replicante_models_agent::info::ShardRole;
 
 use crate::error::ErrorKind;
+use crate::version::common::member_role;
 
 /// Section of the replSetGetStatus command that we care about.
 #[derive(Debug, Deserialize)]
@@ -48,19 +49,7 @@ impl ReplSetStatus {
 
     /// Extracts the node's role in the Replica Set.
     pub fn role(&self) -> Result<ShardRole> {
-        match self.my_state {
-            0 => Ok(ShardRole::Unknown(String::from("STARTUP"))),
-            1 => Ok(ShardRole::Primary),
-            2 => Ok(ShardRole::Secondary),
-            3 => Ok(ShardRole::Unknown(String::from("RECOVERING"))),
-            5 => Ok(ShardRole::Unknown(String::from("STARTUP2"))),
-            6 => Ok(ShardRole::Unknown(String::from("UNKNOWN"))),
-            7 => Ok(ShardRole::Unknown(String::from("ARBITER"))),
-            8 => Ok(ShardRole::Unknown(String::from("DOWN"))),
-            9 => Ok(ShardRole::Unknown(String::from("ROLLBACK"))),
-            10 => Ok(ShardRole::Unknown(String::from("REMOVED"))),
-            state => Err(ErrorKind::UnsupportedSateId(state).into()),
-        }
+        member_role(self.my_state)
     }
 }
 
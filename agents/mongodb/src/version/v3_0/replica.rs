@@ -8,11 +8,14 @@ use opentracingrust::utils::FailSpan;
 use opentracingrust::Log;
 use opentracingrust::Span;
 use slog::error;
+use slog::warn;
 
 use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
+use replicante_agent::RequestBudget;
 use replicante_agent::Result;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::CommitOffset;
@@ -23,10 +26,18 @@ use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
 
 use crate::actions::GracefulStop;
+use crate::config::MongoDB;
 use crate::error::ErrorKind;
 use crate::metrics::MONGODB_OPS_COUNT;
 use crate::metrics::MONGODB_OPS_DURATION;
 use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::rollback::RollbackTracker;
+use crate::version::common::fallback_node_name;
+use crate::version::common::health_probe;
+use crate::version::common::prepare_command;
+use crate::version::common::repl_set_get_status;
+use crate::version::common::warmup;
+use crate::version::common::MemberNames;
 use crate::version::common::AGENT_VERSION;
 
 use super::BuildInfo;
@@ -35,16 +46,32 @@ use super::ReplSetStatus;
 /// MongoDB 3.0 replica set agent.
 pub struct ReplicaSet {
     client: Client,
+    config: MongoDB,
     context: AgentContext,
+    fallback_name: Option<String>,
+    rollback: Arc<RollbackTracker>,
 }
 
 impl ReplicaSet {
-    pub fn new(client: Client, context: AgentContext) -> ReplicaSet {
-        ReplicaSet { client, context }
+    pub fn new(
+        config: MongoDB,
+        client: Client,
+        context: AgentContext,
+        rollback: Arc<RollbackTracker>,
+    ) -> ReplicaSet {
+        let fallback_name = fallback_node_name(&config, &context);
+        ReplicaSet {
+            client,
+            config,
+            context,
+            fallback_name,
+            rollback,
+        }
     }
 
     /// Executes the buildInfo command against the DB.
     fn build_info(&self, parent: &mut Span) -> Result<BuildInfo> {
+        let command = prepare_command(&self.config, doc! {"buildInfo": 1})?;
         let mut span = self.context.tracer.span("buildInfo").auto_finish();
         span.child_of(parent.context().clone());
         span.log(Log::new().log("span.kind", "client-send"));
@@ -55,7 +82,7 @@ impl ReplicaSet {
         let info = self
             .client
             .database("test")
-            .run_command(doc! {"buildInfo": 1}, None)
+            .run_command(command, None)
             .fail_span(&mut span)
             .map_err(|error| {
                 MONGODB_OP_ERRORS_COUNT
@@ -71,34 +98,26 @@ impl ReplicaSet {
         Ok(info)
     }
 
+    /// Determine the replica set and node names from the replica set status.
+    ///
+    /// The fallback node name is reported without a lookup once the request budget runs out.
+    fn member_names(&self, span: &mut Span) -> Result<MemberNames> {
+        // Without a fallback the node can't be identified so the lookup is issued regardless.
+        if !RequestBudget::spend() {
+            if let Some(fallback) = self.fallback_name.as_deref() {
+                return Ok(MemberNames::fallback(fallback));
+            }
+        }
+        let names = self.repl_set_get_status(span).map(|status| {
+            let node_name = status.node_name();
+            (status.set, node_name)
+        });
+        MemberNames::resolve(names, self.fallback_name.as_deref())
+    }
+
     /// Executes the replSetGetStatus command against the DB.
     fn repl_set_get_status(&self, parent: &mut Span) -> Result<ReplSetStatus> {
-        let mut span = self.context.tracer.span("replSetGetStatus").auto_finish();
-        span.child_of(parent.context().clone());
-        span.log(Log::new().log("span.kind", "client-send"));
-        MONGODB_OPS_COUNT
-            .with_label_values(&["replSetGetStatus"])
-            .inc();
-        let timer = MONGODB_OPS_DURATION
-            .with_label_values(&["replSetGetStatus"])
-            .start_timer();
-        let status = self
-            .client
-            .database("admin")
-            .run_command(doc! {"replSetGetStatus" => 1}, None)
-            .fail_span(&mut span)
-            .map_err(|error| {
-                MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["replSetGetStatus"])
-                    .inc();
-                error
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        let status = bson::from_bson(Bson::Document(status))
-            .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
-        Ok(status)
+        repl_set_get_status(&self.client, &self.config, &self.context, parent)
     }
 }
 
@@ -106,7 +125,7 @@ impl Agent for ReplicaSet {
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         vec![(
             ActionHook::StoreGracefulStop,
-            Arc::new(GracefulStop::new(self.client.clone())),
+            Arc::new(GracefulStop::new(self.client.clone(), self.config.clone())),
         )]
     }
 
@@ -117,31 +136,82 @@ impl Agent for ReplicaSet {
         Ok(info)
     }
 
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        let mut extras = self.rollback.extras();
+        // Names are looked up again rather than shared with concurrent info requests.
+        if let Ok(names) = self.member_names(span) {
+            extras.extend(names.extras());
+        }
+        Ok(extras)
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let info = self.build_info(span)?;
-        let status = self.repl_set_get_status(span)?;
-        let node_name = status.node_name()?;
-        let cluster = status.set;
-        Ok(DatastoreInfo::new(
-            cluster,
-            "MongoDB",
-            node_name,
-            info.version,
-            None,
-        ))
+        // Report the fallback node name, flagged as provisional, if the node is not yet known.
+        let names = self.member_names(span)?;
+        if let Some(error) = names.cause.as_ref() {
+            warn!(
+                self.context.logger,
+                "Failed to determine replica set member, reporting partial datastore info";
+                "fallback" => &names.node_name,
+                failure_info(error),
+            );
+        }
+        Ok(names.info(None, info.version))
+    }
+
+    fn health(&self, span: &mut Span) -> Result<()> {
+        health_probe(&self.client, &self.config, &self.context, span)
     }
 
     fn shards(&self, span: &mut Span) -> Result<Shards> {
+        if !RequestBudget::spend() {
+            return Ok(Shards::new(Vec::new()));
+        }
         let status = self.repl_set_get_status(span)?;
-        let last_op = status.last_op()?;
-        let role = status.role()?;
-        let lag = match role {
-            ShardRole::Primary => None,
-            _ => match status.primary_optime() {
-                Ok(head) => Some(CommitOffset::seconds(head - last_op)),
+        // Report the shard, marked as degraded, even if role or last_op can't be determined.
+        let role = match status.role() {
+            Ok(role) => role,
+            Err(error) => {
+                warn!(self.context.logger, "Failed to determine node role"; failure_info(&error));
+                self.context.tag_span(
+                    span,
+                    "role.error",
+                    format!("Failed role detection: {:?}", error),
+                );
+                ShardRole::Unknown(String::from("DEGRADED"))
+            }
+        };
+        let last_op = match status.last_op() {
+            Ok(last_op) => Some(last_op),
+            Err(error) => {
+                warn!(
+                    self.context.logger,
+                    "Failed to determine last operation";
+                    failure_info(&error),
+                );
+                self.context.tag_span(
+                    span,
+                    "last_op.error",
+                    format!("Failed last_op: {:?}", error),
+                );
+                None
+            }
+        };
+        let lag = match (&role, last_op) {
+            (ShardRole::Primary, _) | (_, None) => None,
+            (_, Some(last_op)) => match status.primary_optime() {
+                Ok(head) => self
+                    .context
+                    .reasonable_lag(head - last_op, span)
+                    .map(CommitOffset::seconds),
                 Err(error) => {
                     error!(self.context.logger, "Failed to compute lag"; failure_info(&error));
-                    span.tag("lag.error", format!("Failed lag computation: {:?}", error));
+                    self.context.tag_span(
+                        span,
+                        "lag.error",
+                        format!("Failed lag computation: {:?}", error),
+                    );
                     None
                 }
             },
@@ -150,9 +220,14 @@ impl Agent for ReplicaSet {
         let shards = vec![Shard::new(
             name,
             role,
-            Some(CommitOffset::seconds(last_op)),
+            last_op.map(CommitOffset::seconds),
             lag,
         )];
         Ok(Shards::new(shards))
     }
+
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        let connections = self.config.min_pool_size.unwrap_or(1);
+        warmup(&self.client, &self.config, &self.context, connections, span)
+    }
 }
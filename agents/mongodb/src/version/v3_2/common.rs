@@ -1,41 +1,100 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
 use bson::doc;
 use bson::Bson;
+use bson::Document;
 use failure::ResultExt;
 
 use mongodb::sync::Client;
 use opentracingrust::utils::FailSpan;
 use opentracingrust::Log;
 use opentracingrust::Span;
+use serde_json::json;
 use slog::error;
+use slog::warn;
 
 use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
+use replicante_agent::RequestBudget;
 use replicante_agent::Result;
 
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::CommitOffset;
+use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shard;
 use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
 
+use crate::config::MongoDB;
 use crate::error::ErrorKind;
+use crate::metrics::MONGODB_ELECTIONS_COUNT;
 use crate::metrics::MONGODB_OPS_COUNT;
 use crate::metrics::MONGODB_OPS_DURATION;
 use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::metrics::MONGODB_SERVER_STATUS;
+use crate::metrics::MONGODB_WT_CACHE_BYTES;
+use crate::metrics::MONGODB_WT_CACHE_DIRTY_RATIO;
+use crate::metrics::MONGODB_WT_CACHE_MAX_BYTES;
+use crate::metrics::MONGODB_WT_CACHE_USED_RATIO;
+use crate::rollback::RollbackTracker;
 
+use super::super::common::fallback_node_name;
+use super::super::common::health_probe;
+use super::super::common::prepare_command;
+use super::super::common::repl_set_get_status;
+use super::super::common::warmup;
+use super::super::common::with_read_concern;
+use super::super::common::MemberNames;
+use super::super::common::Sampled;
 use super::super::common::AGENT_VERSION;
 use super::BuildInfo;
+use super::GetParameter;
 use super::ReplSetStatus;
+use super::ServerStatus;
+
+/// Expensive serverStatus sections excluded unless needed by `server_status_metrics`.
+const SERVER_STATUS_EXCLUDED: &[&str] = &["locks", "metrics", "repl"];
 
 /// MongoDB 3.2+ logic common to both RS and Shareded modes.
 pub struct CommonLogic {
     client: Client,
+    config: MongoDB,
     context: AgentContext,
+    election: ElectionTracker,
+    extras: Mutex<Option<DatastoreExtras>>,
+    fallback_name: Option<String>,
+    primary_loss: PrimaryLossGrace,
+    rollback: Arc<RollbackTracker>,
+    server_status: Sampled<ServerStatus>,
 }
 
 impl CommonLogic {
-    pub fn new(client: Client, context: AgentContext) -> CommonLogic {
-        CommonLogic { client, context }
+    pub fn new(
+        config: MongoDB,
+        client: Client,
+        context: AgentContext,
+        rollback: Arc<RollbackTracker>,
+    ) -> CommonLogic {
+        let interval = Duration::from_secs(config.expensive_metrics_interval);
+        let grace = Duration::from_secs(config.primary_loss_grace);
+        let fallback_name = fallback_node_name(&config, &context);
+        CommonLogic {
+            client,
+            config,
+            context,
+            election: ElectionTracker::default(),
+            extras: Mutex::new(None),
+            fallback_name,
+            primary_loss: PrimaryLossGrace::new(grace),
+            rollback,
+            server_status: Sampled::new(interval),
+        }
     }
 
     /// Returns agent information.
@@ -46,6 +105,7 @@ impl CommonLogic {
 
     /// Executes the buildInfo command against the DB.
     pub fn build_info(&self, parent: &mut Span) -> Result<BuildInfo> {
+        let command = self.command(doc! {"buildInfo" => 1})?;
         let mut span = self.context.tracer.span("buildInfo").auto_finish();
         span.child_of(parent.context().clone());
         span.log(Log::new().log("span.kind", "client-send"));
@@ -56,7 +116,7 @@ impl CommonLogic {
         let info = self
             .client
             .database("test")
-            .run_command(doc! {"buildInfo" => 1}, None)
+            .run_command(command, None)
             .fail_span(&mut span)
             .map_err(|error| {
                 MONGODB_OP_ERRORS_COUNT
@@ -72,64 +132,823 @@ impl CommonLogic {
         Ok(info)
     }
 
+    /// Returns datastore information for a replica set member.
+    ///
+    /// The cluster name is taken from the replica set status unless `cluster` is given.
+    /// If the node name can't be determined the version is still reported with the
+    /// fallback node name and the datastore extras flag the information as incomplete.
+    pub fn member_info(&self, cluster: Option<String>, span: &mut Span) -> Result<DatastoreInfo> {
+        let info = self.build_info(span)?;
+        let names = self.member_names(span)?;
+        if let Some(error) = names.cause.as_ref() {
+            warn!(
+                self.context.logger,
+                "Failed to determine replica set member, reporting partial datastore info";
+                "fallback" => &names.node_name,
+                failure_info(error),
+            );
+        }
+        Ok(names.info(cluster, info.version))
+    }
+
+    /// Determine the replica set and node names from the replica set status.
+    ///
+    /// The fallback node name is reported without a lookup once the request budget runs out.
+    fn member_names(&self, span: &mut Span) -> Result<MemberNames> {
+        // Without a fallback the node can't be identified so the lookup is issued regardless.
+        if !RequestBudget::spend() {
+            if let Some(fallback) = self.fallback_name.as_deref() {
+                return Ok(MemberNames::fallback(fallback));
+            }
+        }
+        let names = self.repl_set_get_status(span).map(|status| {
+            let node_name = status.node_name();
+            (status.set, node_name)
+        });
+        MemberNames::resolve(names, self.fallback_name.as_deref())
+    }
+
+    /// Prepare a command, refusing it if it is not in the configured `command_allowlist`.
+    ///
+    /// Allowed commands are tagged with the configured `tenant_id`, if any.
+    pub fn command(&self, command: Document) -> Result<Document> {
+        prepare_command(&self.config, command)
+    }
+
+    /// Prepare a read command and attach the configured read concern to it.
+    pub fn read_command(&self, command: Document) -> Result<Document> {
+        let command = self.command(command)?;
+        Ok(with_read_concern(command, self.config.read_concern))
+    }
+
     /// Access the mongodb client.
     pub fn client(&self) -> Client {
         self.client.clone()
     }
 
+    /// Access the MongoDB agent configuration.
+    pub fn config(&self) -> MongoDB {
+        self.config.clone()
+    }
+
+    /// Returns datastore info extras queried from the DB, if enrichment is enabled.
+    ///
+    /// Server parameters are cached once fetched and are otherwise fetched again next time.
+    /// Server status extras follow the `expensive_metrics_interval` refreshes instead.
+    /// Failed queries are logged and the extras they provide are omitted.
+    ///
+    /// Rollbacks in progress, stale shard information, incomplete datastore information
+    /// and provisional node names are always flagged, and the latest election details
+    /// reported, regardless of enrichment and caching.
+    pub fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        let mut extras = self.enrichment_extras(span);
+        extras.extend(self.server_status_extras(span));
+        extras.extend(self.rollback.extras());
+        extras.extend(self.primary_loss.extras());
+        extras.extend(self.election.extras());
+        // Names are looked up again rather than shared with concurrent info requests.
+        if let Ok(names) = self.member_names(span) {
+            extras.extend(names.extras());
+        }
+        Ok(extras)
+    }
+
+    /// Returns (possibly cached) datastore info extras derived from server parameters.
+    ///
+    /// Parameters are not fetched, nor cached, if the request budget can't afford them.
+    fn enrichment_extras(&self, span: &mut Span) -> DatastoreExtras {
+        if !self.config.enrichment {
+            return DatastoreExtras::new();
+        }
+        let mut cache = self.extras.lock().expect("MongoDB extras lock poisoned");
+        if let Some(extras) = cache.as_ref() {
+            return extras.clone();
+        }
+
+        if !RequestBudget::spend() {
+            return DatastoreExtras::new();
+        }
+        match self.get_parameter(span) {
+            Ok(params) => {
+                let extras = params.extras();
+                *cache = Some(extras.clone());
+                extras
+            }
+            Err(error) => {
+                warn!(
+                    self.context.logger,
+                    "Failed to fetch MongoDB parameters";
+                    failure_info(&error),
+                );
+                DatastoreExtras::new()
+            }
+        }
+    }
+
+    /// Returns datastore info extras derived from the (sampled) server status.
+    ///
+    /// The server status also feeds the serverStatus gauges so it is sampled even with
+    /// enrichment disabled, in which case skipping it does not flag the response incomplete.
+    fn server_status_extras(&self, span: &mut Span) -> DatastoreExtras {
+        let affordable = if self.config.enrichment {
+            RequestBudget::spend()
+        } else {
+            RequestBudget::spend_optional()
+        };
+        if !affordable {
+            return DatastoreExtras::new();
+        }
+        match self.server_status(span) {
+            Ok(status) if self.config.enrichment => status.extras(),
+            Ok(_) => DatastoreExtras::new(),
+            Err(error) => {
+                warn!(
+                    self.context.logger,
+                    "Failed to fetch MongoDB server status";
+                    failure_info(&error),
+                );
+                DatastoreExtras::new()
+            }
+        }
+    }
+
+    /// Executes the getParameter command against the DB.
+    pub fn get_parameter(&self, parent: &mut Span) -> Result<GetParameter> {
+        let command = self.command(doc! {"getParameter" => "*"})?;
+        let mut span = self.context.tracer.span("getParameter").auto_finish();
+        span.child_of(parent.context().clone());
+        span.log(Log::new().log("span.kind", "client-send"));
+        MONGODB_OPS_COUNT.with_label_values(&["getParameter"]).inc();
+        let timer = MONGODB_OPS_DURATION
+            .with_label_values(&["getParameter"])
+            .start_timer();
+        let params = self
+            .client
+            .database("admin")
+            .run_command(command, None)
+            .fail_span(&mut span)
+            .map_err(|error| {
+                MONGODB_OP_ERRORS_COUNT
+                    .with_label_values(&["getParameter"])
+                    .inc();
+                error
+            })
+            .with_context(|_| ErrorKind::StoreOpFailed("getParameter"))?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        let params = bson::from_bson(Bson::Document(params))
+            .with_context(|_| ErrorKind::BsonDecode("getParameter"))?;
+        Ok(params)
+    }
+
+    /// Executes the configured health probe against the DB.
+    pub fn health(&self, span: &mut Span) -> Result<()> {
+        health_probe(&self.client, &self.config, &self.context, span)
+    }
+
+    /// Open the configured minimum pool connections and probe the DB.
+    pub fn warmup(&self, span: &mut Span) -> Result<()> {
+        let connections = self.config.min_pool_size.unwrap_or(1);
+        warmup(&self.client, &self.config, &self.context, connections, span)
+    }
+
     /// Executes the replSetGetStatus command against the DB.
     pub fn repl_set_get_status(&self, parent: &mut Span) -> Result<ReplSetStatus> {
-        let mut span = self.context.tracer.span("replSetGetStatus").auto_finish();
+        let status: ReplSetStatus =
+            repl_set_get_status(&self.client, &self.config, &self.context, parent)?;
+        self.election.observe(&status);
+        Ok(status)
+    }
+
+    /// Returns the result of the serverStatus command, refreshed at most once per
+    /// `expensive_metrics_interval`.
+    pub fn server_status(&self, parent: &mut Span) -> Result<ServerStatus> {
+        sample_server_status(&self.server_status, || self.server_status_command(parent))
+    }
+
+    /// Executes the serverStatus command against the DB.
+    ///
+    /// Sections the agent does not use and that are expensive to collect are excluded.
+    /// Paths listed in `server_status_metrics` are exported as gauges.
+    fn server_status_command(&self, parent: &mut Span) -> Result<ServerStatus> {
+        let mut span = self.context.tracer.span("serverStatus").auto_finish();
         span.child_of(parent.context().clone());
         span.log(Log::new().log("span.kind", "client-send"));
-        MONGODB_OPS_COUNT
-            .with_label_values(&["replSetGetStatus"])
-            .inc();
+        MONGODB_OPS_COUNT.with_label_values(&["serverStatus"]).inc();
         let timer = MONGODB_OPS_DURATION
-            .with_label_values(&["replSetGetStatus"])
+            .with_label_values(&["serverStatus"])
             .start_timer();
+        let mut command = doc! {"serverStatus" => 1};
+        for section in SERVER_STATUS_EXCLUDED {
+            let needed = self
+                .config
+                .server_status_metrics
+                .iter()
+                .any(|path| path.split('.').next() == Some(*section));
+            if !needed {
+                command.insert(*section, 0);
+            }
+        }
+        let command = self.command(command)?;
         let status = self
             .client
             .database("admin")
-            .run_command(doc! {"replSetGetStatus" => 1}, None)
+            .run_command(command, None)
             .fail_span(&mut span)
             .map_err(|error| {
                 MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["replSetGetStatus"])
+                    .with_label_values(&["serverStatus"])
                     .inc();
                 error
             })
-            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
+            .with_context(|_| ErrorKind::StoreOpFailed("serverStatus"))?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
+        export_server_status_metrics(&self.context, &status, &self.config.server_status_metrics);
         let status = bson::from_bson(Bson::Document(status))
-            .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
+            .with_context(|_| ErrorKind::BsonDecode("serverStatus"))?;
         Ok(status)
     }
 
     /// Returns shard information from a MongoD instance.
     pub fn shards(&self, span: &mut Span) -> Result<Shards> {
+        if !RequestBudget::spend() {
+            return Ok(Shards::new(Vec::new()));
+        }
         let status = self.repl_set_get_status(span)?;
-        let last_op = status.last_op()?;
-        let role = status.role()?;
-        let lag = match role {
-            ShardRole::Primary => None,
-            _ => match status.primary_optime() {
-                Ok(head) => Some(CommitOffset::seconds(head - last_op)),
-                Err(error) => {
-                    error!(self.context.logger, "Failed to compute lag"; failure_info(&error));
-                    span.tag("lag.error", format!("Failed lag computation: {:?}", error));
-                    None
-                }
+        let reading = status_reading(&self.context, &status, span);
+        let reading = self.primary_loss.observe(&self.context, reading, span);
+        Ok(Shards::new(vec![reading.shard(status.set)]))
+    }
+}
+
+/// Return the sampled server status, exporting WiredTiger cache usage whenever it is refreshed.
+fn sample_server_status<F>(sample: &Sampled<ServerStatus>, collect: F) -> Result<ServerStatus>
+where
+    F: FnOnce() -> Result<ServerStatus>,
+{
+    sample.get(|| {
+        let status = collect()?;
+        if let Some(cache) = status.wired_tiger_cache() {
+            MONGODB_WT_CACHE_BYTES.set(cache.bytes);
+            MONGODB_WT_CACHE_MAX_BYTES.set(cache.max_bytes);
+            if let Some(ratio) = cache.used_ratio() {
+                MONGODB_WT_CACHE_USED_RATIO.set(ratio);
+            }
+            if let Some(ratio) = cache.dirty_ratio() {
+                MONGODB_WT_CACHE_DIRTY_RATIO.set(ratio);
+            }
+        }
+        Ok(status)
+    })
+}
+
+/// Export the requested paths of a serverStatus response as gauges.
+///
+/// Paths that are missing from the response or are not numeric are skipped with a warning.
+fn export_server_status_metrics(context: &AgentContext, status: &Document, paths: &[String]) {
+    for path in paths {
+        match lookup_path(status, path).and_then(numeric_value) {
+            Some(value) => MONGODB_SERVER_STATUS.with_label_values(&[path]).set(value),
+            None => warn!(
+                context.logger,
+                "Skipping unknown or non-numeric serverStatus metric";
+                "path" => path,
+            ),
+        }
+    }
+}
+
+/// Find the value at a dotted path in a BSON document.
+fn lookup_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut value = document.get(parts.next()?)?;
+    for part in parts {
+        value = match value {
+            Bson::Document(document) => document.get(part)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Convert numeric BSON values to gauge values.
+fn numeric_value(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::FloatingPoint(value) => Some(*value),
+        Bson::I32(value) => Some(f64::from(*value)),
+        Bson::I64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+/// Track the election term and primary election id reported by replSetGetStatus.
+#[derive(Default)]
+struct ElectionTracker {
+    last: Mutex<Option<Election>>,
+}
+
+/// Election details of the replica set.
+#[derive(Clone, Debug, Default)]
+struct Election {
+    id: Option<String>,
+    term: Option<i64>,
+}
+
+impl ElectionTracker {
+    /// Datastore info extras with the latest election term and id, when known.
+    fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        let last = self.last.lock().expect("MongoDB election lock poisoned");
+        let election = last.clone().unwrap_or_default();
+        if let Some(id) = election.id {
+            extras.insert("election_id".into(), json!(id));
+        }
+        if let Some(term) = election.term {
+            extras.insert("election_term".into(), json!(term));
+        }
+        extras
+    }
+
+    /// Record the latest election details and count term changes.
+    fn observe(&self, status: &ReplSetStatus) {
+        let election = Election {
+            id: status.election_id(),
+            term: status.term,
+        };
+        let mut last = self.last.lock().expect("MongoDB election lock poisoned");
+        let previous = last.as_ref().and_then(|last| last.term);
+        if let (Some(previous), Some(term)) = (previous, election.term) {
+            if previous != term {
+                MONGODB_ELECTIONS_COUNT.inc();
+            }
+        }
+        *last = Some(election);
+    }
+}
+
+/// Keep reporting the last known role and lag for a grace period after the primary is lost.
+///
+/// Elections briefly leave nodes without a primary, and so without lag, or without a role.
+/// Reporting the last known good values for a short time avoids flapping alerts.
+struct PrimaryLossGrace {
+    grace: Duration,
+    last_good: Mutex<Option<(Instant, ShardReading)>>,
+    stale: AtomicBool,
+}
+
+impl PrimaryLossGrace {
+    fn new(grace: Duration) -> PrimaryLossGrace {
+        PrimaryLossGrace {
+            grace,
+            last_good: Mutex::new(None),
+            stale: AtomicBool::new(false),
+        }
+    }
+
+    /// Datastore info extras flagging stale shard information.
+    fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        if self.stale.load(Ordering::Relaxed) {
+            extras.insert("shard_stale".into(), json!(true));
+        }
+        extras
+    }
+
+    /// Record good readings and replace degraded ones with the last good reading, if recent.
+    fn observe(
+        &self,
+        context: &AgentContext,
+        reading: ShardReading,
+        span: &mut Span,
+    ) -> ShardReading {
+        if self.grace == Duration::from_secs(0) {
+            return reading;
+        }
+        let mut last_good = self
+            .last_good
+            .lock()
+            .expect("MongoDB primary loss grace lock poisoned");
+        if !reading.degraded() {
+            *last_good = Some((Instant::now(), reading.clone()));
+            self.stale.store(false, Ordering::Relaxed);
+            return reading;
+        }
+        let stale = match last_good.as_ref() {
+            Some((seen, good)) if seen.elapsed() < self.grace => good,
+            _ => {
+                self.stale.store(false, Ordering::Relaxed);
+                return reading;
+            }
+        };
+        if !self.stale.swap(true, Ordering::Relaxed) {
+            warn!(
+                context.logger,
+                "Reporting last known role and lag while the primary is unavailable";
+                "role" => ?stale.role,
+            );
+        }
+        span.tag("shard.stale", true);
+        ShardReading {
+            role: stale.role.clone(),
+            last_op: reading.last_op.or(stale.last_op),
+            lag: stale.lag,
+        }
+    }
+}
+
+/// Role, last operation and lag (in seconds) of a node.
+#[derive(Clone, Debug)]
+struct ShardReading {
+    role: ShardRole,
+    last_op: Option<i64>,
+    lag: Option<i64>,
+}
+
+impl ShardReading {
+    /// Check if the role or lag could not be determined, as happens during elections.
+    fn degraded(&self) -> bool {
+        match &self.role {
+            ShardRole::Primary => false,
+            ShardRole::Unknown(state) if state == "DEGRADED" => true,
+            _ => self.lag.is_none(),
+        }
+    }
+
+    fn shard(self, set: String) -> Shard {
+        let last_op = self.last_op.map(CommitOffset::seconds);
+        let lag = self.lag.map(CommitOffset::seconds);
+        Shard::new(set, self.role, last_op, lag)
+    }
+}
+
+/// Read the node's shard information from a replSetGetStatus response.
+///
+/// The shard is reported even if the node's role or last operation can't be determined
+/// (for example while an election is in progress) so it remains visible to the control plane.
+/// In such cases the role is reported as `DEGRADED` and the offsets are omitted.
+fn status_reading(context: &AgentContext, status: &ReplSetStatus, span: &mut Span) -> ShardReading {
+    let role = match status.role() {
+        Ok(role) => role,
+        Err(error) => {
+            warn!(context.logger, "Failed to determine node role"; failure_info(&error));
+            context.tag_span(
+                span,
+                "role.error",
+                format!("Failed role detection: {:?}", error),
+            );
+            ShardRole::Unknown(String::from("DEGRADED"))
+        }
+    };
+    let last_op = match status.last_op() {
+        Ok(last_op) => Some(last_op),
+        Err(error) => {
+            warn!(context.logger, "Failed to determine last operation"; failure_info(&error));
+            context.tag_span(
+                span,
+                "last_op.error",
+                format!("Failed last_op: {:?}", error),
+            );
+            None
+        }
+    };
+    let lag = match (&role, last_op) {
+        (ShardRole::Primary, _) | (_, None) => None,
+        (_, Some(last_op)) => match status.primary_optime() {
+            Ok(head) => context.reasonable_lag(head - last_op, span),
+            Err(error) => {
+                error!(context.logger, "Failed to compute lag"; failure_info(&error));
+                context.tag_span(
+                    span,
+                    "lag.error",
+                    format!("Failed lag computation: {:?}", error),
+                );
+                None
+            }
+        },
+    };
+    ShardReading { role, last_op, lag }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bson::doc;
+    use bson::Bson;
+    use failure::Fail;
+    use serde_json::json;
+
+    use replicante_agent::config::Agent as AgentConfig;
+    use replicante_agent::AgentContext;
+    use replicante_agent::Result;
+    use replicante_models_agent::info::CommitOffset;
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+
+    use super::export_server_status_metrics;
+    use super::fallback_node_name;
+    use super::sample_server_status;
+    use super::status_reading;
+    use super::BuildInfo;
+    use super::ElectionTracker;
+    use super::MemberNames;
+    use super::PrimaryLossGrace;
+    use super::ReplSetStatus;
+    use super::Sampled;
+    use super::ServerStatus;
+    use crate::config::MongoDB;
+    use crate::error::ErrorKind;
+    use crate::metrics::MONGODB_ELECTIONS_COUNT;
+    use crate::metrics::MONGODB_SERVER_STATUS;
+    use crate::metrics::MONGODB_WT_CACHE_BYTES;
+    use crate::metrics::MONGODB_WT_CACHE_USED_RATIO;
+
+    fn build_info() -> BuildInfo {
+        bson::from_bson(Bson::Document(doc! {"version": "3.6.0"})).unwrap()
+    }
+
+    fn member_names(status: Result<ReplSetStatus>, fallback: Option<&str>) -> Result<MemberNames> {
+        let names = status.map(|status| {
+            let node_name = status.node_name();
+            (status.set, node_name)
+        });
+        MemberNames::resolve(names, fallback)
+    }
+
+    #[test]
+    fn member_info_complete() {
+        let status: ReplSetStatus = bson::from_bson(healthy_status()).unwrap();
+        let names = member_names(Ok(status), Some("fallback")).unwrap();
+        let info = names.info(None, build_info().version);
+        assert!(names.extras().is_empty());
+        assert_eq!(info.cluster_id, "test-rs");
+        assert_eq!(info.node_id, "host1");
+        assert_eq!(info.version, "3.6.0");
+    }
+
+    #[test]
+    fn member_info_partial_without_status() {
+        let status = Err(ErrorKind::StoreOpFailed("replSetGetStatus").into());
+        let names = member_names(status, Some("mongo-0.example.com")).unwrap();
+        let info = names.info(Some("sharded".to_string()), build_info().version);
+        let extras = names.extras();
+        assert_eq!(extras.get("info_incomplete"), Some(&json!(true)));
+        assert_eq!(extras.get("node_name_provisional"), Some(&json!(true)));
+        assert_eq!(info.cluster_id, "sharded");
+        assert_eq!(info.node_id, "mongo-0.example.com");
+        assert_eq!(info.version, "3.6.0");
+    }
+
+    #[test]
+    fn member_info_fails_without_status_or_fallback() {
+        let status = Err(ErrorKind::StoreOpFailed("replSetGetStatus").into());
+        let error = member_names(status, None).err().unwrap();
+        assert_eq!(error.name().unwrap(), "StoreOpFailed");
+    }
+
+    #[test]
+    fn member_info_hostname_fallback_without_self() {
+        let context = AgentContext::mock();
+        let hostname = fallback_node_name(&MongoDB::default(), &context).unwrap();
+        let status = Bson::Document(doc! {
+            "members": [member(0, 1514677701, false, 1)],
+            "myState": 5,
+            "set": "test-rs",
+        });
+        let status: ReplSetStatus = bson::from_bson(status).unwrap();
+        let names = member_names(Ok(status), Some(&hostname)).unwrap();
+        let info = names.info(None, build_info().version);
+        assert_eq!(
+            names.extras().get("node_name_provisional"),
+            Some(&json!(true))
+        );
+        assert_eq!(info.cluster_id, "test-rs");
+        assert_eq!(info.node_id, hostname);
+    }
+
+    fn member(id: i32, ts: u32, is_self: bool, state: i32) -> Bson {
+        Bson::Document(doc! {
+            "_id": id,
+            "name": format!("host{}", id),
+            "optime": {
+                "ts": Bson::TimeStamp(i64::from(ts) << 32),
             },
+            "self": is_self,
+            "state": state,
+        })
+    }
+
+    fn shard(status: Bson) -> Shard {
+        shard_with_context(&AgentContext::mock(), status)
+    }
+
+    fn shard_with_context(context: &AgentContext, status: Bson) -> Shard {
+        let mut span = context.tracer.span("test");
+        let status: ReplSetStatus = bson::from_bson(status).unwrap();
+        status_reading(context, &status, &mut span).shard(status.set)
+    }
+
+    fn graced_shard(grace: &PrimaryLossGrace, status: Bson) -> Shard {
+        let context = AgentContext::mock();
+        let mut span = context.tracer.span("test");
+        let status: ReplSetStatus = bson::from_bson(status).unwrap();
+        let reading = status_reading(&context, &status, &mut span);
+        grace
+            .observe(&context, reading, &mut span)
+            .shard(status.set)
+    }
+
+    fn election_status() -> Bson {
+        Bson::Document(doc! {
+            "set": "test-rs",
+            "members": [
+                member(0, 1514677701, false, 2),
+                member(1, 1514677700, true, 2),
+            ],
+            "myState": 2,
+        })
+    }
+
+    fn healthy_status() -> Bson {
+        Bson::Document(doc! {
+            "set": "test-rs",
+            "members": [
+                member(0, 1514677701, false, 1),
+                member(1, 1514677698, true, 2),
+            ],
+            "myState": 2,
+        })
+    }
+
+    #[test]
+    fn skewed_lag_is_suppressed() {
+        let mut config = AgentConfig::mock();
+        config.shards.max_reasonable_lag = Some(3600);
+        let context = AgentContext::mock_with_config(config);
+
+        // Plausible lag is reported as usual.
+        let shard = shard_with_context(&context, healthy_status());
+        assert_eq!(shard.lag, Some(CommitOffset::seconds(3)));
+
+        // A primary optime days ahead of the node is most likely clock skew.
+        let status = Bson::Document(doc! {
+            "set": "test-rs",
+            "members": [
+                member(0, 1514677698 + 10 * 86400, false, 1),
+                member(1, 1514677698, true, 2),
+            ],
+            "myState": 2,
+        });
+        let shard = shard_with_context(&context, status);
+        let expected = Shard::new(
+            String::from("test-rs"),
+            ShardRole::Secondary,
+            Some(CommitOffset::seconds(1514677698)),
+            None,
+        );
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn election_term_changes_counted() {
+        let tracker = ElectionTracker::default();
+        let status = |term: i64| -> ReplSetStatus {
+            let mut status = healthy_status();
+            if let Bson::Document(status) = &mut status {
+                status.insert("term", term);
+            }
+            bson::from_bson(status).unwrap()
+        };
+        let before = MONGODB_ELECTIONS_COUNT.get();
+        tracker.observe(&status(3));
+        tracker.observe(&status(3));
+        assert_eq!(MONGODB_ELECTIONS_COUNT.get(), before);
+        tracker.observe(&status(4));
+        assert_eq!(MONGODB_ELECTIONS_COUNT.get(), before + 1.0);
+        assert_eq!(tracker.extras().get("election_term"), Some(&json!(4)));
+    }
+
+    #[test]
+    fn election_without_primary() {
+        let status = Bson::Document(doc! {
+            "set": "test-rs",
+            "members": [
+                member(0, 1514677701, false, 2),
+                member(1, 1514677698, true, 2),
+                member(2, 1514677701, false, 8),
+            ],
+            "myState": 2,
+        });
+        let shard = shard(status);
+        let expected = Shard::new(
+            String::from("test-rs"),
+            ShardRole::Secondary,
+            Some(CommitOffset::seconds(1514677698)),
+            None,
+        );
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn degraded_without_self() {
+        let status = Bson::Document(doc! {
+            "set": "test-rs",
+            "members": [member(0, 1514677701, false, 1)],
+            "myState": 22,
+        });
+        let shard = shard(status);
+        let role = ShardRole::Unknown("DEGRADED".into());
+        let expected = Shard::new(String::from("test-rs"), role, None, None);
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn primary_loss_within_grace_reports_last_known() {
+        let grace = PrimaryLossGrace::new(Duration::from_secs(60));
+        graced_shard(&grace, healthy_status());
+        let shard = graced_shard(&grace, election_status());
+        let expected = Shard::new(
+            String::from("test-rs"),
+            ShardRole::Secondary,
+            Some(CommitOffset::seconds(1514677700)),
+            Some(CommitOffset::seconds(3)),
+        );
+        assert_eq!(shard, expected);
+        assert_eq!(grace.extras().get("shard_stale"), Some(&json!(true)));
+
+        // Fresh readings clear the stale flag.
+        graced_shard(&grace, healthy_status());
+        assert!(grace.extras().is_empty());
+    }
+
+    #[test]
+    fn primary_loss_after_grace_reports_unknown_lag() {
+        let grace = PrimaryLossGrace::new(Duration::from_secs(60));
+        graced_shard(&grace, healthy_status());
+        {
+            // Age the last good reading past the grace period.
+            let mut last_good = grace.last_good.lock().unwrap();
+            last_good.as_mut().unwrap().0 -= Duration::from_secs(61);
+        }
+        let shard = graced_shard(&grace, election_status());
+        let expected = Shard::new(
+            String::from("test-rs"),
+            ShardRole::Secondary,
+            Some(CommitOffset::seconds(1514677700)),
+            None,
+        );
+        assert_eq!(shard, expected);
+        assert!(grace.extras().is_empty());
+    }
+
+    fn wired_tiger_status(bytes: i64) -> ServerStatus {
+        let status = doc! {
+            "wiredTiger": {
+                "cache": {
+                    "bytes currently in the cache": bytes,
+                    "maximum bytes configured": 1000_i64,
+                },
+            },
+        };
+        bson::from_bson(Bson::Document(status)).unwrap()
+    }
+
+    #[test]
+    fn wired_tiger_gauges_follow_refreshes() {
+        let sampled = Sampled::new(Duration::from_secs(0));
+        sample_server_status(&sampled, || Ok(wired_tiger_status(250))).unwrap();
+        assert_eq!(MONGODB_WT_CACHE_BYTES.get(), 250.0);
+        assert_eq!(MONGODB_WT_CACHE_USED_RATIO.get(), 0.25);
+        sample_server_status(&sampled, || Ok(wired_tiger_status(500))).unwrap();
+        assert_eq!(MONGODB_WT_CACHE_BYTES.get(), 500.0);
+        assert_eq!(MONGODB_WT_CACHE_USED_RATIO.get(), 0.5);
+    }
+
+    #[test]
+    fn server_status_paths_exported_as_gauges() {
+        let context = AgentContext::mock();
+        let status = doc! {
+            "host": "node1",
+            "connections": {"current": 12, "available": 800},
+            "metrics": {"document": {"inserted": 4_200_000_000_i64, "deleted": 7}},
+            "storageEngine": {"name": "wiredTiger"},
+            "ok": 1.0,
         };
-        let name = status.set;
-        let shards = vec![Shard::new(
-            name,
-            role,
-            Some(CommitOffset::seconds(last_op)),
-            lag,
-        )];
-        Ok(Shards::new(shards))
+        let paths = vec![
+            "metrics.document.inserted".to_string(),
+            "connections.current".to_string(),
+            "metrics.document.missing".to_string(),
+            "storageEngine.name".to_string(),
+        ];
+        export_server_status_metrics(&context, &status, &paths);
+        let inserted = MONGODB_SERVER_STATUS
+            .with_label_values(&["metrics.document.inserted"])
+            .get();
+        let current = MONGODB_SERVER_STATUS
+            .with_label_values(&["connections.current"])
+            .get();
+        assert_eq!(inserted, 4_200_000_000.0);
+        assert_eq!(current, 12.0);
     }
 }
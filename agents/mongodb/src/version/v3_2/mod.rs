@@ -12,6 +12,10 @@ lazy_static! {
 }
 
 pub use self::models::BuildInfo;
+pub use self::models::ChunkDistribution;
+pub use self::models::ChunksAggregation;
+pub use self::models::GetParameter;
 pub use self::models::ReplSetStatus;
+pub use self::models::ServerStatus;
 pub use self::replica::ReplicaSet;
 pub use self::sharded::Sharded;
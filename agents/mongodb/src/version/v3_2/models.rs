@@ -1,10 +1,16 @@
+use std::collections::BTreeMap;
+
+use bson::Bson;
 use bson::TimeStamp;
 use serde_derive::Deserialize;
+use serde_json::json;
 
+use replicante_agent::DatastoreExtras;
 use replicante_agent::Result;
 use replicante_models_agent::info::ShardRole;
 
 use crate::error::ErrorKind;
+use crate::version::common::member_role;
 
 /// Section of the buildInfo command that we care about.
 #[derive(Deserialize)]
@@ -12,6 +18,188 @@ pub struct BuildInfo {
     pub version: String,
 }
 
+/// Result of the `config.chunks` aggregation counting chunks by shard.
+#[derive(Debug, Deserialize)]
+pub struct ChunksAggregation {
+    pub cursor: ChunksCursor,
+}
+
+impl ChunksAggregation {
+    /// Number of chunks owned by each shard, keyed by shard name.
+    pub fn distribution(self) -> ChunkDistribution {
+        let counts = self
+            .cursor
+            .first_batch
+            .into_iter()
+            .map(|shard| (shard.shard, shard.chunks))
+            .collect();
+        ChunkDistribution(counts)
+    }
+}
+
+/// Section of the aggregation cursor that we care about.
+#[derive(Debug, Deserialize)]
+pub struct ChunksCursor {
+    #[serde(rename = "firstBatch")]
+    pub first_batch: Vec<ShardChunks>,
+}
+
+/// Number of chunks owned by a shard.
+#[derive(Debug, Deserialize)]
+pub struct ShardChunks {
+    #[serde(rename = "_id")]
+    pub shard: String,
+    pub chunks: i64,
+}
+
+/// Distribution of chunks across the shards of the cluster.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkDistribution(BTreeMap<String, i64>);
+
+impl ChunkDistribution {
+    /// Datastore info extras reporting the number of chunks on each shard.
+    pub fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        extras.insert("chunks_per_shard".into(), json!(self.0));
+        extras
+    }
+}
+
+/// MongoDB featureCompatibilityVersion parameter.
+///
+/// The parameter does not exist before MongoDB 3.4 and its format changed over versions.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FeatureCompatibilityVersion {
+    /// MongoDB 3.4 reports the version as a string.
+    Legacy(String),
+
+    /// MongoDB 3.6+ reports a document, with a target version while upgrading or downgrading.
+    Document {
+        version: String,
+        #[serde(rename = "targetVersion", default)]
+        target_version: Option<String>,
+    },
+}
+
+impl FeatureCompatibilityVersion {
+    /// Version the node is operating at, which may be older than the binary version.
+    pub fn version(&self) -> &str {
+        match self {
+            FeatureCompatibilityVersion::Legacy(version) => version,
+            FeatureCompatibilityVersion::Document { version, .. } => version,
+        }
+    }
+
+    /// Version the node is transitioning to, if an upgrade or downgrade is in progress.
+    pub fn target_version(&self) -> Option<&str> {
+        match self {
+            FeatureCompatibilityVersion::Legacy(_) => None,
+            FeatureCompatibilityVersion::Document { target_version, .. } => {
+                target_version.as_deref()
+            }
+        }
+    }
+}
+
+/// Section of the getParameter command that we care about.
+#[derive(Debug, Deserialize)]
+pub struct GetParameter {
+    #[serde(rename = "featureCompatibilityVersion", default)]
+    pub feature_compatibility_version: Option<FeatureCompatibilityVersion>,
+}
+
+impl GetParameter {
+    /// Datastore info extras derived from server parameters.
+    pub fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        if let Some(fcv) = self.feature_compatibility_version.as_ref() {
+            extras.insert("fcv".into(), json!(fcv.version()));
+            if let Some(target) = fcv.target_version() {
+                extras.insert("fcv_target".into(), json!(target));
+            }
+        }
+        extras
+    }
+}
+
+/// Section of the serverStatus command that we care about.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerStatus {
+    #[serde(rename = "storageEngine", default)]
+    pub storage_engine: Option<StorageEngine>,
+
+    /// Only reported when the WiredTiger storage engine is in use.
+    #[serde(rename = "wiredTiger", default)]
+    pub wired_tiger: Option<WiredTiger>,
+}
+
+impl ServerStatus {
+    /// Datastore info extras derived from the server status.
+    pub fn extras(&self) -> DatastoreExtras {
+        let mut extras = DatastoreExtras::new();
+        if let Some(engine) = self.storage_engine.as_ref() {
+            extras.insert("storage_engine".into(), json!(engine.name));
+        }
+        if let Some(ratio) = self
+            .wired_tiger_cache()
+            .and_then(WiredTigerCache::used_ratio)
+        {
+            extras.insert("wt_cache_used_ratio".into(), json!(ratio));
+        }
+        extras
+    }
+
+    /// WiredTiger cache statistics, if the node uses WiredTiger.
+    pub fn wired_tiger_cache(&self) -> Option<&WiredTigerCache> {
+        self.wired_tiger
+            .as_ref()
+            .and_then(|wired_tiger| wired_tiger.cache.as_ref())
+    }
+}
+
+/// Section of the serverStatus storageEngine information that we care about.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StorageEngine {
+    pub name: String,
+}
+
+/// Section of the serverStatus WiredTiger information that we care about.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WiredTiger {
+    #[serde(default)]
+    pub cache: Option<WiredTigerCache>,
+}
+
+/// Section of the serverStatus WiredTiger cache statistics that we care about.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WiredTigerCache {
+    #[serde(rename = "bytes currently in the cache")]
+    pub bytes: f64,
+    #[serde(rename = "tracked dirty bytes in the cache", default)]
+    pub dirty_bytes: f64,
+    #[serde(rename = "maximum bytes configured")]
+    pub max_bytes: f64,
+}
+
+impl WiredTigerCache {
+    /// Fraction of the configured cache size in use.
+    pub fn used_ratio(&self) -> Option<f64> {
+        if self.max_bytes <= 0.0 {
+            return None;
+        }
+        Some(self.bytes / self.max_bytes)
+    }
+
+    /// Fraction of the configured cache size holding dirty data.
+    pub fn dirty_ratio(&self) -> Option<f64> {
+        if self.max_bytes <= 0.0 {
+            return None;
+        }
+        Some(self.dirty_bytes / self.max_bytes)
+    }
+}
+
 /// Section of the replSetGetStatus command that we care about.
 #[derive(Debug, Deserialize)]
 pub struct ReplSetStatus {
@@ -19,9 +207,25 @@ pub struct ReplSetStatus {
     #[serde(rename = "myState")]
     pub my_state: i32,
     pub set: String,
+
+    /// Election term, only reported by the replica set protocol version 1.
+    #[serde(default)]
+    pub term: Option<i64>,
 }
 
 impl ReplSetStatus {
+    /// Extracts the election id of the primary, if known.
+    pub fn election_id(&self) -> Option<String> {
+        self.members
+            .iter()
+            .find(|member| member.state == 1)
+            .and_then(|member| member.election_id.as_ref())
+            .map(|id| match id {
+                Bson::ObjectId(id) => id.to_hex(),
+                id => id.to_string(),
+            })
+    }
+
     /// Extracts the timestamp (in seconds) of the latest operation.
     pub fn last_op(&self) -> Result<i64> {
         for member in &self.members {
@@ -54,25 +258,16 @@ impl ReplSetStatus {
 
     /// Extracts the node's role in the Replica Set.
     pub fn role(&self) -> Result<ShardRole> {
-        match self.my_state {
-            0 => Ok(ShardRole::Unknown(String::from("STARTUP"))),
-            1 => Ok(ShardRole::Primary),
-            2 => Ok(ShardRole::Secondary),
-            3 => Ok(ShardRole::Unknown(String::from("RECOVERING"))),
-            5 => Ok(ShardRole::Unknown(String::from("STARTUP2"))),
-            6 => Ok(ShardRole::Unknown(String::from("UNKNOWN"))),
-            7 => Ok(ShardRole::Unknown(String::from("ARBITER"))),
-            8 => Ok(ShardRole::Unknown(String::from("DOWN"))),
-            9 => Ok(ShardRole::Unknown(String::from("ROLLBACK"))),
-            10 => Ok(ShardRole::Unknown(String::from("REMOVED"))),
-            state => Err(ErrorKind::UnsupportedSateId(state).into()),
-        }
+        member_role(self.my_state)
     }
 }
 
 /// Section of the replSetGetStatus member that we care about.
 #[derive(Debug, Deserialize)]
 pub struct ReplSetStatusMember {
+    /// Reported for the primary only, and only by some versions.
+    #[serde(rename = "electionId", default)]
+    pub election_id: Option<Bson>,
     #[serde(rename = "self", default = "ReplSetStatusMember::default_self")]
     pub is_self: bool,
     pub name: String,
@@ -100,8 +295,12 @@ mod tests {
 
     use replicante_agent::ErrorKind;
     use replicante_models_agent::info::ShardRole;
+    use serde_json::json;
 
+    use super::ChunksAggregation;
+    use super::GetParameter;
     use super::ReplSetStatus;
+    use super::ServerStatus;
 
     lazy_static! {
         static ref MONGO_TIMESTAMP_ONE: Bson = {
@@ -138,6 +337,110 @@ mod tests {
         })
     }
 
+    #[test]
+    fn get_parameter_fcv_extras() {
+        let params = Bson::Document(doc! {
+            "featureCompatibilityVersion": {
+                "version": "4.0",
+            },
+            "ok": 1.0,
+        });
+        let params: GetParameter = bson::from_bson(params).unwrap();
+        let extras = params.extras();
+        assert_eq!(extras.get("fcv"), Some(&json!("4.0")));
+    }
+
+    #[test]
+    fn get_parameter_fcv_v3_4() {
+        let params = Bson::Document(doc! {
+            "featureCompatibilityVersion": "3.4",
+            "ok": 1.0,
+        });
+        let params: GetParameter = bson::from_bson(params).unwrap();
+        let extras = params.extras();
+        assert_eq!(extras.get("fcv"), Some(&json!("3.4")));
+        assert_eq!(extras.get("fcv_target"), None);
+    }
+
+    #[test]
+    fn get_parameter_fcv_v4_0_upgrading() {
+        let params = Bson::Document(doc! {
+            "featureCompatibilityVersion": {
+                "version": "3.6",
+                "targetVersion": "4.0",
+            },
+            "ok": 1.0,
+        });
+        let params: GetParameter = bson::from_bson(params).unwrap();
+        let extras = params.extras();
+        assert_eq!(extras.get("fcv"), Some(&json!("3.6")));
+        assert_eq!(extras.get("fcv_target"), Some(&json!("4.0")));
+    }
+
+    #[test]
+    fn get_parameter_without_fcv() {
+        let params = Bson::Document(doc! {"ok": 1.0});
+        let params: GetParameter = bson::from_bson(params).unwrap();
+        assert!(params.extras().is_empty());
+    }
+
+    #[test]
+    fn chunk_distribution_extras() {
+        let result = Bson::Document(doc! {
+            "cursor": {
+                "firstBatch": [
+                    {"_id": "shard01", "chunks": 12},
+                    {"_id": "shard02", "chunks": 9},
+                    {"_id": "shard03", "chunks": 11},
+                ],
+                "id": 0_i64,
+                "ns": "config.chunks",
+            },
+            "ok": 1.0,
+        });
+        let result: ChunksAggregation = bson::from_bson(result).unwrap();
+        let extras = result.distribution().extras();
+        let expected = json!({"shard01": 12, "shard02": 9, "shard03": 11});
+        assert_eq!(extras.get("chunks_per_shard"), Some(&expected));
+    }
+
+    #[test]
+    fn server_status_storage_engine_extras() {
+        let status = Bson::Document(doc! {
+            "storageEngine": {
+                "name": "wiredTiger",
+                "persistent": true,
+            },
+            "ok": 1.0,
+        });
+        let status: ServerStatus = bson::from_bson(status).unwrap();
+        let extras = status.extras();
+        assert_eq!(extras.get("storage_engine"), Some(&json!("wiredTiger")));
+        assert_eq!(extras.get("wt_cache_used_ratio"), None);
+    }
+
+    #[test]
+    fn server_status_wired_tiger_cache_ratio() {
+        let status = Bson::Document(doc! {
+            "storageEngine": {"name": "wiredTiger"},
+            "wiredTiger": {
+                "cache": {
+                    "bytes currently in the cache": 805_306_368_i64,
+                    "maximum bytes configured": 1_073_741_824_i64,
+                    "tracked dirty bytes in the cache": 53_687_091,
+                    "pages evicted by application threads": 0,
+                },
+            },
+            "ok": 1.0,
+        });
+        let status: ServerStatus = bson::from_bson(status).unwrap();
+        let cache = status.wired_tiger_cache().unwrap();
+        assert_eq!(cache.used_ratio(), Some(0.75));
+        assert!((cache.dirty_ratio().unwrap() - 0.05).abs() < 1e-6);
+        let extras = status.extras();
+        assert_eq!(extras.get("wt_cache_used_ratio"), Some(&json!(0.75)));
+    }
+
     #[test]
     fn last_op() {
         let rs: ReplSetStatus = bson::from_bson(make_rs()).unwrap();
@@ -206,6 +509,36 @@ mod tests {
         };
     }
 
+    #[test]
+    fn election_id_and_term() {
+        let election_id = bson::oid::ObjectId::with_string("7fffffff0000000000000003").unwrap();
+        let rs = Bson::Document(doc! {
+            "set": "test-rs",
+            "term": 3_i64,
+            "members": [{
+                "_id": 0,
+                "electionId": election_id,
+                "name": "host0",
+                "optime": {
+                    "ts": MONGO_TIMESTAMP_ONE.clone(),
+                },
+                "self": true,
+                "state": 1,
+            }],
+            "myState": 1,
+        });
+        let rs: ReplSetStatus = bson::from_bson(rs).unwrap();
+        assert_eq!(rs.term, Some(3));
+        assert_eq!(rs.election_id(), Some("7fffffff0000000000000003".into()));
+    }
+
+    #[test]
+    fn election_id_and_term_optional() {
+        let rs: ReplSetStatus = bson::from_bson(make_rs()).unwrap();
+        assert_eq!(rs.term, None);
+        assert_eq!(rs.election_id(), None);
+    }
+
     #[test]
     fn primary_optime() {
         let rs: ReplSetStatus = bson::from_bson(make_rs()).unwrap();
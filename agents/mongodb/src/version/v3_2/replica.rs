@@ -7,6 +7,7 @@ use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
 use replicante_agent::Result;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
@@ -14,6 +15,8 @@ use replicante_models_agent::info::Shards;
 
 use super::common::CommonLogic;
 use crate::actions::GracefulStop;
+use crate::config::MongoDB;
+use crate::rollback::RollbackTracker;
 
 /// MongoDB 3.2+ replica set agent.
 pub struct ReplicaSet {
@@ -21,8 +24,13 @@ pub struct ReplicaSet {
 }
 
 impl ReplicaSet {
-    pub fn new(client: Client, context: AgentContext) -> ReplicaSet {
-        let common = CommonLogic::new(client, context);
+    pub fn new(
+        config: MongoDB,
+        client: Client,
+        context: AgentContext,
+        rollback: Arc<RollbackTracker>,
+    ) -> ReplicaSet {
+        let common = CommonLogic::new(config, client, context, rollback);
         ReplicaSet { common }
     }
 }
@@ -31,7 +39,10 @@ impl Agent for ReplicaSet {
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         vec![(
             ActionHook::StoreGracefulStop,
-            Arc::new(GracefulStop::new(self.common.client())),
+            Arc::new(GracefulStop::new(
+                self.common.client(),
+                self.common.config(),
+            )),
         )]
     }
 
@@ -39,21 +50,23 @@ impl Agent for ReplicaSet {
         self.common.agent_info(span)
     }
 
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        self.common.datastore_extras(span)
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
-        let info = self.common.build_info(span)?;
-        let status = self.common.repl_set_get_status(span)?;
-        let node_name = status.node_name()?;
-        let cluster = status.set;
-        Ok(DatastoreInfo::new(
-            cluster,
-            "MongoDB",
-            node_name,
-            info.version,
-            None,
-        ))
+        self.common.member_info(None, span)
+    }
+
+    fn health(&self, span: &mut Span) -> Result<()> {
+        self.common.health(span)
     }
 
     fn shards(&self, span: &mut Span) -> Result<Shards> {
         self.common.shards(span)
     }
+
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        self.common.warmup(span)
+    }
 }
@@ -1,47 +1,129 @@
 use std::sync::Arc;
+use std::time::Duration;
 
+use bson::doc;
+use bson::Bson;
+use failure::ResultExt;
 use mongodb::sync::Client;
+use opentracingrust::utils::FailSpan;
+use opentracingrust::Log;
 use opentracingrust::Span;
+use slog::warn;
 
 use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::DatastoreExtras;
 use replicante_agent::Result;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
+use replicante_util_failure::failure_info;
 
+use super::super::common::Sampled;
 use super::super::Sharding;
 use super::common::CommonLogic;
+use super::ChunkDistribution;
+use super::ChunksAggregation;
 use crate::actions::GracefulStop;
+use crate::config::MongoDB;
+use crate::error::ErrorKind;
+use crate::metrics::MONGODB_OPS_COUNT;
+use crate::metrics::MONGODB_OPS_DURATION;
+use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::rollback::RollbackTracker;
+
+/// Maximum number of shards reported in the chunks distribution.
+const CHUNKS_MAX_SHARDS: i32 = 1000;
 
 /// MongoDB 3.2+ sharded agent.
 pub struct Sharded {
+    chunks: Sampled<ChunkDistribution>,
     cluster_name: String,
     common: CommonLogic,
+    context: AgentContext,
     is_mongos: bool,
     mongos_node_name: Option<String>,
 }
 
 impl Sharded {
-    pub fn new(sharding: Sharding, client: Client, context: AgentContext) -> Sharded {
-        let common = CommonLogic::new(client, context);
+    pub fn new(
+        config: MongoDB,
+        sharding: Sharding,
+        client: Client,
+        context: AgentContext,
+        rollback: Arc<RollbackTracker>,
+    ) -> Sharded {
+        let interval = Duration::from_secs(config.expensive_metrics_interval);
+        let common = CommonLogic::new(config, client, context.clone(), rollback);
         let is_mongos = sharding.mongos_node_name.is_some();
         Sharded {
+            chunks: Sampled::new(interval),
             cluster_name: sharding.cluster_name,
             common,
+            context,
             is_mongos,
             mongos_node_name: sharding.mongos_node_name,
         }
     }
+
+    /// Returns the number of chunks on each shard, refreshed at most once per
+    /// `expensive_metrics_interval`.
+    fn chunks(&self, parent: &mut Span) -> Result<ChunkDistribution> {
+        self.chunks.get(|| self.chunks_aggregate(parent))
+    }
+
+    /// Aggregates the `config.chunks` collection to count chunks by shard.
+    fn chunks_aggregate(&self, parent: &mut Span) -> Result<ChunkDistribution> {
+        let mut span = self.context.tracer.span("chunksByShard").auto_finish();
+        span.child_of(parent.context().clone());
+        span.log(Log::new().log("span.kind", "client-send"));
+        MONGODB_OPS_COUNT
+            .with_label_values(&["chunksByShard"])
+            .inc();
+        let timer = MONGODB_OPS_DURATION
+            .with_label_values(&["chunksByShard"])
+            .start_timer();
+        let command = doc! {
+            "aggregate": "chunks",
+            "pipeline": [
+                {"$group": {"_id": "$shard", "chunks": {"$sum": 1}}},
+                {"$sort": {"_id": 1}},
+                {"$limit": CHUNKS_MAX_SHARDS},
+            ],
+            "cursor": {"batchSize": CHUNKS_MAX_SHARDS},
+        };
+        let command = self.common.read_command(command)?;
+        let result = self
+            .common
+            .client()
+            .database("config")
+            .run_command(command, None)
+            .fail_span(&mut span)
+            .map_err(|error| {
+                MONGODB_OP_ERRORS_COUNT
+                    .with_label_values(&["chunksByShard"])
+                    .inc();
+                error
+            })
+            .with_context(|_| ErrorKind::StoreOpFailed("chunksByShard"))?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        let result: ChunksAggregation = bson::from_bson(Bson::Document(result))
+            .with_context(|_| ErrorKind::BsonDecode("chunksByShard"))?;
+        Ok(result.distribution())
+    }
 }
 
 impl Agent for Sharded {
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         vec![(
             ActionHook::StoreGracefulStop,
-            Arc::new(GracefulStop::new(self.common.client())),
+            Arc::new(GracefulStop::new(
+                self.common.client(),
+                self.common.config(),
+            )),
         )]
     }
 
@@ -49,10 +131,25 @@ impl Agent for Sharded {
         self.common.agent_info(span)
     }
 
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        let mut extras = self.common.datastore_extras(span)?;
+        if self.is_mongos {
+            match self.chunks(span) {
+                Ok(chunks) => extras.extend(chunks.extras()),
+                Err(error) => warn!(
+                    self.context.logger,
+                    "Failed to fetch MongoDB chunks distribution";
+                    failure_info(&error),
+                ),
+            };
+        }
+        Ok(extras)
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
-        let info = self.common.build_info(span)?;
         let cluster = self.cluster_name.clone();
         if self.is_mongos {
+            let info = self.common.build_info(span)?;
             let node_name = self.mongos_node_name.as_ref().unwrap().clone();
             Ok(DatastoreInfo::new(
                 cluster,
@@ -62,18 +159,14 @@ impl Agent for Sharded {
                 None,
             ))
         } else {
-            let status = self.common.repl_set_get_status(span)?;
-            let node_name = status.node_name()?;
-            Ok(DatastoreInfo::new(
-                cluster,
-                "MongoDB",
-                node_name,
-                info.version,
-                None,
-            ))
+            self.common.member_info(Some(cluster), span)
         }
     }
 
+    fn health(&self, span: &mut Span) -> Result<()> {
+        self.common.health(span)
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
         if self.is_mongos {
             Ok(Shards::new(Vec::new()))
@@ -81,4 +174,8 @@ impl Agent for Sharded {
             self.common.shards(span)
         }
     }
+
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        self.common.warmup(span)
+    }
 }
@@ -0,0 +1,183 @@
+use std::process::Command;
+
+use failure::ResultExt;
+use opentracingrust::Span;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::SemVersion;
+use replicante_agent::Transaction;
+
+use crate::agent::to_semver;
+use crate::error::ErrorKind;
+use crate::zk4lw::Client;
+use crate::zk4lw::Srvr;
+
+/// Kind of the `ForceElection` action.
+const KIND: &str = "replicante.zookeeper/force_election";
+
+/// Have the leader of the ensemble relinquish leadership to trigger an election.
+///
+/// Leaders only step down when they are reconfigured, so relinquishing leadership is
+/// delegated to the `zookeeper.force_election_command` configured by the operator
+/// (usually a `reconfig` of the local server). Dynamic reconfiguration was introduced
+/// in Zookeeper 3.5.0 and the action is not available on earlier versions:
+///
+///   1. When first invoked, the node is checked to be the leader, the command is
+///      executed and the mode before the election is recorded.
+///   2. On later invocations the node mode is checked and, once the server answers
+///      again, the action completes recording the mode after the election.
+pub struct ForceElection {
+    client: Client,
+    command: Vec<String>,
+}
+
+impl ForceElection {
+    pub fn new(client: Client, command: Vec<String>) -> ForceElection {
+        ForceElection { client, command }
+    }
+
+    /// Run the force election command.
+    fn exec(&self, record: &dyn ActionRecordView) -> Result<()> {
+        let action_id = ActionRecordView::id(record);
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .output()
+            .with_context(|_| BaseKind::ExternalActionStart(KIND.into(), action_id))?;
+        if !output.status.success() {
+            let stdout =
+                String::from_utf8(output.stdout).unwrap_or_else(|_| "{binary blob}".to_string());
+            let stderr =
+                String::from_utf8(output.stderr).unwrap_or_else(|_| "{binary blob}".to_string());
+            let error = BaseKind::ExternalActionExec(action_id, stdout, stderr);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+}
+
+impl Action for ForceElection {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: KIND.into(),
+            description: "Have the ensemble leader relinquish leadership".into(),
+        }
+    }
+
+    fn idempotent(&self) -> bool {
+        // A replayed command would reconfigure the newly elected leader.
+        false
+    }
+
+    fn singleton(&self) -> bool {
+        // Only one election can happen in the ensemble at a time.
+        true
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let span = span.map(|span| span.context().clone());
+        match record.state() {
+            ActionState::New => {
+                let srvr = self.client.exec::<Srvr>()?;
+                ensure_supported(&srvr.zk_version)?;
+                ensure_leader(&srvr.zk_mode)?;
+                self.exec(record)?;
+                let payload = json!({"mode_before": srvr.zk_mode});
+                tx.action()
+                    .transition(record, ActionState::Running, payload, span)
+            }
+            _ => {
+                // Servers do not answer while the election is in progress.
+                let srvr = match self.client.exec::<Srvr>() {
+                    Ok(srvr) => srvr,
+                    Err(_) => return Ok(()),
+                };
+                let payload = election_payload(record.state_payload().as_ref(), &srvr.zk_mode);
+                tx.action()
+                    .transition(record, ActionState::Done, payload, span)
+            }
+        }
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Build the payload of a completed election from the mode recorded before it.
+fn election_payload(before: Option<&Json>, mode_after: &str) -> Json {
+    let mode_before = before
+        .and_then(|before| before.get("mode_before"))
+        .cloned()
+        .unwrap_or(Json::Null);
+    json!({
+        "mode_after": mode_after,
+        "mode_before": mode_before,
+    })
+}
+
+/// Ensure the `srvr` mode reports the node as the ensemble leader.
+fn ensure_leader(mode: &str) -> Result<()> {
+    if mode != "leader" {
+        return Err(ErrorKind::NotLeader(mode.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Ensure the server supports dynamic reconfiguration (Zookeeper 3.5.0 and later).
+fn ensure_supported(version: &str) -> Result<()> {
+    let version = to_semver(version)?;
+    let version = SemVersion::parse(&version).with_context(|_| ErrorKind::VersionParse)?;
+    if version < SemVersion::new(3, 5, 0) {
+        return Err(BaseKind::ActionNotAvailable(KIND.into()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Fail;
+    use serde_json::json;
+
+    use super::election_payload;
+    use super::ensure_leader;
+    use super::ensure_supported;
+
+    #[test]
+    fn election_modes_recorded() {
+        let before = json!({"mode_before": "leader"});
+        let payload = election_payload(Some(&before), "follower");
+        assert_eq!(
+            payload,
+            json!({"mode_after": "follower", "mode_before": "leader"}),
+        );
+    }
+
+    #[test]
+    fn follower_refused() {
+        let error = ensure_leader("follower").unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        let error = ensure_leader("standalone").unwrap_err();
+        assert_eq!(error.name().unwrap(), "InvalidStoreState");
+        ensure_leader("leader").unwrap();
+    }
+
+    #[test]
+    fn unsupported_version_not_available() {
+        let error = ensure_supported("3.4.14-4c25d480, built on 03/06/2019").unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotAvailable");
+        ensure_supported("3.5.7-f0fdd529, built on 02/10/2020").unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::Result;
+
+use crate::config::Zookeeper;
+use crate::error::ErrorKind;
+use crate::zk4lw::Client;
+
+mod force_election;
+mod read_only;
+
+pub use self::force_election::ForceElection;
+pub use self::read_only::ReadOnly;
+
+/// Register Zookeeper specific actions.
+///
+/// The force election action is only registered when a `force_election_command` is configured.
+pub fn register(config: &Zookeeper) -> Result<()> {
+    let client = Client::new(config.target.clone(), config.fourlw_timeout());
+    ACTIONS::register(ReadOnly::new(client.clone()));
+    if let Some(command) = config.force_election_command.as_ref() {
+        if command.is_empty() {
+            let message = "empty command for zookeeper.force_election_command".into();
+            return Err(ErrorKind::Initialisation(message).into());
+        }
+        ACTIONS::register(ForceElection::new(client, command.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use failure::ResultExt;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::SemVersion;
+use replicante_agent::Transaction;
+
+use crate::agent::to_semver;
+use crate::error::ErrorKind;
+use crate::zk4lw::Client;
+use crate::zk4lw::Srvr;
+
+/// Kind of the `ReadOnly` action.
+const KIND: &str = "replicante.zookeeper/read_only";
+
+/// Mode reported by `srvr` for servers in read-only mode.
+const READ_ONLY_MODE: &str = "read-only";
+
+/// Set the read-only mode of the local server and report the resulting mode.
+///
+/// Read-only mode was introduced in Zookeeper 3.4.0 but it is only entered by servers
+/// started with `readonlymode.enabled` that lose quorum: no release can switch it on request.
+/// The action therefore succeeds, reporting the mode, if the server already is in the
+/// requested mode and is rejected as not available otherwise.
+pub struct ReadOnly {
+    client: Client,
+}
+
+impl ReadOnly {
+    pub fn new(client: Client) -> ReadOnly {
+        ReadOnly { client }
+    }
+}
+
+impl Action for ReadOnly {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: KIND.into(),
+            description: "Toggle the read-only mode of the Zookeeper server".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args = validate_action_args::<ReadOnlyArgs>(record.args().clone())
+            .with_context(|_| BaseKind::ActionDecode)?;
+        let srvr = self.client.exec::<Srvr>()?;
+        let payload = read_only_mode(&srvr.zk_version, &srvr.zk_mode, args.read_only)?;
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<ReadOnlyArgs>(args.clone()).map(|_| ())
+    }
+}
+
+/// Arguments accepted by the `ReadOnly` action.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadOnlyArgs {
+    read_only: bool,
+}
+
+/// Check the server can be in the requested mode and report the resulting mode.
+fn read_only_mode(version: &str, mode: &str, read_only: bool) -> Result<Json> {
+    let version = to_semver(version)?;
+    let version = SemVersion::parse(&version).with_context(|_| ErrorKind::VersionParse)?;
+    if version < SemVersion::new(3, 4, 0) {
+        return Err(BaseKind::ActionNotAvailable(KIND.into()).into());
+    }
+    if (mode == READ_ONLY_MODE) != read_only {
+        return Err(BaseKind::ActionNotAvailable(KIND.into()).into());
+    }
+    Ok(json!({
+        "mode": mode,
+        "read_only": read_only,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use failure::Fail;
+    use serde_json::json;
+
+    use replicante_agent::actions::Action;
+
+    use super::read_only_mode;
+    use super::ReadOnly;
+    use crate::zk4lw::Client;
+
+    const VERSION: &str = "3.5.7-f0fdd52973d373ffd9c86b81d99842dc2c7f660e, built on 02/10/2020";
+
+    #[test]
+    fn args_require_boolean() {
+        let client = Client::new("localhost:2181".into(), Duration::from_secs(1));
+        let action = ReadOnly::new(client);
+        action.validate_args(&json!({"read_only": true})).unwrap();
+        action.validate_args(&json!({"read_only": false})).unwrap();
+        assert!(action.validate_args(&json!({"read_only": "yes"})).is_err());
+        assert!(action.validate_args(&json!({"read_only": 1})).is_err());
+        assert!(action.validate_args(&json!({})).is_err());
+        assert!(action
+            .validate_args(&json!({"read_only": true, "other": 1}))
+            .is_err());
+    }
+
+    #[test]
+    fn unsupported_version_not_available() {
+        let error =
+            read_only_mode("3.3.6-1366786, built on 07/29/2012", "follower", false).unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotAvailable");
+    }
+
+    #[test]
+    fn mode_change_not_available() {
+        let error = read_only_mode(VERSION, "follower", true).unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotAvailable");
+        let error = read_only_mode(VERSION, "read-only", false).unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotAvailable");
+    }
+
+    #[test]
+    fn current_mode_reported() {
+        let payload = read_only_mode(VERSION, "read-only", true).unwrap();
+        assert_eq!(payload, json!({"mode": "read-only", "read_only": true}));
+        let payload = read_only_mode(VERSION, "leader", false).unwrap();
+        assert_eq!(payload, json!({"mode": "leader", "read_only": false}));
+    }
+}
@@ -1,14 +1,14 @@
-use failure::ResultExt;
 use lazy_static::lazy_static;
 use opentracingrust::Log;
 use opentracingrust::Span;
 use opentracingrust::StartOptions;
-use zk_4lw::Client;
+use slog::debug;
 use zk_4lw::FourLetterWord;
 
 use replicante_agent::fail_span;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::RequestBudget;
 use replicante_agent::Result;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::AgentVersion;
@@ -17,14 +17,27 @@ use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shard;
 use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
+use replicante_util_failure::failure_info;
 
 use super::error::ErrorKind;
+use super::metrics::CONNECTION_COUNT;
 use super::metrics::OPS_COUNT;
 use super::metrics::OPS_DURATION;
 use super::metrics::OP_ERRORS_COUNT;
+use super::metrics::WATCH_COUNT;
+use super::zk4lw::Client;
 use super::zk4lw::Conf;
+use super::zk4lw::Cons;
+use super::zk4lw::Ruok;
 use super::zk4lw::Srvr;
+use super::zk4lw::SrvrResponse;
+use super::zk4lw::Stat;
+use super::zk4lw::Wchs;
 use super::Config;
+use crate::config::StatusCommand;
+
+/// Health probes supported by the Zookeeper agent, cheapest first.
+pub const HEALTH_PROBES: &[&str] = &["ruok", "srvr"];
 
 lazy_static! {
     pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
@@ -37,7 +50,7 @@ lazy_static! {
 /// Converts a Zookeeper version into a Semver compatible string.
 ///
 /// In particular it reformats the commit hash as metadata.
-fn to_semver(version: &str) -> Result<String> {
+pub fn to_semver(version: &str) -> Result<String> {
     let ver = version
         .split(',')
         .next()
@@ -50,19 +63,38 @@ fn to_semver(version: &str) -> Result<String> {
     }
 }
 
+/// Build the shard reported by the node from its `srvr` (or `stat`) summary.
+///
+/// Observers do not vote on commits so their zxid is reported with a distinct
+/// `observer-zxid` unit: it is informational and not comparable with voters' offsets
+/// to avoid false replication lag reports.
+fn shard(cluster: String, srvr: &SrvrResponse) -> Shard {
+    let (role, unit) = match srvr.zk_mode.as_ref() {
+        "leader" => (ShardRole::Primary, "zxid"),
+        "follower" => (ShardRole::Secondary, "zxid"),
+        "observer" => (ShardRole::Unknown("observer".into()), "observer-zxid"),
+        unkown => (ShardRole::Unknown(unkown.into()), "zxid"),
+    };
+    let commit_offset = CommitOffset::unit(srvr.zk_zxid, unit);
+    Shard::new(cluster, role, Some(commit_offset), None)
+}
+
 /// Zookeeper 3.3+ agent.
 pub struct ZookeeperAgent {
     agent_context: AgentContext,
     cluster_name: String,
+    status_command: StatusCommand,
     zk_client: Client,
 }
 
 impl ZookeeperAgent {
     pub fn new(config: Config, context: AgentContext) -> ZookeeperAgent {
+        let timeout = config.zookeeper.fourlw_timeout();
         ZookeeperAgent {
             agent_context: context,
             cluster_name: config.zookeeper.cluster,
-            zk_client: Client::new(config.zookeeper.target),
+            status_command: config.zookeeper.command,
+            zk_client: Client::new(config.zookeeper.target, timeout),
         }
     }
 
@@ -79,21 +111,88 @@ impl ZookeeperAgent {
         span.log(Log::new().log("span.kind", "client-send"));
         OPS_COUNT.with_label_values(&["conf"]).inc();
         let timer = OPS_DURATION.with_label_values(&["conf"]).start_timer();
-        let conf = self
-            .zk_client
-            .exec::<Conf>()
-            .map_err(|error| {
-                OP_ERRORS_COUNT.with_label_values(&["conf"]).inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("conf"))?;
+        let conf = self.zk_client.exec::<Conf>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["conf"]).inc();
+            fail_span(error, &mut *span)
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
         Ok(conf)
     }
 
-    /// Executes the "conf" 4lw against the zookeeper server.
-    fn srvr(&self, root: &Span) -> Result<<Srvr as FourLetterWord>::Response> {
+    /// Refresh the connections and watches gauges.
+    ///
+    /// Failures are logged and ignored as these metrics are only diagnostic aids.
+    /// Collection is skipped, without flagging the response as incomplete,
+    /// when the request budget runs out.
+    fn connection_metrics(&self, span: &Span) {
+        if !RequestBudget::spend_optional() {
+            return;
+        }
+        match self.cons(span) {
+            Ok(cons) => CONNECTION_COUNT.set(cons.zk_connections as f64),
+            Err(error) => debug!(
+                self.agent_context.logger,
+                "Failed to collect Zookeeper connections";
+                failure_info(&error),
+            ),
+        };
+        match self.wchs(span) {
+            Ok(wchs) => WATCH_COUNT.set(wchs.zk_watches as f64),
+            Err(error) => debug!(
+                self.agent_context.logger,
+                "Failed to collect Zookeeper watches";
+                failure_info(&error),
+            ),
+        };
+    }
+
+    /// Executes the "cons" 4lw against the zookeeper server.
+    fn cons(&self, root: &Span) -> Result<<Cons as FourLetterWord>::Response> {
+        let mut span = self
+            .agent_context
+            .tracer
+            .span_with_options(
+                "cons",
+                StartOptions::default().child_of(root.context().clone()),
+            )
+            .auto_finish();
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&["cons"]).inc();
+        let timer = OPS_DURATION.with_label_values(&["cons"]).start_timer();
+        let cons = self.zk_client.exec::<Cons>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["cons"]).inc();
+            fail_span(error, &mut *span)
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(cons)
+    }
+
+    /// Executes the "ruok" 4lw against the zookeeper server.
+    fn ruok(&self, root: &Span) -> Result<()> {
+        let mut span = self
+            .agent_context
+            .tracer
+            .span_with_options(
+                "ruok",
+                StartOptions::default().child_of(root.context().clone()),
+            )
+            .auto_finish();
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&["ruok"]).inc();
+        let timer = OPS_DURATION.with_label_values(&["ruok"]).start_timer();
+        self.zk_client.exec::<Ruok>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["ruok"]).inc();
+            fail_span(error, &mut *span)
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(())
+    }
+
+    /// Executes the "srvr" 4lw against the zookeeper server.
+    fn srvr(&self, root: &Span) -> Result<SrvrResponse> {
         let mut span = self
             .agent_context
             .tracer
@@ -105,18 +204,66 @@ impl ZookeeperAgent {
         span.log(Log::new().log("span.kind", "client-send"));
         OPS_COUNT.with_label_values(&["srvr"]).inc();
         let timer = OPS_DURATION.with_label_values(&["srvr"]).start_timer();
-        let srvr = self
-            .zk_client
-            .exec::<Srvr>()
-            .map_err(|error| {
-                OP_ERRORS_COUNT.with_label_values(&["srvr"]).inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("srvr"))?;
+        let srvr = self.zk_client.exec::<Srvr>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["srvr"]).inc();
+            fail_span(error, &mut *span)
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
         Ok(srvr)
     }
+
+    /// Executes the "stat" 4lw against the zookeeper server.
+    fn stat(&self, root: &Span) -> Result<<Stat as FourLetterWord>::Response> {
+        let mut span = self
+            .agent_context
+            .tracer
+            .span_with_options(
+                "stat",
+                StartOptions::default().child_of(root.context().clone()),
+            )
+            .auto_finish();
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&["stat"]).inc();
+        let timer = OPS_DURATION.with_label_values(&["stat"]).start_timer();
+        let stat = self.zk_client.exec::<Stat>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["stat"]).inc();
+            fail_span(error, &mut *span)
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(stat)
+    }
+
+    /// Fetch the server status summary with the configured 4lw.
+    fn status(&self, root: &Span) -> Result<SrvrResponse> {
+        match self.status_command {
+            StatusCommand::Srvr => self.srvr(root),
+            StatusCommand::Stat => self.stat(root).map(|stat| stat.summary),
+        }
+    }
+
+    /// Executes the "wchs" 4lw against the zookeeper server.
+    fn wchs(&self, root: &Span) -> Result<<Wchs as FourLetterWord>::Response> {
+        let mut span = self
+            .agent_context
+            .tracer
+            .span_with_options(
+                "wchs",
+                StartOptions::default().child_of(root.context().clone()),
+            )
+            .auto_finish();
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&["wchs"]).inc();
+        let timer = OPS_DURATION.with_label_values(&["wchs"]).start_timer();
+        let wchs = self.zk_client.exec::<Wchs>().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&["wchs"]).inc();
+            fail_span(error, &mut *span)
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(wchs)
+    }
 }
 
 impl Agent for ZookeeperAgent {
@@ -127,29 +274,72 @@ impl Agent for ZookeeperAgent {
 
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let name = self.conf(span)?.zk_server_id;
-        let version = to_semver(&self.srvr(span)?.zk_version)?;
+        let version = to_semver(&self.status(span)?.zk_version)?;
         let info = DatastoreInfo::new(self.cluster_name.clone(), "Zookeeper", name, version, None);
         Ok(info)
     }
 
+    fn health(&self, span: &mut Span) -> Result<()> {
+        match self.agent_context.config.health.probe(HEALTH_PROBES)? {
+            "srvr" => self.srvr(span).map(|_| ()),
+            _ => self.ruok(span),
+        }
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
-        let srvr = self.srvr(span)?;
-        let role = match srvr.zk_mode.as_ref() {
-            "leader" => ShardRole::Primary,
-            "follower" => ShardRole::Secondary,
-            unkown => ShardRole::Unknown(unkown.into()),
-        };
-        let commit_offset = CommitOffset::unit(srvr.zk_zxid, "zxid");
-        let commit_offset = Some(commit_offset);
-        let shard = Shard::new(self.cluster_name.clone(), role, commit_offset, None);
+        let status = self.status(span)?;
+        let shard = shard(self.cluster_name.clone(), &status);
         let shards = Shards::new(vec![shard]);
+        self.connection_metrics(span);
         Ok(shards)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use replicante_models_agent::info::CommitOffset;
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+
+    use super::shard;
     use super::to_semver;
+    use crate::zk4lw::Srvr;
+
+    fn srvr(mode: &str) -> <Srvr as FourLetterWord>::Response {
+        let response = format!(
+            r#"Zookeeper version: 3.5.7-f0fdd52973d373ffd9c86b81d99842dc2c7f660e, built on 02/10/2020 11:30 GMT
+Latency min/avg/max: 0/0.0/0
+Received: 3
+Sent: 2
+Connections: 1
+Outstanding: 0
+Zxid: 0x200000002
+Mode: {}
+Node count: 5
+"#,
+            mode
+        );
+        Srvr::parse_response(&response).unwrap()
+    }
+
+    #[test]
+    fn shard_for_follower() {
+        let shard = shard("test".into(), &srvr("follower"));
+        let offset = CommitOffset::unit(0x2_0000_0002, "zxid");
+        let expected = Shard::new("test".into(), ShardRole::Secondary, Some(offset), None);
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn shard_for_observer() {
+        let shard = shard("test".into(), &srvr("observer"));
+        let role = ShardRole::Unknown("observer".into());
+        let offset = CommitOffset::unit(0x2_0000_0002, "observer-zxid");
+        let expected = Shard::new("test".into(), role, Some(offset), None);
+        assert_eq!(shard, expected);
+    }
 
     #[test]
     fn conver_to_semver() {
@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 
 use failure::ResultExt;
 use serde_derive::Deserialize;
@@ -64,15 +65,62 @@ pub struct Zookeeper {
     /// Name of the zookeeper cluster.
     pub cluster: String,
 
+    /// Four letter word command used to inspect the server status.
+    #[serde(default)]
+    pub command: StatusCommand,
+
+    /// Command that makes the local server relinquish leadership (optional).
+    ///
+    /// Enables the `replicante.zookeeper/force_election` action when set.
+    #[serde(default)]
+    pub force_election_command: Option<Vec<String>>,
+
+    /// Seconds to wait for the 4lw server to accept connections and for each read and write.
+    #[serde(default = "Zookeeper::default_fourlw_timeout")]
+    pub fourlw_timeout: u64,
+
     /// Host and port (in host:port format) of the zookeeper 4lw server.
     #[serde(default = "Zookeeper::default_target")]
     pub target: String,
 }
 
 impl Zookeeper {
+    pub fn default_fourlw_timeout() -> u64 {
+        10
+    }
+
     pub fn default_target() -> String {
         "localhost:2181".into()
     }
+
+    /// Timeout applied to 4lw requests.
+    pub fn fourlw_timeout(&self) -> Duration {
+        Duration::from_secs(self.fourlw_timeout)
+    }
+
+    /// Check options that can't be validated by the type system.
+    pub fn validate(&self) -> Result<()> {
+        if self.fourlw_timeout == 0 {
+            return Err(ErrorKind::ConfigOption("zookeeper.fourlw_timeout").into());
+        }
+        Ok(())
+    }
+}
+
+/// Four letter word commands the agent can inspect the server status with.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum StatusCommand {
+    #[serde(rename = "srvr")]
+    Srvr,
+
+    #[serde(rename = "stat")]
+    Stat,
+}
+
+impl Default for StatusCommand {
+    fn default() -> StatusCommand {
+        StatusCommand::Srvr
+    }
 }
 
 #[cfg(test)]
@@ -80,6 +128,7 @@ mod tests {
     use std::io::Cursor;
 
     use super::Config;
+    use super::StatusCommand;
 
     #[test]
     #[should_panic(expected = "invalid type: string")]
@@ -93,4 +142,20 @@ mod tests {
         let cursor = Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test}}");
         Config::from_reader(cursor).unwrap();
     }
+
+    #[test]
+    fn status_command_stat() {
+        let cursor =
+            Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test, command: stat}}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert_eq!(config.zookeeper.command, StatusCommand::Stat);
+    }
+
+    #[test]
+    fn zero_fourlw_timeout_rejected() {
+        let cursor =
+            Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test, fourlw_timeout: 0}}");
+        let config = Config::from_reader(cursor).unwrap();
+        assert!(config.zookeeper.validate().is_err());
+    }
 }
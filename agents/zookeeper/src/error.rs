@@ -12,12 +12,18 @@ pub enum ErrorKind {
     /// Alias for `ConfigOption`.
     ConfigOption(&'static str),
 
+    /// Zookeeper specifc `Connection`.
+    Connection(String),
+
     /// Alias for `Initialisation`.
     Initialisation(String),
 
     /// Alias for `Io`.
     Io(String),
 
+    /// `InvalidStoreState` caused by an operation that requires the node to be the leader.
+    NotLeader(String),
+
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
@@ -36,8 +42,13 @@ impl From<ErrorKind> for BaseKind {
         match error {
             ErrorKind::ConfigLoad => BaseKind::ConfigLoad,
             ErrorKind::ConfigOption(option) => BaseKind::ConfigOption(option),
+            ErrorKind::Connection(target) => BaseKind::Connection("zookeeper", target),
             ErrorKind::Initialisation(message) => BaseKind::Initialisation(message),
             ErrorKind::Io(path) => BaseKind::Io(path),
+            ErrorKind::NotLeader(mode) => BaseKind::InvalidStoreState(format!(
+                "operation requires the leader node, node is {}",
+                mode
+            )),
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
             ErrorKind::VersionParse => BaseKind::ResponseDecode("text", "version"),
         }
@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use replicante_agent::Result;
 use replicante_agent::SemVersion;
 
+mod actions;
 mod agent;
 mod config;
 mod error;
@@ -44,12 +45,15 @@ pub fn run() -> Result<bool> {
     let config_location = cli_args.value_of("config").unwrap();
     let config = Config::from_file(config_location)?;
     let config = config.transform();
+    config.agent.health.probe(agent::HEALTH_PROBES)?;
+    config.zookeeper.validate()?;
 
     // Run the agent using the provided default helper.
     let agent_conf = config.agent.clone();
     let release = RELEASE.as_str();
     replicante_agent::process::run(agent_conf, "repliagent-zookeeper", release, |context, _| {
         metrics::register_metrics(context);
+        actions::register(&config.zookeeper)?;
         let agent = ZookeeperAgent::new(config, context.clone());
         replicante_agent::process::update_checker(CURRENT_VERSION.clone(), UPDATE_META, context)?;
         Ok(agent)
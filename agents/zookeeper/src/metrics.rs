@@ -1,37 +1,56 @@
 use lazy_static::lazy_static;
 use prometheus::CounterVec;
-use prometheus::HistogramOpts;
+use prometheus::Gauge;
 use prometheus::HistogramVec;
-use prometheus::Opts;
-use slog::debug;
 
+use replicante_agent::register_collector;
 use replicante_agent::AgentContext;
+use replicante_agent::MetricOpts;
 
 lazy_static! {
+    pub static ref CONNECTION_COUNT: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_zookeeper_connection_count",
+            "Number of client connections to the Zookeeper server"
+        )
+        .opts()
+    )
+    .expect("Failed to create CONNECTION_COUNT gauge");
     pub static ref OP_ERRORS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_zookeeper_operation_errors",
             "Number of Zookeeper operations failed"
-        ),
+        )
+        .opts(),
         &["operation"]
     )
     .expect("Failed to create OP_ERRORS_COUNT counter");
     pub static ref OPS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
+        MetricOpts::new(
             "repliagent_zookeeper_operations",
             "Number of Zookeeper operations issued"
-        ),
+        )
+        .opts(),
         &["operation"]
     )
     .expect("Failed to create OPS_COUNT counter");
     pub static ref OPS_DURATION: HistogramVec = HistogramVec::new(
-        HistogramOpts::new(
+        MetricOpts::new(
             "repliagent_zookeeper_operations_duration",
             "Duration (in seconds) of Zookeeper operations"
-        ),
+        )
+        .histogram_opts(),
         &["operation"]
     )
     .expect("Failed to create OPS_DURATION histogram");
+    pub static ref WATCH_COUNT: Gauge = Gauge::with_opts(
+        MetricOpts::new(
+            "repliagent_zookeeper_watch_count",
+            "Number of watches set by clients on the Zookeeper server"
+        )
+        .opts()
+    )
+    .expect("Failed to create WATCH_COUNT gauge");
 }
 
 /// Attemps to register metrics with the Repositoy.
@@ -40,13 +59,29 @@ lazy_static! {
 pub fn register_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let registry = &context.metrics;
-    if let Err(error) = registry.register(Box::new(OPS_COUNT.clone())) {
-        debug!(logger, "Failed to register OPS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(OP_ERRORS_COUNT.clone())) {
-        debug!(logger, "Failed to register OP_ERRORS_COUNT"; "error" => ?error);
-    }
-    if let Err(error) = registry.register(Box::new(OPS_DURATION.clone())) {
-        debug!(logger, "Failed to register OPS_DURATION"; "error" => ?error);
-    }
+    register_collector(
+        logger,
+        registry,
+        "CONNECTION_COUNT",
+        Box::new(CONNECTION_COUNT.clone()),
+    );
+    register_collector(logger, registry, "OPS_COUNT", Box::new(OPS_COUNT.clone()));
+    register_collector(
+        logger,
+        registry,
+        "OP_ERRORS_COUNT",
+        Box::new(OP_ERRORS_COUNT.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "OPS_DURATION",
+        Box::new(OPS_DURATION.clone()),
+    );
+    register_collector(
+        logger,
+        registry,
+        "WATCH_COUNT",
+        Box::new(WATCH_COUNT.clone()),
+    );
 }
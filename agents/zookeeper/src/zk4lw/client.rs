@@ -0,0 +1,104 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use failure::ResultExt;
+use zk_4lw::FourLetterWord;
+
+use replicante_agent::Result;
+
+use crate::error::ErrorKind;
+
+/// Issue four letter word commands to a Zookeeper server.
+///
+/// Unlike `zk_4lw::Client`, connecting to the server and each read and write on the
+/// socket are subject to a timeout so a hung server can't block the agent.
+#[derive(Clone, Debug)]
+pub struct Client {
+    target: String,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(target: String, timeout: Duration) -> Client {
+        Client { target, timeout }
+    }
+
+    /// Send the 4lw command to the server and parse its response.
+    ///
+    /// Network errors, timeouts included, are reported as `Connection` errors.
+    pub fn exec<F: FourLetterWord>(&self) -> Result<F::Response> {
+        let response = self
+            .send(F::command())
+            .with_context(|_| ErrorKind::Connection(self.target.clone()))?;
+        let response = F::parse_response(&response)
+            .with_context(|_| ErrorKind::StoreOpFailed(F::command()))?;
+        Ok(response)
+    }
+
+    fn send(&self, command: &str) -> io::Result<String> {
+        let mut error = None;
+        for address in self.target.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => return self.request(stream, command),
+                Err(connect) => error = Some(connect),
+            }
+        }
+        let error = error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "target resolved to no addresses")
+        });
+        Err(error)
+    }
+
+    fn request(&self, mut stream: TcpStream, command: &str) -> io::Result<String> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut response = String::new();
+        stream
+            .write_all(command.as_bytes())
+            .and_then(|_| stream.read_to_string(&mut response))
+            .map_err(|error| match error.kind() {
+                // Socket timeouts are reported as `WouldBlock` on some platforms.
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("4lw request timed out after {:?}", self.timeout),
+                ),
+                _ => error,
+            })?;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    use failure::Fail;
+
+    use super::Client;
+    use crate::zk4lw::Ruok;
+
+    #[test]
+    fn unresponsive_server_times_out() {
+        // The listener accepts connections at the OS level but never responds.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let client = Client::new(target, Duration::from_millis(100));
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            sender.send(client.exec::<Ruok>()).unwrap();
+        });
+        let result = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("4lw request blocked past its timeout");
+        let error = result.unwrap_err();
+        assert_eq!(error.name().unwrap(), "Connection");
+        drop(listener);
+    }
+}
@@ -0,0 +1,53 @@
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+/// The "cons" command
+pub struct Cons;
+
+impl FourLetterWord for Cons {
+    type Response = Response;
+    fn command() -> &'static str {
+        "cons"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        // Busy servers can list a very large number of connections so lines are
+        // counted as they are scanned and no per-connection details are retained.
+        let zk_connections = response
+            .lines()
+            .filter(|line| line.trim_start().starts_with('/'))
+            .count() as u64;
+        Ok(Response { zk_connections })
+    }
+}
+
+/// Sub-set of the "cons" response the agent needs.
+pub struct Response {
+    pub zk_connections: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Cons;
+
+    #[test]
+    fn parse_valid_response() {
+        let response = Cons::parse_response(
+            r#" /172.17.0.1:53422[1](queued=0,recved=120,sent=121,sid=0x100007c5c6b0000,lop=PING,est=1591800000000,to=30000,lcxid=0x2,lzxid=0x600000004,lresp=1591800120000,llat=0,minlat=0,avglat=0,maxlat=3)
+ /172.17.0.5:41234[1](queued=0,recved=4,sent=4,sid=0x100007c5c6b0001,lop=GETD,est=1591800060000,to=30000,lcxid=0x1,lzxid=0x600000004,lresp=1591800061000,llat=1,minlat=0,avglat=0,maxlat=1)
+ /127.0.0.1:53530[0](queued=0,recved=1,sent=0)
+
+"#,
+        )
+        .unwrap();
+        assert_eq!(response.zk_connections, 3);
+    }
+
+    #[test]
+    fn parse_empty() {
+        let response = Cons::parse_response("").unwrap();
+        assert_eq!(response.zk_connections, 0);
+    }
+}
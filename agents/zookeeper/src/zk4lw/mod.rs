@@ -1,5 +1,16 @@
+mod client;
 mod conf;
+mod cons;
+mod ruok;
 mod srvr;
+mod stat;
+mod wchs;
 
+pub use self::client::Client;
 pub use self::conf::Conf;
+pub use self::cons::Cons;
+pub use self::ruok::Ruok;
+pub use self::srvr::Response as SrvrResponse;
 pub use self::srvr::Srvr;
+pub use self::stat::Stat;
+pub use self::wchs::Wchs;
@@ -0,0 +1,38 @@
+use zk_4lw::Error;
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+/// The "ruok" command
+pub struct Ruok;
+
+impl FourLetterWord for Ruok {
+    type Response = ();
+    fn command() -> &'static str {
+        "ruok"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        // Servers that are not running in a healthy state do not respond at all.
+        match response.trim() {
+            "imok" => Ok(()),
+            _ => Err(Error::MissingField("imok")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Ruok;
+
+    #[test]
+    fn parse_imok() {
+        Ruok::parse_response("imok").unwrap();
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(Ruok::parse_response("").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+use super::srvr;
+use super::Srvr;
+
+/// The "stat" command
+///
+/// The response is the same as `srvr` with an additional list of connected clients.
+pub struct Stat;
+
+impl FourLetterWord for Stat {
+    type Response = Response;
+    fn command() -> &'static str {
+        "stat"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        // Split the client list out of the response and parse the rest as a "srvr" summary.
+        let mut zk_clients = Vec::new();
+        let mut summary = Vec::new();
+        for line in response.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('/') {
+                zk_clients.push(trimmed.to_string());
+            } else if trimmed != "Clients:" && !trimmed.is_empty() {
+                summary.push(line);
+            }
+        }
+        let summary = Srvr::parse_response(&summary.join("\n"))?;
+        Ok(Response {
+            summary,
+            zk_clients,
+        })
+    }
+}
+
+/// Sub-set of the "stat" response the agent needs.
+pub struct Response {
+    pub summary: srvr::Response,
+    pub zk_clients: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Stat;
+
+    #[test]
+    fn parse_valid_response() {
+        let response = Stat::parse_response(r#"Zookeeper version: 3.4.13-2d71af4dbe22557fda74f9a9b4309b15a7487f03, built on 06/29/2018 04:05 GMT
+Clients:
+ /172.17.0.1:53422[1](queued=0,recved=120,sent=121)
+ /127.0.0.1:53530[0](queued=0,recved=1,sent=0)
+
+Latency min/avg/max: 0/0/0
+Received: 8
+Sent: 7
+Connections: 2
+Outstanding: 0
+Zxid: 0x600000004
+Mode: follower
+Node count: 4
+"#).unwrap();
+        assert_eq!(response.summary.zk_mode, "follower");
+        assert_eq!(
+            response.summary.zk_version,
+            "3.4.13-2d71af4dbe22557fda74f9a9b4309b15a7487f03, built on 06/29/2018 04:05 GMT"
+        );
+        assert_eq!(response.summary.zk_zxid, 25769803780);
+        assert_eq!(response.summary.zk_extras.get("Connections").unwrap(), "2");
+        assert_eq!(
+            response.zk_clients,
+            vec![
+                "/172.17.0.1:53422[1](queued=0,recved=120,sent=121)",
+                "/127.0.0.1:53530[0](queued=0,recved=1,sent=0)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use zk_4lw::Error;
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+/// The "wchs" command
+pub struct Wchs;
+
+impl FourLetterWord for Wchs {
+    type Response = Response;
+    fn command() -> &'static str {
+        "wchs"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        let mut zk_connections: Option<u64> = None;
+        let mut zk_paths: Option<u64> = None;
+        let mut zk_watches: Option<u64> = None;
+
+        for line in response.lines() {
+            let line = line.trim();
+            if let Some(total) = line.strip_prefix("Total watches:") {
+                zk_watches = Some(total.trim().parse()?);
+                continue;
+            }
+            // Summary line: "<N> connections watching <M> paths".
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let ["connections", "watching", _, "paths"] = words.get(1..).unwrap_or(&[]) {
+                zk_connections = Some(words[0].parse()?);
+                zk_paths = Some(words[3].parse()?);
+            }
+        }
+
+        macro_rules! error_if_none {
+            ($($name:ident)*) => {
+                $(
+                    match $name {
+                        Some(v) => v,
+                        None => return Err(Error::MissingField(stringify!($name))),
+                    }
+                )*
+            }
+        }
+        Ok(Response {
+            zk_connections: error_if_none!(zk_connections),
+            zk_paths: error_if_none!(zk_paths),
+            zk_watches: error_if_none!(zk_watches),
+        })
+    }
+}
+
+/// Watches summary from the "wchs" response.
+pub struct Response {
+    pub zk_connections: u64,
+    pub zk_paths: u64,
+    pub zk_watches: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Wchs;
+
+    #[test]
+    fn parse_valid_response() {
+        let response = Wchs::parse_response(
+            r#"3 connections watching 12 paths
+Total watches:27"#,
+        )
+        .unwrap();
+        assert_eq!(response.zk_connections, 3);
+        assert_eq!(response.zk_paths, 12);
+        assert_eq!(response.zk_watches, 27);
+    }
+
+    #[test]
+    fn parse_no_watches() {
+        let response = Wchs::parse_response(
+            r#"0 connections watching 0 paths
+Total watches:0
+"#,
+        )
+        .unwrap();
+        assert_eq!(response.zk_watches, 0);
+    }
+
+    #[test]
+    fn parse_missing_total() {
+        assert!(Wchs::parse_response("1 connections watching 2 paths").is_err());
+    }
+}
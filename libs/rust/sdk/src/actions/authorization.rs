@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use super::ActionDescriptor;
+use super::ActionRequester;
+
+/// Outcome of an action authorization check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ActionAuthorization {
+    /// The action can be scheduled.
+    Allow,
+
+    /// The action can't be scheduled, with a reason reported to the client.
+    Deny(String),
+}
+
+/// Decide which actions can be scheduled by whom.
+///
+/// Authorizers are checked before actions are stored, after their arguments are validated.
+/// Denied actions are rejected with a `403 Forbidden` response.
+pub trait ActionAuthorizer: Send + Sync {
+    /// Check if the requester identified by `identity` can schedule the `action`.
+    fn authorize(
+        &self,
+        action: &ActionDescriptor,
+        identity: &RequestIdentity,
+    ) -> ActionAuthorization;
+
+    /// Additional check for actions flagged as `Action::destructive`.
+    ///
+    /// Destructive actions must pass both this check and `authorize`.
+    /// Authorizers deny destructive actions unless they explicitly allow them.
+    fn authorize_destructive(
+        &self,
+        _action: &ActionDescriptor,
+        _identity: &RequestIdentity,
+    ) -> ActionAuthorization {
+        ActionAuthorization::Deny("destructive actions are not allowed by this agent".into())
+    }
+}
+
+/// Default `ActionAuthorizer` that allows all actions.
+pub struct AllowAll;
+
+impl ActionAuthorizer for AllowAll {
+    fn authorize(&self, _: &ActionDescriptor, _: &RequestIdentity) -> ActionAuthorization {
+        ActionAuthorization::Allow
+    }
+
+    fn authorize_destructive(
+        &self,
+        _: &ActionDescriptor,
+        _: &RequestIdentity,
+    ) -> ActionAuthorization {
+        ActionAuthorization::Allow
+    }
+}
+
+/// Information about the client requesting an action.
+pub struct RequestIdentity<'a> {
+    /// HTTP headers attached to the action request.
+    pub headers: &'a HashMap<String, String>,
+
+    /// Address of the client connected to the API, if known.
+    pub peer_addr: Option<SocketAddr>,
+
+    /// Entity that requested the action, as declared by the client.
+    pub requester: &'a ActionRequester,
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use failure::Fail;
+use failure::ResultExt;
+use uuid::Uuid;
+
+use crate::config::CoordinationConfig;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Name of the lock file in `actions.coordination.lock_dir`.
+const LOCK_FILE: &str = "singleton-actions.lock";
+
+/// Cluster-wide lock held by singleton actions.
+///
+/// The lock is a file in a directory shared by all agents in the cluster.
+/// The file is created exclusively and stores the ID of the action holding the lock.
+/// Stale locks are atomically renamed aside before they are removed so only one agent
+/// can take them over.
+pub struct ClusterLock {
+    path: PathBuf,
+    stale_after: Duration,
+}
+
+impl ClusterLock {
+    pub fn new(config: &CoordinationConfig) -> ClusterLock {
+        ClusterLock {
+            path: PathBuf::from(&config.lock_dir).join(LOCK_FILE),
+            stale_after: Duration::from_secs(config.stale_after),
+        }
+    }
+
+    /// Acquire the lock for the given action, or refresh it if the action already holds it.
+    ///
+    /// Returns the ID of the action holding the lock if it is held by another action.
+    pub fn acquire(&self, owner: &Uuid) -> Result<Option<String>> {
+        let owner = owner.to_string();
+        // Retry once after removing an abandoned lock.
+        for _ in 0..2 {
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path);
+            match file {
+                Ok(mut file) => {
+                    file.write_all(owner.as_bytes())
+                        .with_context(|_| ErrorKind::Io(self.path_name()))?;
+                    return Ok(None);
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(error) => return Err(error.context(ErrorKind::Io(self.path_name())).into()),
+            };
+
+            let holder = match fs::read_to_string(&self.path) {
+                Ok(holder) => holder,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.context(ErrorKind::Io(self.path_name())).into()),
+            };
+            if holder == owner {
+                // Refresh without re-creating a lock that was taken over as stale.
+                let file = OpenOptions::new()
+                    .write(true)
+                    .truncate(true)
+                    .open(&self.path);
+                match file {
+                    Ok(mut file) => file
+                        .write_all(owner.as_bytes())
+                        .with_context(|_| ErrorKind::Io(self.path_name()))?,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => {
+                        return Err(error.context(ErrorKind::Io(self.path_name())).into())
+                    }
+                };
+                return Ok(None);
+            }
+            if !self.stale(&self.path)? {
+                return Ok(Some(holder));
+            }
+            if let Some(holder) = self.take_over(&owner, &holder)? {
+                return Ok(Some(holder));
+            }
+        }
+        let holder =
+            fs::read_to_string(&self.path).with_context(|_| ErrorKind::Io(self.path_name()))?;
+        Ok(Some(holder))
+    }
+
+    /// Release the lock if it is held by the given action.
+    pub fn release(&self, owner: &Uuid) -> Result<()> {
+        let holder = match fs::read_to_string(&self.path) {
+            Ok(holder) => holder,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.context(ErrorKind::Io(self.path_name())).into()),
+        };
+        if holder != owner.to_string() {
+            return Ok(());
+        }
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.context(ErrorKind::Io(self.path_name())).into()),
+        }
+    }
+
+    fn path_name(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    /// Check if the lock at `path` was last refreshed more than `stale_after` ago.
+    fn stale(&self, path: &Path) -> Result<bool> {
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|_| ErrorKind::Io(path.to_string_lossy().to_string()))?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        Ok(age > self.stale_after)
+    }
+
+    /// Remove a stale lock held by `holder` so `owner` can try to create it again.
+    ///
+    /// Renames are atomic so only one agent moves any given lock file aside.
+    /// The moved lock is checked again because another agent may have replaced the
+    /// stale lock, or its holder refreshed it, since it was found stale.
+    /// In that case the lock is put back and the ID of its holder is returned.
+    fn take_over(&self, owner: &str, holder: &str) -> Result<Option<String>> {
+        let aside = self.path.with_extension(format!("lock.{}.stale", owner));
+        let aside_name = aside.to_string_lossy().to_string();
+        match fs::rename(&self.path, &aside) {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.context(ErrorKind::Io(self.path_name())).into()),
+        };
+        let moved =
+            fs::read_to_string(&aside).with_context(|_| ErrorKind::Io(aside_name.clone()))?;
+        if moved != holder || !self.stale(&aside)? {
+            // Hard links never replace an existing file, unlike a rename back.
+            match fs::hard_link(&aside, &self.path) {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(error) => return Err(error.context(ErrorKind::Io(self.path_name())).into()),
+            };
+            fs::remove_file(&aside).with_context(|_| ErrorKind::Io(aside_name))?;
+            return Ok(Some(moved));
+        }
+        fs::remove_file(&aside).with_context(|_| ErrorKind::Io(aside_name))?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::ClusterLock;
+    use crate::config::CoordinationConfig;
+
+    fn lock(dir: &str) -> ClusterLock {
+        ClusterLock::new(&CoordinationConfig {
+            fail_fast: true,
+            lock_dir: dir.to_string(),
+            stale_after: 0,
+        })
+    }
+
+    #[test]
+    fn replaced_stale_lock_is_not_taken_over() {
+        let dir = std::env::temp_dir().join(format!("repliagent-locks-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let lock = lock(&dir.to_string_lossy());
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let third = Uuid::new_v4();
+        assert_eq!(lock.acquire(&first).unwrap(), None);
+
+        // Another agent took over the stale lock after it was found stale.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(lock.acquire(&second).unwrap(), None);
+        let holder = lock
+            .take_over(&third.to_string(), &first.to_string())
+            .unwrap();
+        let current = fs::read_to_string(&lock.path).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(holder, Some(second.to_string()));
+        assert_eq!(current, second.to_string());
+        assert_eq!(files, 1);
+    }
+}
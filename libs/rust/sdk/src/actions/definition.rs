@@ -27,6 +27,7 @@ pub use replicante_models_agent::actions::ActionListItem;
 pub use replicante_models_agent::actions::ActionRequester;
 pub use replicante_models_agent::actions::ActionState;
 
+use crate::config::CachedResponse;
 use crate::store::Transaction;
 use crate::ErrorKind;
 use crate::Result;
@@ -46,7 +47,62 @@ pub trait Action: Send + Sync + 'static {
     /// Action metadata and attributes.
     fn describe(&self) -> ActionDescriptor;
 
+    /// Flag actions that can cause data loss or otherwise can't be undone.
+    ///
+    /// Destructive actions must also be allowed by `ActionAuthorizer::authorize_destructive`
+    /// before they can be scheduled.
+    fn destructive(&self) -> bool {
+        false
+    }
+
+    /// Flag actions that can safely be invoked again for the same state.
+    ///
+    /// If the agent crashes after an action is invoked but before the outcome is persisted
+    /// the action will be invoked again on restart.
+    /// Actions that are not idempotent are instead failed when such replays are detected.
+    fn idempotent(&self) -> bool {
+        true
+    }
+
+    /// Cached API responses made outdated by the action.
+    ///
+    /// The listed caches are cleared once the action completes successfully,
+    /// so the next request reports the datastore state after the action.
+    fn invalidates(&self) -> Vec<CachedResponse> {
+        Vec::new()
+    }
+
+    /// Flag actions that can only be performed on a primary node.
+    ///
+    /// When `defer_primary_ops` is configured these actions are not invoked until
+    /// the node is primary for at least one shard.
+    fn requires_primary(&self) -> bool {
+        false
+    }
+
+    /// Flag actions that must run on only one node in the cluster at a time.
+    ///
+    /// When `actions.coordination` is configured singleton actions acquire a cluster-wide
+    /// lock before they are first invoked and release it once they finish.
+    fn singleton(&self) -> bool {
+        false
+    }
+
+    /// Time, in seconds, the action is allowed to take before it is failed.
+    ///
+    /// The timeout is counted from the time the action is scheduled and can be overridden
+    /// when the action is created. By default actions do not time out.
+    fn timeout(&self) -> Option<u64> {
+        None
+    }
+
     /// Invoke the action to advance the given `ActionRecord`.
+    ///
+    /// Implementations record progress by transitioning the record with `tx.action()`:
+    /// to `ActionState::Done` once the action succeeded or to `ActionState::Failed`
+    /// if it can't complete. Records left `New` or `Running` are invoked again later.
+    /// Errors returned from `invoke` fail the action (see `actions.datastore_down_grace`
+    /// for errors caused by an unreachable datastore).
     fn invoke(
         &self,
         tx: &mut Transaction,
@@ -126,6 +182,9 @@ pub struct ActionRecord {
     /// Time the agent recorded the action in the DB.
     pub scheduled_ts: DateTime<Utc>,
 
+    /// Time, in seconds, the action is allowed to take, overriding the action's default.
+    pub timeout_override: Option<u64>,
+
     /// Arguments passed to the action when invoked.
     args: Json,
 
@@ -151,6 +210,7 @@ impl ActionRecord {
         scheduled_ts: DateTime<Utc>,
         state: ActionState,
         state_payload: Option<Json>,
+        timeout_override: Option<u64>,
     ) -> ActionRecord {
         ActionRecord {
             agent_version,
@@ -164,6 +224,7 @@ impl ActionRecord {
             scheduled_ts,
             state,
             state_payload,
+            timeout_override,
         }
     }
 
@@ -193,6 +254,7 @@ impl ActionRecord {
             scheduled_ts: Utc::now(),
             state: ActionState::New,
             state_payload: None,
+            timeout_override: None,
         }
     }
 
@@ -336,14 +398,9 @@ pub type ActionValidity<T = ()> = std::result::Result<T, ActionValidityError>;
 pub enum ActionValidityError {
     #[fail(display = "invalid action arguments: {}", _0)]
     InvalidArgs(String),
-}
 
-impl ActionValidityError {
-    fn kind(&self) -> &str {
-        match self {
-            ActionValidityError::InvalidArgs(_) => "InvalidArgs",
-        }
-    }
+    #[fail(display = "invalid action argument {}: {}", field, reason)]
+    InvalidField { field: String, reason: String },
 }
 
 impl ResponseError for ActionValidityError {
@@ -353,9 +410,14 @@ impl ResponseError for ActionValidityError {
 
     fn error_response(&self) -> HttpResponse {
         let status = self.status_code();
-        HttpResponse::build(status).json(json!({
+        let mut body = json!({
             "error": self.to_string(),
-            "kind": self.kind(),
-        }))
+            "kind": "InvalidArgs",
+        });
+        // Field level details are additive so clients matching on the kind keep working.
+        if let ActionValidityError::InvalidField { field, reason } = self {
+            body["fields"] = json!([{"field": field, "reason": reason}]);
+        }
+        HttpResponse::build(status).json(body)
     }
 }
@@ -1,14 +1,22 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
+use chrono::DateTime;
+use chrono::Utc;
 use failure::ResultExt;
 use humthreads::Builder;
 use opentracingrust::Span;
 use slog::debug;
 use slog::trace;
 use slog::warn;
+use uuid::Uuid;
+
+use replicante_models_agent::info::ShardRole;
 
 use replicante_util_failure::capture_fail;
 use replicante_util_failure::failure_info;
@@ -16,35 +24,59 @@ use replicante_util_failure::SerializableFail;
 use replicante_util_tracing::fail_span;
 use replicante_util_upkeep::Upkeep;
 
+use super::coordination::ClusterLock;
+use crate::actions::is_finished;
 use crate::actions::Action;
+use crate::actions::ActionListItem;
 use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
 use crate::actions::ACTIONS;
-use crate::metrics::ACTION_COUNT;
-use crate::metrics::ACTION_DURATION;
-use crate::metrics::ACTION_ERRORS;
-use crate::metrics::ACTION_PRUNE_DURATION;
+use crate::classify::ErrorClassifier;
+use crate::config::CachedResponse;
+use crate::config::MAX_DURATION_SECS;
 use crate::store::Transaction;
+use crate::Agent;
 use crate::AgentContext;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
 
+/// OpenTracing tag hinting tracers to capture the trace regardless of sampling.
+///
+/// See https://github.com/opentracing/specification/blob/master/semantic_conventions.md
+const SAMPLING_PRIORITY_TAG: &str = "sampling.priority";
+
 /// Start background thread to execute registered actions.
-pub fn spawn(context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
+pub fn spawn(agent: Arc<dyn Agent>, context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
+    let invocations = Invocations::default();
+    if context.config.actions.heartbeat_timeout.is_some() {
+        let agent = Arc::clone(&agent);
+        spawn_monitor(agent, context.clone(), Arc::clone(&invocations), upkeep)?;
+    }
     let thread = Builder::new("r:b:actions")
         .full_name("replicante:base:actions:engine")
         .spawn(move |scope| {
             let logger = context.logger.clone();
-            let execute_interval = Duration::from_secs(context.config.actions.execute_interval);
-            let prune_interval = Duration::from_secs(context.config.actions.prune_interval);
-            let engine = Engine::new(context);
+            let jitter = &context.config.jitter;
+            let execute_interval =
+                jitter.apply(Duration::from_secs(context.config.actions.execute_interval));
+            let prune_interval =
+                jitter.apply(Duration::from_secs(context.config.actions.prune_interval));
+            let tasks = context.tasks.clone();
+            tasks.register("actions.poll", execute_interval);
+            tasks.register("actions.prune", prune_interval);
+            let engine = Engine::new(context)
+                .with_agent(agent)
+                .with_invocations(invocations);
             // Initialise last_prune to 2 * prune_interval ago to prune after start.
             let mut last_prune = Instant::now() - (2 * prune_interval);
             scope.activity("waiting to poll for actions");
             while !scope.should_shutdown() {
                 let _activity = scope.scoped_activity("handling actions");
-                if let Err(error) = engine.poll() {
+                let result = engine.poll();
+                tasks.record("actions.poll", &result);
+                if let Err(error) = result {
                     capture_fail!(
                         &error,
                         logger,
@@ -55,7 +87,9 @@ pub fn spawn(context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
                 if last_prune.elapsed() > prune_interval {
                     last_prune = Instant::now();
                     let _activity = scope.scoped_activity("pruning actions history");
-                    if let Err(error) = engine.clean() {
+                    let result = engine.clean();
+                    tasks.record("actions.prune", &result);
+                    if let Err(error) = result {
                         capture_fail!(
                             &error,
                             logger,
@@ -72,14 +106,143 @@ pub fn spawn(context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
     Ok(())
 }
 
+/// Start background thread to fail actions whose executor stopped heartbeating.
+///
+/// The monitor runs on its own thread so it keeps working if an action invocation hangs.
+/// Actions the engine is invoking are tracked in `invocations` and are never failed.
+fn spawn_monitor(
+    agent: Arc<dyn Agent>,
+    context: AgentContext,
+    invocations: Invocations,
+    upkeep: &mut Upkeep,
+) -> Result<()> {
+    let thread = Builder::new("r:b:actions:monitor")
+        .full_name("replicante:base:actions:monitor")
+        .spawn(move |scope| {
+            let logger = context.logger.clone();
+            let interval = context
+                .config
+                .jitter
+                .apply(Duration::from_secs(context.config.actions.execute_interval));
+            let tasks = context.tasks.clone();
+            tasks.register("actions.monitor", interval);
+            let engine = Engine::new(context)
+                .with_agent(agent)
+                .with_invocations(invocations);
+            scope.activity("waiting to check actions heartbeats");
+            while !scope.should_shutdown() {
+                let _activity = scope.scoped_activity("checking actions heartbeats");
+                let result = engine.monitor();
+                tasks.record("actions.monitor", &result);
+                if let Err(error) = result {
+                    capture_fail!(
+                        &error,
+                        logger,
+                        "Error while checking actions heartbeats";
+                        failure_info(&error),
+                    );
+                }
+                thread::sleep(interval);
+            }
+        })
+        .with_context(|_| ErrorKind::ThreadSpawn("actions monitor"))?;
+    upkeep.register_thread(thread);
+    Ok(())
+}
+
+/// Outcome of checking a queued action before it is invoked.
+enum Candidate {
+    /// The action is ready to be invoked.
+    Ready(Arc<dyn Action>),
+
+    /// The action can't be invoked yet: later actions in the queue can go first.
+    Deferred,
+
+    /// The action was failed instead of being invoked.
+    Failed,
+}
+
+/// IDs of the actions the engine is invoking, shared with the heartbeat monitor.
+///
+/// Heartbeats are recorded in the store when an invocation starts, so a crashed executor
+/// leaves them to go stale. Live invocations are tracked here instead of refreshing the
+/// heartbeat from a side thread, which would contend with the open invoke transaction.
+type Invocations = Arc<Mutex<HashSet<Uuid>>>;
+
+/// Track an action as being invoked until dropped.
+struct Invoking<'a> {
+    id: Uuid,
+    invocations: &'a Invocations,
+}
+
+impl<'a> Invoking<'a> {
+    fn new(invocations: &'a Invocations, id: Uuid) -> Invoking<'a> {
+        invocations
+            .lock()
+            .expect("actions invocations lock poisoned")
+            .insert(id);
+        Invoking { id, invocations }
+    }
+}
+
+impl<'a> Drop for Invoking<'a> {
+    fn drop(&mut self) {
+        self.invocations
+            .lock()
+            .expect("actions invocations lock poisoned")
+            .remove(&self.id);
+    }
+}
+
 /// Actions engine logic.
-struct Engine {
+pub(super) struct Engine {
+    /// Agent to check the node role with, if `active_roles` is set.
+    agent: Option<Arc<dyn Agent>>,
+
+    /// Classify datastore errors returned by actions, to spot an unreachable datastore.
+    classifier: ErrorClassifier,
+
     context: AgentContext,
+
+    /// Actions being invoked, which the heartbeat monitor must not fail.
+    invocations: Invocations,
+
+    /// Cluster-wide lock for singleton actions, if coordination is configured.
+    lock: Option<ClusterLock>,
+
+    /// Actions that found the datastore unreachable, and when that first happened.
+    unreachable: Mutex<HashMap<Uuid, Instant>>,
 }
 
 impl Engine {
     pub fn new(context: AgentContext) -> Engine {
-        Engine { context }
+        let lock = context
+            .config
+            .actions
+            .coordination
+            .as_ref()
+            .map(ClusterLock::new);
+        let classifier = ErrorClassifier::new(&context.config.datastore.connection_errors);
+        Engine {
+            agent: None,
+            classifier,
+            context,
+            invocations: Invocations::default(),
+            lock,
+            unreachable: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check the node role with the given agent before executing actions.
+    pub fn with_agent(mut self, agent: Arc<dyn Agent>) -> Engine {
+        self.agent = Some(agent);
+        self
+    }
+
+    /// Share the set of actions being invoked with other engines (like the monitor).
+    pub fn with_invocations(mut self, invocations: Invocations) -> Engine {
+        self.invocations = invocations;
+        self
     }
 
     /// Perform historic actions cleanup to prevent endless DB growth.
@@ -87,64 +250,82 @@ impl Engine {
         trace!(self.context.logger, "Pruning actions history");
         let keep = self.context.config.actions.prune_keep;
         let limit = self.context.config.actions.prune_limit;
-        let _timer = ACTION_PRUNE_DURATION.start_timer();
-        self.context
-            .store
-            .with_transaction(|tx| tx.actions().prune(keep, limit, None))
+        let payload_retention = self.context.config.actions.payload_retention;
+        let _timer = self.context.sdk_metrics.action_prune_duration.start_timer();
+        self.context.store.with_transaction(|tx| {
+            if let Some(retention) = payload_retention {
+                let finished_before = Utc::now() - chrono::Duration::seconds(retention as i64);
+                tx.actions().prune_payloads(finished_before, limit, None)?;
+            }
+            tx.actions().prune(keep, limit, None)
+        })
+    }
+
+    /// Fail unfinished actions whose heartbeat is older than `actions.heartbeat_timeout`.
+    pub fn monitor(&self) -> Result<()> {
+        let timeout = match self.context.config.actions.heartbeat_timeout {
+            None => return Ok(()),
+            Some(timeout) => timeout,
+        };
+        let mut span = Some(self.context.tracer.span("actions.monitor").auto_finish());
+        match self.role_active(span.as_deref_mut()) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(error) => return Err(fail_span(error, span.as_deref_mut())),
+        };
+        let heartbeat_before = Utc::now() - chrono::Duration::seconds(timeout as i64);
+        match self.fail_unresponsive(heartbeat_before, timeout, span.as_deref()) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(fail_span(error, span.as_deref_mut())),
+        }
     }
 
     /// Looks for running or pending actions and processes them.
     pub fn poll(&self) -> Result<()> {
         // Wrapped in `Some` to allow transition to optional Tracer easier.
         let mut span = Some(self.context.tracer.span("actions.poll").auto_finish());
-        let rv = self.context.store.with_transaction(|tx| {
-            let record = tx
-                .action()
-                .next(span.as_ref().map(|span| span.context().clone()))?;
-            let record = match record {
-                None => return Ok(()),
-                Some(record) => record,
-            };
-            if let Some(span) = span.as_mut() {
-                span.tag("action.kind", record.kind.clone());
-                span.tag("action.id", record.id.to_string());
-                match record.trace_get(&self.context.tracer) {
-                    Ok(None) => (),
-                    Ok(Some(context)) => span.follows(context),
-                    Err(error) => {
-                        capture_fail!(
-                            &error,
-                            self.context.logger,
-                            "Failed to extract tracing context from action record";
-                            failure_info(&error),
-                            "id" => %&record.id,
-                            "kind" => &record.kind,
-                        );
+        match self.role_active(span.as_deref_mut()) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(error) => return Err(fail_span(error, span.as_deref_mut())),
+        };
+        let rv = match self.next(span.as_deref_mut()) {
+            Err(error) => Err(error),
+            Ok(None) => Ok(()),
+            Ok(Some((record, action))) => {
+                // Stop tracking the invocation only once its outcome is committed.
+                let _invoking = Invoking::new(&self.invocations, record.id);
+                self.context.store.with_transaction(|tx| {
+                    let idempotent = action.idempotent();
+                    let singleton = action.singleton();
+                    let invalidates = self.invalidates(&record, action.as_ref());
+                    let result = self.call(tx, &record, action, span.as_deref_mut());
+                    let retry = idempotent && self.datastore_down(&record, &result);
+                    let outcome = match result {
+                        // Leave the record untouched so the action is invoked again.
+                        Err(_) if retry => Ok(()),
+                        Err(error) => self.fail(tx, &record, error, span.as_deref()),
+                        Ok(()) if idempotent => Ok(()),
+                        Ok(()) => tx.action().mark_invoked(
+                            &record,
+                            false,
+                            span.as_ref().map(|span| span.context().clone()),
+                        ),
+                    };
+                    if self.context.config.actions.heartbeat_timeout.is_some() {
+                        let context = span.as_ref().map(|span| span.context().clone());
+                        tx.action().clear_heartbeat(&record, context)?;
                     }
-                };
-            }
-            ACTION_COUNT.with_label_values(&[&record.kind]).inc();
-            let action = match ACTIONS::get(&record.kind) {
-                Some(action) => action,
-                None => {
-                    let error = ErrorKind::ActionNotAvailable(record.kind.clone());
-                    return self.fail(tx, &record, error.into(), span.as_deref());
-                }
-            };
-            // To limit the noise generated by this message, emit it only once few cycles.
-            if ACTION_COUNT.with_label_values(&[&record.kind]).get() % 10.0 == 0.0 {
-                debug!(
-                    self.context.logger,
-                    "Invoking action handler";
-                    "id" => %&record.id,
-                    "kind" => &record.kind,
-                );
-            }
-            match self.call(tx, &record, action, span.as_deref_mut()) {
-                Err(error) => self.fail(tx, &record, error, span.as_deref()),
-                Ok(()) => Ok(()),
+                    if singleton {
+                        self.release_lock(tx, &record, span.as_deref())?;
+                    }
+                    if outcome.is_ok() && !invalidates.is_empty() {
+                        self.invalidate_caches(tx, &record, &invalidates, span.as_deref())?;
+                    }
+                    outcome
+                })
             }
-        });
+        };
         match rv {
             Ok(()) => Ok(()),
             Err(error) => Err(fail_span(error, span.as_deref_mut())),
@@ -153,6 +334,188 @@ impl Engine {
 }
 
 impl Engine {
+    /// Check if the node holds one of the `active_roles`, pausing actions if it does not.
+    fn role_active(&self, parent: Option<&mut Span>) -> Result<bool> {
+        let agent = match self.agent.as_ref() {
+            None => return Ok(true),
+            Some(agent) => agent,
+        };
+        let mut span = self.context.tracer.span("actions.role").auto_finish();
+        if let Some(parent) = parent {
+            span.child_of(parent.context().clone());
+        }
+        match self.context.ensure_active_role(agent.as_ref(), &mut span) {
+            Ok(()) => Ok(true),
+            Err(error) => match error.kind() {
+                ErrorKind::WrongRole(role, _) => {
+                    trace!(
+                        self.context.logger,
+                        "Actions paused while the node is not in an active role";
+                        "role" => role,
+                    );
+                    Ok(false)
+                }
+                _ => Err(error),
+            },
+        }
+    }
+
+    /// Check if an action requiring a primary node can be invoked.
+    ///
+    /// With `defer_primary_ops` set, actions stay pending while the node is not primary
+    /// and are failed once they waited longer than allowed since they were scheduled.
+    fn primary_ready(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        parent: Option<&mut Span>,
+    ) -> Result<bool> {
+        let wait = match self.context.config.defer_primary_ops {
+            None => return Ok(true),
+            Some(wait) => wait,
+        };
+        let agent = match self.agent.as_ref() {
+            None => return Ok(true),
+            Some(agent) => agent,
+        };
+        let mut span = self.context.tracer.span("actions.primary").auto_finish();
+        if let Some(parent) = parent {
+            span.child_of(parent.context().clone());
+        }
+        let shards = agent.shards(&mut span)?;
+        let primary = shards.shards.iter().any(|shard| match shard.role {
+            ShardRole::Primary => true,
+            _ => false,
+        });
+        if primary {
+            return Ok(true);
+        }
+        let elapsed = Utc::now() - record.scheduled_ts;
+        if elapsed > chrono::Duration::seconds(wait as i64) {
+            let error = ErrorKind::ActionNotPrimary(record.id.to_string(), wait);
+            self.fail(tx, record, error.into(), Some(&*span))?;
+            return Ok(false);
+        }
+        trace!(
+            self.context.logger,
+            "Action deferred until the node is primary";
+            "id" => %&record.id,
+            "kind" => &record.kind,
+        );
+        Ok(false)
+    }
+
+    /// Fetch the next action to invoke, if any.
+    ///
+    /// Actions are invoked in the order they are queued, skipping over deferred actions
+    /// so they don't hold back the rest of the queue while they wait.
+    ///
+    /// Non-idempotent actions are marked as invoked in a transaction committed before
+    /// the action is invoked, so that a crash between the invocation and the outcome being
+    /// persisted is detected (and the action failed) instead of invoking the action again.
+    fn next(&self, mut span: Option<&mut Span>) -> Result<Option<(ActionRecord, Arc<dyn Action>)>> {
+        self.context.store.with_transaction(|tx| {
+            let queue: Vec<ActionListItem> = tx
+                .actions()
+                .queue(span.as_ref().map(|span| span.context().clone()))?
+                .collect::<Result<_>>()?;
+            for item in queue {
+                let id = item.id.to_string();
+                let record = tx
+                    .action()
+                    .get(&id, span.as_ref().map(|span| span.context().clone()))?;
+                let record = match record {
+                    None => continue,
+                    Some(record) => record,
+                };
+                match self.check(tx, &record, span.as_deref_mut())? {
+                    Candidate::Ready(action) => return Ok(Some((record, action))),
+                    Candidate::Deferred => continue,
+                    Candidate::Failed => return Ok(None),
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    /// Check if a queued action can be invoked, preparing it to be if so.
+    fn check(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        mut span: Option<&mut Span>,
+    ) -> Result<Candidate> {
+        if let Some(span) = span.as_mut() {
+            span.tag("action.kind", record.kind.clone());
+            span.tag("action.id", record.id.to_string());
+            // Actions are rare but valuable to trace: always ask tracers to keep them.
+            span.tag(SAMPLING_PRIORITY_TAG, 1);
+            match record.trace_get(&self.context.tracer) {
+                Ok(None) => (),
+                Ok(Some(context)) => span.follows(context),
+                Err(error) => {
+                    capture_fail!(
+                        &error,
+                        self.context.logger,
+                        "Failed to extract tracing context from action record";
+                        failure_info(&error),
+                        "id" => %&record.id,
+                        "kind" => &record.kind,
+                    );
+                }
+            };
+        }
+        let action = match ACTIONS::get(&record.kind) {
+            Some(action) => action,
+            None => {
+                let error = ErrorKind::ActionNotAvailable(record.kind.clone());
+                self.fail(tx, record, error.into(), span.as_deref())?;
+                return Ok(Candidate::Failed);
+            }
+        };
+        if let Some(timeout) = record.timeout_override.or_else(|| action.timeout()) {
+            // Timeouts set in code (or on imported records) skip config validation.
+            let limit = timeout.min(MAX_DURATION_SECS) as i64;
+            let elapsed = Utc::now() - record.scheduled_ts;
+            if elapsed > chrono::Duration::seconds(limit) {
+                let error = ErrorKind::ActionTimedOut(record.id.to_string(), timeout);
+                self.fail(tx, record, error.into(), span.as_deref())?;
+                return Ok(Candidate::Failed);
+            }
+        }
+        if action.requires_primary() && !self.primary_ready(tx, record, span.as_deref_mut())? {
+            return Ok(Candidate::Deferred);
+        }
+        let context = span.as_ref().map(|span| span.context().clone());
+        if !action.idempotent() && tx.action().invoked(record, context.clone())? {
+            let error = ErrorKind::ActionReplayed(record.id.to_string());
+            self.fail(tx, record, error.into(), span.as_deref())?;
+            return Ok(Candidate::Failed);
+        }
+        if action.singleton() && !self.acquire_lock(tx, record, span.as_deref())? {
+            return Ok(Candidate::Deferred);
+        }
+        if !action.idempotent() {
+            tx.action().mark_invoked(record, true, context.clone())?;
+        }
+        if self.context.config.actions.heartbeat_timeout.is_some() {
+            tx.action().heartbeat(record, context)?;
+        }
+        let metrics = &self.context.sdk_metrics;
+        let kind = metrics.action_kind_labels.label(&record.kind);
+        metrics.action_count.with_label_values(&[kind]).inc();
+        // To limit the noise generated by this message, emit it only once few cycles.
+        if metrics.action_count.with_label_values(&[kind]).get() % 10.0 == 0.0 {
+            debug!(
+                self.context.logger,
+                "Invoking action handler";
+                "id" => %&record.id,
+                "kind" => &record.kind,
+            );
+        }
+        Ok(Candidate::Ready(action))
+    }
+
     fn call(
         &self,
         tx: &mut Transaction,
@@ -160,10 +523,198 @@ impl Engine {
         action: Arc<dyn Action>,
         span: Option<&mut Span>,
     ) -> Result<()> {
-        let _timer = ACTION_DURATION
-            .with_label_values(&[&record.kind])
+        let metrics = &self.context.sdk_metrics;
+        let _timer = metrics
+            .action_duration
+            .with_label_values(&[metrics.action_kind_labels.label(&record.kind)])
             .start_timer();
-        action.invoke(tx, record, span)
+        action
+            .invoke(tx, record, span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    /// Check if the action should be invoked again because the datastore is unreachable.
+    ///
+    /// Only idempotent actions are retried: the others may have changed the datastore
+    /// before losing the connection and are failed like any other error.
+    /// Actions are retried until `actions.datastore_down_grace` seconds have passed since
+    /// they first found the datastore unreachable. Any other outcome resets the grace.
+    fn datastore_down(&self, record: &ActionRecord, result: &Result<()>) -> bool {
+        let mut unreachable = self
+            .unreachable
+            .lock()
+            .expect("actions engine unreachable lock poisoned");
+        let error = match result {
+            Err(error) => error,
+            Ok(()) => {
+                unreachable.remove(&record.id);
+                return false;
+            }
+        };
+        let connection_error = match error.kind() {
+            ErrorKind::Connection(_, _) => true,
+            ErrorKind::DatastoreUnreachable(_) => true,
+            _ => false,
+        };
+        let grace = Duration::from_secs(self.context.config.actions.datastore_down_grace);
+        if !connection_error || grace == Duration::from_secs(0) {
+            unreachable.remove(&record.id);
+            return false;
+        }
+        let since = *unreachable.entry(record.id).or_insert_with(Instant::now);
+        if since.elapsed() >= grace {
+            unreachable.remove(&record.id);
+            return false;
+        }
+        warn!(
+            self.context.logger,
+            "Datastore unreachable, action will be invoked again";
+            "id" => %&record.id,
+            "kind" => &record.kind,
+            failure_info(error),
+        );
+        true
+    }
+
+    /// Acquire the cluster lock for a singleton action, if coordination is configured.
+    ///
+    /// Returns `false` if the action can't be invoked because another action holds the lock.
+    /// With `actions.coordination.fail_fast` set such actions are also failed.
+    fn acquire_lock(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        span: Option<&Span>,
+    ) -> Result<bool> {
+        let lock = match self.lock.as_ref() {
+            None => return Ok(true),
+            Some(lock) => lock,
+        };
+        let holder = match lock.acquire(&record.id)? {
+            None => return Ok(true),
+            Some(holder) => holder,
+        };
+        let fail_fast = self
+            .context
+            .config
+            .actions
+            .coordination
+            .as_ref()
+            .map(|coordination| coordination.fail_fast)
+            .unwrap_or(false);
+        if fail_fast {
+            let error = ErrorKind::ActionLocked(record.id.to_string(), holder);
+            self.fail(tx, record, error.into(), span)?;
+        } else {
+            debug!(
+                self.context.logger,
+                "Singleton action waiting for the cluster lock";
+                "id" => %&record.id,
+                "kind" => &record.kind,
+                "holder" => holder,
+            );
+        }
+        Ok(false)
+    }
+
+    /// Response caches made outdated by the action, declared by it or configured by kind.
+    fn invalidates(&self, record: &ActionRecord, action: &dyn Action) -> Vec<CachedResponse> {
+        let mut caches = action.invalidates();
+        if let Some(configured) = self
+            .context
+            .config
+            .actions
+            .invalidate_caches
+            .get(&record.kind)
+        {
+            caches.extend(configured.iter().copied());
+        }
+        caches
+    }
+
+    /// Clear response caches made outdated by the action once it has completed.
+    ///
+    /// Caches are left alone while the action is running or if it failed.
+    fn invalidate_caches(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        caches: &[CachedResponse],
+        span: Option<&Span>,
+    ) -> Result<()> {
+        let id = record.id.to_string();
+        let done = tx
+            .action()
+            .get(&id, span.map(|span| span.context().clone()))?
+            .map(|record| *record.state() == ActionState::Done)
+            .unwrap_or(false);
+        if !done {
+            return Ok(());
+        }
+        for cache in caches {
+            self.context.caches.invalidate(*cache);
+        }
+        debug!(
+            self.context.logger,
+            "Invalidated cached responses after action completed";
+            "id" => %&record.id,
+            "kind" => &record.kind,
+            "caches" => ?caches,
+        );
+        Ok(())
+    }
+
+    /// Release the cluster lock held by a singleton action once it has finished.
+    fn release_lock(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        span: Option<&Span>,
+    ) -> Result<()> {
+        let lock = match self.lock.as_ref() {
+            None => return Ok(()),
+            Some(lock) => lock,
+        };
+        let id = record.id.to_string();
+        let finished = tx
+            .action()
+            .get(&id, span.map(|span| span.context().clone()))?
+            .map(|record| is_finished(ActionRecordView::raw_state(&record)))
+            .unwrap_or(true);
+        if finished {
+            lock.release(&record.id)?;
+        }
+        Ok(())
+    }
+
+    /// Fail unfinished actions that did not heartbeat since `heartbeat_before`.
+    ///
+    /// Actions the engine is invoking are skipped: the lock on the invocations is held
+    /// while the store is checked so outcomes committed meanwhile are not failed.
+    fn fail_unresponsive(
+        &self,
+        heartbeat_before: DateTime<Utc>,
+        timeout: u64,
+        span: Option<&Span>,
+    ) -> Result<()> {
+        let invocations = self
+            .invocations
+            .lock()
+            .expect("actions invocations lock poisoned");
+        self.context.store.with_transaction(|tx| {
+            let records: Vec<ActionRecord> = tx
+                .actions()
+                .unresponsive(heartbeat_before, span.map(|span| span.context().clone()))?
+                .collect::<Result<_>>()?;
+            for record in records {
+                if invocations.contains(&record.id) {
+                    continue;
+                }
+                let error = ErrorKind::ActionUnresponsive(record.id.to_string(), timeout);
+                self.fail(tx, &record, error.into(), span)?;
+            }
+            Ok(())
+        })
     }
 
     fn fail(
@@ -180,7 +731,14 @@ impl Engine {
             "kind" => &record.kind,
             failure_info(&error),
         );
-        ACTION_ERRORS.with_label_values(&[&record.kind]).inc();
+        let metrics = &self.context.sdk_metrics;
+        metrics
+            .action_errors
+            .with_label_values(&[metrics.action_kind_labels.label(&record.kind)])
+            .inc();
+        if let Some(lock) = self.lock.as_ref() {
+            lock.release(&record.id)?;
+        }
         let error = SerializableFail::from(&error);
         let error = serde_json::to_value(&error).with_context(|_| ErrorKind::ActionEncode)?;
         tx.action().transition(
@@ -194,19 +752,547 @@ impl Engine {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use opentracingrust::Span;
     use serde_json::json;
+    use serde_json::Value as Json;
+    use uuid::Uuid;
 
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+    use replicante_models_agent::info::Shards;
     use replicante_util_failure::SerializableFail;
 
     use super::super::impls::debug::Progress;
     use super::Engine;
+    use super::Invoking;
+    use crate::actions::Action;
+    use crate::actions::ActionDescriptor;
     use crate::actions::ActionRecord;
     use crate::actions::ActionRecordView;
     use crate::actions::ActionRequester;
     use crate::actions::ActionState;
+    use crate::actions::ActionValidity;
     use crate::actions::ActionsRegister;
+    use crate::actions::CachedResponse;
     use crate::actions::ACTIONS;
+    use crate::config::Agent as AgentConfig;
+    use crate::config::CoordinationConfig;
+    use crate::store::Transaction;
+    use crate::testing::MockAgent;
+    use crate::Agent;
     use crate::AgentContext;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    struct NotIdempotent {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Action for NotIdempotent {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/not.idempotent".into(),
+                description: "replicante_agent::actions::engine::tests::NotIdempotent".into(),
+            }
+        }
+
+        fn idempotent(&self) -> bool {
+            false
+        }
+
+        fn invoke(
+            &self,
+            tx: &mut Transaction,
+            record: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tx.action()
+                .transition(record, ActionState::Running, None, None)
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    struct PrimaryOnly {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Action for PrimaryOnly {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/primary.only".into(),
+                description: "replicante_agent::actions::engine::tests::PrimaryOnly".into(),
+            }
+        }
+
+        fn requires_primary(&self) -> bool {
+            true
+        }
+
+        fn invoke(
+            &self,
+            tx: &mut Transaction,
+            record: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tx.action()
+                .transition(record, ActionState::Done, None, None)
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    struct Singleton {
+        max_running: Arc<AtomicUsize>,
+        running: Arc<AtomicUsize>,
+    }
+
+    impl Action for Singleton {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/singleton".into(),
+                description: "replicante_agent::actions::engine::tests::Singleton".into(),
+            }
+        }
+
+        fn singleton(&self) -> bool {
+            true
+        }
+
+        fn invoke(
+            &self,
+            tx: &mut Transaction,
+            record: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            tx.action()
+                .transition(record, ActionState::Done, None, None)
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    struct StepDown;
+
+    impl Action for StepDown {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/step.down".into(),
+                description: "replicante_agent::actions::engine::tests::StepDown".into(),
+            }
+        }
+
+        fn invalidates(&self) -> Vec<CachedResponse> {
+            vec![CachedResponse::Shards]
+        }
+
+        fn invoke(
+            &self,
+            tx: &mut Transaction,
+            record: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            tx.action()
+                .transition(record, ActionState::Done, None, None)
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    struct Unreachable {
+        calls: Arc<AtomicUsize>,
+        idempotent: bool,
+    }
+
+    impl Action for Unreachable {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/unreachable".into(),
+                description: "replicante_agent::actions::engine::tests::Unreachable".into(),
+            }
+        }
+
+        fn idempotent(&self) -> bool {
+            self.idempotent
+        }
+
+        fn invoke(
+            &self,
+            _: &mut Transaction,
+            _: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(ErrorKind::Connection("test", "localhost".into()).into())
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    struct Refused {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Action for Refused {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.example.io/refused".into(),
+                description: "replicante_agent::actions::engine::tests::Refused".into(),
+            }
+        }
+
+        fn invoke(
+            &self,
+            _: &mut Transaction,
+            _: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let error = failure::err_msg("Connection refused (os error 111)")
+                .context(ErrorKind::StoreOpFailed("replSetStepDown"));
+            Err(error.into())
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    fn poll_unreachable(grace: u64, polls: usize, idempotent: bool) -> (usize, ActionState) {
+        let action = ActionRecord::new(
+            "test.example.io/unreachable",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        let mut config = AgentConfig::mock();
+        config.actions.datastore_down_grace = grace;
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(Unreachable {
+            calls: calls.clone(),
+            idempotent,
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            for _ in 0..polls {
+                engine.poll().expect("poll failed to process action");
+            }
+        });
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        (calls.load(Ordering::SeqCst), action.state().clone())
+    }
+
+    #[test]
+    fn datastore_down_requeues_action() {
+        let (calls, state) = poll_unreachable(60, 2, true);
+        assert_eq!(calls, 2);
+        assert_eq!(state, ActionState::New);
+    }
+
+    #[test]
+    fn datastore_down_classified_from_action_errors() {
+        let action = ActionRecord::new(
+            "test.example.io/refused",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        let mut config = AgentConfig::mock();
+        config.actions.datastore_down_grace = 60;
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(Refused {
+            calls: calls.clone(),
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+            engine.poll().expect("poll failed to process action");
+        });
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(*action.state(), ActionState::New);
+    }
+
+    #[test]
+    fn datastore_down_without_grace_fails() {
+        let (calls, state) = poll_unreachable(0, 2, true);
+        assert_eq!(calls, 1);
+        assert_eq!(state, ActionState::Failed);
+    }
+
+    #[test]
+    fn datastore_down_not_idempotent_fails() {
+        let (calls, state) = poll_unreachable(60, 2, false);
+        assert_eq!(calls, 1);
+        assert_eq!(state, ActionState::Failed);
+    }
+
+    #[test]
+    fn primary_only_actions_wait_for_promotion() {
+        let action = ActionRecord::new(
+            "test.example.io/primary.only",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id.to_string();
+        let mut config = AgentConfig::mock();
+        config.defer_primary_ops = Some(600);
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let with_role = |role: ShardRole| -> Arc<dyn Agent> {
+            let mut agent = MockAgent::new();
+            agent.shards = Ok(Shards::new(vec![Shard::new(
+                "rs0".into(),
+                role,
+                None,
+                None,
+            )]));
+            Arc::new(agent)
+        };
+        let state = || {
+            context
+                .store
+                .with_transaction(|tx| tx.action().get(&id, None))
+                .unwrap()
+                .unwrap()
+                .state()
+                .clone()
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(PrimaryOnly {
+            calls: calls.clone(),
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone()).with_agent(with_role(ShardRole::Secondary));
+            engine.poll().expect("poll failed to process action");
+            assert_eq!(calls.load(Ordering::SeqCst), 0);
+            assert_eq!(state(), ActionState::New);
+
+            // The node is promoted.
+            let engine = Engine::new(context.clone()).with_agent(with_role(ShardRole::Primary));
+            engine.poll().expect("poll failed to process action");
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(state(), ActionState::Done);
+    }
+
+    #[test]
+    fn deferred_actions_do_not_block_queue() {
+        let deferred = ActionRecord::new(
+            "test.example.io/primary.only",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let queued = ActionRecord::new(
+            "test.example.io/not.idempotent",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let mut config = AgentConfig::mock();
+        config.defer_primary_ops = Some(600);
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(deferred, None)?;
+                tx.action().insert(queued, None)
+            })
+            .unwrap();
+        let mut agent = MockAgent::new();
+        agent.shards = Ok(Shards::new(vec![Shard::new(
+            "rs0".into(),
+            ShardRole::Secondary,
+            None,
+            None,
+        )]));
+        let primary_calls = Arc::new(AtomicUsize::new(0));
+        let queued_calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(PrimaryOnly {
+            calls: primary_calls.clone(),
+        });
+        register.register(NotIdempotent {
+            calls: queued_calls.clone(),
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone()).with_agent(Arc::new(agent));
+            engine.poll().expect("poll failed to process action");
+        });
+        assert_eq!(primary_calls.load(Ordering::SeqCst), 0);
+        assert_eq!(queued_calls.load(Ordering::SeqCst), 1);
+
+        // Only invoked actions are counted.
+        let metrics = &context.sdk_metrics;
+        let count = |kind: &str| {
+            let kind = metrics.action_kind_labels.label(kind);
+            metrics.action_count.with_label_values(&[kind]).get()
+        };
+        assert_eq!(count("test.example.io/primary.only"), 0.0);
+        assert_eq!(count("test.example.io/not.idempotent"), 1.0);
+    }
+
+    #[test]
+    fn step_down_invalidates_shards_cache() {
+        let action = ActionRecord::new(
+            "test.example.io/step.down",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let mut config = AgentConfig::mock();
+        config.cache.max_stale = 60;
+        config.cache.ttl = 60;
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let shards = |role: ShardRole| {
+            let shard = Shard::new("rs0".into(), role, None, None);
+            Shards::new(vec![shard])
+        };
+        context.caches.mock_shards(shards(ShardRole::Primary));
+
+        let mut register = ActionsRegister::default();
+        register.register(StepDown);
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        let cached = context.caches.mock_shards(shards(ShardRole::Secondary));
+        assert_eq!(cached.shards[0].role, ShardRole::Secondary);
+    }
+
+    #[test]
+    fn singleton_actions_run_on_one_agent_at_a_time() {
+        let lock_dir = std::env::temp_dir().join(format!("repliagent-locks-{}", Uuid::new_v4()));
+        std::fs::create_dir(&lock_dir).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let running = Arc::new(AtomicUsize::new(0));
+
+        // Two agents, each with its own store, sharing the lock directory.
+        let agents: Vec<_> = (0..2)
+            .map(|_| {
+                let mut config = AgentConfig::mock();
+                config.actions.coordination = Some(CoordinationConfig {
+                    fail_fast: false,
+                    lock_dir: lock_dir.to_string_lossy().to_string(),
+                    stale_after: 600,
+                });
+                let context = AgentContext::mock_with_config(config);
+                let action = ActionRecord::new(
+                    "test.example.io/singleton",
+                    None,
+                    None,
+                    json!({}),
+                    ActionRequester::AgentApi,
+                );
+                let id = action.id.to_string();
+                context
+                    .store
+                    .with_transaction(|tx| tx.action().insert(action, None))
+                    .unwrap();
+                let barrier = Arc::clone(&barrier);
+                let max_running = Arc::clone(&max_running);
+                let running = Arc::clone(&running);
+                thread::spawn(move || {
+                    let mut register = ActionsRegister::default();
+                    register.register(Singleton {
+                        max_running,
+                        running,
+                    });
+                    let state = || {
+                        context
+                            .store
+                            .with_transaction(|tx| tx.action().get(&id, None))
+                            .unwrap()
+                            .unwrap()
+                            .state()
+                            .clone()
+                    };
+                    ACTIONS::test_with(register, || {
+                        let engine = Engine::new(context.clone());
+                        barrier.wait();
+                        for _ in 0..100 {
+                            engine.poll().expect("poll failed to process action");
+                            if state() == ActionState::Done {
+                                break;
+                            }
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                    });
+                    state()
+                })
+            })
+            .collect();
+        let states: Vec<ActionState> = agents
+            .into_iter()
+            .map(|agent| agent.join().unwrap())
+            .collect();
+        std::fs::remove_dir_all(&lock_dir).unwrap();
+        assert_eq!(states, vec![ActionState::Done, ActionState::Done]);
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+    }
 
     #[test]
     fn fail_action_with_unkown_kind() {
@@ -237,6 +1323,177 @@ mod tests {
         assert_eq!(payload.error, "actions with kind test are not available");
     }
 
+    #[test]
+    fn timeout_override_enforced() {
+        let mut action = ActionRecord::new(
+            "agent.replicante.io/debug.progress",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        action.scheduled_ts = Utc::now() - chrono::Duration::seconds(10);
+        action.timeout_override = Some(5);
+        let id = action.id;
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register_reserved(Progress {});
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        let payload: SerializableFail = serde_json::from_value(payload).unwrap();
+        assert_eq!(
+            payload.error,
+            format!("action with id '{}' timed out after 5 seconds", id)
+        );
+    }
+
+    #[test]
+    fn only_invocations_in_progress_monitored() {
+        let deferred = ActionRecord::new(
+            "test.example.io/primary.only",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let running = ActionRecord::new(
+            "agent.replicante.io/debug.progress",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let deferred_id = deferred.id.to_string();
+        let running_id = running.id;
+        let mut config = AgentConfig::mock();
+        config.actions.heartbeat_timeout = Some(60);
+        config.defer_primary_ops = Some(600);
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(deferred, None)?;
+                tx.action().insert(running, None)
+            })
+            .unwrap();
+        let mut agent = MockAgent::new();
+        agent.shards = Ok(Shards::new(vec![Shard::new(
+            "rs0".into(),
+            ShardRole::Secondary,
+            None,
+            None,
+        )]));
+        let mut register = ActionsRegister::default();
+        register.register(PrimaryOnly {
+            calls: Arc::new(AtomicUsize::new(0)),
+        });
+        register.register_reserved(Progress {});
+        let state = |id: &str| {
+            context
+                .store
+                .with_transaction(|tx| tx.action().get(id, None))
+                .unwrap()
+                .unwrap()
+                .state()
+                .clone()
+        };
+        let heartbeat_before = Utc::now() + chrono::Duration::seconds(5);
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone()).with_agent(Arc::new(agent));
+            engine.poll().expect("poll failed to process action");
+
+            // Neither deferred actions nor actions between invocations are monitored.
+            engine
+                .fail_unresponsive(heartbeat_before, 60, None)
+                .expect("monitor failed to check heartbeats");
+            assert_eq!(ActionState::New, state(&deferred_id));
+            assert_eq!(ActionState::Running, state(&running_id.to_string()));
+
+            // Actions the engine is invoking are not failed while the invocation runs.
+            engine.next(None).expect("failed to start invoking action");
+            let invoking = Invoking::new(&engine.invocations, running_id);
+            engine
+                .fail_unresponsive(heartbeat_before, 60, None)
+                .expect("monitor failed to check heartbeats");
+            assert_eq!(ActionState::Running, state(&running_id.to_string()));
+            drop(invoking);
+            engine
+                .fail_unresponsive(heartbeat_before, 60, None)
+                .expect("monitor failed to check heartbeats");
+        });
+        assert_eq!(ActionState::New, state(&deferred_id));
+        assert_eq!(ActionState::Failed, state(&running_id.to_string()));
+    }
+
+    #[test]
+    fn stale_heartbeat_fails_action() {
+        let action = ActionRecord::new(
+            "agent.replicante.io/debug.progress",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        let mut config = AgentConfig::mock();
+        config.actions.heartbeat_timeout = Some(60);
+        let context = AgentContext::mock_with_config(config);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register_reserved(Progress {});
+        let state = || {
+            context
+                .store
+                .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+                .unwrap()
+                .unwrap()
+        };
+        ACTIONS::test_with(register, || {
+            // Simulate a crash after the invocation started.
+            let engine = Engine::new(context.clone());
+            engine.next(None).expect("failed to start invoking action");
+
+            // The heartbeat recorded by the invocation is still fresh.
+            engine
+                .monitor()
+                .expect("monitor failed to check heartbeats");
+            assert_eq!(ActionState::New, *state().state());
+
+            // Check as if the timeout elapsed without the executor coming back.
+            let heartbeat_before = Utc::now() + chrono::Duration::seconds(5);
+            engine
+                .fail_unresponsive(heartbeat_before, 60, None)
+                .expect("monitor failed to check heartbeats");
+        });
+        let action = state();
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        let payload: SerializableFail = serde_json::from_value(payload).unwrap();
+        assert_eq!(
+            payload.error,
+            format!(
+                "executor unresponsive: action with id '{}' sent no heartbeat for 60 seconds",
+                id
+            )
+        );
+    }
+
     #[test]
     fn no_action_noop() {
         let context = AgentContext::mock();
@@ -273,4 +1530,90 @@ mod tests {
         assert_eq!(id, action.id);
         assert_eq!(ActionState::Running, *action.state());
     }
+
+    #[test]
+    fn not_idempotent_clears_marker_on_success() {
+        let action = ActionRecord::new(
+            "test.example.io/not.idempotent",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(NotIdempotent {
+            calls: calls.clone(),
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let (action, invoked) = context
+            .store
+            .with_transaction(|tx| {
+                let action = tx.action().get(&id.to_string(), None)?.unwrap();
+                let invoked = tx.action().invoked(&action, None)?;
+                Ok((action, invoked))
+            })
+            .unwrap();
+        assert_eq!(ActionState::Running, *action.state());
+        assert!(!invoked);
+    }
+
+    #[test]
+    fn not_idempotent_replay_after_crash_fails() {
+        let action = ActionRecord::new(
+            "test.example.io/not.idempotent",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        let context = AgentContext::mock();
+        // Simulate a crash after the action was invoked but before the outcome was stored.
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action().mark_invoked(&action, true, None)
+            })
+            .unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut register = ActionsRegister::default();
+        register.register(NotIdempotent {
+            calls: calls.clone(),
+        });
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action
+            .state_payload()
+            .clone()
+            .expect("need a state payload");
+        let payload: SerializableFail = serde_json::from_value(payload).unwrap();
+        assert_eq!(
+            payload.error,
+            format!(
+                "action with id '{}' was already invoked and is not safe to invoke again",
+                id
+            ),
+        );
+    }
 }
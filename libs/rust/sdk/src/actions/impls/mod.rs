@@ -11,7 +11,9 @@ use crate::Result;
 #[cfg(any(debug_assertions, test))]
 pub(crate) mod debug;
 mod external;
+mod plugin;
 mod service;
+mod store;
 mod test;
 
 /// Register standard agent actions.
@@ -22,7 +24,9 @@ pub fn register_std_actions(
     debug!(context.logger, "Registering standard actions");
     let graceful = hooks.get(&ActionHook::StoreGracefulStop).cloned();
     self::external::register(context)?;
+    self::plugin::register(context)?;
     self::service::register(context, graceful);
+    self::store::register(context);
     self::test::register(context);
 
     #[cfg(any(debug_assertions, test))]
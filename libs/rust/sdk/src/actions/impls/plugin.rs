@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+use failure::ResultExt;
+use opentracingrust::Span;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use serde_json::Value as Json;
+use slog::debug;
+use slog::Logger;
+use uuid::Uuid;
+
+use crate::actions::Action;
+use crate::actions::ActionDescriptor;
+use crate::actions::ActionRecordView;
+use crate::actions::ActionState;
+use crate::actions::ActionValidity;
+use crate::actions::ACTIONS;
+use crate::store::Transaction;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+
+pub fn register(context: &AgentContext) -> Result<()> {
+    let dir = match context.config.actions.plugins_dir.as_ref() {
+        None => return Ok(()),
+        Some(dir) => dir,
+    };
+    debug!(context.logger, "Registering action plugins"; "dir" => dir);
+    for plugin in discover(Path::new(dir), &context.logger)? {
+        ACTIONS::register_reserved(plugin);
+    }
+    Ok(())
+}
+
+/// Find executables in `dir` and create a plugin action for each of them.
+///
+/// Plugins are registered as `plugin.agent.replicante.io/<FILE NAME>` action kinds.
+fn discover(dir: &Path, logger: &Logger) -> Result<Vec<PluginAction>> {
+    let error = || ErrorKind::Initialisation(format!("unable to list plugins in {:?}", dir));
+    let mut plugins = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|_| error())? {
+        let path = entry.with_context(|_| error())?.path();
+        let metadata = fs::metadata(&path).with_context(|_| error())?;
+        if !metadata.is_file() || !is_executable(&metadata) {
+            debug!(logger, "Skipping non-executable file in plugins directory"; "path" => ?path);
+            continue;
+        }
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let kind = format!("plugin.agent.replicante.io/{}", name);
+        plugins.push(PluginAction::new(kind, path, logger.clone()));
+    }
+    plugins.sort_by(|left, right| left.kind.cmp(&right.kind));
+    Ok(plugins)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_: &fs::Metadata) -> bool {
+    true
+}
+
+/// Execute actions implemented by external executables.
+///
+/// Plugins are invoked every time the action is invoked with the action record as JSON
+/// on standard input and must print a JSON report to standard output:
+///
+///   * `{"status": "finished", "payload": ...}` when the action completed successfully.
+///   * `{"status": "failed", "payload": ...}` when the action failed.
+///   * `{"status": "running", "payload": ...}` when the plugin needs to be invoked again.
+///
+/// The optional payload is stored as the action state payload.
+#[derive(Debug)]
+pub struct PluginAction {
+    kind: String,
+    logger: Logger,
+    path: PathBuf,
+}
+
+impl PluginAction {
+    pub fn new(kind: String, path: PathBuf, logger: Logger) -> PluginAction {
+        PluginAction { kind, logger, path }
+    }
+
+    /// Run the plugin executable and decode its report.
+    fn exec(&self, record: &dyn ActionRecordView) -> Result<PluginReport> {
+        let action_id = ActionRecordView::id(record);
+        let info = PluginActionInfo {
+            args: record.args().clone(),
+            headers: ActionRecordView::headers(record).clone(),
+            id: action_id,
+            kind: self.kind.clone(),
+            state: record.state().clone(),
+            state_payload: record.state_payload().clone(),
+        };
+        let info = serde_json::to_vec(&info)
+            .with_context(|_| ErrorKind::ExternalActionStart(self.kind.clone(), action_id))?;
+        let mut child = Command::new(&self.path)
+            .stderr(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|_| ErrorKind::ExternalActionStart(self.kind.clone(), action_id))?;
+        {
+            let stdin = child.stdin.as_mut().expect("failed to open stdin");
+            stdin
+                .write_all(&info)
+                .with_context(|_| ErrorKind::ExternalActionStart(self.kind.clone(), action_id))?;
+        }
+        let output = child
+            .wait_with_output()
+            .with_context(|_| ErrorKind::ExternalActionStart(self.kind.clone(), action_id))?;
+        let stdout =
+            String::from_utf8(output.stdout).unwrap_or_else(|_| "{binary blob}".to_string());
+        debug!(
+            self.logger,
+            "Action plugin executed";
+            "action_id" => %action_id,
+            "kind" => &self.kind,
+            "stdout" => &stdout,
+        );
+        if !output.status.success() {
+            let stderr =
+                String::from_utf8(output.stderr).unwrap_or_else(|_| "{binary blob}".to_string());
+            let error = ErrorKind::ExternalActionExec(action_id, stdout, stderr);
+            return Err(error.into());
+        }
+        let report = serde_json::from_str(&stdout)
+            .with_context(|_| ErrorKind::ExternalActionCheckDecode(action_id))?;
+        Ok(report)
+    }
+}
+
+impl Action for PluginAction {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            description: format!("Action plugin at {}", self.path.display()),
+            kind: self.kind.clone(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let report = self.exec(record)?;
+        let (state, payload) = match report {
+            PluginReport::Failed(payload) => (ActionState::Failed, payload),
+            PluginReport::Finished(payload) => (ActionState::Done, payload),
+            PluginReport::Running(payload) => (ActionState::Running, payload),
+        };
+        tx.action().transition(
+            record,
+            state,
+            payload.payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PluginActionInfo {
+    args: Json,
+    headers: HashMap<String, String>,
+    id: Uuid,
+    kind: String,
+    state: ActionState,
+    state_payload: Option<Json>,
+}
+
+/// Expected outcomes from a plugin execution.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status")]
+enum PluginReport {
+    #[serde(rename = "failed")]
+    Failed(PluginPayload),
+
+    #[serde(rename = "finished")]
+    Finished(PluginPayload),
+
+    #[serde(rename = "running")]
+    Running(PluginPayload),
+}
+
+/// Optional payload attached to plugin reports.
+#[derive(Serialize, Deserialize)]
+struct PluginPayload {
+    #[serde(default)]
+    payload: Option<Json>,
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::discover;
+    use crate::actions::engine::Engine;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRecordView;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::actions::ActionsRegister;
+    use crate::actions::ACTIONS;
+    use crate::AgentContext;
+
+    /// Sample plugin echoing the action information it receives as the payload.
+    const ECHO_PLUGIN: &str = r#"#!/bin/sh
+read -r input
+printf '{"status": "finished", "payload": %s}' "$input"
+"#;
+
+    fn plugins_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("repliagent-plugins-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let plugin = dir.join("echo");
+        fs::write(&plugin, ECHO_PLUGIN).unwrap();
+        fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("README"), "not a plugin").unwrap();
+        dir
+    }
+
+    #[test]
+    fn plugin_runs_end_to_end() {
+        let context = AgentContext::mock();
+        let dir = plugins_dir();
+        let plugins = discover(&dir, &context.logger);
+        let mut register = ActionsRegister::default();
+        for plugin in plugins.unwrap() {
+            register.register_reserved(plugin);
+        }
+
+        let args = json!({"answer": 42});
+        let action = ActionRecord::new(
+            "plugin.agent.replicante.io/echo",
+            None,
+            None,
+            args.clone(),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id;
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        fs::remove_dir_all(&dir).unwrap();
+
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ActionState::Done, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["args"], args);
+        assert_eq!(payload["kind"], json!("plugin.agent.replicante.io/echo"));
+        assert_eq!(payload["state"], json!("NEW"));
+    }
+}
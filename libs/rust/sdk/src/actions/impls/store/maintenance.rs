@@ -0,0 +1,59 @@
+use opentracingrust::Span;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use crate::actions::Action;
+use crate::actions::ActionDescriptor;
+use crate::actions::ActionRecordView;
+use crate::actions::ActionState;
+use crate::actions::ActionValidity;
+use crate::store::Store;
+use crate::store::Transaction;
+use crate::Result;
+
+/// Compact the agent store to reclaim space left by pruned actions.
+///
+/// Compaction uses a dedicated connection and blocks other writers until complete
+/// so it never runs concurrently with action updates.
+pub struct Maintenance {
+    store: Store,
+}
+
+impl Maintenance {
+    pub fn new(store: Store) -> Maintenance {
+        Maintenance { store }
+    }
+}
+
+impl Action for Maintenance {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/store.maintenance".into(),
+            description: "Compact the agent store and report reclaimed space".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let report = self.store.maintenance()?;
+        let payload = json!({
+            "size_before": report.size_before,
+            "size_after": report.size_after,
+            "reclaimed": report.reclaimed(),
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
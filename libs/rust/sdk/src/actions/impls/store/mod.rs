@@ -0,0 +1,13 @@
+use slog::debug;
+
+use crate::actions::ACTIONS;
+use crate::AgentContext;
+
+mod maintenance;
+
+/// Register all store actions.
+pub fn register(context: &AgentContext) {
+    debug!(context.logger, "Registering store actions");
+    let store = context.store.clone();
+    ACTIONS::register_reserved(self::maintenance::Maintenance::new(store));
+}
@@ -15,6 +15,8 @@ use crate::ErrorKind;
 use crate::Result;
 
 pub mod advanced;
+mod authorization;
+mod coordination;
 mod definition;
 mod engine;
 mod impls;
@@ -23,6 +25,10 @@ mod register;
 mod tests;
 pub mod utils;
 
+pub use self::authorization::ActionAuthorization;
+pub use self::authorization::ActionAuthorizer;
+pub use self::authorization::AllowAll;
+pub use self::authorization::RequestIdentity;
 pub use self::definition::Action;
 pub use self::definition::ActionDescriptor;
 pub use self::definition::ActionHistoryItem;
@@ -36,6 +42,7 @@ pub use self::definition::ActionValidity;
 pub use self::definition::ActionValidityError;
 pub use self::register::ActionsRegister;
 pub use self::register::ACTIONS;
+pub use crate::config::CachedResponse;
 
 lazy_static::lazy_static! {
     /// Codified version of the state transitions from docs/docs/assets/action-states.dot
@@ -65,10 +72,18 @@ lazy_static::lazy_static! {
 ///   * Agent actions can be explicitly disabled with the `actions.enabled` option.
 ///   * An error is returned if `actions.enabled` is `true` but `tls.clients_ca_bundle`
 ///     is not set.
+///   * An error is returned if both `actions.enabled_kinds` and `actions.disabled_kinds`
+///     are set.
 pub fn actions_enabled(config: &Config) -> Result<bool> {
     if let Some(false) = config.actions.enabled {
         return Ok(false);
     }
+    if config.actions.enabled_kinds.is_some() && config.actions.disabled_kinds.is_some() {
+        return Err(ErrorKind::ConfigClash(
+            "can't set both actions.enabled_kinds and actions.disabled_kinds",
+        )
+        .into());
+    }
     let mutual_tls = config
         .api
         .tls
@@ -103,9 +118,14 @@ pub fn ensure_transition_allowed(from: &ActionState, to: &ActionState) {
     }
 }
 
+/// Check if the action state is final and the action can't transition any further.
+pub fn is_finished(state: &ActionState) -> bool {
+    !ALLOWED_TRANSITIONS.contains_key(state)
+}
+
 /// Initialise the actions system based on configuration.
 pub fn initialise(
-    agent: &dyn Agent,
+    agent: &Arc<dyn Agent>,
     context: &mut AgentContext,
     upkeep: &mut Upkeep,
 ) -> Result<()> {
@@ -116,16 +136,56 @@ pub fn initialise(
     }
 
     debug!(context.logger, "Initialising actions system ...");
-    let hooks = self::register_agent_actions(agent, context);
+    let hooks = self::register_agent_actions(agent.as_ref(), context);
     self::impls::register_std_actions(context, hooks)?;
     ACTIONS::complete_registration();
     debug!(context.logger, "Actions registration phase completed");
+    if context.config.actions.validate_descriptors {
+        self::validate_descriptors()?;
+    }
 
-    self::engine::spawn(context.clone(), upkeep)?;
+    self::engine::spawn(Arc::clone(agent), context.clone(), upkeep)?;
     info!(context.logger, "Actions system initialised");
     Ok(())
 }
 
+/// Check all registered actions have well formed descriptors.
+///
+/// Kinds must follow the `SCOPE/ACTION` format with a DNS like `SCOPE` (dot separated,
+/// with no empty components) and a non-empty `ACTION`. Descriptions can't be empty.
+fn validate_descriptors() -> Result<()> {
+    for action in ACTIONS::iter() {
+        let descriptor = action.describe();
+        if let Some(problem) = descriptor_problem(&descriptor) {
+            let message = format!("action kind '{}' {}", descriptor.kind, problem);
+            return Err(ErrorKind::Initialisation(message).into());
+        }
+    }
+    Ok(())
+}
+
+/// Describe what is wrong with an action descriptor, if anything.
+fn descriptor_problem(descriptor: &ActionDescriptor) -> Option<&'static str> {
+    let kind = &descriptor.kind;
+    if kind.is_empty() {
+        return Some("is empty");
+    }
+    let (scope, action) = match kind.find('/') {
+        None => return Some("is not scoped"),
+        Some(index) => (&kind[..index], &kind[index + 1..]),
+    };
+    if !scope.contains('.') || scope.split('.').any(str::is_empty) {
+        return Some("does not have a DNS like scope");
+    }
+    if action.is_empty() {
+        return Some("does not name an action");
+    }
+    if descriptor.description.trim().is_empty() {
+        return Some("has no description");
+    }
+    None
+}
+
 /// Register standard agent actions.
 fn register_agent_actions(
     agent: &dyn Agent,
@@ -15,6 +15,8 @@ use super::ActionRecordView;
 use super::ActionState;
 use super::ActionValidity;
 use super::ActionValidityError;
+use super::ActionsRegister;
+use super::ACTIONS;
 use crate::config::Agent as Config;
 use crate::config::TlsConfig;
 use crate::store::Transaction;
@@ -45,6 +47,31 @@ impl Action for TestAction {
     }
 }
 
+/// Action with a kind that is scoped but not by a DNS like scope.
+struct MalformedAction {}
+
+impl Action for MalformedAction {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "mock/action".into(),
+            description: "Action with a malformed kind".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        _: &mut Transaction,
+        _: &dyn ActionRecordView,
+        _: Option<&mut Span>,
+    ) -> Result<()> {
+        panic!("TODO: MalformedAction::invoke")
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
 #[test]
 fn disabled_by_default() {
     let config = Config::mock();
@@ -92,6 +119,55 @@ fn enabled_explicitly_without_tls() {
     };
 }
 
+#[test]
+fn enabled_and_disabled_kinds_clash() {
+    let mut config = Config::mock();
+    config.actions.disabled_kinds = Some(vec!["replicante.io/test.fail".into()]);
+    config.actions.enabled_kinds = Some(vec!["replicante.io/test.success".into()]);
+    match super::actions_enabled(&config) {
+        Ok(_) => panic!("expected configuration error"),
+        Err(error) => assert_eq!(error.name().unwrap(), "ConfigClash"),
+    };
+}
+
+#[test]
+fn finished_states() {
+    assert!(super::is_finished(&ActionState::Done));
+    assert!(super::is_finished(&ActionState::Failed));
+    assert!(!super::is_finished(&ActionState::New));
+    assert!(!super::is_finished(&ActionState::Running));
+}
+
+#[test]
+fn states_serde_round_trip() {
+    let states = vec![
+        ActionState::New,
+        ActionState::Running,
+        ActionState::Done,
+        ActionState::Failed,
+    ];
+    for state in states {
+        let encoded = serde_json::to_value(&state).unwrap();
+        let decoded: ActionState = serde_json::from_value(encoded).unwrap();
+        assert_eq!(decoded, state);
+    }
+}
+
+#[test]
+fn malformed_descriptors_fail_startup() {
+    let mut register = ActionsRegister::default();
+    register.register_reserved(TestAction {});
+    ACTIONS::test_with(register.clone(), || {
+        super::validate_descriptors().expect("valid descriptors rejected");
+    });
+
+    register.register(MalformedAction {});
+    ACTIONS::test_with(register, || match super::validate_descriptors() {
+        Ok(_) => panic!("expected initialisation error"),
+        Err(error) => assert_eq!(error.name().unwrap(), "Initialisation"),
+    });
+}
+
 #[actix_rt::test]
 async fn validation_fails() {
     let mut app =
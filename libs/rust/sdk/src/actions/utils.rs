@@ -1,22 +1,180 @@
+use std::collections::BTreeMap;
+
+use failure::Fail;
 use serde::de::DeserializeOwned;
 use serde_json::Value as Json;
+use serde_path_to_error::Path;
 
 use crate::actions::ActionValidity;
 use crate::actions::ActionValidityError;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Value replacing redacted action arguments.
+pub const REDACTED: &str = "<redacted>";
+
+/// Ensure action arguments are within the given nesting depth and number of values.
+///
+/// Arguments are walked iteratively so pathological inputs are rejected
+/// before any (possibly recursive) validation is attempted.
+pub fn check_args_limits(args: &Json, max_depth: u32, max_nodes: u32) -> Result<()> {
+    let mut nodes: u32 = 0;
+    let mut stack = vec![(args, 0)];
+    while let Some((value, depth)) = stack.pop() {
+        nodes += 1;
+        if nodes > max_nodes {
+            let reason = format!("action arguments have more than {} values", max_nodes);
+            return Err(failure::err_msg(reason)
+                .context(ErrorKind::ActionDecode)
+                .into());
+        }
+        if depth > max_depth {
+            let reason = format!("action arguments are nested deeper than {}", max_depth);
+            return Err(failure::err_msg(reason)
+                .context(ErrorKind::ActionDecode)
+                .into());
+        }
+        match value {
+            Json::Array(items) => stack.extend(items.iter().map(|item| (item, depth + 1))),
+            Json::Object(fields) => stack.extend(fields.values().map(|field| (field, depth + 1))),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Replace the values of the given fields, at any depth, with `REDACTED`.
+pub fn redact_args(args: &mut Json, fields: &[String]) {
+    match args {
+        Json::Array(items) => items.iter_mut().for_each(|item| redact_args(item, fields)),
+        Json::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|field| field == key) {
+                    *value = Json::String(REDACTED.into());
+                } else {
+                    redact_args(value, fields);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Check if any value, at any depth, was replaced by `redact_args`.
+pub fn has_redacted_args(args: &Json) -> bool {
+    match args {
+        Json::Array(items) => items.iter().any(has_redacted_args),
+        Json::Object(object) => object.values().any(has_redacted_args),
+        Json::String(value) => value == REDACTED,
+        _ => false,
+    }
+}
+
+/// Replace `{{ name }}` placeholders in string arguments, at any depth, with the given values.
+///
+/// Unknown and unterminated placeholders are reported against the argument containing them.
+pub fn template_args(args: &mut Json, values: &BTreeMap<String, String>) -> ActionValidity<()> {
+    template_value(args, values, &mut Vec::new())
+}
+
+fn template_value(
+    value: &mut Json,
+    values: &BTreeMap<String, String>,
+    path: &mut Vec<String>,
+) -> ActionValidity<()> {
+    match value {
+        Json::String(text) => {
+            *text = template_string(text, values).map_err(|reason| {
+                ActionValidityError::InvalidField {
+                    field: path.join("."),
+                    reason,
+                }
+            })?;
+        }
+        Json::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push(index.to_string());
+                template_value(item, values, path)?;
+                path.pop();
+            }
+        }
+        Json::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                path.push(key.clone());
+                template_value(field, values, path)?;
+                path.pop();
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn template_string(
+    text: &str,
+    values: &BTreeMap<String, String>,
+) -> std::result::Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let end = placeholder
+            .find("}}")
+            .ok_or_else(|| "unterminated `{{` placeholder".to_string())?;
+        let name = placeholder[..end].trim();
+        let value = values
+            .get(name)
+            .ok_or_else(|| format!("unknown placeholder `{{{{ {} }}}}`", name))?;
+        result.push_str(value);
+        rest = &placeholder[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
 
 /// Validate the JSON arguments can be decoded in the given type T.
+///
+/// Errors report the path of the invalid argument (`a.b.0.c`) along with the reason.
 pub fn validate_action_args<T>(args: Json) -> ActionValidity<T>
 where
     T: DeserializeOwned,
 {
-    match serde_json::from_value(args) {
-        Ok(args) => Ok(args),
-        Err(error) => Err(ActionValidityError::InvalidArgs(error.to_string())),
+    serde_path_to_error::deserialize(args).map_err(|error| {
+        let reason = error.inner().to_string();
+        ActionValidityError::InvalidField {
+            field: field_path(error.path(), &reason),
+            reason,
+        }
+    })
+}
+
+/// Path to the argument an error refers to.
+///
+/// Missing and unknown fields are reported against the object containing them
+/// so the name of the field is taken from the error reason.
+fn field_path(path: &Path, reason: &str) -> String {
+    let mut field = path.to_string();
+    if field == "." {
+        field.clear();
+    }
+    if reason.starts_with("missing field `") || reason.starts_with("unknown field `") {
+        if let Some(name) = reason.split('`').nth(1) {
+            if !field.is_empty() {
+                field.push('.');
+            }
+            field.push_str(name);
+        }
     }
+    field
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
+    use actix_web::body::Body;
+    use actix_web::ResponseError;
     use serde_derive::Deserialize;
     use serde_json::json;
 
@@ -29,12 +187,66 @@ mod tests {
         b: bool,
     }
 
+    #[derive(Deserialize, Debug)]
+    struct NestedArgs {
+        #[allow(dead_code)]
+        nested: Vec<TestArgs>,
+    }
+
     #[test]
     fn args_not_valid() {
         let args = json!({"b": true});
         let args: ActionValidity<TestArgs> = super::validate_action_args(args);
         match args {
-            Err(ActionValidityError::InvalidArgs(_)) => (),
+            Err(ActionValidityError::InvalidField { field, reason }) => {
+                assert_eq!(field, "a");
+                assert_eq!(reason, "missing field `a`");
+            }
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn args_not_valid_nested() {
+        let args = json!({"nested": [{"a": "c", "b": true}, {"a": "d", "b": 42}]});
+        let args: ActionValidity<NestedArgs> = super::validate_action_args(args);
+        match args {
+            Err(ActionValidityError::InvalidField { field, .. }) => {
+                assert_eq!(field, "nested.1.b");
+            }
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn args_not_valid_response_lists_fields() {
+        let args = json!({"a": "c"});
+        let error = super::validate_action_args::<TestArgs>(args).unwrap_err();
+        let response = error.error_response();
+        let body = match response.body().as_ref() {
+            Some(Body::Bytes(body)) => body.clone(),
+            other => panic!("unexpected body: {:?}", other),
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let expected = json!([{"field": "b", "reason": "missing field `b`"}]);
+        assert_eq!(body["fields"], expected);
+        assert_eq!(body["kind"], "InvalidArgs");
+    }
+
+    #[test]
+    fn template_unknown_placeholder_rejected() {
+        let mut values = BTreeMap::new();
+        values.insert("node_name".to_string(), "node-1".to_string());
+        let mut args = json!({"nodes": ["{{ node_name }}", "{{node_name}}-backup"]});
+        super::template_args(&mut args, &values).unwrap();
+        assert_eq!(args, json!({"nodes": ["node-1", "node-1-backup"]}));
+
+        let mut args = json!({"target": {"host": "{{ datacenter }}"}});
+        match super::template_args(&mut args, &values) {
+            Err(ActionValidityError::InvalidField { field, reason }) => {
+                assert_eq!(field, "target.host");
+                assert_eq!(reason, "unknown placeholder `{{ datacenter }}`");
+            }
             other => panic!("unexpected value: {:?}", other),
         }
     }
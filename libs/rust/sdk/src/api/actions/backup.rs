@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::web::Bytes;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use failure::ResultExt;
+use futures::stream;
+use serde_json::json;
+use uuid::Uuid;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+use replicante_util_tracing::fail_span;
+
+use crate::actions::utils::has_redacted_args;
+use crate::actions::utils::redact_args;
+use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
+use crate::AgentContext;
+use crate::Error;
+use crate::ErrorKind;
+
+/// Number of action records fetched from the store at once while exporting.
+const EXPORT_PAGE_SIZE: u32 = 100;
+
+/// Maximum size, in bytes, of NDJSON bodies accepted by the import endpoint.
+const IMPORT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Export all action records as NDJSON (one record per line).
+///
+/// Records are fetched from the store one page at a time as the response is sent.
+/// Like all actions endpoints, this is only available if clients are authenticated
+/// with mutual TLS (or the actions system is explicitly enabled).
+pub fn export(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/export");
+    web::resource("/export")
+        .wrap(tracer)
+        .route(web::get().to(export_responder))
+}
+
+async fn export_responder(context: web::Data<AgentContext>) -> impl Responder {
+    let records = ExportRecords::new(context.get_ref().clone());
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream::iter(records))
+}
+
+/// Import action records from an NDJSON body, such as one returned by `export`.
+///
+/// All records are imported in one transaction: if any record fails to decode,
+/// already exists or is not importable nothing is imported.
+///
+/// Only finished records can be imported: pending or running records would be
+/// executed without the checks applied when actions are scheduled.
+/// Records with redacted arguments are rejected as their original arguments are lost.
+pub fn import(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/import");
+    web::resource("/import")
+        .app_data(web::PayloadConfig::new(IMPORT_MAX_SIZE))
+        .wrap(tracer)
+        .route(web::post().to(import_responder))
+}
+
+async fn import_responder(
+    context: web::Data<AgentContext>,
+    body: Bytes,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let records = with_request_span(&mut request, |span| {
+        let records = decode_records(&body).map_err(|error| fail_span(error, span))?;
+        for record in &records {
+            ensure_importable(record).map_err(|error| fail_span(error, span))?;
+        }
+        Ok(records)
+    })?;
+    let imported = records.len();
+    with_request_span(&mut request, |span| {
+        let span_context = span.as_ref().map(|span| span.context().clone());
+        context
+            .store
+            .with_transaction(|tx| {
+                for record in records {
+                    tx.action().insert(record, span_context.clone())?;
+                }
+                Ok(())
+            })
+            .map_err(|error| fail_span(error, span))
+    })?;
+    Ok(HttpResponse::Ok().json(json!({ "imported": imported })))
+}
+
+/// Iterate over NDJSON encoded action records, fetching pages from the store as needed.
+struct ExportRecords {
+    after: Option<Uuid>,
+    context: AgentContext,
+    done: bool,
+    page: std::vec::IntoIter<ActionRecord>,
+}
+
+impl ExportRecords {
+    fn new(context: AgentContext) -> ExportRecords {
+        ExportRecords {
+            after: None,
+            context,
+            done: false,
+            page: Vec::new().into_iter(),
+        }
+    }
+
+    fn fetch(&self) -> crate::Result<Vec<ActionRecord>> {
+        let after = self.after;
+        self.context.store.with_transaction(|tx| {
+            tx.actions()
+                .export(after, EXPORT_PAGE_SIZE, None)?
+                .collect()
+        })
+    }
+}
+
+impl Iterator for ExportRecords {
+    type Item = std::result::Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.page.next() {
+                self.after = Some(record.id);
+                let config = &self.context.config.actions;
+                let mut redacted = config.export_redacted_args.clone();
+                if let Some(fields) = config.redacted_args.get(&record.kind) {
+                    redacted.extend(fields.iter().cloned());
+                }
+                return Some(encode_record(&record, &redacted));
+            }
+            if self.done {
+                return None;
+            }
+            match self.fetch() {
+                Ok(page) => {
+                    self.done = page.len() < EXPORT_PAGE_SIZE as usize;
+                    self.page = page.into_iter();
+                }
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+/// Decode NDJSON action records, ignoring blank lines.
+fn decode_records(body: &[u8]) -> crate::Result<Vec<ActionRecord>> {
+    body.split(|byte| *byte == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|line| {
+            serde_json::from_slice(line)
+                .with_context(|_| ErrorKind::ActionDecode)
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+/// Reject records that are not finished or have redacted arguments.
+fn ensure_importable(record: &ActionRecord) -> crate::Result<()> {
+    let id = record.id.to_string();
+    if !record.state().is_finished() {
+        let error = ErrorKind::ActionNotImportable(id, "only finished actions can be imported");
+        return Err(error.into());
+    }
+    if has_redacted_args(record.args()) {
+        let error = ErrorKind::ActionNotImportable(id, "arguments were redacted on export");
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// Encode an action record as an NDJSON line, redacting sensitive arguments.
+fn encode_record(record: &ActionRecord, redacted: &[String]) -> crate::Result<Bytes> {
+    let mut record = serde_json::to_value(record).with_context(|_| ErrorKind::ActionEncode)?;
+    if let Some(args) = record.get_mut("args") {
+        redact_args(args, redacted);
+    }
+    let mut line = serde_json::to_vec(&record).with_context(|_| ErrorKind::ActionEncode)?;
+    line.push(b'\n');
+    Ok(Bytes::from(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use failure::Fail;
+
+    use super::decode_records;
+    use super::encode_record;
+    use super::ensure_importable;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRecordView;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+
+    #[test]
+    fn export_redacts_sensitive_args() {
+        let args = json!({
+            "user": "admin",
+            "password": "secret",
+            "nodes": [{"host": "a", "password": "also-secret"}],
+        });
+        let record = ActionRecord::new("test", None, None, args, ActionRequester::AgentApi);
+        let redacted = vec!["password".to_string()];
+        let line = encode_record(&record, &redacted).unwrap();
+        let decoded = decode_records(&line).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].id, record.id);
+        let expected = json!({
+            "user": "admin",
+            "password": "<redacted>",
+            "nodes": [{"host": "a", "password": "<redacted>"}],
+        });
+        assert_eq!(decoded[0].args(), &expected);
+    }
+
+    #[test]
+    fn only_finished_records_without_redacted_args_imported() {
+        let new = |args| ActionRecord::new("test", None, None, args, ActionRequester::AgentApi);
+        let mut done = new(json!({"user": "admin"}));
+        done.set_state(ActionState::Done);
+        ensure_importable(&done).unwrap();
+
+        let pending = new(json!({"user": "admin"}));
+        let error = ensure_importable(&pending).unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotImportable");
+
+        let mut redacted = new(json!({"nodes": [{"password": "<redacted>"}]}));
+        redacted.set_state(ActionState::Failed);
+        let error = ensure_importable(&redacted).unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotImportable");
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use actix_web::web;
 use actix_web::HttpResponse;
 use actix_web::Responder;
@@ -11,7 +13,11 @@ use crate::api::APIRoot;
 use crate::api::AppConfigContext;
 
 mod action;
+mod backup;
 mod list;
+mod rate_limit;
+
+pub use self::rate_limit::ActionRateLimiter;
 
 /// Return a list of available agent actions.
 #[actix_web::get("/available")]
@@ -44,17 +50,24 @@ pub fn configure_disabled(conf: &mut AppConfigContext) {
 /// Configure the API server with actions API enabled.
 pub fn configure_enabled(conf: &mut AppConfigContext) {
     APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
+        let export = self::backup::export(&conf.context.agent);
         let finished = self::list::finished(&conf.context.agent);
+        let import = self::backup::import(&conf.context.agent);
         let info = self::action::info(&conf.context.agent);
         let queue = self::list::queue(&conf.context.agent);
-        let schedule = self::action::schedule(&conf.context.agent);
+        let limiter = &conf.context.action_rate_limiter;
+        let replay = self::action::replay(&conf.context.agent, Arc::clone(limiter));
+        let schedule = self::action::schedule(&conf.context.agent, Arc::clone(limiter));
         let scope = web::scope("/actions")
             .service(index_enabled)
             .service(available)
+            .service(export)
             .service(finished)
+            .service(import)
             .service(queue)
             .service(info)
-            .service(schedule);
+            .service(schedule)
+            .service(replay);
         conf.scoped_service(root.prefix(), scope);
     });
 }
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::actions::RequestIdentity;
+use crate::config::RateLimitConfig;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Token bucket tracking the actions a requester can still create.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limit the rate at which each requester can create actions.
+///
+/// Buckets are kept in memory, shared by all API server workers, for up to
+/// `max_requesters` requesters. Limits are not enforced if `actions.rate_limit` is not set.
+pub struct ActionRateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    config: Option<RateLimitConfig>,
+}
+
+impl ActionRateLimiter {
+    pub fn new(config: Option<RateLimitConfig>) -> ActionRateLimiter {
+        ActionRateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+
+    /// Take a token from the requester's bucket, failing if the bucket is empty.
+    pub fn check(&self, identity: &RequestIdentity) -> Result<()> {
+        let config = match self.config.as_ref() {
+            None => return Ok(()),
+            Some(config) => config,
+        };
+        let key = match identity.peer_addr {
+            None => format!("{:?}", identity.requester),
+            Some(addr) => format!("{:?}@{}", identity.requester, addr.ip()),
+        };
+        let now = Instant::now();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("actions rate limiter lock poisoned");
+        if !buckets.contains_key(&key) && buckets.len() >= config.max_requesters {
+            evict(&mut buckets, config, now);
+        }
+        let bucket = buckets.entry(key.clone()).or_insert_with(|| Bucket {
+            tokens: f64::from(config.burst),
+            updated: now,
+        });
+        bucket.tokens = refill(bucket, config, now);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let wait = retry_after(bucket, config);
+            return Err(ErrorKind::ActionRateLimited(key, wait).into());
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Number of tokens in the bucket after refilling it for the time elapsed since its last update.
+fn refill(bucket: &Bucket, config: &RateLimitConfig, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    let tokens = bucket.tokens + elapsed * f64::from(config.per_minute) / 60.0;
+    tokens.min(f64::from(config.burst))
+}
+
+/// Seconds until the bucket is refilled with a token, rounded up.
+fn retry_after(bucket: &Bucket, config: &RateLimitConfig) -> u64 {
+    let per_second = f64::from(config.per_minute.max(1)) / 60.0;
+    let wait = (1.0 - bucket.tokens) / per_second;
+    wait.ceil().max(1.0) as u64
+}
+
+/// Make room for a new bucket.
+///
+/// Full buckets are dropped first, as they are no different from new buckets.
+/// If all buckets are in use, the least recently updated bucket is dropped.
+fn evict(buckets: &mut HashMap<String, Bucket>, config: &RateLimitConfig, now: Instant) {
+    let burst = f64::from(config.burst);
+    buckets.retain(|_, bucket| refill(bucket, config, now) < burst);
+    if buckets.len() < config.max_requesters {
+        return;
+    }
+    let oldest = buckets
+        .iter()
+        .min_by_key(|(_, bucket)| bucket.updated)
+        .map(|(key, _)| key.clone());
+    if let Some(oldest) = oldest {
+        buckets.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::ResponseError;
+
+    use super::ActionRateLimiter;
+    use crate::actions::ActionRequester;
+    use crate::actions::RequestIdentity;
+    use crate::config::RateLimitConfig;
+
+    #[test]
+    fn requesters_tracked_are_bounded() {
+        let limiter = ActionRateLimiter::new(Some(RateLimitConfig {
+            burst: 1,
+            max_requesters: 2,
+            per_minute: 1,
+        }));
+        let headers = HashMap::new();
+        let requester = ActionRequester::AgentApi;
+        for host in 0..10 {
+            let identity = RequestIdentity {
+                headers: &headers,
+                peer_addr: Some(format!("10.0.0.{}:4000", host).parse().unwrap()),
+                requester: &requester,
+            };
+            limiter.check(&identity).unwrap();
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejections_report_retry_after() {
+        let limiter = ActionRateLimiter::new(Some(RateLimitConfig {
+            burst: 1,
+            max_requesters: 2,
+            per_minute: 2,
+        }));
+        let headers = HashMap::new();
+        let requester = ActionRequester::AgentApi;
+        let identity = RequestIdentity {
+            headers: &headers,
+            peer_addr: Some("10.0.0.1:4000".parse().unwrap()),
+            requester: &requester,
+        };
+        limiter.check(&identity).unwrap();
+        let error = limiter.check(&identity).unwrap_err();
+        let response = error.error_response();
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after = response.headers().get(RETRY_AFTER).unwrap();
+        let retry_after: u64 = retry_after.to_str().unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= 30);
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use failure::Fail;
+#[cfg(test)]
+use replicante_models_agent::info::Shards;
+
+use super::info::DatastoreInfoReport;
+use super::shards::ShardsReport;
+use crate::config::Agent as AgentConfig;
+use crate::config::CacheConfig;
+use crate::config::CachedResponse;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Caches for agent endpoints, shared by all API server workers.
+pub struct ResponseCaches {
+    pub datastore: ResponseCache<DatastoreInfoReport>,
+    pub shards: ResponseCache<ShardsReport>,
+}
+
+impl ResponseCaches {
+    pub fn new(config: &AgentConfig) -> ResponseCaches {
+        let min_interval = Duration::from_millis(config.datastore.min_probe_interval);
+        ResponseCaches {
+            datastore: ResponseCache::new("datastore info", &config.cache, min_interval),
+            shards: ResponseCache::new("shards", &config.cache, min_interval),
+        }
+    }
+
+    /// Drop the cached response of the given kind so the next request fetches it again.
+    pub fn invalidate(&self, kind: CachedResponse) {
+        match kind {
+            CachedResponse::Datastore => self.datastore.invalidate(),
+            CachedResponse::Shards => self.shards.invalidate(),
+        }
+    }
+
+    /// Return the cached shards, caching the given `shards` if none are cached.
+    #[cfg(test)]
+    pub fn mock_shards(&self, shards: Shards) -> Shards {
+        let report = ShardsReport {
+            incomplete: false,
+            shards,
+        };
+        self.shards.get(|| Ok(report)).unwrap().shards
+    }
+}
+
+/// Responses that can be collected only in part.
+pub trait PartialResponse {
+    /// The response is missing information, for example because the request budget ran out.
+    fn incomplete(&self) -> bool;
+}
+
+impl PartialResponse for DatastoreInfoReport {
+    fn incomplete(&self) -> bool {
+        self.incomplete.unwrap_or(false)
+    }
+}
+
+impl PartialResponse for ShardsReport {
+    fn incomplete(&self) -> bool {
+        self.incomplete
+    }
+}
+
+/// Cache a response for up to `ttl` seconds, capped at `max_stale` seconds.
+///
+/// Failed fetches and incomplete responses are never cached. If a cached response expired and can't be refreshed
+/// the error is reported as `CacheExpired` so clients know data is not available.
+///
+/// Even with caching disabled, responses are reused for `min_interval` so request storms
+/// don't turn into datastore probe storms. Requests arriving while a fetch is in progress
+/// wait for it and share its response.
+pub struct ResponseCache<T> {
+    entry: Mutex<Option<(Instant, T)>>,
+    min_interval: Duration,
+    name: &'static str,
+    ttl: Duration,
+}
+
+impl<T: Clone + PartialResponse> ResponseCache<T> {
+    pub fn new(
+        name: &'static str,
+        config: &CacheConfig,
+        min_interval: Duration,
+    ) -> ResponseCache<T> {
+        let max_stale = Duration::from_secs(config.max_stale);
+        let ttl = Duration::from_secs(config.ttl).min(max_stale);
+        let min_interval = min_interval.min(max_stale);
+        ResponseCache {
+            entry: Mutex::new(None),
+            min_interval,
+            name,
+            ttl,
+        }
+    }
+
+    /// Drop the cached response, if any.
+    pub fn invalidate(&self) {
+        let mut entry = self.entry.lock().expect("ResponseCache lock poisoned");
+        *entry = None;
+    }
+
+    /// Return the cached response if still valid, otherwise fetch and cache a new one.
+    pub fn get<F>(&self, fetch: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let reuse_for = self.ttl.max(self.min_interval);
+        if reuse_for == Duration::from_secs(0) {
+            return fetch();
+        }
+        let mut entry = self.entry.lock().expect("ResponseCache lock poisoned");
+        if let Some((cached, response)) = entry.as_ref() {
+            if cached.elapsed() < reuse_for {
+                return Ok(response.clone());
+            }
+        }
+        // Only cached responses expire: responses reused to rate limit probes do not.
+        let expired = entry.take().is_some() && self.ttl > Duration::from_secs(0);
+        match fetch() {
+            Ok(response) => {
+                if !response.incomplete() {
+                    *entry = Some((Instant::now(), response.clone()));
+                }
+                Ok(response)
+            }
+            Err(error) if expired => Err(error.context(ErrorKind::CacheExpired(self.name)).into()),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use failure::Fail;
+
+    use super::PartialResponse;
+    use super::ResponseCache;
+    use crate::config::CacheConfig;
+
+    impl PartialResponse for u32 {
+        fn incomplete(&self) -> bool {
+            false
+        }
+    }
+
+    impl PartialResponse for (u32, bool) {
+        fn incomplete(&self) -> bool {
+            self.1
+        }
+    }
+
+    fn cache(ttl: u64, max_stale: u64) -> ResponseCache<u32> {
+        let config = CacheConfig { max_stale, ttl };
+        ResponseCache::new("test", &config, Duration::from_secs(0))
+    }
+
+    fn rate_limited_cache() -> ResponseCache<u32> {
+        let config = CacheConfig {
+            max_stale: 60,
+            ttl: 0,
+        };
+        ResponseCache::new("test", &config, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn concurrent_requests_share_one_probe() {
+        let cache = Arc::new(rate_limited_cache());
+        let probes = Arc::new(AtomicUsize::new(0));
+        let requests: Vec<_> = (0..20)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let probes = Arc::clone(&probes);
+                thread::spawn(move || {
+                    cache.get(|| {
+                        let probe = probes.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(10));
+                        Ok(probe as u32)
+                    })
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.join().unwrap().unwrap(), 0);
+        }
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn probe_interval_refresh_errors_are_not_expired() {
+        let cache = rate_limited_cache();
+        cache.get(|| Ok(1)).unwrap();
+        {
+            // Age the response past the probe interval.
+            let mut entry = cache.entry.lock().unwrap();
+            entry.as_mut().unwrap().0 -= Duration::from_secs(61);
+        }
+        let error = cache.get(|| Err("test".into())).unwrap_err();
+        assert_ne!(error.name(), Some("CacheExpired"));
+    }
+
+    #[test]
+    fn fresh_responses_are_cached() {
+        let cache = cache(60, 60);
+        assert_eq!(cache.get(|| Ok(1)).unwrap(), 1);
+        assert_eq!(cache.get(|| Ok(2)).unwrap(), 1);
+    }
+
+    #[test]
+    fn incomplete_responses_are_not_cached() {
+        let config = CacheConfig {
+            max_stale: 60,
+            ttl: 60,
+        };
+        let cache = ResponseCache::new("test", &config, Duration::from_secs(0));
+        assert_eq!(cache.get(|| Ok((1, true))).unwrap(), (1, true));
+        assert_eq!(cache.get(|| Ok((2, false))).unwrap(), (2, false));
+        assert_eq!(cache.get(|| Ok((3, false))).unwrap(), (2, false));
+    }
+
+    #[test]
+    fn max_stale_triggers_refresh() {
+        let cache = cache(60, 0);
+        assert_eq!(cache.get(|| Ok(1)).unwrap(), 1);
+        assert_eq!(cache.get(|| Ok(2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn expired_refresh_errors() {
+        let cache = cache(60, 60);
+        cache.get(|| Ok(1)).unwrap();
+        {
+            // Age the cached response past max_stale.
+            let mut entry = cache.entry.lock().unwrap();
+            entry.as_mut().unwrap().0 -= std::time::Duration::from_secs(61);
+        }
+        let error = cache.get(|| Err("test".into())).unwrap_err();
+        assert_eq!(error.name().unwrap(), "CacheExpired");
+        assert_eq!(cache.get(|| Ok(3)).unwrap(), 3);
+    }
+}
@@ -1,79 +1,339 @@
 use std::sync::Arc;
 
 use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::IF_NONE_MATCH;
 use actix_web::web;
 use actix_web::HttpRequest;
-use actix_web::HttpResponse;
 use actix_web::Responder;
 use actix_web::Result;
 use opentracingrust::Log;
+use opentracingrust::Span;
+use serde_derive::Serialize;
+use slog::warn;
 
+use replicante_models_agent::info::AgentInfo;
+use replicante_models_agent::info::DatastoreInfo;
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
+use replicante_util_failure::failure_info;
 use replicante_util_tracing::fail_span;
 
+use super::cache::ResponseCaches;
+use crate::api::headers::json_with_etag;
 use crate::Agent;
 use crate::AgentContext;
+use crate::DatastoreExtras;
+use crate::RequestBudget;
+
+/// Agent information, with the configuration checksum, as reported by the API.
+#[derive(Debug, Serialize)]
+pub struct AgentInfoReport {
+    #[serde(flatten)]
+    pub info: AgentInfo,
+
+    /// Hash of the agent configuration, see `Agent::checksum`.
+    pub config_checksum: String,
+}
+
+/// Datastore information, with optional extras, as reported by the API.
+#[derive(Clone, Debug, Serialize)]
+pub struct DatastoreInfoReport {
+    #[serde(flatten)]
+    pub info: DatastoreInfo,
+
+    /// Key grouping related clusters, from the `cluster_group` option.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_group: Option<String>,
+
+    #[serde(skip_serializing_if = "DatastoreExtras::is_empty")]
+    pub extras: DatastoreExtras,
+
+    /// Set when the request budget ran out before all information was collected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incomplete: Option<bool>,
+}
 
 /// API interface to Agent::agent_info
 pub fn agent(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let config_checksum = context.config.checksum();
     web::resource("/agent")
+        .data(config_checksum)
         .wrap(tracer)
         .route(web::get().to(agent_respoder))
 }
 
 async fn agent_respoder(
     agent: web::Data<Arc<dyn Agent>>,
+    config_checksum: web::Data<String>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
     with_request_span(&mut request, |span| {
         let span = span.expect("unable to find tracing span for request");
         span.log(Log::new().log("span.kind", "server-receive"));
         let info = agent
             .agent_info(span)
             .map_err(|error| fail_span(error, &mut *span))?;
-        let response = HttpResponse::Ok().json(info);
+        let report = AgentInfoReport {
+            info,
+            config_checksum: config_checksum.get_ref().clone(),
+        };
+        let response = json_with_etag(if_none_match.as_ref(), &report);
         span.log(Log::new().log("span.kind", "server-send"));
         Ok(response)
     })
 }
 
 /// API interface to Agent::datastore_info
-pub fn datastore(context: &AgentContext) -> impl HttpServiceFactory {
+pub fn datastore(context: &AgentContext, caches: Arc<ResponseCaches>) -> impl HttpServiceFactory {
     let cluster_display_name_override = context.config.cluster_display_name_override.clone();
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
     web::resource("/datastore")
         .data(cluster_display_name_override)
+        .data(caches)
+        .data(RequestBudget::new(&context.config.datastore))
         .wrap(tracer)
         .route(web::get().to(datastore_responder))
 }
 
 async fn datastore_responder(
     agent: web::Data<Arc<dyn Agent>>,
+    context: web::Data<AgentContext>,
     cluster_display_name_override: web::Data<Option<String>>,
+    caches: web::Data<Arc<ResponseCaches>>,
+    budget: web::Data<RequestBudget>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
     with_request_span(&mut request, |span| {
         let span = span.expect("unable to find tracing span for request");
         span.log(Log::new().log("span.kind", "server-receive"));
-        let mut info = agent
-            .datastore_info(span)
+        let report = caches
+            .datastore
+            .get(|| {
+                let (report, incomplete) = budget.run(|| {
+                    datastore_report(&agent, &context, &cluster_display_name_override, span)
+                });
+                let mut report = report?;
+                if incomplete {
+                    report.incomplete = Some(true);
+                }
+                Ok(report)
+            })
             .map_err(|error| fail_span(error, &mut *span))?;
-
-        // Inject the cluster_display_name override if configured.
-        info.cluster_display_name = cluster_display_name_override
-            .as_ref()
-            .as_ref()
-            .cloned()
-            .or(info.cluster_display_name);
-
-        let response = HttpResponse::Ok().json(info);
+        let response = json_with_etag(if_none_match.as_ref(), &report);
         span.log(Log::new().log("span.kind", "server-send"));
         Ok(response)
     })
 }
+
+/// Fetch datastore information and extras from the agent.
+fn datastore_report(
+    agent: &Arc<dyn Agent>,
+    context: &AgentContext,
+    cluster_display_name_override: &Option<String>,
+    span: &mut Span,
+) -> crate::Result<DatastoreInfoReport> {
+    let mut info = agent.datastore_info(span)?;
+
+    // Inject the cluster_display_name override if configured.
+    info.cluster_display_name = cluster_display_name_override
+        .as_ref()
+        .cloned()
+        .or(info.cluster_display_name);
+
+    // Replace the detected node name if configured.
+    if let Some(node_name) = context.config.node_name_override.as_ref() {
+        info.node_id = node_name.clone();
+    }
+
+    // Extras are optional so failing to fetch them should not fail the request.
+    // They are also the first thing to go when the request budget runs out.
+    if !RequestBudget::spend() {
+        return Ok(DatastoreInfoReport {
+            info,
+            cluster_group: context.config.cluster_group.clone(),
+            extras: DatastoreExtras::new(),
+            incomplete: None,
+        });
+    }
+    let extras = match agent.datastore_extras(span) {
+        Ok(extras) => extras,
+        Err(error) => {
+            context.tag_span(span, "extras.error", error.to_string());
+            warn!(
+                context.logger,
+                "Failed to fetch datastore info extras";
+                failure_info(&error),
+            );
+            DatastoreExtras::new()
+        }
+    };
+    Ok(DatastoreInfoReport {
+        info,
+        cluster_group: context.config.cluster_group.clone(),
+        extras,
+        incomplete: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::header::ETAG;
+    use actix_web::http::header::IF_NONE_MATCH;
+    use actix_web::http::StatusCode;
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use super::ResponseCaches;
+    use crate::api::headers::api_headers;
+    use crate::api::headers::API_VERSIONS_HEADER;
+    use crate::api::headers::BUILD_HEADER;
+    use crate::testing::MockAgent;
+    use crate::Agent;
+    use crate::AgentContext;
+    use crate::DatastoreExtras;
+
+    async fn request_datastore(agent: MockAgent) -> Json {
+        request_datastore_with_context(agent, AgentContext::mock()).await
+    }
+
+    async fn request_datastore_with_context(agent: MockAgent, context: AgentContext) -> Json {
+        let caches = Arc::new(ResponseCaches::new(&context.config));
+        let agent: Arc<dyn Agent> = Arc::new(agent);
+        let app = App::new()
+            .data(agent)
+            .data(context.clone())
+            .service(super::datastore(&context, caches));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/datastore").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body = read_body(response).await;
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn agent_reports_config_checksum() {
+        let context = AgentContext::mock();
+        let agent: Arc<dyn Agent> = Arc::new(MockAgent::new());
+        let app = App::new().data(agent).service(super::agent(&context));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/agent").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body = read_body(response).await;
+        let info: Json = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["config_checksum"], json!(context.config.checksum()));
+    }
+
+    #[actix_rt::test]
+    async fn agent_etag_not_modified() {
+        let context = AgentContext::mock();
+        let agent: Arc<dyn Agent> = Arc::new(MockAgent::new());
+        let app = App::new().data(agent).service(super::agent(&context));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/agent").to_request();
+        let response = call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let request = TestRequest::get()
+            .uri("/agent")
+            .header(IF_NONE_MATCH, etag.clone())
+            .to_request();
+        let response = call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(ETAG), Some(&etag));
+        assert!(read_body(response).await.is_empty());
+
+        let request = TestRequest::get()
+            .uri("/agent")
+            .header(IF_NONE_MATCH, "\"stale\"")
+            .to_request();
+        let response = call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_body(response).await;
+        let info: Json = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["config_checksum"], json!(context.config.checksum()));
+    }
+
+    #[actix_rt::test]
+    async fn datastore_advertises_api_version() {
+        let context = AgentContext::mock();
+        let caches = Arc::new(ResponseCaches::new(&context.config));
+        let agent: Arc<dyn Agent> = Arc::new(MockAgent::new());
+        let app = App::new()
+            .data(agent)
+            .data(context.clone())
+            .wrap(api_headers(&context.config.api))
+            .service(super::datastore(&context, caches));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/datastore").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let version = response.headers().get(API_VERSIONS_HEADER).unwrap();
+        assert_eq!(version, "v1");
+        assert!(response.headers().contains_key(BUILD_HEADER));
+    }
+
+    #[actix_rt::test]
+    async fn datastore_with_extras() {
+        let mut extras = DatastoreExtras::new();
+        extras.insert("fcv".into(), json!("4.0"));
+        let mut agent = MockAgent::new();
+        agent.datastore_extras = Ok(extras);
+        let info = request_datastore(agent).await;
+        assert_eq!(info["extras"], json!({"fcv": "4.0"}));
+        assert_eq!(info["version"], json!("1.2.3"));
+    }
+
+    #[actix_rt::test]
+    async fn datastore_node_name_override() {
+        let info = request_datastore(MockAgent::new()).await;
+        assert_eq!(info["node_id"], json!("mock"));
+        let mut context = AgentContext::mock();
+        context.config.node_name_override = Some("node.example.com".into());
+        let info = request_datastore_with_context(MockAgent::new(), context).await;
+        assert_eq!(info["node_id"], json!("node.example.com"));
+    }
+
+    #[actix_rt::test]
+    async fn datastore_cluster_group() {
+        let info = request_datastore(MockAgent::new()).await;
+        assert!(info.get("cluster_group").is_none());
+        let mut context = AgentContext::mock();
+        context.config.cluster_group = Some("rs0-global".into());
+        let info = request_datastore_with_context(MockAgent::new(), context).await;
+        assert_eq!(info["cluster_group"], json!("rs0-global"));
+        assert_eq!(info["cluster_id"], json!("id"));
+        assert_eq!(info["cluster_display_name"], json!("display"));
+    }
+
+    #[actix_rt::test]
+    async fn datastore_without_extras() {
+        let info = request_datastore(MockAgent::new()).await;
+        assert!(info.get("extras").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn datastore_extras_errors_are_ignored() {
+        let mut agent = MockAgent::new();
+        agent.datastore_extras = Err("test".into());
+        let info = request_datastore(agent).await;
+        assert!(info.get("extras").is_none());
+        assert_eq!(info["version"], json!("1.2.3"));
+    }
+}
@@ -1,22 +1,35 @@
+use std::sync::Arc;
+
 use actix_web::web;
 
 use replicante_util_actixweb::RootDescriptor;
 
+mod cache;
+mod health;
 mod info;
 mod shards;
 
 use crate::api::APIRoot;
 use crate::api::AppConfigContext;
 
+pub use self::cache::ResponseCaches;
+
 /// Configure all agent endpoints.
 pub fn configure(conf: &mut AppConfigContext) {
     APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
         let agent = self::info::agent(&conf.context.agent);
-        let datastore = self::info::datastore(&conf.context.agent);
-        let shards = self::shards::shards(&conf.context.agent);
+        let caches = Arc::clone(&conf.context.caches);
+        let datastore = self::info::datastore(&conf.context.agent, Arc::clone(&caches));
+        let health = self::health::health(&conf.context.agent);
+        let ready = self::health::ready(&conf.context.agent);
+        let shards = self::shards::shards(&conf.context.agent, Arc::clone(&caches));
+        let shards_stream = self::shards::shards_stream(&conf.context.agent, caches);
         let scope = web::scope("/info").service(agent).service(datastore);
         let prefix = root.prefix();
         conf.scoped_service(prefix, scope);
+        conf.scoped_service(prefix, health);
+        conf.scoped_service(prefix, ready);
         conf.scoped_service(prefix, shards);
+        conf.scoped_service(prefix, shards_stream);
     });
 }
@@ -2,41 +2,326 @@ use std::sync::Arc;
 
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
+use actix_web::web::Bytes;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
+use futures::stream;
 use opentracingrust::Log;
+use opentracingrust::Span;
+use serde_derive::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
 
+use replicante_models_agent::info::Shard;
+use replicante_models_agent::info::ShardRole;
+use replicante_models_agent::info::Shards;
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
 use replicante_util_tracing::fail_span;
 
+use super::cache::ResponseCaches;
+use crate::api::headers::INCOMPLETE_HEADER;
+use crate::config::ShardsFormat;
 use crate::Agent;
 use crate::AgentContext;
+use crate::RequestBudget;
 use crate::Result;
 
+/// Shards reported by the agent, flagged if the request budget cut collection short.
+#[derive(Clone, Debug)]
+pub struct ShardsReport {
+    pub incomplete: bool,
+    pub shards: Shards,
+}
+
 /// API interface to Agent::shards
-pub fn shards(context: &AgentContext) -> impl HttpServiceFactory {
+pub fn shards(context: &AgentContext, caches: Arc<ResponseCaches>) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
     web::resource("/shards")
+        .data(caches)
+        .data(context.config.api.shards_format)
+        .data(RequestBudget::new(&context.config.datastore))
         .wrap(tracer)
         .route(web::get().to(shards_responder))
 }
 
+/// API interface to Agent::shards, streaming shards as NDJSON (one shard per line).
+///
+/// Shards are encoded one at a time as the response is sent so large sets of shards
+/// don't need to be encoded into a single buffer.
+pub fn shards_stream(
+    context: &AgentContext,
+    caches: Arc<ResponseCaches>,
+) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    web::resource("/shards/stream")
+        .data(caches)
+        .data(context.config.api.shards_format)
+        .data(RequestBudget::new(&context.config.datastore))
+        .wrap(tracer)
+        .route(web::get().to(shards_stream_responder))
+}
+
 async fn shards_responder(
     agent: web::Data<Arc<dyn Agent>>,
+    budget: web::Data<RequestBudget>,
+    caches: web::Data<Arc<ResponseCaches>>,
+    format: web::Data<ShardsFormat>,
+    mut request: HttpRequest,
+) -> Result<impl Responder> {
+    with_request_span(&mut request, |span| {
+        let span = span.expect("unable to find tracing span for request");
+        span.log(Log::new().log("span.kind", "server-receive"));
+        let report = caches
+            .shards
+            .get(|| shards_report(&agent, &budget, span))
+            .map_err(|error| fail_span(error, &mut *span))?;
+        let response = HttpResponse::Ok().json(encode_shards(&report, *format.get_ref()));
+        span.log(Log::new().log("span.kind", "server-send"));
+        Ok(response)
+    })
+}
+
+async fn shards_stream_responder(
+    agent: web::Data<Arc<dyn Agent>>,
+    budget: web::Data<RequestBudget>,
+    caches: web::Data<Arc<ResponseCaches>>,
+    format: web::Data<ShardsFormat>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
     with_request_span(&mut request, |span| {
         let span = span.expect("unable to find tracing span for request");
         span.log(Log::new().log("span.kind", "server-receive"));
-        let shards = agent
-            .shards(span)
+        let report = caches
+            .shards
+            .get(|| shards_report(&agent, &budget, span))
             .map_err(|error| fail_span(error, &mut *span))?;
-        let response = HttpResponse::Ok().json(shards);
+        let format = *format.get_ref();
+        let lines = report.shards.shards.into_iter().map(move |shard| {
+            serde_json::to_vec(&encode_shard(&shard, format)).map(|mut line| {
+                line.push(b'\n');
+                Bytes::from(line)
+            })
+        });
+        let mut response = HttpResponse::Ok();
+        response.content_type("application/x-ndjson");
+        if report.incomplete {
+            response.header(INCOMPLETE_HEADER, "true");
+        }
+        let response = response.streaming(stream::iter(lines));
         span.log(Log::new().log("span.kind", "server-send"));
         Ok(response)
     })
 }
+
+/// Fetch shards from the agent within the request budget.
+fn shards_report(
+    agent: &Arc<dyn Agent>,
+    budget: &RequestBudget,
+    span: &mut Span,
+) -> Result<ShardsReport> {
+    let (shards, incomplete) = budget.run(|| agent.shards(span));
+    let shards = shards?;
+    Ok(ShardsReport { incomplete, shards })
+}
+
+/// Shard attributes in the `ShardsFormat::Legacy` shape.
+#[derive(Serialize)]
+struct LegacyShard<'a> {
+    id: &'a str,
+    lag: Option<i64>,
+    last_op: Option<i64>,
+    role: &'a ShardRole,
+}
+
+/// Encode a shard in the given format.
+fn encode_shard(shard: &Shard, format: ShardsFormat) -> Json {
+    let shard = match format {
+        ShardsFormat::Structured => serde_json::to_value(shard),
+        ShardsFormat::Legacy => serde_json::to_value(LegacyShard {
+            id: &shard.id,
+            lag: shard.lag.as_ref().map(|lag| lag.value),
+            last_op: shard.commit_offset.as_ref().map(|offset| offset.value),
+            role: &shard.role,
+        }),
+    };
+    shard.expect("shard serialisation must succeed")
+}
+
+/// Encode a shards response in the given format.
+///
+/// Responses cut short by the request budget are flagged as `incomplete`.
+fn encode_shards(report: &ShardsReport, format: ShardsFormat) -> Json {
+    let shards: Vec<Json> = report
+        .shards
+        .shards
+        .iter()
+        .map(|shard| encode_shard(shard, format))
+        .collect();
+    let mut response = json!({ "shards": shards });
+    if report.incomplete {
+        response["incomplete"] = json!(true);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use opentracingrust::Span;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use replicante_models_agent::info::AgentInfo;
+    use replicante_models_agent::info::CommitOffset;
+    use replicante_models_agent::info::DatastoreInfo;
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+    use replicante_models_agent::info::Shards;
+
+    use super::encode_shards;
+    use super::ResponseCaches;
+    use super::ShardsReport;
+    use crate::config::ShardsFormat;
+    use crate::testing::MockAgent;
+    use crate::Agent;
+    use crate::AgentContext;
+    use crate::RequestBudget;
+    use crate::Result;
+
+    /// Agent spending one operation from the request budget for each shard it reports.
+    struct BudgetedAgent(MockAgent);
+
+    impl Agent for BudgetedAgent {
+        fn agent_info(&self, span: &mut Span) -> Result<AgentInfo> {
+            self.0.agent_info(span)
+        }
+
+        fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
+            self.0.datastore_info(span)
+        }
+
+        fn shards(&self, span: &mut Span) -> Result<Shards> {
+            let shards = self
+                .0
+                .shards(span)?
+                .shards
+                .into_iter()
+                .take_while(|_| RequestBudget::spend())
+                .collect();
+            Ok(Shards::new(shards))
+        }
+    }
+
+    fn shards() -> Shards {
+        Shards::new(vec![Shard::new(
+            "shard-a".into(),
+            ShardRole::Secondary,
+            Some(CommitOffset::seconds(1514677698)),
+            Some(CommitOffset::seconds(2)),
+        )])
+    }
+
+    fn report(shards: Shards) -> ShardsReport {
+        ShardsReport {
+            incomplete: false,
+            shards,
+        }
+    }
+
+    #[test]
+    fn encode_legacy_shape() {
+        let shards = encode_shards(&report(shards()), ShardsFormat::Legacy);
+        let role = serde_json::to_value(ShardRole::Secondary).unwrap();
+        let expected = json!({"shards": [{
+            "id": "shard-a",
+            "lag": 2,
+            "last_op": 1514677698,
+            "role": role,
+        }]});
+        assert_eq!(shards, expected);
+    }
+
+    #[test]
+    fn encode_structured_shape() {
+        let shards = shards();
+        let encoded = encode_shards(&report(shards.clone()), ShardsFormat::Structured);
+        assert_eq!(encoded, serde_json::to_value(&shards).unwrap());
+        let shard = &encoded["shards"][0];
+        assert_eq!(shard["id"], json!("shard-a"));
+        assert_eq!(shard["lag"]["value"], json!(2));
+        assert_eq!(shard["commit_offset"]["value"], json!(1514677698));
+        assert!(shard.get("last_op").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn budget_exceeded_returns_partial_shards() {
+        let mut context = AgentContext::mock();
+        context.config.datastore.request_budget_ops = Some(2);
+        let caches = Arc::new(ResponseCaches::new(&context.config));
+        let mut agent = MockAgent::new();
+        agent.shards = Ok(Shards::new(vec![
+            Shard::new("shard-a".into(), ShardRole::Primary, None, None),
+            Shard::new("shard-b".into(), ShardRole::Secondary, None, None),
+            Shard::new("shard-c".into(), ShardRole::Secondary, None, None),
+        ]));
+        let agent: Arc<dyn Agent> = Arc::new(BudgetedAgent(agent));
+        let app = App::new()
+            .data(agent)
+            .service(super::shards(&context, caches));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/shards").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body = read_body(response).await;
+        let body: Json = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["incomplete"], json!(true));
+        let shards = body["shards"].as_array().unwrap();
+        assert_eq!(shards.len(), 2);
+        assert_eq!(shards[0]["id"], json!("shard-a"));
+    }
+
+    #[actix_rt::test]
+    async fn stream_emits_one_shard_per_line() {
+        let context = AgentContext::mock();
+        let caches = Arc::new(ResponseCaches::new(&context.config));
+        let mut agent = MockAgent::new();
+        agent.shards = Ok(Shards::new(vec![
+            Shard::new("shard-a".into(), ShardRole::Primary, None, None),
+            Shard::new("shard-b".into(), ShardRole::Secondary, None, None),
+            Shard::new("shard-c".into(), ShardRole::Secondary, None, None),
+        ]));
+        let agent: Arc<dyn Agent> = Arc::new(agent);
+        let app = App::new()
+            .data(agent)
+            .service(super::shards_stream(&context, caches));
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/shards/stream").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let content_type = response.headers().get("Content-Type").unwrap();
+        assert_eq!(content_type, "application/x-ndjson");
+
+        let body = read_body(response).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let ids: Vec<Json> = body
+            .lines()
+            .map(|line| serde_json::from_str::<Json>(line).unwrap()["id"].clone())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![json!("shard-a"), json!("shard-b"), json!("shard-c")]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use failure::ResultExt;
+use socket2::Domain;
+use socket2::Protocol;
+use socket2::Socket;
+use socket2::Type;
+
+use crate::config::AddressFamily;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Maximum number of pending connections for API listeners.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Addresses the API server is actually listening on.
+///
+/// These are only known once the server is bound and can differ from `api.bind`,
+/// for example when binding to port `0` to let the OS pick a free port.
+#[derive(Clone, Debug, Default)]
+pub struct BoundAddresses(Arc<RwLock<Vec<SocketAddr>>>);
+
+impl BoundAddresses {
+    /// Snapshot of the addresses the API server is listening on.
+    pub fn get(&self) -> Vec<SocketAddr> {
+        self.0.read().expect("BoundAddresses lock poisoned").clone()
+    }
+
+    /// Record the addresses the API server is listening on.
+    pub fn set(&self, addrs: Vec<SocketAddr>) {
+        *self.0.write().expect("BoundAddresses lock poisoned") = addrs;
+    }
+}
+
+/// Determine the addresses to bind the API server to for an address family.
+///
+/// Unspecified addresses (`0.0.0.0` and `::`) are mapped to the unspecified address
+/// of each requested family, otherwise `bind` must resolve to addresses of each family.
+pub fn addresses(bind: &str, family: AddressFamily) -> Result<Vec<SocketAddr>> {
+    let resolved: Vec<SocketAddr> = bind
+        .to_socket_addrs()
+        .with_context(|_| ErrorKind::ConfigOption("api.bind"))?
+        .collect();
+    let port = resolved
+        .first()
+        .map(SocketAddr::port)
+        .ok_or(ErrorKind::ConfigOption("api.bind"))?;
+    let unspecified = resolved.iter().any(|addr| addr.ip().is_unspecified());
+    let ipv4 = if unspecified {
+        Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port))
+    } else {
+        resolved.iter().find(|addr| addr.is_ipv4()).cloned()
+    };
+    let ipv6 = if unspecified {
+        Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port))
+    } else {
+        resolved.iter().find(|addr| addr.is_ipv6()).cloned()
+    };
+    let addresses = match family {
+        AddressFamily::IPv4 => vec![ipv4],
+        AddressFamily::IPv6 => vec![ipv6],
+        AddressFamily::Both => vec![ipv4, ipv6],
+    };
+    addresses
+        .into_iter()
+        .map(|addr| addr.ok_or_else(|| ErrorKind::ConfigOption("api.address_family").into()))
+        .collect()
+}
+
+/// Create TCP listeners for the API server to accept connections from.
+///
+/// IPv6 listeners are restricted to IPv6 traffic so they can share
+/// a port with an IPv4 listener on dual-stack hosts.
+pub fn listeners(bind: &str, family: AddressFamily) -> Result<Vec<TcpListener>> {
+    addresses(bind, family)?
+        .into_iter()
+        .map(|addr| {
+            let domain = if addr.is_ipv6() {
+                Domain::ipv6()
+            } else {
+                Domain::ipv4()
+            };
+            let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))
+                .with_context(|_| ErrorKind::Initialisation(format!("unable to bind {}", addr)))?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(true).with_context(|_| {
+                    ErrorKind::Initialisation(format!("unable to bind {}", addr))
+                })?;
+            }
+            socket
+                .set_reuse_address(true)
+                .and_then(|_| socket.bind(&addr.into()))
+                .and_then(|_| socket.listen(LISTEN_BACKLOG))
+                .with_context(|_| ErrorKind::Initialisation(format!("unable to bind {}", addr)))?;
+            Ok(socket.into_tcp_listener())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::net::SocketAddr;
+    use std::net::TcpStream;
+
+    use super::addresses;
+    use super::listeners;
+    use crate::config::AddressFamily;
+
+    #[test]
+    fn addresses_ipv4_only() {
+        let addrs = addresses("0.0.0.0:8000", AddressFamily::IPv4).unwrap();
+        assert_eq!(addrs, vec!["0.0.0.0:8000".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn addresses_both_from_unspecified() {
+        let addrs = addresses("[::]:8000", AddressFamily::Both).unwrap();
+        let expected: Vec<SocketAddr> = vec![
+            "0.0.0.0:8000".parse().unwrap(),
+            "[::]:8000".parse().unwrap(),
+        ];
+        assert_eq!(addrs, expected);
+    }
+
+    #[test]
+    fn addresses_missing_family() {
+        assert!(addresses("127.0.0.1:8000", AddressFamily::IPv6).is_err());
+    }
+
+    #[test]
+    fn both_listeners_accept_connections() {
+        let listeners = listeners("0.0.0.0:0", AddressFamily::Both).unwrap();
+        assert_eq!(listeners.len(), 2);
+        for listener in &listeners {
+            let mut addr = listener.local_addr().unwrap();
+            if addr.is_ipv6() {
+                addr.set_ip(Ipv6Addr::LOCALHOST.into());
+            } else {
+                addr.set_ip([127, 0, 0, 1].into());
+            }
+            TcpStream::connect(addr).unwrap();
+            listener.accept().unwrap();
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::Error;
+use futures::future::ok;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
+
+use crate::ErrorKind;
+
+/// Maximum number of concurrent requests allowed for each limited endpoint.
+///
+/// Counters are shared by all API server workers.
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimits {
+    limits: HashMap<String, (usize, AtomicUsize)>,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(limits: &BTreeMap<String, usize>) -> ConcurrencyLimits {
+        let limits = limits
+            .iter()
+            .map(|(endpoint, limit)| (endpoint.clone(), (*limit, AtomicUsize::new(0))))
+            .collect();
+        ConcurrencyLimits { limits }
+    }
+
+    /// Reserve a slot to handle a request for the endpoint, if one is available.
+    ///
+    /// Returns `None` if the endpoint is saturated and `Some(None)` if it is not limited.
+    fn acquire(self: &Arc<Self>, endpoint: &str) -> Option<Option<ConcurrencyPermit>> {
+        let (limit, active) = match self.limits.get(endpoint) {
+            None => return Some(None),
+            Some(limit) => limit,
+        };
+        if active.fetch_add(1, Ordering::SeqCst) >= *limit {
+            active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        let permit = ConcurrencyPermit {
+            endpoint: endpoint.to_string(),
+            limits: Arc::clone(self),
+        };
+        Some(Some(permit))
+    }
+}
+
+/// Slot reserved to handle a request, released when dropped.
+struct ConcurrencyPermit {
+    endpoint: String,
+    limits: Arc<ConcurrencyLimits>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        if let Some((_, active)) = self.limits.limits.get(&self.endpoint) {
+            active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Reject requests to endpoints that are handling as many requests as they are allowed to.
+///
+/// Endpoints are identified by the pattern of the matched route (`/api/unstable/shards`)
+/// and rejected requests fail with `429 Too Many Requests` and a default `Retry-After`.
+pub struct ConcurrencyLimitMiddleware {
+    limits: Arc<ConcurrencyLimits>,
+}
+
+impl ConcurrencyLimitMiddleware {
+    pub fn new(limits: Arc<ConcurrencyLimits>) -> ConcurrencyLimitMiddleware {
+        ConcurrencyLimitMiddleware { limits }
+    }
+}
+
+impl<S, B> Transform<S> for ConcurrencyLimitMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ConcurrencyLimitService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConcurrencyLimitService {
+            limits: Arc::clone(&self.limits),
+            service,
+        })
+    }
+}
+
+/// Service wrapper created by `ConcurrencyLimitMiddleware`.
+pub struct ConcurrencyLimitService<S> {
+    limits: Arc<ConcurrencyLimits>,
+    service: S,
+}
+
+impl<S, B> Service for ConcurrencyLimitService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, context: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(context)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let endpoint = request
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| request.path().to_string());
+        let permit = match self.limits.acquire(&endpoint) {
+            Some(permit) => permit,
+            None => {
+                let error: crate::Error = ErrorKind::EndpointSaturated(endpoint).into();
+                return Box::pin(async { Err(error.into()) });
+            }
+        };
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use actix_web::dev::Service;
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::http::StatusCode;
+    use actix_web::test::init_service;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+
+    use super::ConcurrencyLimitMiddleware;
+    use super::ConcurrencyLimits;
+
+    #[actix_rt::test]
+    async fn saturated_endpoint_rejected() {
+        let mut limits = BTreeMap::new();
+        limits.insert("/shards".to_string(), 2);
+        let limits = Arc::new(ConcurrencyLimits::new(&limits));
+        let app = App::new()
+            .wrap(ConcurrencyLimitMiddleware::new(limits))
+            .route(
+                "/shards",
+                web::get().to(|| async { HttpResponse::Ok().finish() }),
+            )
+            .route(
+                "/health",
+                web::get().to(|| async { HttpResponse::Ok().finish() }),
+            );
+        let mut app = init_service(app).await;
+
+        // Requests hold their slot until they complete, so don't await them yet.
+        let first = app.call(TestRequest::get().uri("/shards").to_request());
+        let second = app.call(TestRequest::get().uri("/shards").to_request());
+        let rejected = app
+            .call(TestRequest::get().uri("/shards").to_request())
+            .await
+            .err()
+            .expect("expected the request to be rejected");
+        let response = rejected.as_response_error().error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        // Endpoints without a limit are not affected.
+        let health = app.call(TestRequest::get().uri("/health").to_request());
+        assert!(health.await.is_ok());
+
+        // Slots are released once requests complete.
+        assert!(first.await.is_ok());
+        assert!(second.await.is_ok());
+        let shards = app.call(TestRequest::get().uri("/shards").to_request());
+        assert!(shards.await.is_ok());
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::Error;
+use failure::ResultExt;
+use futures::future::ok;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
+use semver::Version;
+use semver::VersionReq;
+use slog::warn;
+use slog::Logger;
+
+use super::headers::CORE_VERSION_HEADER;
+use crate::config::APIConfig;
+use crate::ErrorKind;
+
+/// Check Replicante Core versions advertised by requests against the supported range.
+#[derive(Debug)]
+pub struct CoreVersionCheck {
+    logger: Logger,
+    reject: bool,
+    supported: VersionReq,
+    supported_raw: String,
+}
+
+impl CoreVersionCheck {
+    /// Parse the supported versions range, if one is configured.
+    pub fn new(config: &APIConfig, logger: Logger) -> crate::Result<Option<CoreVersionCheck>> {
+        let config = match config.core_versions.as_ref() {
+            None => return Ok(None),
+            Some(config) => config,
+        };
+        let supported = VersionReq::parse(&config.supported)
+            .with_context(|_| ErrorKind::ConfigOption("api.core_versions.supported"))?;
+        Ok(Some(CoreVersionCheck {
+            logger,
+            reject: config.reject,
+            supported,
+            supported_raw: config.supported.clone(),
+        }))
+    }
+
+    /// Check the Core version sending a request, if it advertised one.
+    ///
+    /// Versions that can't be parsed are treated as unsupported.
+    fn check(&self, request: &ServiceRequest) -> crate::Result<()> {
+        let version = match request.headers().get(CORE_VERSION_HEADER) {
+            None => return Ok(()),
+            Some(version) => version.to_str().unwrap_or("<invalid>"),
+        };
+        let supported = Version::parse(version)
+            .map(|version| self.supported.matches(&version))
+            .unwrap_or(false);
+        if supported {
+            return Ok(());
+        }
+        if !self.reject {
+            warn!(
+                self.logger,
+                "Request from unsupported Replicante Core version";
+                "core_version" => version,
+                "supported" => &self.supported_raw,
+            );
+            return Ok(());
+        }
+        let error =
+            ErrorKind::IncompatibleCoreVersion(version.to_string(), self.supported_raw.clone());
+        Err(error.into())
+    }
+}
+
+/// Reject (or warn about) requests from Replicante Core versions the agent does not support.
+///
+/// All requests are accepted if no supported range is configured.
+pub struct CoreVersionMiddleware {
+    check: Option<Arc<CoreVersionCheck>>,
+}
+
+impl CoreVersionMiddleware {
+    pub fn new(check: Option<Arc<CoreVersionCheck>>) -> CoreVersionMiddleware {
+        CoreVersionMiddleware { check }
+    }
+}
+
+impl<S, B> Transform<S> for CoreVersionMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CoreVersionService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CoreVersionService {
+            check: self.check.clone(),
+            service,
+        })
+    }
+}
+
+/// Service wrapper created by `CoreVersionMiddleware`.
+pub struct CoreVersionService<S> {
+    check: Option<Arc<CoreVersionCheck>>,
+    service: S,
+}
+
+impl<S, B> Service for CoreVersionService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, context: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(context)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        if let Some(check) = self.check.as_ref() {
+            if let Err(error) = check.check(&request) {
+                return Box::pin(async { Err(error.into()) });
+            }
+        }
+        Box::pin(self.service.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::test::init_service;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+    use slog::o;
+    use slog::Discard;
+    use slog::Logger;
+
+    use super::CoreVersionCheck;
+    use super::CoreVersionMiddleware;
+    use crate::api::headers::CORE_VERSION_HEADER;
+    use crate::config::APIConfig;
+    use crate::config::CoreVersionsConfig;
+
+    #[actix_rt::test]
+    async fn unsupported_core_version_rejected() {
+        let mut config = APIConfig::default();
+        config.core_versions = Some(CoreVersionsConfig {
+            reject: true,
+            supported: ">=0.7.0, <0.9.0".into(),
+        });
+        let logger = Logger::root(Discard, o!());
+        let check = CoreVersionCheck::new(&config, logger)
+            .unwrap()
+            .map(Arc::new);
+        let app = App::new()
+            .wrap(CoreVersionMiddleware::new(check))
+            .route("/", web::get().to(|| async { HttpResponse::Ok().finish() }));
+        let mut app = init_service(app).await;
+
+        let request = |version: &str| {
+            TestRequest::get()
+                .uri("/")
+                .header(CORE_VERSION_HEADER, version)
+                .to_request()
+        };
+        let rejected = app
+            .call(request("0.9.1"))
+            .await
+            .err()
+            .expect("expected the request to be rejected");
+        let response = rejected.as_response_error().error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let accepted = app.call(request("0.8.2")).await.unwrap();
+        assert_eq!(accepted.status(), StatusCode::OK);
+        let unversioned = app.call(TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(unversioned.unwrap().status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,190 @@
+use std::task::Context;
+use std::task::Poll;
+
+use actix_web::body::Body;
+use actix_web::body::MessageBody;
+use actix_web::body::ResponseBody;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::Error;
+use futures::future::ok;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
+
+use crate::config::ErrorsConfig;
+
+/// Send verbose error responses, with cause chains and backtraces, to trusted clients.
+///
+/// Agent errors are rendered with only the error message by default.
+/// Clients are trusted if `api.errors.verbose` is set or if they send the configured
+/// `api.errors.trusted_header` with the expected value.
+pub struct ErrorVerbosityMiddleware {
+    config: ErrorsConfig,
+}
+
+impl ErrorVerbosityMiddleware {
+    pub fn new(config: ErrorsConfig) -> ErrorVerbosityMiddleware {
+        ErrorVerbosityMiddleware { config }
+    }
+}
+
+impl<S, B> Transform<S> for ErrorVerbosityMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ErrorVerbosityService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ErrorVerbosityService {
+            config: self.config.clone(),
+            service,
+        })
+    }
+}
+
+/// Service wrapper created by `ErrorVerbosityMiddleware`.
+pub struct ErrorVerbosityService<S> {
+    config: ErrorsConfig,
+    service: S,
+}
+
+impl<S> ErrorVerbosityService<S> {
+    /// Check if the client sending the request is trusted with verbose errors.
+    fn trusted(&self, request: &ServiceRequest) -> bool {
+        if self.config.verbose {
+            return true;
+        }
+        match self.config.trusted_header.as_ref() {
+            None => false,
+            Some(trusted) => request
+                .headers()
+                .get(trusted.name.as_str())
+                .map(|value| value.as_bytes() == trusted.value.as_bytes())
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl<S, B> Service for ErrorVerbosityService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, context: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(context)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let trusted = self.trusted(&request);
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            let verbose = if trusted {
+                response
+                    .response()
+                    .error()
+                    .and_then(|error| error.as_error::<crate::Error>())
+                    .map(|error| error.verbose_response())
+            } else {
+                None
+            };
+            // Replace the body only so the error remains attached to the response.
+            let response = response.map_body(|_, body| match verbose {
+                Some(mut verbose) => verbose.take_body(),
+                None => ResponseBody::Body(Body::from_message(body)),
+            });
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+    use serde_json::Value as Json;
+
+    use replicante_util_failure::SerializableFail;
+
+    use super::ErrorVerbosityMiddleware;
+    use crate::config::ErrorsConfig;
+    use crate::config::TrustedHeader;
+    use crate::ErrorKind;
+
+    fn error() -> crate::Error {
+        ErrorKind::ActionDecode.into()
+    }
+
+    /// Attributes of the JSON object, sorted.
+    fn keys(body: &Json) -> Vec<String> {
+        let mut keys: Vec<String> = body.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    async fn failing_request(request: TestRequest) -> Json {
+        let config = ErrorsConfig {
+            trusted_header: Some(TrustedHeader {
+                name: "X-Trusted-Client".into(),
+                value: "secret".into(),
+            }),
+            verbose: false,
+        };
+        let app = App::new()
+            .wrap(ErrorVerbosityMiddleware::new(config))
+            .route(
+                "/",
+                web::get().to(|| async { Err::<HttpResponse, _>(error()) }),
+            );
+        let mut app = init_service(app).await;
+        let response = call_service(&mut app, request.uri("/").to_request()).await;
+        assert!(response.status().is_client_error());
+        let body = read_body(response).await;
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn trusted_clients_get_verbose_errors() {
+        let request = TestRequest::get().header("X-Trusted-Client", "secret");
+        let body = failing_request(request).await;
+        let verbose = serde_json::to_value(SerializableFail::from(&error())).unwrap();
+        assert_eq!(body["error"], "unable to decode action information");
+        assert_eq!(keys(&body), keys(&verbose));
+        assert!(keys(&body).len() > 2);
+    }
+
+    #[actix_rt::test]
+    async fn untrusted_clients_get_terse_errors() {
+        let body = failing_request(TestRequest::get()).await;
+        assert_eq!(body["error"], "unable to decode action information");
+        for key in keys(&body) {
+            assert!(key == "error" || key == "variant", "unexpected key {}", key);
+        }
+
+        let request = TestRequest::get().header("X-Trusted-Client", "wrong");
+        let body = failing_request(request).await;
+        for key in keys(&body) {
+            assert!(key == "error" || key == "variant", "unexpected key {}", key);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use actix_web::http::header::HeaderValue;
+use actix_web::http::header::ETAG;
+use actix_web::http::ContentEncoding;
+use actix_web::middleware::Compress;
+use actix_web::middleware::DefaultHeaders;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::config::APIConfig;
+
+/// Header listing the API versions supported by the agent.
+pub const API_VERSIONS_HEADER: &str = "X-Replicante-Agent-Api";
+
+/// Header with the build the agent was compiled from.
+pub const BUILD_HEADER: &str = "X-Replicante-Agent-Build";
+
+/// Header with the version of Replicante Core sending the request.
+pub const CORE_VERSION_HEADER: &str = "X-Replicante-Core-Version";
+
+/// Header set on streamed responses cut short by the request budget.
+pub const INCOMPLETE_HEADER: &str = "X-Replicante-Agent-Incomplete";
+
+/// Middleware advertising API versions and build information on all responses.
+///
+/// Clients can inspect these headers to negotiate behaviour with the agent.
+///
+/// # Panics
+/// If the configured API versions are not valid header values (see `APIConfig::validate`).
+pub fn api_headers(config: &APIConfig) -> DefaultHeaders {
+    DefaultHeaders::new()
+        .header(API_VERSIONS_HEADER, config.versions.join(", "))
+        .header(BUILD_HEADER, env!("GIT_BUILD_HASH"))
+}
+
+/// JSON response with an `ETag` computed from the hash of the encoded body.
+///
+/// If the request's `If-None-Match` header lists the same tag an empty
+/// 304 Not Modified response is returned instead.
+pub fn json_with_etag<T>(if_none_match: Option<&HeaderValue>, body: &T) -> HttpResponse
+where
+    T: Serialize,
+{
+    let body = serde_json::to_vec(body).expect("response must serialise to JSON");
+    let etag = format!("\"{:x}\"", Sha256::digest(&body));
+    let not_modified = if_none_match
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        })
+        .unwrap_or(false);
+    if not_modified {
+        return HttpResponse::NotModified().header(ETAG, etag).finish();
+    }
+    HttpResponse::Ok()
+        .header(ETAG, etag)
+        .content_type("application/json")
+        .body(body)
+}
+
+/// Middleware compressing responses if enabled in the configuration.
+///
+/// When enabled the encoding is negotiated with the client's `Accept-Encoding` header.
+pub fn compression(config: &APIConfig) -> Compress {
+    let encoding = if config.compression {
+        ContentEncoding::Auto
+    } else {
+        ContentEncoding::Identity
+    };
+    Compress::new(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::ACCEPT_ENCODING;
+    use actix_web::http::header::CONTENT_ENCODING;
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+
+    use super::compression;
+    use crate::config::APIConfig;
+
+    async fn content_encoding(enabled: bool) -> Option<String> {
+        let config = APIConfig {
+            compression: enabled,
+            ..APIConfig::default()
+        };
+        let app = App::new().wrap(compression(&config)).route(
+            "/",
+            web::get().to(|| async { HttpResponse::Ok().body("a".repeat(1024)) }),
+        );
+        let mut app = init_service(app).await;
+        let request = TestRequest::get()
+            .uri("/")
+            .header(ACCEPT_ENCODING, "gzip")
+            .to_request();
+        let response = call_service(&mut app, request).await;
+        response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap().to_string())
+    }
+
+    #[actix_rt::test]
+    async fn compression_disabled() {
+        assert_eq!(content_encoding(false).await, None);
+    }
+
+    #[actix_rt::test]
+    async fn compression_gzip_when_accepted() {
+        assert_eq!(content_encoding(true).await, Some("gzip".into()));
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde_derive::Serialize;
+
+use crate::api::bind::BoundAddresses;
+
+/// Expose the addresses the API server is actually listening on.
+pub fn resource(bound: &BoundAddresses) -> impl HttpServiceFactory {
+    web::resource("/bind")
+        .data(bound.clone())
+        .route(web::get().to(responder))
+}
+
+async fn responder(bound: web::Data<BoundAddresses>) -> impl Responder {
+    let response = BindResponse {
+        addresses: bound.get(),
+    };
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Debug, Serialize)]
+struct BindResponse {
+    addresses: Vec<SocketAddr>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use actix_web::HttpServer;
+    use serde_json::Value as Json;
+
+    use super::resource;
+    use crate::api::bind::BoundAddresses;
+
+    #[actix_rt::test]
+    async fn reports_port_selected_by_os() {
+        let server = HttpServer::new(App::new)
+            .bind("127.0.0.1:0")
+            .expect("unable to bind test server");
+        let bound = BoundAddresses::default();
+        bound.set(server.addrs());
+
+        let mut app = init_service(App::new().service(resource(&bound))).await;
+        let request = TestRequest::get().uri("/bind").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body = read_body(response).await;
+        let body: Json = serde_json::from_slice(&body).unwrap();
+        let addresses: Vec<SocketAddr> = serde_json::from_value(body["addresses"].clone()).unwrap();
+        assert_eq!(addresses.len(), 1);
+        assert!(addresses[0].ip().is_loopback());
+        assert_ne!(addresses[0].port(), 0);
+    }
+}
@@ -0,0 +1,26 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use serde_derive::Serialize;
+
+use crate::store::AgentEvent;
+use crate::AgentContext;
+
+/// Expose the most recent agent lifecycle events, newest event first.
+#[actix_web::get("/events")]
+pub async fn responder(context: web::Data<AgentContext>) -> Result<impl Responder> {
+    let events = context.store.with_transaction(|tx| {
+        let mut events = Vec::new();
+        for event in tx.events().recent(None)? {
+            events.push(event?);
+        }
+        Ok(events)
+    })?;
+    Ok(HttpResponse::Ok().json(EventsResponse { events }))
+}
+
+#[derive(Debug, Serialize)]
+struct EventsResponse {
+    events: Vec<AgentEvent>,
+}
@@ -0,0 +1,19 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde_derive::Serialize;
+
+use crate::health::HealthRecord;
+use crate::AgentContext;
+
+/// Expose the history of recent health check results.
+#[actix_web::get("/health")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    let history = context.health_history.records();
+    HttpResponse::Ok().json(HealthHistoryResponse { history })
+}
+
+#[derive(Debug, Serialize)]
+struct HealthHistoryResponse {
+    history: Vec<HealthRecord>,
+}
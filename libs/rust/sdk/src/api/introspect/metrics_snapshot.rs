@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use prometheus::proto::MetricFamily;
+use prometheus::proto::MetricType;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::AgentContext;
+
+/// Expose current counter and gauge values as JSON, for checks without a Prometheus scraper.
+///
+/// Histograms and summaries are left out as they don't reduce to a readable value.
+/// Metrics can be selected by name prefix with the `prefix` query parameter.
+#[actix_web::get("/metrics_snapshot")]
+pub async fn responder(
+    context: web::Data<AgentContext>,
+    query: web::Query<SnapshotQuery>,
+) -> impl Responder {
+    let prefix = query.prefix.as_deref().unwrap_or("");
+    let metrics = context
+        .metrics
+        .gather()
+        .iter()
+        .filter(|family| family.get_name().starts_with(prefix))
+        .filter_map(snapshot)
+        .collect();
+    HttpResponse::Ok().json(SnapshotResponse { metrics })
+}
+
+/// Reduce a metric family to its current values, if it is a counter or a gauge.
+fn snapshot(family: &MetricFamily) -> Option<(String, MetricSnapshot)> {
+    let kind = match family.get_field_type() {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        _ => return None,
+    };
+    let samples = family
+        .get_metric()
+        .iter()
+        .map(|metric| {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                .collect();
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => metric.get_counter().get_value(),
+                _ => metric.get_gauge().get_value(),
+            };
+            Sample { labels, value }
+        })
+        .collect();
+    let metric = MetricSnapshot {
+        help: family.get_help().to_string(),
+        kind,
+        samples,
+    };
+    Some((family.get_name().to_string(), metric))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotQuery {
+    prefix: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MetricSnapshot {
+    help: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug, Serialize)]
+struct Sample {
+    labels: BTreeMap<String, String>,
+    value: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotResponse {
+    metrics: BTreeMap<String, MetricSnapshot>,
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use prometheus::Gauge;
+    use prometheus::Histogram;
+    use prometheus::HistogramOpts;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use crate::AgentContext;
+
+    #[actix_rt::test]
+    async fn snapshot_includes_gauge_value() {
+        let context = AgentContext::mock();
+        let gauge = Gauge::new("repliagent_test_gauge", "Test gauge").unwrap();
+        gauge.set(42.0);
+        context.metrics.register(Box::new(gauge)).unwrap();
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("repliagent_test_histogram", "Test")).unwrap();
+        context.metrics.register(Box::new(histogram)).unwrap();
+        let app = App::new().data(context).service(super::responder);
+        let mut app = init_service(app).await;
+
+        let request = TestRequest::get()
+            .uri("/metrics_snapshot?prefix=repliagent_test_")
+            .to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body: Json = read_body_json(response).await;
+        let gauge = &body["metrics"]["repliagent_test_gauge"];
+        assert_eq!(gauge["type"], json!("gauge"));
+        assert_eq!(gauge["samples"][0]["value"], json!(42.0));
+        assert!(body["metrics"].get("repliagent_test_histogram").is_none());
+    }
+}
@@ -8,6 +8,11 @@ use crate::api::APIRoot;
 use crate::api::AppConfigContext;
 use crate::AgentContext;
 
+mod bind;
+mod events;
+mod health;
+mod metrics_snapshot;
+mod tasks;
 mod threads;
 
 /// Configure all introspection endpoints.
@@ -15,7 +20,13 @@ pub fn configure(conf: &mut AppConfigContext) {
     APIRoot::UnstableIntrospect.and_then(&conf.context.flags, |root| {
         let metrics = metrics(&conf.context.agent);
         let prefix = root.prefix();
+        let bind = bind::resource(&conf.context.bound);
+        conf.scoped_service(prefix, bind);
         conf.scoped_service(prefix, metrics);
+        conf.scoped_service(prefix, self::events::responder);
+        conf.scoped_service(prefix, self::health::responder);
+        conf.scoped_service(prefix, self::metrics_snapshot::responder);
+        conf.scoped_service(prefix, self::tasks::responder);
         conf.scoped_service(prefix, self::threads::responder);
     });
 }
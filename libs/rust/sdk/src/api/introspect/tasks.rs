@@ -0,0 +1,55 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde_derive::Serialize;
+
+use crate::tasks::TaskStatus;
+use crate::AgentContext;
+
+/// Expose background tasks with their schedule and most recent run.
+#[actix_web::get("/tasks")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    let tasks = context.tasks.tasks();
+    HttpResponse::Ok().json(TasksResponse { tasks })
+}
+
+#[derive(Debug, Serialize)]
+struct TasksResponse {
+    tasks: Vec<TaskStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use crate::AgentContext;
+
+    #[actix_rt::test]
+    async fn lists_task_after_run() {
+        let context = AgentContext::mock();
+        context
+            .tasks
+            .register("actions.poll", Duration::from_secs(10));
+        context.tasks.record("actions.poll", &Ok(()));
+        let app = App::new().data(context).service(super::responder);
+        let mut app = init_service(app).await;
+
+        let request = TestRequest::get().uri("/tasks").to_request();
+        let response = call_service(&mut app, request).await;
+        assert!(response.status().is_success());
+        let body: Json = read_body_json(response).await;
+        let task = &body["tasks"][0];
+        assert_eq!(task["name"], json!("actions.poll"));
+        assert_eq!(task["interval"], json!(10));
+        assert_eq!(task["last_status"], json!("success"));
+        assert!(task["last_run"].is_string());
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Instant;
+
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::http::StatusCode;
+use actix_web::Error;
+use futures::future::ok;
+use futures::future::LocalBoxFuture;
+use futures::future::Ready;
+
+use crate::metrics::SdkMetrics;
+
+/// Endpoint label for requests that did not match any route.
+const UNMATCHED_ENDPOINT: &str = "<unmatched>";
+
+/// Record count, status and duration of every request handled by the API server.
+///
+/// Requests are labelled with the pattern of the matched route (`/actions/info/{id}`)
+/// rather than the requested path to keep the number of metric series bounded.
+pub struct HttpMetricsMiddleware {
+    metrics: Arc<SdkMetrics>,
+}
+
+impl HttpMetricsMiddleware {
+    pub fn new(metrics: Arc<SdkMetrics>) -> HttpMetricsMiddleware {
+        HttpMetricsMiddleware { metrics }
+    }
+}
+
+impl<S, B> Transform<S> for HttpMetricsMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HttpMetricsService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HttpMetricsService {
+            metrics: Arc::clone(&self.metrics),
+            service,
+        })
+    }
+}
+
+/// Service wrapper created by `HttpMetricsMiddleware`.
+pub struct HttpMetricsService<S> {
+    metrics: Arc<SdkMetrics>,
+    service: S,
+}
+
+impl<S, B> Service for HttpMetricsService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, context: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(context)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let metrics = Arc::clone(&self.metrics);
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            let (endpoint, status) = match response.as_ref() {
+                Ok(response) => (response.request().match_pattern(), response.status()),
+                Err(error) => (None, error.as_response_error().status_code()),
+            };
+            let endpoint = endpoint.unwrap_or_else(|| UNMATCHED_ENDPOINT.to_string());
+            observe(&metrics, &endpoint, status, start);
+            response
+        })
+    }
+}
+
+fn observe(metrics: &SdkMetrics, endpoint: &str, status: StatusCode, start: Instant) {
+    metrics
+        .http_requests_count
+        .with_label_values(&[endpoint, status.as_str()])
+        .inc();
+    metrics
+        .http_requests_duration
+        .with_label_values(&[endpoint])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+
+    use super::HttpMetricsMiddleware;
+    use crate::AgentContext;
+
+    #[actix_rt::test]
+    async fn requests_counted_by_endpoint_and_status() {
+        let context = AgentContext::mock();
+        let metrics = Arc::clone(&context.sdk_metrics);
+        let endpoint = "/test/metrics/{id}";
+        let middleware = HttpMetricsMiddleware::new(Arc::clone(&metrics));
+        let app = App::new().wrap(middleware).route(
+            endpoint,
+            web::get().to(|| async { HttpResponse::NotFound().finish() }),
+        );
+        let mut app = init_service(app).await;
+        let request = TestRequest::get().uri("/test/metrics/42").to_request();
+        call_service(&mut app, request).await;
+        let request = TestRequest::get().uri("/test/metrics/43").to_request();
+        call_service(&mut app, request).await;
+        let count = metrics
+            .http_requests_count
+            .with_label_values(&[endpoint, "404"])
+            .get();
+        assert_eq!(count, 2.0);
+        let ok = metrics
+            .http_requests_count
+            .with_label_values(&[endpoint, "200"])
+            .get();
+        assert_eq!(ok, 0.0);
+    }
+}
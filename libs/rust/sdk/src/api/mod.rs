@@ -1,38 +1,56 @@
 use std::sync::mpsc::sync_channel;
 use std::sync::Arc;
 
-use actix_web::middleware;
 use actix_web::App;
 use actix_web::HttpServer;
 use failure::ResultExt;
 use humthreads::Builder;
 use openssl::ssl::SslAcceptor;
+use openssl::ssl::SslAcceptorBuilder;
 use openssl::ssl::SslFiletype;
 use openssl::ssl::SslMethod;
 use openssl::ssl::SslVerifyMode;
+use openssl::ssl::SslVersion;
 use slog::info;
 
 use replicante_util_actixweb::APIFlags;
 use replicante_util_actixweb::LoggingMiddleware;
-use replicante_util_actixweb::MetricsMiddleware;
 use replicante_util_actixweb::RootDescriptor;
 use replicante_util_actixweb::SentryMiddleware;
 use replicante_util_upkeep::Upkeep;
 
 mod actions;
 mod agent;
+mod bind;
+mod concurrency;
+mod core_version;
+mod errors;
+mod headers;
 mod index;
 mod introspect;
+mod metrics;
 mod roots;
 
+use self::actions::ActionRateLimiter;
+use self::bind::BoundAddresses;
+use self::concurrency::ConcurrencyLimitMiddleware;
+use self::concurrency::ConcurrencyLimits;
+use self::core_version::CoreVersionCheck;
+use self::core_version::CoreVersionMiddleware;
+use self::errors::ErrorVerbosityMiddleware;
+use self::headers::api_headers;
+use self::headers::compression;
+use self::metrics::HttpMetricsMiddleware;
 use crate::actions::actions_enabled;
 use crate::config::SentryCaptureApi;
-use crate::metrics::REQUESTS;
+use crate::config::TlsConfig;
+use crate::config::TlsVersion;
 use crate::Agent;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
 
+pub use self::agent::ResponseCaches;
 pub use self::roots::APIRoot;
 
 /// Context for `AppConfig` configuration callbacks.
@@ -41,7 +59,10 @@ pub type AppConfigContext<'a> = replicante_util_actixweb::AppConfigContext<'a, A
 /// Context for `AppConfig` configuration callbacks.
 #[derive(Clone)]
 pub struct APIContext {
+    pub action_rate_limiter: Arc<ActionRateLimiter>,
     pub agent: AgentContext,
+    pub bound: BoundAddresses,
+    pub caches: Arc<ResponseCaches>,
     pub flags: APIFlags,
 }
 
@@ -64,16 +85,19 @@ fn configure(conf: &mut AppConfigContext) {
 ///
 ///   * It fails to bind to the configured port.
 ///   * It fails to start the HTTP server.
-pub fn spawn_server<A>(agent: A, context: AgentContext, upkeep: &mut Upkeep) -> Result<()>
-where
-    A: Agent + 'static,
-{
-    let agent: Arc<dyn Agent> = Arc::new(agent);
+pub fn spawn_server(
+    agent: Arc<dyn Agent>,
+    context: AgentContext,
+    upkeep: &mut Upkeep,
+) -> Result<()> {
+    let core_versions = CoreVersionCheck::new(&context.config.api, context.logger.clone())?;
+    let core_versions = core_versions.map(Arc::new);
     let (send_server, receive_server) = sync_channel(0);
     let thread = Builder::new("r:b:api")
         .full_name("replicante:base:api")
         .spawn(move |scope| {
             let config = context.config.api.clone();
+            let min_version = context.config.tls.min_version;
             let logger = context.logger.clone();
             let sentry_capture_api = context
                 .config
@@ -97,11 +121,19 @@ where
                 api_conf.register(introspect::configure);
                 api_conf
             };
+            let bound = BoundAddresses::default();
+            let rate_limit = context.config.actions.rate_limit.clone();
             let api_context = APIContext {
+                action_rate_limiter: Arc::new(ActionRateLimiter::new(rate_limit)),
                 agent: context.clone(),
+                bound: bound.clone(),
+                caches: Arc::clone(&context.caches),
                 flags: context.config.api.trees.clone().into(),
             };
 
+            // Concurrency limits are shared across all workers.
+            let limits = Arc::new(ConcurrencyLimits::new(&config.endpoint_concurrency));
+
             // Initialise and configure HTTP server and App factory.
             let mut server = HttpServer::new(move || {
                 // Give every mounted route access to the global context.
@@ -110,9 +142,13 @@ where
                 // Register application middlewares.
                 // Remember that middlewares are executed in reverse registration order.
                 let app = app
+                    .wrap(ErrorVerbosityMiddleware::new(config.errors.clone()))
+                    .wrap(ConcurrencyLimitMiddleware::new(Arc::clone(&limits)))
+                    .wrap(CoreVersionMiddleware::new(core_versions.clone()))
                     .wrap(LoggingMiddleware::new(context.logger.clone()))
-                    .wrap(MetricsMiddleware::new(REQUESTS.clone()))
-                    .wrap(middleware::Compress::default());
+                    .wrap(HttpMetricsMiddleware::new(Arc::clone(&context.sdk_metrics)))
+                    .wrap(api_headers(&context.config.api))
+                    .wrap(compression(&context.config.api));
                 // Add the sentry middleware if configured.
                 let app = match sentry_capture_api {
                     SentryCaptureApi::Client => app.wrap(SentryMiddleware::new(400)),
@@ -139,34 +175,39 @@ where
             }
 
             // Configure TLS/HTTPS if enabled and bind to the given address.
-            let server = match config.tls {
-                None => server
+            let server = match (config.address_family, config.tls) {
+                (None, None) => server
                     .bind(&config.bind)
                     .expect("unable to bind API server"),
-                Some(tls) => {
-                    let mut builder = SslAcceptor::mozilla_modern(SslMethod::tls())
-                        .expect("unable to initialse TLS acceptor for API server");
-                    builder
-                        .set_certificate_file(&tls.server_cert, SslFiletype::PEM)
-                        .expect("unable to set TLS server public certificate");
-                    builder
-                        .set_private_key_file(&tls.server_key, SslFiletype::PEM)
-                        .expect("unable to set TLS server privte key");
-                    if let Some(bundle) = tls.clients_ca_bundle {
-                        builder
-                            .set_ca_file(&bundle)
-                            .expect("unable to set clients CAs bundle");
-                        builder
-                            .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
-                    }
-                    server
-                        .bind_openssl(&config.bind, builder)
+                (None, Some(tls)) => server
+                    .bind_openssl(&config.bind, tls_acceptor(&tls, min_version))
+                    .expect("unable to bind API server"),
+                (Some(family), tls) => {
+                    let listeners =
+                        bind::listeners(&config.bind, family).expect("unable to bind API server");
+                    listeners.into_iter().fold(server, |server, listener| {
+                        match tls.as_ref() {
+                            None => server.listen(listener),
+                            Some(tls) => {
+                                server.listen_openssl(listener, tls_acceptor(tls, min_version))
+                            }
+                        }
                         .expect("unable to bind API server")
+                    })
                 }
             };
 
+            // Record the addresses actually bound (relevant when binding to port 0).
+            bound.set(server.addrs());
+
             // Start HTTP server and block until shutdown.
-            info!(logger, "Starting API server"; "bind" => &config.bind);
+            info!(
+                logger,
+                "Starting API server";
+                "bind" => &config.bind,
+                "bound" => ?bound.get(),
+                "address_family" => ?config.address_family,
+            );
             scope.activity("running https://actix.rs/ HTTP(S) server");
             let mut runner = actix_rt::System::new("replicante:base:api");
             let server = server.run();
@@ -185,3 +226,57 @@ where
     });
     Ok(())
 }
+
+/// Initialise a TLS acceptor for the API server.
+///
+/// # Panics
+/// If the TLS acceptor cannot be configured with the given certificates.
+fn tls_acceptor(tls: &TlsConfig, min_version: TlsVersion) -> SslAcceptorBuilder {
+    let mut builder = tls_acceptor_builder(min_version);
+    builder
+        .set_certificate_file(&tls.server_cert, SslFiletype::PEM)
+        .expect("unable to set TLS server public certificate");
+    builder
+        .set_private_key_file(&tls.server_key, SslFiletype::PEM)
+        .expect("unable to set TLS server privte key");
+    if let Some(bundle) = tls.clients_ca_bundle.as_ref() {
+        builder
+            .set_ca_file(bundle)
+            .expect("unable to set clients CAs bundle");
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    builder
+}
+
+/// Initialise a TLS acceptor refusing handshakes below the given protocol version.
+///
+/// # Panics
+/// If the TLS acceptor cannot be initialised.
+fn tls_acceptor_builder(min_version: TlsVersion) -> SslAcceptorBuilder {
+    let mut builder = SslAcceptor::mozilla_modern(SslMethod::tls())
+        .expect("unable to initialse TLS acceptor for API server");
+    let min_version = match min_version {
+        TlsVersion::Tls1_2 => SslVersion::TLS1_2,
+        TlsVersion::Tls1_3 => SslVersion::TLS1_3,
+    };
+    builder
+        .set_min_proto_version(Some(min_version))
+        .expect("unable to set TLS minimum protocol version");
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::ssl::SslVersion;
+
+    use super::tls_acceptor_builder;
+    use crate::config::TlsVersion;
+
+    #[test]
+    fn tls_min_version_applied() {
+        let builder = tls_acceptor_builder(TlsVersion::Tls1_3);
+        assert_eq!(builder.min_proto_version(), Some(SslVersion::TLS1_3));
+        let builder = tls_acceptor_builder(TlsVersion::Tls1_2);
+        assert_eq!(builder.min_proto_version(), Some(SslVersion::TLS1_2));
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use prometheus::Gauge;
+
+use crate::config::ReconnectConfig;
+
+/// Exponential backoff between attempts to reconnect to a failing datastore.
+///
+/// Every consecutive failure doubles the delay before the next attempt, up to the
+/// configured maximum, and a success resets it.
+pub struct Backoff {
+    config: ReconnectConfig,
+    gauge: Gauge,
+    state: Mutex<BackoffState>,
+}
+
+struct BackoffState {
+    delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    /// Create a backoff reporting the current delay with the given gauge.
+    pub fn new(config: ReconnectConfig, gauge: Gauge) -> Backoff {
+        Backoff {
+            config,
+            gauge,
+            state: Mutex::new(BackoffState {
+                delay: Duration::from_secs(0),
+                retry_at: None,
+            }),
+        }
+    }
+
+    /// Current delay between reconnect attempts, zero if the last attempt succeeded.
+    pub fn delay(&self) -> Duration {
+        self.state.lock().expect("Backoff lock poisoned").delay
+    }
+
+    /// Record a failed attempt and return the delay before the next one.
+    pub fn failure(&self) -> Duration {
+        let mut state = self.state.lock().expect("Backoff lock poisoned");
+        let initial = Duration::from_secs(self.config.initial_interval);
+        let max = Duration::from_secs(self.config.max_interval);
+        let delay = if state.delay == Duration::from_secs(0) {
+            initial
+        } else {
+            state.delay * 2
+        };
+        state.delay = delay.min(max);
+        state.retry_at = Some(Instant::now() + state.delay);
+        self.gauge.set(state.delay.as_secs_f64());
+        state.delay
+    }
+
+    /// Time left before the next attempt is allowed, if the agent is backing off.
+    pub fn remaining(&self) -> Option<Duration> {
+        let state = self.state.lock().expect("Backoff lock poisoned");
+        state
+            .retry_at
+            .and_then(|retry_at| retry_at.checked_duration_since(Instant::now()))
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+    }
+
+    /// Record a successful attempt and reset the backoff.
+    pub fn success(&self) {
+        let mut state = self.state.lock().expect("Backoff lock poisoned");
+        state.delay = Duration::from_secs(0);
+        state.retry_at = None;
+        self.gauge.set(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prometheus::Gauge;
+
+    use super::Backoff;
+    use crate::config::ReconnectConfig;
+
+    #[test]
+    fn grows_on_failures_and_resets_on_success() {
+        let gauge = Gauge::new("test_backoff", "test").unwrap();
+        let config = ReconnectConfig {
+            initial_interval: 1,
+            max_interval: 5,
+        };
+        let backoff = Backoff::new(config, gauge.clone());
+        assert!(backoff.remaining().is_none());
+        assert_eq!(backoff.failure(), Duration::from_secs(1));
+        assert_eq!(backoff.failure(), Duration::from_secs(2));
+        assert_eq!(backoff.failure(), Duration::from_secs(4));
+        assert_eq!(backoff.failure(), Duration::from_secs(5));
+        assert_eq!(backoff.failure(), Duration::from_secs(5));
+        assert!(backoff.remaining().is_some());
+        assert_eq!(gauge.get(), 5.0);
+
+        backoff.success();
+        assert_eq!(backoff.delay(), Duration::from_secs(0));
+        assert!(backoff.remaining().is_none());
+        assert_eq!(backoff.failure(), Duration::from_secs(1));
+    }
+}
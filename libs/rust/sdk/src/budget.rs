@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::config::DatastoreConfig;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Usage>> = RefCell::new(None);
+}
+
+/// Datastore operations issued so far by the request handled on the current thread.
+struct Usage {
+    deadline: Option<Instant>,
+    exhausted: bool,
+    max_ops: Option<u32>,
+    ops: u32,
+}
+
+/// Bound the datastore operations a single API request can issue.
+///
+/// Agents call `RequestBudget::spend` before each operation and stop collecting data
+/// when it returns `false`, responding with what they collected so far.
+/// Operations that only feed diagnostics use `RequestBudget::spend_optional` instead
+/// so skipping them does not flag the response as incomplete.
+/// Agents decide what counts as an operation: usually a datastore command or the
+/// group of commands needed to report one item.
+/// The budget is unlimited unless `datastore.request_budget_ops` or
+/// `datastore.request_budget_time` are set.
+#[derive(Clone, Debug)]
+pub struct RequestBudget {
+    max_ops: Option<u32>,
+    max_time: Option<Duration>,
+}
+
+impl RequestBudget {
+    pub fn new(config: &DatastoreConfig) -> RequestBudget {
+        RequestBudget {
+            max_ops: config.request_budget_ops,
+            max_time: config.request_budget_time.map(Duration::from_millis),
+        }
+    }
+
+    /// Run `handler` with the budget applied to operations issued on the current thread.
+    ///
+    /// Returns the handler result and whether the budget was exhausted along the way.
+    pub fn run<F, T>(&self, handler: F) -> (T, bool)
+    where
+        F: FnOnce() -> T,
+    {
+        let usage = Usage {
+            deadline: self.max_time.map(|max_time| Instant::now() + max_time),
+            exhausted: false,
+            max_ops: self.max_ops,
+            ops: 0,
+        };
+        let scope = Scope {
+            previous: CURRENT.with(|current| current.replace(Some(usage))),
+        };
+        let result = handler();
+        let exhausted = CURRENT.with(|current| {
+            current
+                .borrow()
+                .as_ref()
+                .map(|usage| usage.exhausted)
+                .unwrap_or(false)
+        });
+        drop(scope);
+        (result, exhausted)
+    }
+
+    /// Account for a datastore operation against the budget of the current request.
+    ///
+    /// Returns `false` if the budget is exhausted and the operation should be skipped.
+    /// Operations issued outside of `RequestBudget::run` are always allowed.
+    pub fn spend() -> bool {
+        RequestBudget::spend_op(true)
+    }
+
+    /// Account for an operation whose result is not part of the response.
+    ///
+    /// Like `RequestBudget::spend` but the request is not flagged as exhausted when
+    /// the operation is skipped.
+    pub fn spend_optional() -> bool {
+        RequestBudget::spend_op(false)
+    }
+
+    fn spend_op(required: bool) -> bool {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            let usage = match current.as_mut() {
+                None => return true,
+                Some(usage) => usage,
+            };
+            let out_of_ops = usage.max_ops.map(|max| usage.ops >= max).unwrap_or(false);
+            let out_of_time = usage
+                .deadline
+                .map(|deadline| Instant::now() >= deadline)
+                .unwrap_or(false);
+            if out_of_ops || out_of_time {
+                usage.exhausted |= required;
+                return false;
+            }
+            usage.ops += 1;
+            true
+        })
+    }
+}
+
+/// Restore the budget of the enclosing request, if any, even if the handler panics.
+struct Scope {
+    previous: Option<Usage>,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| current.replace(previous));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RequestBudget;
+
+    fn budget(max_ops: Option<u32>, max_time: Option<Duration>) -> RequestBudget {
+        RequestBudget { max_ops, max_time }
+    }
+
+    #[test]
+    fn ops_beyond_budget_are_skipped() {
+        let (spent, exhausted) =
+            budget(Some(2), None).run(|| (0..5).take_while(|_| RequestBudget::spend()).count());
+        assert_eq!(spent, 2);
+        assert!(exhausted);
+    }
+
+    #[test]
+    fn optional_ops_do_not_exhaust_budget() {
+        let (spent, exhausted) = budget(Some(1), None).run(|| {
+            (0..3)
+                .take_while(|_| RequestBudget::spend_optional())
+                .count()
+        });
+        assert_eq!(spent, 1);
+        assert!(!exhausted);
+    }
+
+    #[test]
+    fn time_budget_expires() {
+        let (spent, exhausted) = budget(None, Some(Duration::from_millis(0)))
+            .run(|| (0..5).take_while(|_| RequestBudget::spend()).count());
+        assert_eq!(spent, 0);
+        assert!(exhausted);
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let (spent, exhausted) =
+            budget(None, None).run(|| (0..5).take_while(|_| RequestBudget::spend()).count());
+        assert_eq!(spent, 5);
+        assert!(!exhausted);
+        assert!(RequestBudget::spend());
+    }
+}
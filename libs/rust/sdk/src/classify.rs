@@ -0,0 +1,219 @@
+use std::sync::Arc;
+
+use failure::Fail;
+use opentracingrust::Span;
+
+use replicante_models_agent::info::AgentInfo;
+use replicante_models_agent::info::DatastoreInfo;
+use replicante_models_agent::info::Shards;
+
+use crate::actions::Action;
+use crate::actions::ActionAuthorizer;
+use crate::actions::ActionHook;
+use crate::config::ConnectionErrorsConfig;
+use crate::config::DatastoreConfig;
+use crate::Agent;
+use crate::DatastoreExtras;
+use crate::Error;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Map driver errors to finer error kinds describing why the datastore can't be used.
+///
+/// Only `Connection` and `StoreOpFailed` errors are classified, as those wrap driver errors.
+/// Classified errors keep the original error as their cause.
+pub struct ErrorClassifier {
+    auth: Vec<String>,
+    tls: Vec<String>,
+    unreachable: Vec<String>,
+}
+
+impl ErrorClassifier {
+    pub fn new(config: &ConnectionErrorsConfig) -> ErrorClassifier {
+        let lowercase = |patterns: &[String]| -> Vec<String> {
+            patterns
+                .iter()
+                .map(|pattern| pattern.to_lowercase())
+                .collect()
+        };
+        ErrorClassifier {
+            auth: lowercase(&config.auth),
+            tls: lowercase(&config.tls),
+            unreachable: lowercase(&config.unreachable),
+        }
+    }
+
+    /// Return the error with a finer kind, if the error matches any pattern.
+    ///
+    /// Patterns are matched against the causes only: the message of the error itself
+    /// is written by the agent and includes datastore addresses that could match.
+    pub fn classify(&self, error: Error) -> Error {
+        match error.kind() {
+            ErrorKind::Connection(_, _) | ErrorKind::StoreOpFailed(_) => (),
+            _ => return error,
+        };
+        let messages: Vec<String> = <dyn Fail>::iter_chain(&error)
+            .skip(1)
+            .map(|cause| cause.to_string().to_lowercase())
+            .collect();
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| messages.iter().any(|message| message.contains(pattern)))
+        };
+        let message = error.to_string();
+        let kind = if matches(&self.auth) {
+            ErrorKind::DatastoreAuth(message)
+        } else if matches(&self.tls) {
+            ErrorKind::DatastoreTls(message)
+        } else if matches(&self.unreachable) {
+            ErrorKind::DatastoreUnreachable(message)
+        } else {
+            return error;
+        };
+        error.context(kind).into()
+    }
+}
+
+/// Wrap an agent so errors from its datastore operations are classified.
+pub struct ClassifiedAgent {
+    agent: Arc<dyn Agent>,
+    classifier: ErrorClassifier,
+}
+
+impl ClassifiedAgent {
+    pub fn wrap(agent: Arc<dyn Agent>, config: &DatastoreConfig) -> Arc<dyn Agent> {
+        let classifier = ErrorClassifier::new(&config.connection_errors);
+        Arc::new(ClassifiedAgent { agent, classifier })
+    }
+}
+
+impl Agent for ClassifiedAgent {
+    fn agent_info(&self, span: &mut Span) -> Result<AgentInfo> {
+        self.agent
+            .agent_info(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
+        self.agent
+            .datastore_info(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        self.agent
+            .datastore_extras(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn health(&self, span: &mut Span) -> Result<()> {
+        self.agent
+            .health(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        self.agent
+            .warmup(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn shards(&self, span: &mut Span) -> Result<Shards> {
+        self.agent
+            .shards(span)
+            .map_err(|error| self.classifier.classify(error))
+    }
+
+    fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
+        self.agent.action_hooks()
+    }
+
+    fn action_authorizer(&self) -> Arc<dyn ActionAuthorizer> {
+        self.agent.action_authorizer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use failure::Fail;
+
+    use super::ErrorClassifier;
+    use crate::config::ConnectionErrorsConfig;
+    use crate::Error;
+    use crate::ErrorKind;
+
+    fn classify(error: Error) -> (String, StatusCode) {
+        let classifier = ErrorClassifier::new(&ConnectionErrorsConfig::default());
+        let error = classifier.classify(error);
+        let name = error.name().unwrap().to_string();
+        (name, error.status_code())
+    }
+
+    fn connection_error(message: &str) -> Error {
+        failure::err_msg(message.to_string())
+            .context(ErrorKind::Connection("mongodb", "localhost:27017".into()))
+            .into()
+    }
+
+    #[test]
+    fn auth_errors() {
+        let error = failure::err_msg("Authentication failed.")
+            .context(ErrorKind::StoreOpFailed("buildInfo"))
+            .into();
+        let (name, status) = classify(error);
+        assert_eq!(name, "DatastoreAuth");
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn tls_errors() {
+        let error = connection_error("invalid certificate: UnknownIssuer");
+        let (name, status) = classify(error);
+        assert_eq!(name, "DatastoreTls");
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn unreachable_errors() {
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "Connection refused");
+        let error = refused
+            .context(ErrorKind::Connection("zookeeper", "localhost:2181".into()))
+            .into();
+        let (name, status) = classify(error);
+        assert_eq!(name, "DatastoreUnreachable");
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let dns =
+            connection_error("failed to lookup address information: Name or service not known");
+        let (name, _) = classify(dns);
+        assert_eq!(name, "DatastoreUnreachable");
+    }
+
+    #[test]
+    fn agent_message_not_matched() {
+        let error = failure::err_msg("something unexpected")
+            .context(ErrorKind::Connection(
+                "mongodb",
+                "tls-host.example.com:27017".into(),
+            ))
+            .into();
+        let (name, _) = classify(error);
+        assert_eq!(name, "Connection");
+    }
+
+    #[test]
+    fn unknown_errors_unchanged() {
+        let (name, status) = classify(connection_error("something unexpected"));
+        assert_eq!(name, "Connection");
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let decode: Error = ErrorKind::ResponseDecode("bson", "buildInfo").into();
+        let (name, _) = classify(decode);
+        assert_eq!(name, "ResponseDecode");
+    }
+}
@@ -1,17 +1,119 @@
+use std::collections::BTreeMap;
+
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use super::check_duration;
+use super::CachedResponse;
+use crate::Result;
+
 /// Actions configuration
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct ActionsConfig {
+    /// Resolve `{{ name }}` placeholders in action arguments when actions are scheduled.
+    ///
+    /// Placeholders are replaced with values known to the agent (`cluster`, `hostname`,
+    /// `node_name`) and actions with unknown placeholders are rejected.
+    #[serde(default)]
+    pub args_templating: bool,
+
+    /// Append-only log of action state transitions (optional).
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+
+    /// Cluster-wide coordination of singleton actions (optional).
+    #[serde(default)]
+    pub coordination: Option<CoordinationConfig>,
+
+    /// Time, in seconds, to keep retrying actions while the datastore is unreachable.
+    ///
+    /// Idempotent actions failing because the datastore can't be reached are invoked again
+    /// instead of failing until this grace expires. Set to 0 to fail them immediately.
+    #[serde(default)]
+    pub datastore_down_grace: u64,
+
+    /// Time, in seconds, to de-duplicate scheduled actions for (optional).
+    ///
+    /// Actions scheduled through the API are coalesced into existing actions with the
+    /// same kind and arguments that are pending or running and were created within the window.
+    #[serde(default)]
+    pub dedup_window: Option<u64>,
+
+    /// Action kinds the agent refuses to create (optional).
+    ///
+    /// Can't be set along with `enabled_kinds`.
+    #[serde(default)]
+    pub disabled_kinds: Option<Vec<String>>,
+
     /// Enable/disable agent actions.
     #[serde(default)]
     pub enabled: Option<bool>,
 
+    /// Only action kinds in this list can be created (optional).
+    ///
+    /// Can't be set along with `disabled_kinds`.
+    #[serde(default)]
+    pub enabled_kinds: Option<Vec<String>>,
+
     /// Delay, in seconds, between action executions.
     #[serde(default = "ActionsConfig::default_execute_interval")]
     pub execute_interval: u64,
 
+    /// Names of action arguments to redact when exporting action records.
+    ///
+    /// Arguments are redacted wherever they appear, including in nested objects.
+    #[serde(default)]
+    pub export_redacted_args: Vec<String>,
+
+    /// Time, in seconds, after which actions whose executor stopped heartbeating are failed.
+    ///
+    /// Only actions with an invocation in progress are checked: a heartbeat is recorded
+    /// when the invocation starts and cleared once its outcome is stored, so actions left
+    /// invoked by a crashed agent are failed once the timeout elapses.
+    ///
+    /// Disabled if not set.
+    #[serde(default)]
+    pub heartbeat_timeout: Option<u64>,
+
+    /// Response caches to clear when actions of a kind complete successfully.
+    ///
+    /// Extends the caches actions declare they invalidate, keyed by action kind.
+    #[serde(default)]
+    pub invalidate_caches: BTreeMap<String, Vec<CachedResponse>>,
+
+    /// Maximum nesting depth of action arguments.
+    #[serde(default = "ActionsConfig::default_max_args_depth")]
+    pub max_args_depth: u32,
+
+    /// Maximum number of JSON values (objects, arrays and scalars) in action arguments.
+    #[serde(default = "ActionsConfig::default_max_args_nodes")]
+    pub max_args_nodes: u32,
+
+    /// Maximum timeout override, in seconds, that can be requested when creating actions.
+    #[serde(default = "ActionsConfig::default_max_timeout_override")]
+    pub max_timeout_override: u64,
+
+    /// Maximum number of actions to store (optional).
+    ///
+    /// When the limit is reached the oldest finished actions are deleted to make room
+    /// for new ones. Actions that are not finished are never deleted: if the limit is
+    /// reached with only unfinished actions, new actions are rejected.
+    #[serde(default)]
+    pub max_records: Option<u32>,
+
+    /// Time, in seconds, to keep state payloads of finished actions for (optional).
+    ///
+    /// Payloads of older actions, and their history, are cleared to save space
+    /// while the action records are kept until they are pruned.
+    #[serde(default)]
+    pub payload_retention: Option<u64>,
+
+    /// Directory to load action plugins from (optional).
+    ///
+    /// Each executable in the directory is registered as a `plugin.agent.replicante.io/*` action.
+    #[serde(default)]
+    pub plugins_dir: Option<String>,
+
     /// Delay, in seconds, between historical action prune cycles.
     #[serde(default = "ActionsConfig::default_prune_interval")]
     pub prune_interval: u64,
@@ -23,25 +125,93 @@ pub struct ActionsConfig {
     /// Number of finished actions to prune from the history in one cycle.
     #[serde(default = "ActionsConfig::default_prune_limit")]
     pub prune_limit: u32,
+
+    /// Limit the rate at which each requester can create actions (optional).
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Names of action arguments to redact, by action kind, when actions are returned by the API.
+    ///
+    /// Arguments are redacted wherever they appear, including in nested objects.
+    /// Stored arguments are not changed so actions are invoked with the original values.
+    #[serde(default)]
+    pub redacted_args: BTreeMap<String, Vec<String>>,
+
+    /// Check registered actions have well formed descriptors on startup.
+    ///
+    /// The agent fails to start if any action kind or description is malformed.
+    #[serde(default = "ActionsConfig::default_validate_descriptors")]
+    pub validate_descriptors: bool,
 }
 
 impl Default for ActionsConfig {
     fn default() -> Self {
         ActionsConfig {
+            args_templating: false,
+            audit_log: None,
+            coordination: None,
+            datastore_down_grace: 0,
+            dedup_window: None,
+            disabled_kinds: None,
             enabled: None,
+            enabled_kinds: None,
             execute_interval: Self::default_execute_interval(),
+            export_redacted_args: Vec::new(),
+            heartbeat_timeout: None,
+            invalidate_caches: BTreeMap::new(),
+            max_args_depth: Self::default_max_args_depth(),
+            max_args_nodes: Self::default_max_args_nodes(),
+            max_records: None,
+            max_timeout_override: Self::default_max_timeout_override(),
+            payload_retention: None,
+            plugins_dir: None,
             prune_interval: Self::default_prune_interval(),
             prune_keep: Self::default_prune_keep(),
             prune_limit: Self::default_prune_limit(),
+            rate_limit: None,
+            redacted_args: BTreeMap::new(),
+            validate_descriptors: Self::default_validate_descriptors(),
         }
     }
 }
 
 impl ActionsConfig {
+    /// Check if actions of the given kind can be created.
+    pub fn kind_enabled(&self, kind: &str) -> bool {
+        if let Some(enabled) = self.enabled_kinds.as_ref() {
+            return enabled.iter().any(|enabled| enabled == kind);
+        }
+        if let Some(disabled) = self.disabled_kinds.as_ref() {
+            return !disabled.iter().any(|disabled| disabled == kind);
+        }
+        true
+    }
+
+    /// Reject durations too long to be applied to timestamps.
+    pub fn validate(&self) -> Result<()> {
+        check_duration(self.dedup_window, "actions.dedup_window")?;
+        check_duration(self.heartbeat_timeout, "actions.heartbeat_timeout")?;
+        let max_timeout_override = Some(self.max_timeout_override);
+        check_duration(max_timeout_override, "actions.max_timeout_override")?;
+        check_duration(self.payload_retention, "actions.payload_retention")
+    }
+
     fn default_execute_interval() -> u64 {
         1
     }
 
+    fn default_max_args_depth() -> u32 {
+        32
+    }
+
+    fn default_max_args_nodes() -> u32 {
+        10_000
+    }
+
+    fn default_max_timeout_override() -> u64 {
+        24 * 3600
+    }
+
     fn default_prune_interval() -> u64 {
         3600
     }
@@ -53,6 +223,63 @@ impl ActionsConfig {
     fn default_prune_limit() -> u32 {
         500
     }
+
+    fn default_validate_descriptors() -> bool {
+        true
+    }
+}
+
+/// Actions audit log configuration.
+///
+/// Every action state transition is appended to the log as a JSON line.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct AuditLogConfig {
+    /// Number of rotated log files to keep.
+    #[serde(default = "AuditLogConfig::default_keep")]
+    pub keep: u32,
+
+    /// Size, in bytes, after which the log file is rotated.
+    #[serde(default = "AuditLogConfig::default_max_size")]
+    pub max_size: u64,
+
+    /// Path to the audit log file.
+    pub path: String,
+}
+
+impl AuditLogConfig {
+    fn default_keep() -> u32 {
+        5
+    }
+
+    fn default_max_size() -> u64 {
+        50 * 1024 * 1024
+    }
+}
+
+/// Cluster-wide coordination of singleton actions.
+///
+/// Singleton actions hold a lock, stored in a directory shared by all agents in
+/// the cluster, from the time they are first invoked until they finish.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CoordinationConfig {
+    /// Fail singleton actions that can't acquire the lock instead of waiting for it.
+    #[serde(default)]
+    pub fail_fast: bool,
+
+    /// Directory, shared by all agents in the cluster, to store the lock in.
+    pub lock_dir: String,
+
+    /// Time, in seconds, after which locks not refreshed by their holder are abandoned.
+    ///
+    /// Locks are refreshed every time the action holding them is invoked.
+    #[serde(default = "CoordinationConfig::default_stale_after")]
+    pub stale_after: u64,
+}
+
+impl CoordinationConfig {
+    fn default_stale_after() -> u64 {
+        600
+    }
 }
 
 /// Parameters of a user-defined external action.
@@ -103,3 +330,47 @@ pub struct ExternalActionConfig {
     /// Operator friendly description of what the action does.
     pub description: String,
 }
+
+/// Token bucket limits on the creation of actions by each requester.
+///
+/// Requesters are identified by the requester reported by the client and the client address.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Number of actions a requester can create in a burst.
+    #[serde(default = "RateLimitConfig::default_burst")]
+    pub burst: u32,
+
+    /// Maximum number of requesters to track.
+    ///
+    /// When the limit is reached the least recently seen requesters are forgotten.
+    #[serde(default = "RateLimitConfig::default_max_requesters")]
+    pub max_requesters: usize,
+
+    /// Number of actions a requester can create every minute, once the burst is used up.
+    #[serde(default = "RateLimitConfig::default_per_minute")]
+    pub per_minute: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            burst: Self::default_burst(),
+            max_requesters: Self::default_max_requesters(),
+            per_minute: Self::default_per_minute(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn default_burst() -> u32 {
+        10
+    }
+
+    fn default_max_requesters() -> usize {
+        1024
+    }
+
+    fn default_per_minute() -> u32 {
+        60
+    }
+}
@@ -1,10 +1,15 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::sync::RwLock;
 
+use actix_web::http::header::HeaderValue;
 use lazy_static::lazy_static;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use crate::ErrorKind;
+use crate::Result;
+
 // Define some globals to hold the default overrides.
 lazy_static! {
     static ref DEFAULT_BIND: RwLock<Option<String>> = RwLock::new(None);
@@ -13,10 +18,48 @@ lazy_static! {
 /// Web server configuration options.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct APIConfig {
+    /// IP address families to bind the API server to.
+    ///
+    /// If not set, the API server binds to `bind` as is.
+    #[serde(default)]
+    pub address_family: Option<AddressFamily>,
+
     /// Local addess to bind the API server to.
     #[serde(default = "APIConfig::default_bind")]
     pub bind: String,
 
+    /// Compress responses when clients accept gzip or deflate encodings.
+    #[serde(default)]
+    pub compression: bool,
+
+    /// Replicante Core versions the agent accepts requests from (optional).
+    ///
+    /// Requests from any version are accepted if not set.
+    #[serde(default)]
+    pub core_versions: Option<CoreVersionsConfig>,
+
+    /// Maximum number of concurrent requests for specific endpoints.
+    ///
+    /// Endpoints are identified by their route pattern (`/api/unstable/shards`).
+    /// Endpoints not listed here are not limited.
+    #[serde(default)]
+    pub endpoint_concurrency: BTreeMap<String, usize>,
+
+    /// Details included in error responses.
+    #[serde(default)]
+    pub errors: ErrorsConfig,
+
+    /// Time, in seconds, clients are asked to wait before retrying rejected requests.
+    ///
+    /// Sent with the `Retry-After` header of 429 and 503 error responses when the
+    /// agent can't estimate how long the condition will last.
+    #[serde(default = "APIConfig::default_retry_after")]
+    pub retry_after: u64,
+
+    /// Shape of shards in shards responses, for compatibility with older clients.
+    #[serde(default)]
+    pub shards_format: ShardsFormat,
+
     /// The number of request handling threads.
     #[serde(default)]
     pub threads_count: Option<usize>,
@@ -32,16 +75,28 @@ pub struct APIConfig {
     /// Enable/disable entire API trees.
     #[serde(default)]
     pub trees: APITrees,
+
+    /// API versions advertised to clients with the `X-Replicante-Agent-Api` header.
+    #[serde(default = "APIConfig::default_versions")]
+    pub versions: Vec<String>,
 }
 
 impl Default for APIConfig {
     fn default() -> Self {
         APIConfig {
+            address_family: None,
             bind: Self::default_bind(),
+            compression: false,
+            core_versions: None,
+            endpoint_concurrency: BTreeMap::new(),
+            errors: ErrorsConfig::default(),
+            retry_after: Self::default_retry_after(),
+            shards_format: ShardsFormat::default(),
             threads_count: None,
             timeouts: Timeouts::default(),
             tls: None,
             trees: APITrees::default(),
+            versions: Self::default_versions(),
         }
     }
 }
@@ -56,6 +111,27 @@ impl APIConfig {
             .map(Clone::clone)
             .unwrap_or_else(|| String::from("127.0.0.1:8000"))
     }
+
+    /// Default value for `retry_after` used by serde.
+    fn default_retry_after() -> u64 {
+        1
+    }
+
+    /// Default value for `versions` used by serde.
+    fn default_versions() -> Vec<String> {
+        vec![String::from("v1")]
+    }
+
+    /// Check options that deserialisation alone can't validate.
+    ///
+    /// API versions are sent with every response so they must be valid header values.
+    pub fn validate(&self) -> Result<()> {
+        let versions = self.versions.join(", ");
+        if HeaderValue::from_str(&versions).is_err() {
+            return Err(ErrorKind::ConfigOption("api.versions").into());
+        }
+        Ok(())
+    }
 }
 
 impl APIConfig {
@@ -75,6 +151,42 @@ impl APIConfig {
     }
 }
 
+/// IP address families the API server can bind to.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum AddressFamily {
+    /// Bind an IPv4 listener only.
+    #[serde(rename = "ipv4")]
+    IPv4,
+
+    /// Bind an IPv6 listener only.
+    #[serde(rename = "ipv6")]
+    IPv6,
+
+    /// Bind both an IPv4 and an IPv6 listener.
+    #[serde(rename = "both")]
+    Both,
+}
+
+/// Shapes shards can be reported in by the API.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum ShardsFormat {
+    /// Flat shards with `id`, `role`, `lag` and `last_op` attributes.
+    ///
+    /// Lag and last operation are reported as plain numbers without units.
+    #[serde(rename = "legacy")]
+    Legacy,
+
+    /// Shards with structured commit offsets, as defined by the agent models.
+    #[serde(rename = "structured")]
+    Structured,
+}
+
+impl Default for ShardsFormat {
+    fn default() -> ShardsFormat {
+        ShardsFormat::Structured
+    }
+}
+
 /// Enable/disable entire API trees.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
 pub struct APITrees {
@@ -114,6 +226,52 @@ impl From<APITrees> for HashMap<&'static str, bool> {
     }
 }
 
+/// Replicante Core versions the agent accepts requests from.
+///
+/// Core advertises its version with the `X-Replicante-Core-Version` request header.
+/// Requests without the header are always accepted.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CoreVersionsConfig {
+    /// Reject requests from unsupported versions instead of logging a warning.
+    #[serde(default = "CoreVersionsConfig::default_reject")]
+    pub reject: bool,
+
+    /// Semantic versioning requirement Core versions must match (`>=0.7.0, <0.9.0`).
+    pub supported: String,
+}
+
+impl CoreVersionsConfig {
+    /// Default value for `reject` used by serde.
+    fn default_reject() -> bool {
+        true
+    }
+}
+
+/// Details included in error responses.
+///
+/// Error responses only include the error message by default.
+/// Verbose responses also include the full cause chain and backtraces.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ErrorsConfig {
+    /// Requests with this header (and value) are sent verbose error responses.
+    #[serde(default)]
+    pub trusted_header: Option<TrustedHeader>,
+
+    /// Send verbose error responses to all clients.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+/// HTTP header identifying trusted API clients.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct TrustedHeader {
+    /// Name of the header.
+    pub name: String,
+
+    /// Value the header must have for the client to be trusted.
+    pub value: String,
+}
+
 /// API server timeouts.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Timeouts {
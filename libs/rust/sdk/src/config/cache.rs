@@ -0,0 +1,44 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Caching of datastore info and shards API responses.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Maximum age (in seconds) of cached responses before they are considered invalid.
+    ///
+    /// This is a hard cap on `ttl` so the control plane never acts on stale data.
+    #[serde(default = "CacheConfig::default_max_stale")]
+    pub max_stale: u64,
+
+    /// Time (in seconds) cached responses are served for, 0 to disable caching.
+    #[serde(default)]
+    pub ttl: u64,
+}
+
+/// Cached agent API responses.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum CachedResponse {
+    /// The datastore info endpoint.
+    #[serde(rename = "datastore")]
+    Datastore,
+
+    /// The shards endpoints.
+    #[serde(rename = "shards")]
+    Shards,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_stale: Self::default_max_stale(),
+            ttl: 0,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Default value for `max_stale` used by serde.
+    fn default_max_stale() -> u64 {
+        60
+    }
+}
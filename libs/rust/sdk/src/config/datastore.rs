@@ -0,0 +1,133 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Protections for the datastore against excessive load from the agent.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct DatastoreConfig {
+    /// Patterns used to classify datastore connection errors.
+    #[serde(default)]
+    pub connection_errors: ConnectionErrorsConfig,
+
+    /// Maximum number of datastore operations the agent runs at the same time.
+    ///
+    /// Operations beyond this limit wait for a running operation to complete.
+    /// No limit is enforced when not set; the limit can't be 0.
+    #[serde(default)]
+    pub max_concurrent_ops: Option<usize>,
+
+    /// Time, in milliseconds, operations wait for others to complete before giving up.
+    #[serde(default = "DatastoreConfig::default_max_concurrent_ops_wait")]
+    pub max_concurrent_ops_wait: u64,
+
+    /// Minimum time, in milliseconds, between datastore probes for the same endpoint.
+    ///
+    /// Requests received within this interval share the result of the last probe
+    /// (including one still in progress) instead of issuing new datastore commands.
+    /// Set to 0 to disable.
+    #[serde(default = "DatastoreConfig::default_min_probe_interval")]
+    pub min_probe_interval: u64,
+
+    /// Maximum number of datastore operations a single API request can issue.
+    ///
+    /// Once the budget is used up, requests respond with the data collected so far
+    /// and flag the response as incomplete. No limit is enforced when not set.
+    #[serde(default)]
+    pub request_budget_ops: Option<u32>,
+
+    /// Maximum time, in milliseconds, a single API request can spend on datastore operations.
+    ///
+    /// Once the budget is used up, requests respond with the data collected so far
+    /// and flag the response as incomplete. No limit is enforced when not set.
+    #[serde(default)]
+    pub request_budget_time: Option<u64>,
+}
+
+impl Default for DatastoreConfig {
+    fn default() -> Self {
+        DatastoreConfig {
+            connection_errors: ConnectionErrorsConfig::default(),
+            max_concurrent_ops: None,
+            max_concurrent_ops_wait: Self::default_max_concurrent_ops_wait(),
+            min_probe_interval: Self::default_min_probe_interval(),
+            request_budget_ops: None,
+            request_budget_time: None,
+        }
+    }
+}
+
+impl DatastoreConfig {
+    /// Default value for `max_concurrent_ops_wait` used by serde.
+    fn default_max_concurrent_ops_wait() -> u64 {
+        5000
+    }
+
+    /// Default value for `min_probe_interval` used by serde.
+    fn default_min_probe_interval() -> u64 {
+        100
+    }
+}
+
+/// Patterns used to classify datastore connection errors.
+///
+/// Patterns are matched, ignoring case, against the messages of the error causes.
+/// Errors are classified as the first of authentication, TLS and unreachable errors
+/// with a matching pattern; errors matching no pattern are reported as they are.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ConnectionErrorsConfig {
+    /// Patterns for errors caused by the datastore rejecting the agent credentials.
+    #[serde(default = "ConnectionErrorsConfig::default_auth")]
+    pub auth: Vec<String>,
+
+    /// Patterns for errors caused by TLS negotiation or certificate validation.
+    #[serde(default = "ConnectionErrorsConfig::default_tls")]
+    pub tls: Vec<String>,
+
+    /// Patterns for errors caused by the datastore not being reachable.
+    #[serde(default = "ConnectionErrorsConfig::default_unreachable")]
+    pub unreachable: Vec<String>,
+}
+
+impl Default for ConnectionErrorsConfig {
+    fn default() -> Self {
+        ConnectionErrorsConfig {
+            auth: Self::default_auth(),
+            tls: Self::default_tls(),
+            unreachable: Self::default_unreachable(),
+        }
+    }
+}
+
+impl ConnectionErrorsConfig {
+    /// Default value for `auth` used by serde.
+    fn default_auth() -> Vec<String> {
+        vec![
+            "authentication failed".into(),
+            "auth failed".into(),
+            "not authorized".into(),
+            "sasl".into(),
+        ]
+    }
+
+    /// Default value for `tls` used by serde.
+    fn default_tls() -> Vec<String> {
+        vec![
+            "certificate".into(),
+            "handshake".into(),
+            "ssl".into(),
+            "tls".into(),
+        ]
+    }
+
+    /// Default value for `unreachable` used by serde.
+    fn default_unreachable() -> Vec<String> {
+        vec![
+            "connection refused".into(),
+            "connection reset".into(),
+            "failed to lookup address".into(),
+            "name or service not known".into(),
+            "no route to host".into(),
+            "server selection".into(),
+            "timed out".into(),
+        ]
+    }
+}
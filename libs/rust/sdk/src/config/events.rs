@@ -0,0 +1,26 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Agent lifecycle events configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct EventsConfig {
+    /// Time, in seconds, lifecycle events are kept in the store before they are pruned.
+    #[serde(default = "EventsConfig::default_retention")]
+    pub retention: u64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            retention: Self::default_retention(),
+        }
+    }
+}
+
+impl EventsConfig {
+    /// Default value for `retention` used by serde.
+    fn default_retention() -> u64 {
+        // 7 days.
+        604800
+    }
+}
@@ -0,0 +1,114 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Datastore health checks configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Include the kind and (redacted) message of errors in unhealthy responses.
+    #[serde(default = "HealthConfig::default_error_details")]
+    pub error_details: bool,
+
+    /// Pause background collectors while the last health check found the datastore unhealthy.
+    #[serde(default = "HealthConfig::default_gate_collectors")]
+    pub gate_collectors: bool,
+
+    /// Number of recent health check results to keep in memory for introspection.
+    #[serde(default = "HealthConfig::default_history_size")]
+    pub history_size: usize,
+
+    /// Name of the datastore command used to probe the health of the node.
+    ///
+    /// The probes available depend on the agent.
+    /// If not set the cheapest probe supported by the agent is used.
+    #[serde(default)]
+    pub probe: Option<String>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            error_details: Self::default_error_details(),
+            gate_collectors: Self::default_gate_collectors(),
+            history_size: Self::default_history_size(),
+            probe: None,
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Default value for `error_details` used by serde.
+    fn default_error_details() -> bool {
+        true
+    }
+
+    /// Default value for `gate_collectors` used by serde.
+    fn default_gate_collectors() -> bool {
+        true
+    }
+
+    /// Default value for `history_size` used by serde.
+    fn default_history_size() -> usize {
+        20
+    }
+
+    /// Validate the configured probe against the probes supported by an agent.
+    ///
+    /// Supported probes should be listed from cheapest to most expensive
+    /// as the first probe is selected if none is configured.
+    ///
+    /// # Panics
+    /// If the list of supported probes is empty.
+    pub fn probe(&self, supported: &[&'static str]) -> Result<&'static str> {
+        let default = *supported
+            .first()
+            .expect("agents must support at least one health probe");
+        let probe = match self.probe.as_ref() {
+            None => return Ok(default),
+            Some(probe) => probe,
+        };
+        supported
+            .iter()
+            .find(|supported| **supported == probe.as_str())
+            .copied()
+            .ok_or_else(|| ErrorKind::ConfigOption("agent.health.probe").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Fail;
+
+    use super::HealthConfig;
+
+    #[test]
+    fn probe_configured() {
+        let config = HealthConfig {
+            probe: Some("expensive".into()),
+            ..HealthConfig::default()
+        };
+        let probe = config.probe(&["cheap", "expensive"]).unwrap();
+        assert_eq!(probe, "expensive");
+    }
+
+    #[test]
+    fn probe_default_is_first() {
+        let config = HealthConfig::default();
+        let probe = config.probe(&["cheap", "expensive"]).unwrap();
+        assert_eq!(probe, "cheap");
+    }
+
+    #[test]
+    fn probe_unsupported() {
+        let config = HealthConfig {
+            probe: Some("unknown".into()),
+            ..HealthConfig::default()
+        };
+        match config.probe(&["cheap", "expensive"]) {
+            Ok(_) => panic!("expected configuration error"),
+            Err(error) => assert_eq!(error.name().unwrap(), "ConfigOption"),
+        };
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Jitter applied to background task intervals to spread work across agents.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct JitterConfig {
+    /// Identifier of this agent instance used to derive the jitter (optional).
+    ///
+    /// Agents with the same ID apply the same jitter so delays are reproducible.
+    /// A random ID is generated at startup if one is not set.
+    #[serde(default)]
+    pub instance_id: Option<String>,
+
+    /// Maximum jitter, as a percentage of the interval, added to background task intervals.
+    #[serde(default = "JitterConfig::default_percent")]
+    pub percent: u8,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        JitterConfig {
+            instance_id: None,
+            percent: Self::default_percent(),
+        }
+    }
+}
+
+impl JitterConfig {
+    /// Default value for `percent` used by serde.
+    fn default_percent() -> u8 {
+        10
+    }
+
+    /// Extend the given interval by this instance's jitter.
+    pub fn apply(&self, interval: Duration) -> Duration {
+        let jitter = interval.as_secs_f64() * f64::from(self.percent) / 100.0 * self.fraction();
+        interval + Duration::from_secs_f64(jitter)
+    }
+
+    /// Deterministic value in the `[0, 1)` range derived from the instance ID.
+    fn fraction(&self) -> f64 {
+        // FNV-1a is used over std hashers as their output is not guaranteed to be stable.
+        let seed = self.instance_id.as_deref().unwrap_or("");
+        let hash = seed.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        (hash % 10_000) as f64 / 10_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::JitterConfig;
+
+    fn jitter(instance_id: &str) -> JitterConfig {
+        JitterConfig {
+            instance_id: Some(instance_id.into()),
+            percent: 10,
+        }
+    }
+
+    #[test]
+    fn instances_schedule_at_different_offsets() {
+        let interval = Duration::from_secs(60);
+        let first = jitter("agent-1").apply(interval);
+        let second = jitter("agent-2").apply(interval);
+        assert_ne!(first, second);
+        assert!(first >= interval && first <= Duration::from_secs(66));
+        assert!(second >= interval && second <= Duration::from_secs(66));
+    }
+
+    #[test]
+    fn same_instance_is_reproducible() {
+        let interval = Duration::from_secs(60);
+        assert_eq!(
+            jitter("agent-1").apply(interval),
+            jitter("agent-1").apply(interval)
+        );
+    }
+
+    #[test]
+    fn zero_percent_disables_jitter() {
+        let config = JitterConfig {
+            percent: 0,
+            ..jitter("agent-1")
+        };
+        let interval = Duration::from_secs(60);
+        assert_eq!(config.apply(interval), interval);
+    }
+}
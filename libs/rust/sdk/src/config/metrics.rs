@@ -0,0 +1,25 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Metrics configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Maximum number of distinct values tracked for guarded metric labels.
+    #[serde(default = "MetricsConfig::default_max_label_values")]
+    pub max_label_values: usize,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            max_label_values: Self::default_max_label_values(),
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Default value for `max_label_values` used by serde.
+    fn default_max_label_values() -> usize {
+        100
+    }
+}
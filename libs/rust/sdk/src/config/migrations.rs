@@ -0,0 +1,35 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Agent store migrations configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct MigrationsConfig {
+    /// Number of times failed migrations are retried before startup is aborted.
+    #[serde(default = "MigrationsConfig::default_retries")]
+    pub retries: u32,
+
+    /// Delay, in seconds, before the first retry, doubled after every failed attempt.
+    #[serde(default = "MigrationsConfig::default_retry_interval")]
+    pub retry_interval: u64,
+}
+
+impl Default for MigrationsConfig {
+    fn default() -> Self {
+        MigrationsConfig {
+            retries: Self::default_retries(),
+            retry_interval: Self::default_retry_interval(),
+        }
+    }
+}
+
+impl MigrationsConfig {
+    /// Default value for `retries` used by serde.
+    fn default_retries() -> u32 {
+        5
+    }
+
+    /// Default value for `retry_interval` used by serde.
+    fn default_retry_interval() -> u64 {
+        1
+    }
+}
@@ -1,24 +1,65 @@
 use std::collections::BTreeMap;
 
+use openssl::sha::sha256;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use serde_json::Value as Json;
+use uuid::Uuid;
 
 use replicante_logging::Config as LoggingConfig;
 use replicante_logging::LoggingLevel;
 use replicante_util_tracing::Config as TracerConfig;
 
+use crate::ErrorKind;
+use crate::Result;
+
 mod actions;
 mod api;
+mod cache;
+mod datastore;
+mod events;
+mod health;
+mod jitter;
+mod metrics;
+mod migrations;
+mod reconnect;
 mod sentry;
 mod service;
+mod shards;
+mod spans;
+mod tls;
+mod warmup;
 
 pub use self::actions::ActionsConfig;
+pub use self::actions::AuditLogConfig;
+pub use self::actions::CoordinationConfig;
 pub use self::actions::ExternalActionConfig;
+pub use self::actions::RateLimitConfig;
 pub use self::api::APIConfig;
+pub use self::api::AddressFamily;
+pub use self::api::CoreVersionsConfig;
+pub use self::api::ErrorsConfig;
+pub use self::api::ShardsFormat;
 pub use self::api::TlsConfig;
+pub use self::api::TrustedHeader;
+pub use self::cache::CacheConfig;
+pub use self::cache::CachedResponse;
+pub use self::datastore::ConnectionErrorsConfig;
+pub use self::datastore::DatastoreConfig;
+pub use self::events::EventsConfig;
+pub use self::health::HealthConfig;
+pub use self::jitter::JitterConfig;
+pub use self::metrics::MetricsConfig;
+pub use self::migrations::MigrationsConfig;
+pub use self::reconnect::ReconnectConfig;
 pub use self::sentry::SentryCaptureApi;
 pub use self::sentry::SentryConfig;
 pub use self::service::ServiceConfig;
+pub use self::shards::ShardsConfig;
+pub use self::spans::SpansConfig;
+pub use self::tls::TlsPolicy;
+pub use self::tls::TlsVersion;
+pub use self::warmup::WarmupConfig;
 
 /// Stores the base agent configuration options.
 ///
@@ -34,25 +75,79 @@ pub struct Agent {
     #[serde(default)]
     pub actions: ActionsConfig,
 
+    /// Roles the node must hold for the agent to execute actions (optional).
+    ///
+    /// When set, and the node holds none of these roles, requests to schedule actions
+    /// are rejected and background action execution is paused.
+    #[serde(default)]
+    pub active_roles: Option<Vec<String>>,
+
     /// API server configuration
     #[serde(default)]
     pub api: APIConfig,
 
+    /// Datastore info and shards responses caching.
+    #[serde(default)]
+    pub cache: CacheConfig,
+
     /// Override the cluster display name, or set it if none was detected.
     #[serde(default)]
     pub cluster_display_name_override: Option<String>,
 
+    /// Key grouping related clusters, such as one replica set across regions (optional).
+    #[serde(default)]
+    pub cluster_group: Option<String>,
+
+    /// Protections for the datastore against excessive load.
+    #[serde(default)]
+    pub datastore: DatastoreConfig,
+
     /// Location for the agent to store persistent data.
     pub db: String,
 
+    /// Wait, in seconds, for the node to become primary before invoking actions requiring it.
+    ///
+    /// Primary-only actions scheduled on a warm standby stay pending until the node is
+    /// promoted and fail if that does not happen in time. Disabled when not set.
+    #[serde(default)]
+    pub defer_primary_ops: Option<u64>,
+
+    /// Agent lifecycle events configuration.
+    #[serde(default)]
+    pub events: EventsConfig,
+
     /// User defined external actions.
     #[serde(default)]
     pub external_actions: BTreeMap<String, ExternalActionConfig>,
 
+    /// Datastore health checks configuration.
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// Jitter applied to background task intervals.
+    #[serde(default)]
+    pub jitter: JitterConfig,
+
     /// Logging configuration.
     #[serde(default)]
     pub logging: LoggingConfig,
 
+    /// Metrics configuration.
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    /// Agent store migrations configuration.
+    #[serde(default)]
+    pub migrations: MigrationsConfig,
+
+    /// Override the node name detected from the datastore.
+    #[serde(default)]
+    pub node_name_override: Option<String>,
+
+    /// Datastore reconnect backoff configuration.
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+
     /// Sentry integration configuration.
     #[serde(default)]
     pub sentry: Option<SentryConfig>,
@@ -61,6 +156,18 @@ pub struct Agent {
     #[serde(default)]
     pub service: Option<ServiceConfig>,
 
+    /// Sanity checks applied to shard information.
+    #[serde(default)]
+    pub shards: ShardsConfig,
+
+    /// Limits applied to values attached to tracing spans.
+    #[serde(default)]
+    pub spans: SpansConfig,
+
+    /// TLS requirements for the API server and datastore connections.
+    #[serde(default)]
+    pub tls: TlsPolicy,
+
     /// OpenTracing configuration.
     #[serde(default)]
     pub tracing: TracerConfig,
@@ -68,6 +175,10 @@ pub struct Agent {
     /// Enable the update checker (optional).
     #[serde(default = "Agent::default_update_checker")]
     pub update_checker: bool,
+
+    /// Startup warmup configuration.
+    #[serde(default)]
+    pub warmup: WarmupConfig,
 }
 
 impl Agent {
@@ -75,12 +186,47 @@ impl Agent {
         false
     }
 
+    /// Stable hash of the configuration, used to detect configuration drift across nodes.
+    ///
+    /// Secrets (the sentry DSN) and values generated for each process (the jitter
+    /// instance ID) are excluded so nodes with the same configuration report the same hash.
+    pub fn checksum(&self) -> String {
+        let mut config = serde_json::to_value(self).expect("agent config must serialise to JSON");
+        if let Some(sentry) = config.pointer_mut("/sentry").and_then(Json::as_object_mut) {
+            sentry.remove("dsn");
+        }
+        if let Some(jitter) = config.pointer_mut("/jitter").and_then(Json::as_object_mut) {
+            jitter.remove("instance_id");
+        }
+        // JSON objects are sorted by key so the encoding is deterministic.
+        let config = config.to_string();
+        sha256(config.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Reject option values that would only fail once the agent is running.
+    pub fn validate(&self) -> Result<()> {
+        self.api.validate()?;
+        self.actions.validate()?;
+        // A limit of 0 would make every datastore operation wait and then fail.
+        if self.datastore.max_concurrent_ops == Some(0) {
+            return Err(ErrorKind::ConfigOption("datastore.max_concurrent_ops").into());
+        }
+        check_duration(self.defer_primary_ops, "defer_primary_ops")
+    }
+
     /// Apply transformations to the configuration to derive some parameters.
     ///
     /// Transformations:
     ///
     ///   * Apply verbose debug level logic.
+    ///   * Generate a random jitter instance ID if one is not set.
     pub fn transform(mut self) -> Self {
+        if self.jitter.instance_id.is_none() {
+            self.jitter.instance_id = Some(Uuid::new_v4().to_string());
+        }
         if self.logging.level == LoggingLevel::Debug && !self.logging.verbose {
             self.logging.level = LoggingLevel::Info;
             self.logging
@@ -96,23 +242,117 @@ impl Agent {
     pub fn mock() -> Self {
         Agent {
             actions: ActionsConfig::default(),
+            active_roles: None,
             api: APIConfig::default(),
+            cache: CacheConfig::default(),
             cluster_display_name_override: None,
+            cluster_group: None,
+            datastore: DatastoreConfig::default(),
             db: "mock.db".into(),
+            defer_primary_ops: None,
+            events: EventsConfig::default(),
             external_actions: BTreeMap::default(),
+            health: HealthConfig::default(),
+            jitter: JitterConfig::default(),
             logging: LoggingConfig::default(),
+            metrics: MetricsConfig::default(),
+            migrations: MigrationsConfig::default(),
+            node_name_override: None,
+            reconnect: ReconnectConfig::default(),
             sentry: None,
             service: None,
+            shards: ShardsConfig::default(),
+            spans: SpansConfig::default(),
+            tls: TlsPolicy::default(),
             tracing: TracerConfig::default(),
             update_checker: false,
+            warmup: WarmupConfig::default(),
         }
     }
 }
 
+/// Longest duration, in seconds, accepted by time based options (about 100 years).
+///
+/// Longer durations overflow once converted for timestamp arithmetic.
+pub const MAX_DURATION_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Reject durations longer than `MAX_DURATION_SECS`.
+fn check_duration(seconds: Option<u64>, option: &'static str) -> Result<()> {
+    match seconds {
+        Some(seconds) if seconds > MAX_DURATION_SECS => Err(ErrorKind::ConfigOption(option).into()),
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use failure::Fail;
+
     use super::APIConfig;
     use super::Agent;
+    use super::SentryConfig;
+    use super::MAX_DURATION_SECS;
+
+    #[test]
+    fn checksum_detects_changes() {
+        let config = Agent::mock();
+        let same = config.clone();
+        assert_eq!(config.checksum(), same.checksum());
+
+        let mut changed = config.clone();
+        changed.api.compression = !config.api.compression;
+        assert_ne!(config.checksum(), changed.checksum());
+    }
+
+    #[test]
+    fn checksum_ignores_secrets_and_volatile_fields() {
+        let mut config = Agent::mock();
+        config.sentry = Some(SentryConfig {
+            capture_api_errors: Default::default(),
+            dsn: "https://first-key@sentry.example.com/42".into(),
+        });
+        let mut other = config.clone();
+        other.sentry.as_mut().unwrap().dsn = "https://second-key@sentry.example.com/42".into();
+        other.jitter.instance_id = Some("another-instance".into());
+        assert_eq!(config.checksum(), other.checksum());
+    }
+
+    #[test]
+    fn invalid_api_versions_rejected() {
+        let mut config = Agent::mock();
+        config.api.versions = vec!["v1\nX-Injected: yes".into()];
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name().unwrap(), "ConfigOption");
+        assert!(Agent::mock().validate().is_ok());
+    }
+
+    #[test]
+    fn zero_max_concurrent_ops_rejected() {
+        let mut config = Agent::mock();
+        config.datastore.max_concurrent_ops = Some(0);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name().unwrap(), "ConfigOption");
+        assert_eq!(
+            error.to_string(),
+            "invalid configuration for option datastore.max_concurrent_ops",
+        );
+        config.datastore.max_concurrent_ops = Some(1);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn oversized_durations_rejected() {
+        let mut config = Agent::mock();
+        config.actions.heartbeat_timeout = Some(u64::MAX);
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name().unwrap(), "ConfigOption");
+
+        let mut config = Agent::mock();
+        config.defer_primary_ops = Some(MAX_DURATION_SECS + 1);
+        assert!(config.validate().is_err());
+        config.defer_primary_ops = Some(MAX_DURATION_SECS);
+        assert!(config.validate().is_ok());
+    }
 
     #[test]
     fn override_defauts() {
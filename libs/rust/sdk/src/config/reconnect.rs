@@ -0,0 +1,35 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Datastore reconnect backoff configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ReconnectConfig {
+    /// Delay, in seconds, before reconnecting after the first failure.
+    #[serde(default = "ReconnectConfig::default_initial_interval")]
+    pub initial_interval: u64,
+
+    /// Maximum delay, in seconds, between reconnect attempts.
+    #[serde(default = "ReconnectConfig::default_max_interval")]
+    pub max_interval: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_interval: Self::default_initial_interval(),
+            max_interval: Self::default_max_interval(),
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Default value for `initial_interval` used by serde.
+    fn default_initial_interval() -> u64 {
+        1
+    }
+
+    /// Default value for `max_interval` used by serde.
+    fn default_max_interval() -> u64 {
+        60
+    }
+}
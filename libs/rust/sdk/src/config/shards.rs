@@ -0,0 +1,13 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Sanity checks applied to shard information reported by the datastore.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ShardsConfig {
+    /// Maximum replication lag, in seconds, considered plausible (optional).
+    ///
+    /// Lag computed from timestamps above this value is assumed to be caused by clock skew
+    /// and is reported as unknown instead.
+    #[serde(default)]
+    pub max_reasonable_lag: Option<u64>,
+}
@@ -0,0 +1,27 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Limits applied to values the agent attaches to tracing spans.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct SpansConfig {
+    /// Maximum length, in bytes, of tag values attached to spans.
+    ///
+    /// Longer values are truncated and end with an ellipsis marker.
+    #[serde(default = "SpansConfig::default_max_value_length")]
+    pub max_value_length: usize,
+}
+
+impl Default for SpansConfig {
+    fn default() -> Self {
+        SpansConfig {
+            max_value_length: Self::default_max_value_length(),
+        }
+    }
+}
+
+impl SpansConfig {
+    /// Default value for `max_value_length` used by serde.
+    fn default_max_value_length() -> usize {
+        1024
+    }
+}
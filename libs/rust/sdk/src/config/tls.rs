@@ -0,0 +1,28 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// TLS requirements applied to the API server and datastore connections.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct TlsPolicy {
+    /// Minimum TLS protocol version to negotiate.
+    #[serde(default)]
+    pub min_version: TlsVersion,
+}
+
+/// TLS protocol versions that can be required.
+///
+/// Versions older than TLS 1.2 are insecure and never allowed.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls1_2,
+
+    #[serde(rename = "1.3")]
+    Tls1_3,
+}
+
+impl Default for TlsVersion {
+    fn default() -> TlsVersion {
+        TlsVersion::Tls1_2
+    }
+}
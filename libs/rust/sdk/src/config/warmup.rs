@@ -0,0 +1,40 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// Startup warmup configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct WarmupConfig {
+    /// Warm up datastore connections before the agent reports ready.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Number of times a failed warmup is retried before it is abandoned.
+    #[serde(default = "WarmupConfig::default_retries")]
+    pub retries: u32,
+
+    /// Delay, in seconds, between warmup attempts.
+    #[serde(default = "WarmupConfig::default_retry_interval")]
+    pub retry_interval: u64,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            enabled: false,
+            retries: Self::default_retries(),
+            retry_interval: Self::default_retry_interval(),
+        }
+    }
+}
+
+impl WarmupConfig {
+    /// Default value for `retries` used by serde.
+    fn default_retries() -> u32 {
+        5
+    }
+
+    /// Default value for `retry_interval` used by serde.
+    fn default_retry_interval() -> u64 {
+        5
+    }
+}
@@ -1,21 +1,31 @@
 use std::fmt;
 use std::sync::Arc;
 
+use opentracingrust::Span;
 use opentracingrust::Tracer;
 use prometheus::Registry;
 #[cfg(any(test, feature = "with_test_support"))]
 use slog::o;
+use slog::warn;
 #[cfg(any(test, feature = "with_test_support"))]
 use slog::Discard;
 use slog::Logger;
 
+use replicante_models_agent::info::ShardRole;
 use replicante_util_actixweb::AppConfig;
 use replicante_util_tracing::MaybeTracer;
 
 use crate::api::APIContext;
+use crate::api::ResponseCaches;
 use crate::config::Agent as AgentConfig;
+use crate::health::HealthHistory;
+use crate::metrics::SdkMetrics;
 use crate::store::backend_factory;
 use crate::store::Store;
+use crate::tasks::BackgroundTasks;
+use crate::Agent;
+use crate::ErrorKind;
+use crate::Readiness;
 use crate::Result;
 
 /// Agent services injection.
@@ -27,7 +37,15 @@ use crate::Result;
 #[derive(Clone)]
 pub struct AgentContext {
     pub api_conf: AppConfig<APIContext>,
+
+    /// Cached agent API responses, cleared by actions that make them outdated.
+    pub caches: Arc<ResponseCaches>,
+
     pub config: AgentConfig,
+
+    /// Recent datastore health check results.
+    pub health_history: HealthHistory,
+
     pub logger: Logger,
 
     /// Access the agent's metrics [`Registry`].
@@ -37,9 +55,18 @@ pub struct AgentContext {
     /// [`Registry`]: https://docs.rs/prometheus/0.3.13/prometheus/struct.Registry.html
     pub metrics: Registry,
 
+    /// Metrics collected by the SDK for this agent, registered with `metrics`.
+    pub sdk_metrics: Arc<SdkMetrics>,
+
+    /// Track the agent completing its startup warmup.
+    pub readiness: Readiness,
+
     /// Access the agent's persistent store.
     pub store: Store,
 
+    /// Background tasks run by the agent and the outcome of their last run.
+    pub tasks: BackgroundTasks,
+
     /// Access the agent's [`Tracer`].
     ///
     /// This is the agent's way to access the opentracing compatible tracer.
@@ -51,30 +78,101 @@ pub struct AgentContext {
 impl fmt::Debug for AgentContext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AgentContext")
+            .field("caches", &"<ResponseCaches>")
             .field("config", &self.config)
+            .field("health_history", &self.health_history)
             .field("logger", &self.logger)
             .field("metrics", &"<Registry>")
+            .field("readiness", &self.readiness)
+            .field("sdk_metrics", &"<SdkMetrics>")
             .field("store", &"<Store>")
+            .field("tasks", &self.tasks)
             .field("tracer", &"<Tracer>")
             .finish()
     }
 }
 
 impl AgentContext {
+    /// Attach a tag to the span, truncating values longer than `spans.max_value_length`.
+    ///
+    /// Use this for tags that may carry large values, such as statements or payloads.
+    pub fn tag_span<V>(&self, span: &mut Span, key: &str, value: V)
+    where
+        V: Into<String>,
+    {
+        crate::spans::tag_span(span, key, value, self.config.spans.max_value_length);
+    }
+
+    /// Discard replication lag (in seconds) above `shards.max_reasonable_lag`.
+    ///
+    /// Such lag is most likely caused by clock skew so it is logged and the span tagged
+    /// with `lag.suspect` instead of reporting a misleading value.
+    pub fn reasonable_lag(&self, lag: i64, span: &mut Span) -> Option<i64> {
+        let max = match self.config.shards.max_reasonable_lag {
+            None => return Some(lag),
+            Some(max) => max,
+        };
+        if lag <= max as i64 {
+            return Some(lag);
+        }
+        warn!(
+            self.logger,
+            "Ignoring replication lag above shards.max_reasonable_lag";
+            "lag" => lag,
+            "max_reasonable_lag" => max,
+        );
+        span.tag("lag.suspect", lag.to_string());
+        None
+    }
+
+    /// Ensure the node holds one of the roles in `active_roles`, if set.
+    ///
+    /// Node roles are the roles of the shards reported by the agent.
+    pub fn ensure_active_role(&self, agent: &dyn Agent, span: &mut Span) -> Result<()> {
+        let active = match self.config.active_roles.as_ref() {
+            None => return Ok(()),
+            Some(active) => active,
+        };
+        let shards = agent.shards(span)?;
+        let roles: Vec<&str> = shards
+            .shards
+            .iter()
+            .map(|shard| role_name(&shard.role))
+            .collect();
+        if roles
+            .iter()
+            .any(|role| active.iter().any(|active| active == role))
+        {
+            return Ok(());
+        }
+        let error = ErrorKind::WrongRole(roles.join(", "), active.join(", "));
+        Err(error.into())
+    }
+
     pub fn new(config: AgentConfig, logger: Logger, tracer: Tracer) -> Result<AgentContext> {
         let metrics = Registry::new();
+        let sdk_metrics = Arc::new(SdkMetrics::new(&config.metrics));
         let tracer = Arc::new(tracer);
         let store = backend_factory(
             &config,
             logger.clone(),
             MaybeTracer::new(Arc::clone(&tracer)),
+            Arc::clone(&sdk_metrics),
         )?;
+        let readiness = Readiness::new(!config.warmup.enabled);
+        let health_history = HealthHistory::new(config.health.history_size);
+        let caches = Arc::new(ResponseCaches::new(&config));
         Ok(AgentContext {
             api_conf: AppConfig::default(),
+            caches,
             config,
+            health_history,
             logger,
             metrics,
+            readiness,
+            sdk_metrics,
             store,
+            tasks: BackgroundTasks::default(),
             tracer,
         })
     }
@@ -89,19 +187,37 @@ impl AgentContext {
         let mut upkeep = ::replicante_util_upkeep::Upkeep::new();
         let logger = Logger::root(Discard, o!());
         let metrics = Registry::new();
+        let sdk_metrics = Arc::new(SdkMetrics::new(&config.metrics));
         let store = Store::mock();
         let opts = ::replicante_util_tracing::Opts::new("test", logger.clone(), &mut upkeep);
         let tracer =
             ::replicante_util_tracing::tracer(::replicante_util_tracing::Config::Noop, opts)
                 .unwrap();
         let tracer = Arc::new(tracer);
+        let readiness = Readiness::new(!config.warmup.enabled);
+        let health_history = HealthHistory::new(config.health.history_size);
+        let caches = Arc::new(ResponseCaches::new(&config));
         AgentContext {
             api_conf: AppConfig::default(),
+            caches,
             config,
+            health_history,
             logger,
             metrics,
+            readiness,
+            sdk_metrics,
             store,
+            tasks: BackgroundTasks::default(),
             tracer,
         }
     }
 }
+
+/// Name of a shard role as used in `active_roles`.
+fn role_name(role: &ShardRole) -> &str {
+    match role {
+        ShardRole::Primary => "primary",
+        ShardRole::Secondary => "secondary",
+        ShardRole::Unknown(role) => role,
+    }
+}
@@ -1,15 +1,25 @@
 use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web::ResponseError;
 use failure::Backtrace;
 use failure::Context;
 use failure::Fail;
+use serde_json::Value as Json;
 use uuid::Uuid;
 
 use replicante_util_failure::SerializableFail;
 
+use crate::config::APIConfig;
+
+/// Process-wide `Retry-After`, in seconds, for errors without a better estimate.
+static DEFAULT_RETRY_AFTER: AtomicU64 = AtomicU64::new(1);
+
 /// Error information returned by functions in case of errors.
 #[derive(Debug)]
 pub struct Error(Context<ErrorKind>);
@@ -18,6 +28,21 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         self.0.get_context()
     }
+
+    /// Error response with the full cause chain and backtraces, for trusted clients.
+    pub(crate) fn verbose_response(&self) -> HttpResponse {
+        let info = SerializableFail::from(self);
+        self.response_builder().json(info)
+    }
+
+    /// Response builder with the error status code and a `Retry-After` header, if needed.
+    fn response_builder(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.kind().retry_after() {
+            builder.header(RETRY_AFTER, retry_after.to_string());
+        }
+        builder
+    }
 }
 
 impl Fail for Error {
@@ -52,9 +77,18 @@ impl ResponseError for Error {
     }
 
     fn error_response(&self) -> HttpResponse {
-        let info = SerializableFail::from(self);
-        let status = self.status_code();
-        HttpResponse::build(status).json(info)
+        // Only the error message is included by default to limit information disclosure.
+        // See `crate::api::errors` for verbose responses.
+        let info = serde_json::to_value(SerializableFail::from(self))
+            .expect("error information must serialise to JSON");
+        let info = match info {
+            Json::Object(mut info) => {
+                info.retain(|key, _| key == "error" || key == "variant");
+                Json::Object(info)
+            }
+            info => info,
+        };
+        self.response_builder().json(info)
     }
 }
 
@@ -83,9 +117,66 @@ pub enum ErrorKind {
     #[fail(display = "unable to encode action information")]
     ActionEncode,
 
+    #[fail(display = "action {} is not authorized: {}", _0, _1)]
+    ActionForbidden(String, String),
+
+    #[fail(
+        display = "singleton action '{}' can't run while action '{}' holds the cluster lock",
+        _0, _1
+    )]
+    ActionLocked(String, String),
+
     #[fail(display = "actions with kind {} are not available", _0)]
     ActionNotAvailable(String),
 
+    #[fail(
+        display = "action with id '{}' is not finished and can't be replayed",
+        _0
+    )]
+    ActionNotFinished(String),
+
+    #[fail(display = "action with id '{}' can't be imported: {}", _0, _1)]
+    ActionNotImportable(String, &'static str),
+
+    #[fail(
+        display = "action with id '{}' requires a primary node but the node was not promoted within {} seconds",
+        _0, _1
+    )]
+    ActionNotPrimary(String, u64),
+
+    #[fail(
+        display = "too many actions created by {}, retry in {} seconds",
+        _0, _1
+    )]
+    ActionRateLimited(String, u64),
+
+    #[fail(
+        display = "action with id '{}' was already invoked and is not safe to invoke again",
+        _0
+    )]
+    ActionReplayed(String),
+
+    #[fail(display = "action with id '{}' timed out after {} seconds", _0, _1)]
+    ActionTimedOut(String, u64),
+
+    #[fail(
+        display = "action timeout override of {} seconds exceeds the maximum of {} seconds",
+        _0, _1
+    )]
+    ActionTimeoutTooLong(u64, u64),
+
+    #[fail(
+        display = "executor unresponsive: action with id '{}' sent no heartbeat for {} seconds",
+        _0, _1
+    )]
+    ActionUnresponsive(String, u64),
+
+    #[fail(display = "limit of {} stored actions reached", _0)]
+    ActionsLimitReached(u32),
+
+    #[fail(display = "cached {} expired and could not be refreshed", _0)]
+    CacheExpired(&'static str),
+
     #[fail(display = "invalid configuration: {}", _0)]
     ConfigClash(&'static str),
 
@@ -98,6 +189,30 @@ pub enum ErrorKind {
     #[fail(display = "connection error to {} with address '{}'", _0, _1)]
     Connection(&'static str, String),
 
+    #[fail(display = "datastore rejected the agent credentials: {}", _0)]
+    DatastoreAuth(String),
+
+    #[fail(
+        display = "datastore connection failed, reconnecting in {} seconds",
+        _0
+    )]
+    DatastoreBackoff(u64),
+
+    #[fail(
+        display = "datastore busy: gave up after waiting {} milliseconds for other operations",
+        _0
+    )]
+    DatastoreBusy(u64),
+
+    #[fail(display = "TLS error while connecting to the datastore: {}", _0)]
+    DatastoreTls(String),
+
+    #[fail(display = "datastore is unreachable: {}", _0)]
+    DatastoreUnreachable(String),
+
+    #[fail(display = "endpoint {} is handling too many requests, retry later", _0)]
+    EndpointSaturated(String),
+
     #[fail(display = "unable to check external action {} with ID {}", _0, _1)]
     ExternalActionCheck(String, Uuid),
 
@@ -123,6 +238,12 @@ pub enum ErrorKind {
     #[fail(display = "{}", _0)]
     FreeForm(String),
 
+    #[fail(
+        display = "replicante core version {} is not supported (supported versions: {})",
+        _0, _1
+    )]
+    IncompatibleCoreVersion(String, String),
+
     #[fail(display = "agent initialisation error: {}", _0)]
     Initialisation(String),
 
@@ -167,14 +288,54 @@ pub enum ErrorKind {
 
     #[fail(display = "unable to spawn '{}' thread", _0)]
     ThreadSpawn(&'static str),
+
+    #[fail(
+        display = "wrong role: the node is {} but only {} nodes are active",
+        _0, _1
+    )]
+    WrongRole(String, String),
 }
 
 impl ErrorKind {
+    /// Time, in seconds, clients should wait before retrying requests that failed with this error.
+    ///
+    /// Errors caused by a limiter or cooldown report the time left before requests are
+    /// accepted again, other 429 and 503 errors fall back to the `api.retry_after` option.
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            ErrorKind::ActionRateLimited(_, wait) => Some(*wait),
+            ErrorKind::DatastoreBackoff(remaining) => Some(*remaining),
+            _ => match self.http_status() {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                    Some(DEFAULT_RETRY_AFTER.load(Ordering::Relaxed))
+                }
+                _ => None,
+            },
+        }
+    }
+
     fn http_status(&self) -> StatusCode {
         match self {
             ErrorKind::ActionAlreadyExists(_) => StatusCode::CONFLICT,
+            ErrorKind::ActionDecode => StatusCode::BAD_REQUEST,
             ErrorKind::ActionEncode => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionForbidden(_, _) => StatusCode::FORBIDDEN,
+            ErrorKind::ActionLocked(_, _) => StatusCode::CONFLICT,
             ErrorKind::ActionNotAvailable(_) => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionNotFinished(_) => StatusCode::CONFLICT,
+            ErrorKind::ActionNotImportable(_, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionRateLimited(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::ActionTimeoutTooLong(_, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionsLimitReached(_) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::CacheExpired(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::DatastoreAuth(_) => StatusCode::BAD_GATEWAY,
+            ErrorKind::DatastoreBackoff(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::DatastoreBusy(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::DatastoreTls(_) => StatusCode::BAD_GATEWAY,
+            ErrorKind::DatastoreUnreachable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::EndpointSaturated(_) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::IncompatibleCoreVersion(_, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::WrongRole(_, _) => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -184,17 +345,36 @@ impl ErrorKind {
             ErrorKind::ActionAlreadyExists(_) => "ActionAlreadyExists",
             ErrorKind::ActionDecode => "ActionDecode",
             ErrorKind::ActionEncode => "ActionEncode",
+            ErrorKind::ActionForbidden(_, _) => "ActionForbidden",
+            ErrorKind::ActionLocked(_, _) => "ActionLocked",
             ErrorKind::ActionNotAvailable(_) => "ActionNotAvailable",
+            ErrorKind::ActionNotFinished(_) => "ActionNotFinished",
+            ErrorKind::ActionNotImportable(_, _) => "ActionNotImportable",
+            ErrorKind::ActionNotPrimary(_, _) => "ActionNotPrimary",
+            ErrorKind::ActionRateLimited(_, _) => "ActionRateLimited",
+            ErrorKind::ActionReplayed(_) => "ActionReplayed",
+            ErrorKind::ActionTimedOut(_, _) => "ActionTimedOut",
+            ErrorKind::ActionTimeoutTooLong(_, _) => "ActionTimeoutTooLong",
+            ErrorKind::ActionUnresponsive(_, _) => "ActionUnresponsive",
+            ErrorKind::ActionsLimitReached(_) => "ActionsLimitReached",
+            ErrorKind::CacheExpired(_) => "CacheExpired",
             ErrorKind::ConfigClash(_) => "ConfigClash",
             ErrorKind::ConfigLoad => "ConfigLoad",
             ErrorKind::ConfigOption(_) => "ConfigOption",
             ErrorKind::Connection(_, _) => "Connection",
+            ErrorKind::DatastoreAuth(_) => "DatastoreAuth",
+            ErrorKind::DatastoreBackoff(_) => "DatastoreBackoff",
+            ErrorKind::DatastoreBusy(_) => "DatastoreBusy",
+            ErrorKind::DatastoreTls(_) => "DatastoreTls",
+            ErrorKind::DatastoreUnreachable(_) => "DatastoreUnreachable",
+            ErrorKind::EndpointSaturated(_) => "EndpointSaturated",
             ErrorKind::ExternalActionCheck(_, _) => "ExternalActionCheck",
             ErrorKind::ExternalActionCheckDecode(_) => "ExternalActionCheckDecode",
             ErrorKind::ExternalActionCheckResult(_, _, _) => "ExternalActionCheckResult",
             ErrorKind::ExternalActionExec(_, _, _) => "ExternalActionExec",
             ErrorKind::ExternalActionStart(_, _) => "ExternalActionStart",
             ErrorKind::FreeForm(_) => "FreeForm",
+            ErrorKind::IncompatibleCoreVersion(_, _) => "IncompatibleCoreVersion",
             ErrorKind::Initialisation(_) => "Initialisation",
             ErrorKind::InvalidStoreState(_) => "InvalidStoreState",
             ErrorKind::Io(_) => "Io",
@@ -209,10 +389,19 @@ impl ErrorKind {
             ErrorKind::ServiceOpFailed(_) => "ServiceOpFailed",
             ErrorKind::StoreOpFailed(_) => "StoreOpFailed",
             ErrorKind::ThreadSpawn(_) => "ThreadSpawn",
+            ErrorKind::WrongRole(_, _) => "WrongRole",
         };
         Some(name)
     }
 }
 
+/// Apply the configured `Retry-After` for errors without a better estimate.
+///
+/// Errors are converted into responses without access to the agent configuration,
+/// so the value is stored in a static read by `ErrorKind::retry_after`.
+pub(crate) fn set_default_retry_after(config: &APIConfig) {
+    DEFAULT_RETRY_AFTER.store(config.retry_after, Ordering::Relaxed);
+}
+
 /// Short form alias for functions returning `Error`s.
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde_derive::Serialize;
+use slog::info;
+use slog::Logger;
+
+use crate::AgentContext;
+
+/// Result of a datastore health check, as kept in the health history.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct HealthRecord {
+    /// The health of the node changed since the previous check.
+    pub changed: bool,
+    pub healthy: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Bounded, in-memory history of the most recent health check results.
+///
+/// Only the last `capacity` results are kept, oldest first, so health flapping can be spotted.
+#[derive(Clone, Debug)]
+pub struct HealthHistory {
+    capacity: usize,
+    records: Arc<Mutex<VecDeque<HealthRecord>>>,
+}
+
+impl HealthHistory {
+    pub fn new(capacity: usize) -> HealthHistory {
+        HealthHistory {
+            capacity,
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Record the result of a health check, dropping the oldest result if the history is full.
+    pub fn record(&self, healthy: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().expect("HealthHistory lock poisoned");
+        let changed = records
+            .back()
+            .map(|last| last.healthy != healthy)
+            .unwrap_or(false);
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(HealthRecord {
+            changed,
+            healthy,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Result of the most recent health check, if any is retained.
+    pub fn healthy(&self) -> Option<bool> {
+        let records = self.records.lock().expect("HealthHistory lock poisoned");
+        records.back().map(|record| record.healthy)
+    }
+
+    /// Snapshot of the retained health check results, oldest first.
+    pub fn records(&self) -> Vec<HealthRecord> {
+        let records = self.records.lock().expect("HealthHistory lock poisoned");
+        records.iter().cloned().collect()
+    }
+}
+
+/// Pause background collectors while the datastore is known to be unhealthy.
+///
+/// The datastore is known to be unhealthy when the most recent health check in the
+/// `HealthHistory` failed. A single message is logged when collection pauses and resumes
+/// instead of logging every failed collection attempt during outages.
+pub struct HealthGate {
+    enabled: bool,
+    history: HealthHistory,
+    logger: Logger,
+    name: &'static str,
+    paused: AtomicBool,
+}
+
+impl HealthGate {
+    pub fn new(name: &'static str, context: &AgentContext) -> HealthGate {
+        HealthGate {
+            enabled: context.config.health.gate_collectors,
+            history: context.health_history.clone(),
+            logger: context.logger.clone(),
+            name,
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    /// Check if the collector should run, logging when collection pauses or resumes.
+    pub fn open(&self) -> bool {
+        let unhealthy = self.enabled && self.history.healthy() == Some(false);
+        let paused = self.paused.swap(unhealthy, Ordering::Relaxed);
+        if unhealthy && !paused {
+            info!(
+                self.logger,
+                "Datastore is unhealthy, pausing background collector";
+                "collector" => self.name,
+            );
+        }
+        if !unhealthy && paused {
+            info!(
+                self.logger,
+                "Datastore is healthy again, resuming background collector";
+                "collector" => self.name,
+            );
+        }
+        !unhealthy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HealthGate;
+    use super::HealthHistory;
+    use crate::config::Agent as AgentConfig;
+    use crate::AgentContext;
+
+    #[test]
+    fn gate_pauses_while_unhealthy() {
+        let context = AgentContext::mock();
+        let gate = HealthGate::new("test", &context);
+        assert!(gate.open());
+        context.health_history.record(false);
+        assert!(!gate.open());
+        assert!(!gate.open());
+        context.health_history.record(true);
+        assert!(gate.open());
+    }
+
+    #[test]
+    fn gate_disabled() {
+        let mut config = AgentConfig::mock();
+        config.health.gate_collectors = false;
+        let context = AgentContext::mock_with_config(config);
+        let gate = HealthGate::new("test", &context);
+        context.health_history.record(false);
+        assert!(gate.open());
+    }
+
+    #[test]
+    fn history_reflects_transitions() {
+        let history = HealthHistory::new(4);
+        for healthy in &[true, true, false, true, false, true] {
+            history.record(*healthy);
+        }
+        let records = history.records();
+        let healthy: Vec<bool> = records.iter().map(|record| record.healthy).collect();
+        let changed: Vec<bool> = records.iter().map(|record| record.changed).collect();
+        assert_eq!(healthy, vec![false, true, false, true]);
+        assert_eq!(changed, vec![true, true, true, true]);
+    }
+
+    #[test]
+    fn history_disabled() {
+        let history = HealthHistory::new(0);
+        history.record(true);
+        assert!(history.records().is_empty());
+    }
+}
@@ -8,12 +8,21 @@ pub use replicante_util_tracing::fail_span;
 
 pub mod actions;
 mod api;
+mod backoff;
+mod budget;
+mod classify;
 mod context;
 mod error;
+mod health;
+mod limited;
 mod metrics;
+mod redact;
+mod spans;
 mod store;
+mod tasks;
 mod traits;
 mod versioned;
+mod warmup;
 
 pub mod config;
 pub mod process;
@@ -21,13 +30,29 @@ pub mod process;
 #[cfg(any(test, feature = "with_test_support"))]
 pub mod testing;
 
+pub use self::backoff::Backoff;
+pub use self::budget::RequestBudget;
 pub use self::context::AgentContext;
 pub use self::error::Error;
 pub use self::error::ErrorKind;
 pub use self::error::Result;
+pub use self::health::HealthGate;
+pub use self::health::HealthHistory;
+pub use self::health::HealthRecord;
+pub use self::metrics::register_collector;
 pub use self::metrics::register_metrics;
+pub use self::metrics::LabelGuard;
+pub use self::metrics::MetricOpts;
+pub use self::metrics::OVERFLOW_LABEL;
+pub use self::metrics::STANDARD_LABELS;
+pub use self::redact::redact_credentials;
 pub use self::store::Transaction;
+pub use self::tasks::BackgroundTasks;
+pub use self::tasks::TaskOutcome;
+pub use self::tasks::TaskStatus;
 pub use self::traits::Agent;
+pub use self::traits::DatastoreExtras;
 pub use self::versioned::ActiveAgent;
 pub use self::versioned::AgentFactory;
 pub use self::versioned::VersionedAgent;
+pub use self::warmup::Readiness;
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use opentracingrust::Span;
+use prometheus::Gauge;
+
+use replicante_models_agent::info::AgentInfo;
+use replicante_models_agent::info::DatastoreInfo;
+use replicante_models_agent::info::Shards;
+
+use crate::actions::Action;
+use crate::actions::ActionAuthorizer;
+use crate::actions::ActionHook;
+use crate::Agent;
+use crate::AgentContext;
+use crate::DatastoreExtras;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Cap the number of datastore operations running at the same time.
+///
+/// Operations beyond the limit queue for up to `wait` before failing with
+/// a `DatastoreBusy` error, which clients can retry.
+pub struct OpsLimiter {
+    in_flight: Gauge,
+    limit: usize,
+    running: Mutex<usize>,
+    released: Condvar,
+    wait: Duration,
+}
+
+impl OpsLimiter {
+    /// Create a limiter reporting running operations with the `in_flight` gauge.
+    pub fn new(limit: usize, wait: Duration, in_flight: Gauge) -> OpsLimiter {
+        OpsLimiter {
+            in_flight,
+            limit,
+            running: Mutex::new(0),
+            released: Condvar::new(),
+            wait,
+        }
+    }
+
+    /// Wait for the number of running operations to drop below the limit.
+    ///
+    /// The returned permit counts as a running operation until dropped.
+    pub fn acquire(&self) -> Result<OpsPermit> {
+        let deadline = Instant::now() + self.wait;
+        let mut running = self.running.lock().expect("OpsLimiter lock poisoned");
+        while *running >= self.limit {
+            let now = Instant::now();
+            if now >= deadline {
+                let waited = self.wait.as_millis() as u64;
+                return Err(ErrorKind::DatastoreBusy(waited).into());
+            }
+            running = self
+                .released
+                .wait_timeout(running, deadline - now)
+                .expect("OpsLimiter lock poisoned")
+                .0;
+        }
+        *running += 1;
+        self.in_flight.inc();
+        Ok(OpsPermit { limiter: self })
+    }
+}
+
+/// A running datastore operation, released when dropped.
+pub struct OpsPermit<'a> {
+    limiter: &'a OpsLimiter,
+}
+
+impl<'a> Drop for OpsPermit<'a> {
+    fn drop(&mut self) {
+        let mut running = self
+            .limiter
+            .running
+            .lock()
+            .expect("OpsLimiter lock poisoned");
+        *running -= 1;
+        self.limiter.in_flight.dec();
+        self.limiter.released.notify_one();
+    }
+}
+
+/// Wrap an agent so its datastore operations are subject to an `OpsLimiter`.
+///
+/// Agents are wrapped by the process runner when `datastore.max_concurrent_ops` is set.
+pub struct LimitedAgent {
+    agent: Arc<dyn Agent>,
+    limiter: OpsLimiter,
+}
+
+impl LimitedAgent {
+    /// Apply the configured limit, if any, to the given agent.
+    pub fn wrap(agent: Arc<dyn Agent>, context: &AgentContext) -> Arc<dyn Agent> {
+        let config = &context.config.datastore;
+        match config.max_concurrent_ops {
+            None => agent,
+            Some(limit) => {
+                let wait = Duration::from_millis(config.max_concurrent_ops_wait);
+                let in_flight = context.sdk_metrics.datastore_ops_in_flight.clone();
+                let limiter = OpsLimiter::new(limit, wait, in_flight);
+                Arc::new(LimitedAgent { agent, limiter })
+            }
+        }
+    }
+}
+
+impl Agent for LimitedAgent {
+    fn agent_info(&self, span: &mut Span) -> Result<AgentInfo> {
+        self.agent.agent_info(span)
+    }
+
+    fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
+        let _permit = self.limiter.acquire()?;
+        self.agent.datastore_info(span)
+    }
+
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        let _permit = self.limiter.acquire()?;
+        self.agent.datastore_extras(span)
+    }
+
+    fn health(&self, span: &mut Span) -> Result<()> {
+        let _permit = self.limiter.acquire()?;
+        self.agent.health(span)
+    }
+
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        let _permit = self.limiter.acquire()?;
+        self.agent.warmup(span)
+    }
+
+    fn shards(&self, span: &mut Span) -> Result<Shards> {
+        let _permit = self.limiter.acquire()?;
+        self.agent.shards(span)
+    }
+
+    fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
+        self.agent.action_hooks()
+    }
+
+    fn action_authorizer(&self) -> Arc<dyn ActionAuthorizer> {
+        self.agent.action_authorizer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use prometheus::Gauge;
+
+    use super::OpsLimiter;
+    use crate::ErrorKind;
+
+    #[test]
+    fn operations_beyond_limit_queue() {
+        let in_flight = Gauge::new("test_in_flight", "test").unwrap();
+        let limiter = OpsLimiter::new(1, Duration::from_secs(10), in_flight);
+        let limiter = Arc::new(limiter);
+        let permit = limiter.acquire().unwrap();
+        let (sender, receiver) = channel();
+        let queued = Arc::clone(&limiter);
+        let waiter = thread::spawn(move || {
+            let _permit = queued.acquire().unwrap();
+            sender.send(()).unwrap();
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(permit);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn queued_operations_time_out() {
+        let in_flight = Gauge::new("test_in_flight", "test").unwrap();
+        let limiter = OpsLimiter::new(1, Duration::from_millis(10), in_flight);
+        let _permit = limiter.acquire().unwrap();
+        let error = match limiter.acquire() {
+            Err(error) => error,
+            Ok(_) => panic!("operation should not run past the limit"),
+        };
+        match error.kind() {
+            ErrorKind::DatastoreBusy(10) => (),
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -1,4 +1,13 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
 use lazy_static::lazy_static;
+use prometheus::core::Collector;
+use prometheus::core::Desc;
+use prometheus::proto::MetricFamily;
 use prometheus::Counter;
 use prometheus::CounterVec;
 use prometheus::Gauge;
@@ -6,102 +15,530 @@ use prometheus::Histogram;
 use prometheus::HistogramOpts;
 use prometheus::HistogramVec;
 use prometheus::Opts;
+use prometheus::Registry;
 use slog::debug;
+use slog::warn;
+use slog::Logger;
 
-use replicante_util_actixweb::MetricsCollector;
+use replicante_util_failure::failure_info;
 
+use crate::config::MetricsConfig;
+use crate::Agent;
 use crate::AgentContext;
 
+/// Label value reported in place of values beyond a `LabelGuard` limit.
+pub const OVERFLOW_LABEL: &str = "other";
+
+/// Labels attached to all metrics built with `MetricOpts`.
+pub const STANDARD_LABELS: [&str; 2] = ["cluster", "node"];
+
+/// Value of standard labels that are not configured.
+pub const UNKNOWN_LABEL: &str = "unknown";
+
+/// Process-wide limit for `LabelGuard`s created without an explicit limit.
+static MAX_LABEL_VALUES: AtomicUsize = AtomicUsize::new(100);
+
 lazy_static! {
-    pub static ref ACTION_COUNT: CounterVec = CounterVec::new(
-        Opts::new("repliagent_action_total", "Number of actions invoked"),
-        &["action"],
-    )
-    .expect("Failed to create ACTION_COUNT histogram");
-    pub static ref ACTION_DURATION: HistogramVec = HistogramVec::new(
-        HistogramOpts::new(
-            "repliagent_action_duration",
-            "Duration (in seconds) of an action invokation"
+    /// Process-wide values of the `STANDARD_LABELS`.
+    static ref STANDARD_LABEL_VALUES: Mutex<HashMap<String, String>> = Mutex::new(
+        STANDARD_LABELS
+            .iter()
+            .map(|label| (label.to_string(), UNKNOWN_LABEL.to_string()))
+            .collect()
+    );
+}
+
+/// Metrics collected by the SDK itself.
+///
+/// Every `AgentContext` creates its own collectors and registers them with its own
+/// registry so agents running in the same process do not share metric values.
+pub struct SdkMetrics {
+    pub(crate) action_count: CounterVec,
+    pub(crate) action_duration: HistogramVec,
+    pub(crate) action_errors: CounterVec,
+    /// Guard the action kinds used as labels as clients can request any kind.
+    pub(crate) action_kind_labels: LabelGuard,
+    pub(crate) action_prune_duration: Histogram,
+    pub(crate) datastore_ops_in_flight: Gauge,
+    pub(crate) datastore_reconnect_backoff: Gauge,
+    pub(crate) http_requests_count: CounterVec,
+    pub(crate) http_requests_duration: HistogramVec,
+    pub(crate) sqlite_connection_errors: Counter,
+    pub(crate) sqlite_op_errors_count: CounterVec,
+    pub(crate) sqlite_ops_count: CounterVec,
+    pub(crate) sqlite_ops_duration: HistogramVec,
+    pub(crate) update_available: Gauge,
+}
+
+impl SdkMetrics {
+    pub fn new(config: &MetricsConfig) -> SdkMetrics {
+        let action_count = CounterVec::new(
+            Opts::new("repliagent_action_total", "Number of actions invoked"),
+            &["action"],
+        )
+        .expect("Failed to create ACTION_COUNT histogram");
+        let action_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repliagent_action_duration",
+                "Duration (in seconds) of an action invokation",
+            ),
+            &["action"],
+        )
+        .expect("Failed to create ACTION_DURATION histogram");
+        let action_errors = CounterVec::new(
+            Opts::new(
+                "repliagent_action_errors",
+                "Number of actions that errored while being invoked",
+            ),
+            &["action"],
+        )
+        .expect("Failed to create ACTION_ERRORS histogram");
+        let action_prune_duration = Histogram::with_opts(HistogramOpts::new(
+            "repliagent_action_prune_duration",
+            "Duration (in seconds) of actions DB pruning",
+        ))
+        .expect("Failed to create ACTION_DURATION histogram");
+        let datastore_ops_in_flight = Gauge::new(
+            "repliagent_datastore_ops_in_flight",
+            "Number of datastore operations currently running",
+        )
+        .expect("Failed to create DATASTORE_OPS_IN_FLIGHT gauge");
+        let datastore_reconnect_backoff = Gauge::new(
+            "repliagent_datastore_reconnect_backoff_seconds",
+            "Current delay (in seconds) between datastore reconnect attempts (0 when connected)",
+        )
+        .expect("Failed to create DATASTORE_RECONNECT_BACKOFF gauge");
+        let http_requests_count = CounterVec::new(
+            Opts::new(
+                "repliagent_http_requests_total",
+                "Number of HTTP requests handled by the API server",
+            ),
+            &["endpoint", "status"],
+        )
+        .expect("Failed to create HTTP_REQUESTS_COUNT counter");
+        let http_requests_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repliagent_http_request_duration_seconds",
+                "Duration (in seconds) of HTTP requests handled by the API server",
+            ),
+            &["endpoint"],
+        )
+        .expect("Failed to create HTTP_REQUESTS_DURATION histogram");
+        let sqlite_connection_errors = Counter::new(
+            "repliagent_sqlite_connection_errors",
+            "Number of SQLite connection errors",
+        )
+        .expect("Failed to create SQLITE_CONNECTION_ERRORS counter");
+        let sqlite_op_errors_count = CounterVec::new(
+            Opts::new(
+                "repliagent_sqlite_operation_errors",
+                "Number of SQLite operations failed",
+            ),
+            &["operation"],
+        )
+        .expect("Failed to create SQLITE_OP_ERRORS_COUNT counter");
+        let sqlite_ops_count = CounterVec::new(
+            Opts::new(
+                "repliagent_sqlite_operations",
+                "Number of SQLite operations issued",
+            ),
+            &["operation"],
+        )
+        .expect("Failed to create SQLITE_OPS_COUNT counter");
+        let sqlite_ops_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repliagent_sqlite_operations_duration",
+                "Duration (in seconds) of SQLite operations",
+            ),
+            &["operation"],
+        )
+        .expect("Failed to create SQLITE_OPS_DURATION histogram");
+        let update_available = Gauge::new(
+            "repliagent_updateable",
+            "Set to 1 when an updateded version is available (checked at start only)",
+        )
+        .expect("Failed to create UPDATE_AVAILABLE gauge");
+        SdkMetrics {
+            action_count,
+            action_duration,
+            action_errors,
+            action_kind_labels: LabelGuard::with_limit(config.max_label_values),
+            action_prune_duration,
+            datastore_ops_in_flight,
+            datastore_reconnect_backoff,
+            http_requests_count,
+            http_requests_duration,
+            sqlite_connection_errors,
+            sqlite_op_errors_count,
+            sqlite_ops_count,
+            sqlite_ops_duration,
+            update_available,
+        }
+    }
+}
+
+/// Build options for agent-specific metrics following the agent conventions.
+///
+/// Metric names must start with `repliagent_` and help text must be a non-empty
+/// description without a trailing full stop (it is stripped if present).
+/// Metrics are labelled with the `STANDARD_LABELS`, so dashboards can group metrics
+/// from all agents the same way.
+///
+/// Label values are detected by the agent process runner once the agent is initialised
+/// and are reported when metrics are collected, so metrics built with these options
+/// must be registered with `register_collector`.
+pub struct MetricOpts {
+    help: String,
+    name: String,
+}
+
+impl MetricOpts {
+    /// Panics if the name or help text do not follow the conventions.
+    pub fn new<N, H>(name: N, help: H) -> MetricOpts
+    where
+        N: Into<String>,
+        H: Into<String>,
+    {
+        let name = name.into();
+        let help = help.into();
+        let help = help.trim().trim_end_matches('.').to_string();
+        assert!(
+            name.starts_with("repliagent_"),
+            "metric {} must be prefixed with repliagent_",
+            name
+        );
+        assert!(!help.is_empty(), "metric {} must have help text", name);
+        MetricOpts { help, name }
+    }
+
+    /// Options for histograms.
+    pub fn histogram_opts(&self) -> HistogramOpts {
+        HistogramOpts::new(self.name.clone(), self.help.clone()).const_labels(standard_labels())
+    }
+
+    /// Options for counters and gauges.
+    pub fn opts(&self) -> Opts {
+        Opts::new(self.name.clone(), self.help.clone()).const_labels(standard_labels())
+    }
+}
+
+/// Cap the number of distinct values a metric label can take.
+///
+/// Metrics labelled with unbounded values (shard IDs, collections, ...) can grow
+/// without limits and blow up Prometheus memory usage.
+/// Once the limit is reached, new values are reported as `OVERFLOW_LABEL` instead.
+pub struct LabelGuard {
+    limit: Option<usize>,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl LabelGuard {
+    /// Guard label values up to the `metrics.max_label_values` limit.
+    pub fn new() -> LabelGuard {
+        LabelGuard {
+            limit: None,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Guard label values up to the given limit.
+    pub fn with_limit(limit: usize) -> LabelGuard {
+        LabelGuard {
+            limit: Some(limit),
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Return the label value to report for the given value.
+    pub fn label<'a>(&self, value: &'a str) -> &'a str {
+        let limit = self
+            .limit
+            .unwrap_or_else(|| MAX_LABEL_VALUES.load(Ordering::Relaxed));
+        let mut seen = self.seen.lock().expect("LabelGuard lock poisoned");
+        if seen.contains(value) {
+            return value;
+        }
+        if seen.len() < limit {
+            seen.insert(value.to_string());
+            return value;
+        }
+        OVERFLOW_LABEL
+    }
+}
+
+impl Default for LabelGuard {
+    fn default() -> LabelGuard {
+        LabelGuard::new()
+    }
+}
+
+/// Attemps to register metrics with the context's Registry.
+///
+/// Metrics that fail to register are logged and ignored.
+/// Metrics already registered are reused so this can be called more than once.
+///
+/// Metrics are the context's own `SdkMetrics` so agents with independent contexts
+/// can coexist in the same process, each exposing its own values.
+pub fn register_metrics(context: &AgentContext) {
+    let logger = &context.logger;
+    let registry = &context.metrics;
+    let metrics = &context.sdk_metrics;
+    let collectors: Vec<(&str, Box<dyn Collector>)> = vec![
+        ("ACTION_COUNT", Box::new(metrics.action_count.clone())),
+        ("ACTION_DURATION", Box::new(metrics.action_duration.clone())),
+        ("ACTION_ERRORS", Box::new(metrics.action_errors.clone())),
+        (
+            "ACTION_PRUNE_DURATION",
+            Box::new(metrics.action_prune_duration.clone()),
+        ),
+        (
+            "DATASTORE_OPS_IN_FLIGHT",
+            Box::new(metrics.datastore_ops_in_flight.clone()),
+        ),
+        (
+            "DATASTORE_RECONNECT_BACKOFF",
+            Box::new(metrics.datastore_reconnect_backoff.clone()),
+        ),
+        (
+            "HTTP_REQUESTS_COUNT",
+            Box::new(metrics.http_requests_count.clone()),
         ),
-        &["action"],
-    )
-    .expect("Failed to create ACTION_DURATION histogram");
-    pub static ref ACTION_ERRORS: CounterVec = CounterVec::new(
-        Opts::new(
-            "repliagent_action_errors",
-            "Number of actions that errored while being invoked",
+        (
+            "HTTP_REQUESTS_DURATION",
+            Box::new(metrics.http_requests_duration.clone()),
         ),
-        &["action"],
-    )
-    .expect("Failed to create ACTION_ERRORS histogram");
-    pub static ref ACTION_PRUNE_DURATION: Histogram = Histogram::with_opts(HistogramOpts::new(
-        "repliagent_action_prune_duration",
-        "Duration (in seconds) of actions DB pruning"
-    ))
-    .expect("Failed to create ACTION_DURATION histogram");
-    pub static ref REQUESTS: MetricsCollector = MetricsCollector::new("repliagent");
-    pub static ref SQLITE_CONNECTION_ERRORS: Counter = Counter::new(
-        "repliagent_sqlite_connection_errors",
-        "Number of SQLite connection errors",
-    )
-    .expect("Failed to create UPDATE_AVAILABLE gauge");
-    pub static ref SQLITE_OP_ERRORS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
-            "repliagent_sqlite_operation_errors",
-            "Number of SQLite operations failed",
+        (
+            "SQLITE_CONNECTION_ERRORS",
+            Box::new(metrics.sqlite_connection_errors.clone()),
         ),
-        &["operation"],
-    )
-    .expect("Failed to create SQLITE_OP_ERRORS_COUNT counter");
-    pub static ref SQLITE_OPS_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
-            "repliagent_sqlite_operations",
-            "Number of SQLite operations issued",
+        (
+            "SQLITE_OP_ERRORS_COUNT",
+            Box::new(metrics.sqlite_op_errors_count.clone()),
         ),
-        &["operation"],
-    )
-    .expect("Failed to create SQLITE_OPS_COUNT counter");
-    pub static ref SQLITE_OPS_DURATION: HistogramVec = HistogramVec::new(
-        HistogramOpts::new(
-            "repliagent_sqlite_operations_duration",
-            "Duration (in seconds) of SQLite operations"
+        (
+            "SQLITE_OPS_COUNT",
+            Box::new(metrics.sqlite_ops_count.clone()),
         ),
-        &["operation"],
-    )
-    .expect("Failed to create SQLITE_OPS_DURATION histogram");
-    pub static ref UPDATE_AVAILABLE: Gauge = Gauge::new(
-        "repliagent_updateable",
-        "Set to 1 when an updateded version is available (checked at start only)",
-    )
-    .expect("Failed to create UPDATE_AVAILABLE gauge");
+        (
+            "SQLITE_OPS_DURATION",
+            Box::new(metrics.sqlite_ops_duration.clone()),
+        ),
+        (
+            "UPDATE_AVAILABLE",
+            Box::new(metrics.update_available.clone()),
+        ),
+    ];
+    for (name, collector) in collectors {
+        register_collector(logger, registry, name, collector);
+    }
 }
 
-/// Attemps to register metrics with the Registry.
+/// Register a collector, tolerating collectors that are already registered.
 ///
-/// Metrics that fail to register are logged and ignored.
-pub fn register_metrics(context: &AgentContext) {
-    let logger = &context.logger;
-    let registry = &context.metrics;
-    REQUESTS.register(logger, registry);
-    if let Err(error) = registry.register(Box::new(ACTION_COUNT.clone())) {
-        debug!(logger, "Failed to register ACTION_COUNT"; "error" => ?error);
+/// Hosts embedding agents and tests may set up metrics more than once against the same
+/// registry: collectors registered before are reused. Other failures are logged and ignored.
+pub fn register_collector(
+    logger: &Logger,
+    registry: &Registry,
+    name: &str,
+    collector: Box<dyn Collector>,
+) {
+    match registry.register(Box::new(StandardLabels(collector))) {
+        Ok(()) => (),
+        Err(prometheus::Error::AlreadyReg) => {
+            debug!(logger, "Metric already registered, reusing it"; "metric" => name);
+        }
+        Err(error) => {
+            warn!(logger, "Failed to register metric"; "metric" => name, "error" => ?error);
+        }
+    }
+}
+
+/// Apply the configured limit to `LabelGuard`s created without an explicit limit.
+///
+/// Guards created by agents are usually statics shared by the whole process, so
+/// this setting follows the configuration of the last agent to start.
+pub(crate) fn set_max_label_values(config: &MetricsConfig) {
+    MAX_LABEL_VALUES.store(config.max_label_values, Ordering::Relaxed);
+}
+
+/// Set the values of the `STANDARD_LABELS` to the cluster and node the agent manages.
+///
+/// The cluster and node name overrides take precedence over the names reported by
+/// the datastore. Labels stay `UNKNOWN_LABEL` if the datastore info is needed but
+/// can't be fetched, for example because the datastore is down when the agent starts.
+pub(crate) fn set_standard_labels(context: &AgentContext, agent: &dyn Agent) {
+    let config = &context.config;
+    let mut cluster = config.cluster_display_name_override.clone();
+    let mut node = config.node_name_override.clone();
+    if cluster.is_none() || node.is_none() {
+        let mut span = context.tracer.span("metrics.labels").auto_finish();
+        match agent.datastore_info(&mut span) {
+            Ok(info) => {
+                cluster = cluster
+                    .or(info.cluster_display_name)
+                    .or(Some(info.cluster_id));
+                node = node.or(Some(info.node_id));
+            }
+            Err(error) => {
+                warn!(
+                    context.logger,
+                    "Failed to fetch datastore info for metric labels";
+                    failure_info(&error),
+                );
+            }
+        }
+    }
+    let mut values = STANDARD_LABEL_VALUES
+        .lock()
+        .expect("standard labels lock poisoned");
+    let unknown = || UNKNOWN_LABEL.to_string();
+    values.insert("cluster".into(), cluster.unwrap_or_else(unknown));
+    values.insert("node".into(), node.unwrap_or_else(unknown));
+}
+
+/// Collector reporting the current `STANDARD_LABEL_VALUES` for the `STANDARD_LABELS`.
+///
+/// Metrics are created before the values are detected, so their constant labels
+/// are only placeholders.
+struct StandardLabels(Box<dyn Collector>);
+
+impl Collector for StandardLabels {
+    fn desc(&self) -> Vec<&Desc> {
+        self.0.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let values = standard_labels();
+        let mut families = self.0.collect();
+        for family in families.iter_mut() {
+            for metric in family.mut_metric().iter_mut() {
+                for pair in metric.mut_label().iter_mut() {
+                    if let Some(value) = values.get(pair.get_name()) {
+                        pair.set_value(value.clone());
+                    }
+                }
+            }
+        }
+        families
+    }
+}
+
+fn standard_labels() -> HashMap<String, String> {
+    STANDARD_LABEL_VALUES
+        .lock()
+        .expect("standard labels lock poisoned")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use prometheus::Counter;
+
+    use super::LabelGuard;
+    use super::MetricOpts;
+    use super::OVERFLOW_LABEL;
+    use super::STANDARD_LABELS;
+    use crate::config::Agent as AgentConfig;
+    use crate::testing::MockAgent;
+    use crate::AgentContext;
+
+    fn families(context: &AgentContext) -> Vec<String> {
+        context
+            .metrics
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect()
     }
-    if let Err(error) = registry.register(Box::new(ACTION_DURATION.clone())) {
-        debug!(logger, "Failed to register ACTION_DURATION"; "error" => ?error);
+
+    fn gauge(context: &AgentContext, name: &str) -> f64 {
+        context
+            .metrics
+            .gather()
+            .iter()
+            .find(|family| family.get_name() == name)
+            .map(|family| family.get_metric()[0].get_gauge().get_value())
+            .unwrap_or_else(|| panic!("metric {} not registered", name))
     }
-    if let Err(error) = registry.register(Box::new(ACTION_ERRORS.clone())) {
-        debug!(logger, "Failed to register ACTION_ERRORS"; "error" => ?error);
+
+    #[test]
+    fn contexts_have_independent_metrics() {
+        let first = AgentContext::mock();
+        let second = AgentContext::mock();
+        super::register_metrics(&first);
+        assert!(families(&second).is_empty());
+
+        super::register_metrics(&second);
+        let name = "repliagent_datastore_reconnect_backoff_seconds";
+        first.sdk_metrics.datastore_reconnect_backoff.set(4.0);
+        second.sdk_metrics.datastore_reconnect_backoff.set(8.0);
+        assert_eq!(gauge(&first, name), 4.0);
+        assert_eq!(gauge(&second, name), 8.0);
     }
-    if let Err(error) = registry.register(Box::new(SQLITE_OP_ERRORS_COUNT.clone())) {
-        debug!(logger, "Failed to register SQLITE_OP_ERRORS_COUNT"; "error" => ?error);
+
+    #[test]
+    fn metric_opts_apply_conventions() {
+        let opts = MetricOpts::new("repliagent_test_total", "Number of tests run. ");
+        let counter = Counter::with_opts(opts.opts()).unwrap();
+        let desc = &counter.desc()[0];
+        assert_eq!(desc.help, "Number of tests run");
+        let labels: Vec<&str> = desc
+            .const_label_pairs
+            .iter()
+            .map(|pair| pair.get_name())
+            .collect();
+        assert_eq!(labels, STANDARD_LABELS.to_vec());
     }
-    if let Err(error) = registry.register(Box::new(SQLITE_OPS_COUNT.clone())) {
-        debug!(logger, "Failed to register SQLITE_OPS_COUNT"; "error" => ?error);
+
+    #[test]
+    fn standard_labels_detected_from_datastore() {
+        let mut config = AgentConfig::mock();
+        config.node_name_override = Some("node-override".into());
+        let context = AgentContext::mock_with_config(config);
+        let opts = MetricOpts::new("repliagent_labels_test_total", "Labels test");
+        let counter = Counter::with_opts(opts.opts()).unwrap();
+        super::register_collector(
+            &context.logger,
+            &context.metrics,
+            "LABELS_TEST",
+            Box::new(counter),
+        );
+        super::set_standard_labels(&context, &MockAgent::new());
+        let families = context.metrics.gather();
+        let family = families
+            .iter()
+            .find(|family| family.get_name() == "repliagent_labels_test_total")
+            .expect("metric not registered");
+        let labels: Vec<(&str, &str)> = family.get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|pair| (pair.get_name(), pair.get_value()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![("cluster", "display"), ("node", "node-override")]
+        );
     }
-    if let Err(error) = registry.register(Box::new(SQLITE_OPS_DURATION.clone())) {
-        debug!(logger, "Failed to register SQLITE_OPS_DURATION"; "error" => ?error);
+
+    #[test]
+    fn register_metrics_twice() {
+        let context = AgentContext::mock();
+        super::register_metrics(&context);
+        let registered = families(&context);
+        super::register_metrics(&context);
+        assert_eq!(families(&context), registered);
     }
-    if let Err(error) = registry.register(Box::new(UPDATE_AVAILABLE.clone())) {
-        debug!(logger, "Failed to register UPDATE_AVAILABLE"; "error" => ?error);
+
+    #[test]
+    fn label_guard_overflows_to_other() {
+        let guard = LabelGuard::with_limit(10);
+        let values: Vec<String> = (0..50).map(|id| format!("shard-{}", id)).collect();
+        let labels: Vec<&str> = values.iter().map(|value| guard.label(value)).collect();
+        assert_eq!(&labels[..10], &values[..10]);
+        assert!(labels[10..].iter().all(|label| *label == OVERFLOW_LABEL));
+        // Values seen before the limit was reached are still reported.
+        assert_eq!(guard.label("shard-3"), "shard-3");
     }
 }
@@ -2,7 +2,9 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::env;
 use std::process::exit;
+use std::sync::Arc;
 
+use chrono::Utc;
 use clap::App;
 use clap::Arg;
 use failure::ResultExt;
@@ -13,6 +15,8 @@ use sentry::integrations::failure::capture_fail;
 use sentry::internals::ClientInitGuard;
 use sentry::internals::IntoDsn;
 use serde_derive::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
 use slog::debug;
 use slog::info;
 use slog::warn;
@@ -27,14 +31,21 @@ use replicante_util_upkeep::Upkeep;
 
 use crate::actions;
 use crate::api;
+use crate::classify::ClassifiedAgent;
 use crate::config::Agent as Config;
 use crate::config::SentryConfig;
-use crate::metrics::UPDATE_AVAILABLE;
+use crate::limited::LimitedAgent;
+use crate::metrics::register_collector;
+use crate::redact::redact_credentials;
+use crate::warmup;
 use crate::Agent;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
 
+/// Maximum number of expired lifecycle events pruned each time an event is recorded.
+const EVENTS_PRUNE_LIMIT: u32 = 1000;
+
 /// Configure a command line parser.
 ///
 /// The parser is configure with all the arguments every agent is required to implement.
@@ -75,6 +86,7 @@ where
     A: Agent + 'static,
     F: FnOnce(&AgentContext, &mut Upkeep) -> Result<A>,
 {
+    config.validate()?;
     let mut upkeep = Upkeep::new();
     upkeep.set_logger(logger.clone());
     upkeep
@@ -87,12 +99,25 @@ where
 
     let mut context = AgentContext::new(config, logger.clone(), tracer)?;
     register_process_metrics(&context);
+    crate::metrics::set_max_label_values(&context.config.metrics);
+    crate::error::set_default_retry_after(&context.config.api);
     super::register_metrics(&context);
-    context.store.migrate()?;
+    context
+        .store
+        .migrate_with_retries(&context.config.migrations)?;
+    let detail = json!({"config_checksum": context.config.checksum()});
+    record_lifecycle_event(&context, "agent.started", detail);
     let agent = initialise(&context, &mut upkeep)?;
+    let agent: Arc<dyn Agent> = Arc::new(agent);
+    let agent = LimitedAgent::wrap(agent, &context);
+    let agent = ClassifiedAgent::wrap(agent, &context.config.datastore);
+    crate::metrics::set_standard_labels(&context, agent.as_ref());
     actions::initialise(&agent, &mut context, &mut upkeep)?;
-    api::spawn_server(agent, context, &mut upkeep)?;
+    warmup::spawn(Arc::clone(&agent), context.clone())?;
+    api::spawn_server(agent, context.clone(), &mut upkeep)?;
     let clean_exit = upkeep.keepalive();
+    let detail = json!({ "clean_exit": clean_exit });
+    record_lifecycle_event(&context, "agent.stopping", detail);
     if clean_exit {
         info!(logger, "Agent stopped gracefully");
     } else {
@@ -102,6 +127,50 @@ where
     Ok(clean_exit)
 }
 
+/// Log a summary of the resolved configuration the agent is starting with.
+///
+/// Settings that may embed credentials, such as the sentry DSN, are redacted.
+fn log_startup_config(config: &Config, logger: &Logger) {
+    let sentry_dsn = config
+        .sentry
+        .as_ref()
+        .map(|sentry| redact_credentials(&sentry.dsn));
+    info!(
+        logger,
+        "Starting agent with resolved configuration";
+        "actions_enabled" => ?config.actions.enabled,
+        "actions_execute_interval" => config.actions.execute_interval,
+        "api_bind" => &config.api.bind,
+        "api_tls" => config.api.tls.is_some(),
+        "api_tree_introspect" => config.api.trees.introspect,
+        "api_tree_unstable" => config.api.trees.unstable,
+        "sentry_dsn" => ?sentry_dsn,
+        "store_backend" => "sqlite",
+        "store_path" => &config.db,
+    );
+}
+
+/// Record an agent lifecycle event and prune events older than the configured retention.
+///
+/// Events are informational so failing to record them does not stop the agent.
+fn record_lifecycle_event(context: &AgentContext, kind: &str, detail: Json) {
+    let retention = chrono::Duration::seconds(context.config.events.retention as i64);
+    let before = Utc::now() - retention;
+    let result = context.store.with_transaction(|tx| {
+        tx.record_event(kind, detail)?;
+        tx.events().prune(before, EVENTS_PRUNE_LIMIT, None)
+    });
+    if let Err(error) = result {
+        capture_fail!(
+            &error,
+            context.logger,
+            "Failed to record agent lifecycle event";
+            "kind" => kind,
+            failure_info(&error),
+        );
+    }
+}
+
 /// Configure and instantiate the logger.
 pub fn logger(config: &Config) -> (Logger, GlobalLoggerGuard) {
     let logger_opts = ::replicante_logging::Opts::new(env!("GIT_BUILD_HASH").into());
@@ -141,9 +210,7 @@ pub fn register_process_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let process = ProcessCollector::for_self();
     let registry = &context.metrics;
-    if let Err(error) = registry.register(Box::new(process)) {
-        debug!(logger, "Failed to register process metrics"; "error" => ?error);
-    }
+    register_collector(logger, registry, "process metrics", Box::new(process));
 }
 
 /// Run the agent process.
@@ -164,6 +231,7 @@ where
     R: Into<Cow<'static, str>>,
 {
     let (logger, _scope_guard) = logger(&config);
+    log_startup_config(&config, &logger);
     let _sentry = sentry(config.sentry.clone(), &logger, release.into())?;
     initialise_and_run(config, logger, service, initialise).map_err(|error| {
         capture_fail(&error);
@@ -222,6 +290,7 @@ pub fn update_checker(current: Version, url: &'static str, context: &AgentContex
         return Ok(());
     }
     let logger = context.logger.clone();
+    let update_available = context.sdk_metrics.update_available.clone();
     Builder::new("r:b:update_checker")
         .full_name("replicante:base:update_checker")
         .spawn(move |scope| {
@@ -263,7 +332,7 @@ pub fn update_checker(current: Version, url: &'static str, context: &AgentContex
                 }
             };
             if current < latest {
-                UPDATE_AVAILABLE.set(1.0);
+                update_available.set(1.0);
                 warn!(
                     logger,
                     "A new version is available";
@@ -292,3 +361,67 @@ pub fn update_checker(current: Version, url: &'static str, context: &AgentContex
 struct VersionMeta {
     version: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Arguments;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use slog::Drain;
+    use slog::Key;
+    use slog::Logger;
+    use slog::Never;
+    use slog::OwnedKVList;
+    use slog::Record;
+    use slog::KV;
+
+    use super::log_startup_config;
+    use crate::config::Agent as Config;
+    use crate::config::SentryConfig;
+
+    /// Drain capturing formatted log lines for inspection.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for Capture {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+            let mut line = Line(record.msg().to_string());
+            record.kv().serialize(record, &mut line).unwrap();
+            values.serialize(record, &mut line).unwrap();
+            self.0.lock().unwrap().push(line.0);
+            Ok(())
+        }
+    }
+
+    struct Line(String);
+
+    impl slog::Serializer for Line {
+        fn emit_arguments(&mut self, key: Key, value: &Arguments) -> slog::Result {
+            self.0.push_str(&format!(" {}={}", key, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn startup_config_redacts_secrets() {
+        let mut config = Config::mock();
+        config.api.bind = "10.0.0.1:37017".into();
+        config.sentry = Some(SentryConfig {
+            capture_api_errors: Default::default(),
+            dsn: "https://secret-key@sentry.example.com/42".into(),
+        });
+        let capture = Capture::default();
+        let logger = Logger::root(capture.clone().fuse(), slog::o!());
+        log_startup_config(&config, &logger);
+
+        let lines = capture.0.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("api_bind=10.0.0.1:37017"));
+        assert!(lines[0].contains("sentry.example.com/42"));
+        assert!(!lines[0].contains("secret-key"));
+    }
+}
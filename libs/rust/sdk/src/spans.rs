@@ -0,0 +1,53 @@
+use opentracingrust::Span;
+
+/// Marker appended to span values truncated to the configured maximum length.
+pub const TRUNCATED_MARKER: &str = "...";
+
+/// Attach a tag to the span, truncating the value to at most `max_length` bytes.
+pub fn tag_span<V>(span: &mut Span, key: &str, value: V, max_length: usize)
+where
+    V: Into<String>,
+{
+    span.tag(key, truncate_value(value.into(), max_length));
+}
+
+/// Truncate a value to at most `max_length` bytes, ending it with `TRUNCATED_MARKER`.
+///
+/// Values are only cut at character boundaries so they may end up shorter than the limit.
+pub fn truncate_value(mut value: String, max_length: usize) -> String {
+    if value.len() <= max_length {
+        return value;
+    }
+    let mut cut = max_length.saturating_sub(TRUNCATED_MARKER.len());
+    while !value.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    value.truncate(cut);
+    value.push_str(TRUNCATED_MARKER);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::truncate_value;
+
+    #[test]
+    fn oversized_value_truncated() {
+        let value = "x".repeat(100);
+        let value = truncate_value(value, 20);
+        assert_eq!(value.len(), 20);
+        assert_eq!(value, format!("{}...", "x".repeat(17)));
+    }
+
+    #[test]
+    fn short_value_unchanged() {
+        let value = truncate_value("short".into(), 20);
+        assert_eq!(value, "short");
+    }
+
+    #[test]
+    fn truncate_at_char_boundary() {
+        let value = truncate_value("ééééé".into(), 8);
+        assert_eq!(value, "éé...");
+    }
+}
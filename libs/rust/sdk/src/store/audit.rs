@@ -0,0 +1,170 @@
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde_derive::Serialize;
+use slog::warn;
+use slog::Logger;
+use uuid::Uuid;
+
+use crate::actions::ActionRecord;
+use crate::actions::ActionState;
+use crate::config::AuditLogConfig;
+
+/// A single action state transition, as recorded in the audit log.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub action_id: Uuid,
+    pub from: Option<ActionState>,
+    pub kind: String,
+    pub timestamp: DateTime<Utc>,
+    pub to: ActionState,
+}
+
+impl AuditEntry {
+    pub fn new(record: &ActionRecord, from: Option<ActionState>, to: ActionState) -> AuditEntry {
+        AuditEntry {
+            action_id: record.id,
+            from,
+            kind: record.kind.clone(),
+            timestamp: Utc::now(),
+            to,
+        }
+    }
+}
+
+/// Append-only log of action state transitions, rotated by size.
+///
+/// Failures to write the log are logged and otherwise ignored so they never block actions.
+pub struct AuditLog {
+    config: AuditLogConfig,
+    file: Mutex<Option<File>>,
+    logger: Logger,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditLogConfig, logger: Logger) -> AuditLog {
+        AuditLog {
+            config,
+            file: Mutex::new(None),
+            logger,
+        }
+    }
+
+    /// Append the given entries to the log, one JSON document per line.
+    pub fn record(&self, entries: Vec<AuditEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let mut file = self.file.lock().expect("AuditLog lock poisoned");
+        for entry in entries {
+            if let Err(error) = self.append(&mut file, &entry) {
+                // Reopen the file on the next write in case it was moved or deleted.
+                *file = None;
+                warn!(
+                    self.logger,
+                    "Failed to write action audit log";
+                    "action_id" => %entry.action_id,
+                    "error" => %error,
+                    "path" => &self.config.path,
+                );
+            }
+        }
+    }
+
+    fn append(&self, file: &mut Option<File>, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if file.is_none() {
+            *file = Some(self.open()?);
+        }
+        let size = file.as_ref().unwrap().metadata()?.len();
+        if size > 0 && size + line.len() as u64 > self.config.max_size {
+            *file = None;
+            self.rotate()?;
+            *file = Some(self.open()?);
+        }
+        file.as_mut().unwrap().write_all(&line)
+    }
+
+    fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.config.path)
+    }
+
+    /// Shift rotated files by one (`path.N-1` to `path.N`) and move the log to `path.1`.
+    ///
+    /// The oldest file is overwritten once `keep` rotated files exist.
+    fn rotate(&self) -> io::Result<()> {
+        let path = &self.config.path;
+        if self.config.keep == 0 {
+            return fs::remove_file(path);
+        }
+        for index in (1..self.config.keep).rev() {
+            let from = format!("{}.{}", path, index);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, format!("{}.{}", path, index + 1))?;
+            }
+        }
+        fs::rename(path, format!("{}.1", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::Value as Json;
+    use slog::Logger;
+    use uuid::Uuid;
+
+    use super::AuditEntry;
+    use super::AuditLog;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::config::AuditLogConfig;
+
+    fn audit_path() -> PathBuf {
+        std::env::temp_dir().join(format!("repliagent-audit-{}.log", Uuid::new_v4()))
+    }
+
+    fn entry() -> AuditEntry {
+        let record = ActionRecord::new(
+            "test.example.io/audit",
+            None,
+            None,
+            Json::Null,
+            ActionRequester::AgentApi,
+        );
+        AuditEntry::new(&record, Some(ActionState::New), ActionState::Running)
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let path = audit_path();
+        let config = AuditLogConfig {
+            keep: 1,
+            max_size: 1,
+            path: path.to_str().unwrap().to_string(),
+        };
+        let audit = AuditLog::new(config, Logger::root(slog::Discard, slog::o!()));
+        audit.record(vec![entry(), entry(), entry()]);
+
+        let rotated = format!("{}.1", path.display());
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        let rotated_lines = fs::read_to_string(&rotated).unwrap().lines().count();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+        assert_eq!(lines, 1);
+        assert_eq!(rotated_lines, 1);
+    }
+}
@@ -1,36 +1,56 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use chrono::DateTime;
+use chrono::Utc;
 use opentracingrust::SpanContext;
 use serde_json::Value as Json;
+use uuid::Uuid;
 
 use crate::actions::ActionHistoryItem;
+use crate::actions::ActionListItem;
 use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
 use crate::store::interface::ActionImpl;
 use crate::store::interface::ActionInterface;
 use crate::store::interface::ActionsImpl;
+use crate::store::interface::ActionsInterface;
 use crate::store::interface::ConnectionImpl;
 use crate::store::interface::ConnectionInterface;
+use crate::store::interface::EventsImpl;
+use crate::store::interface::EventsInterface;
 use crate::store::interface::StoreInterface;
 use crate::store::interface::TransactionImpl;
 use crate::store::interface::TransactionInterface;
+use crate::store::AgentEvent;
 use crate::store::Iter;
+use crate::store::MaintenanceReport;
+use crate::ErrorKind;
 use crate::Result;
 
 #[derive(Clone)]
 struct MockState {
     actions: HashMap<String, ActionRecord>,
+    actions_heartbeat: HashMap<String, DateTime<Utc>>,
+    actions_invoked: HashSet<String>,
     actions_queue: VecDeque<String>,
+    events: Vec<AgentEvent>,
+    migrate_conflicts: u32,
 }
 
 impl Default for MockState {
     fn default() -> Self {
         MockState {
             actions: HashMap::new(),
+            actions_heartbeat: HashMap::new(),
+            actions_invoked: HashSet::new(),
             actions_queue: VecDeque::new(),
+            events: Vec::new(),
+            migrate_conflicts: 0,
         }
     }
 }
@@ -47,6 +67,14 @@ impl MockStore {
         let state = Arc::new(Mutex::new(MockState::default()));
         MockStore { state }
     }
+
+    /// Mock a store that fails the first `conflicts` migrations as if other agents were
+    /// migrating it at the same time.
+    pub fn with_migrate_conflicts(conflicts: u32) -> MockStore {
+        let store = MockStore::new();
+        store.state.lock().unwrap().migrate_conflicts = conflicts;
+        store
+    }
 }
 
 impl StoreInterface for MockStore {
@@ -57,7 +85,19 @@ impl StoreInterface for MockStore {
         Ok(connection)
     }
 
+    fn maintenance(&self) -> Result<MaintenanceReport> {
+        Ok(MaintenanceReport {
+            size_before: 0,
+            size_after: 0,
+        })
+    }
+
     fn migrate(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.migrate_conflicts > 0 {
+            state.migrate_conflicts -= 1;
+            return Err(ErrorKind::PersistentMigrate.into());
+        }
         Ok(())
     }
 }
@@ -94,7 +134,16 @@ impl TransactionInterface for Transaction {
 
     /// Access the actions query interface.
     fn actions(&mut self) -> ActionsImpl {
-        panic!("TODO: MockStore::Transaction::actions")
+        ActionsImpl::new(Actions {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Access the lifecycle events query interface.
+    fn events(&mut self) -> EventsImpl {
+        EventsImpl::new(Events {
+            state: self.state.clone(),
+        })
     }
 
     /// Commit and invalidate the transaction.
@@ -116,30 +165,89 @@ struct Action {
 }
 
 impl ActionInterface for Action {
+    fn duplicate(
+        &self,
+        action: &ActionRecord,
+        created_after: DateTime<Utc>,
+        _: Option<SpanContext>,
+    ) -> Result<Option<ActionRecord>> {
+        let state = self.state.lock().unwrap();
+        let duplicate = state
+            .actions
+            .values()
+            .filter(|record| record.kind == action.kind && record.args() == action.args())
+            .filter(|record| !record.state().is_finished())
+            .filter(|record| record.created_ts >= created_after)
+            .max_by_key(|record| record.scheduled_ts)
+            .cloned();
+        Ok(duplicate)
+    }
+
+    fn clear_heartbeat(&self, action: &ActionRecord, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.actions_heartbeat.remove(&action.id.to_string());
+        Ok(())
+    }
+
     fn get(&self, id: &str, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let state = self.state.lock().unwrap();
         let action = state.actions.get(id).cloned();
         Ok(action)
     }
 
+    fn heartbeat(&self, action: &ActionRecord, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .actions_heartbeat
+            .insert(action.id.to_string(), Utc::now());
+        Ok(())
+    }
+
     fn history(&self, _id: &str, _: Option<SpanContext>) -> Result<Iter<ActionHistoryItem>> {
-        panic!("TODO: MockStore::action::history")
+        // History is not tracked by the mock store.
+        Ok(Iter::new(Vec::new().into_iter()))
     }
 
     fn insert(&self, action: ActionRecord, _: Option<SpanContext>) -> Result<()> {
         let id = action.id;
+        let finished = action.state().is_finished();
         let mut state = self.state.lock().unwrap();
         state.actions.insert(id.to_string(), action);
-        state.actions_queue.push_back(id.to_string());
+        if !finished {
+            state.actions_queue.push_back(id.to_string());
+        }
         Ok(())
     }
 
-    fn next(&self, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
+    fn invoked(&self, action: &ActionRecord, _: Option<SpanContext>) -> Result<bool> {
+        let state = self.state.lock().unwrap();
+        let invoked = state.actions_invoked.contains(&action.id.to_string());
+        Ok(invoked)
+    }
+
+    fn mark_invoked(
+        &self,
+        action: &ActionRecord,
+        invoked: bool,
+        _: Option<SpanContext>,
+    ) -> Result<()> {
+        let id = action.id.to_string();
         let mut state = self.state.lock().unwrap();
+        if invoked {
+            state.actions_invoked.insert(id);
+        } else {
+            state.actions_invoked.remove(&id);
+        }
+        Ok(())
+    }
+
+    fn next(&self, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
+        // Like other stores, return the oldest action until it finishes.
+        let state = self.state.lock().unwrap();
         let next = state
             .actions_queue
-            .pop_front()
-            .and_then(|id| state.actions.get(&id))
+            .front()
+            .and_then(|id| state.actions.get(id))
             .cloned();
         Ok(next)
     }
@@ -157,14 +265,114 @@ impl ActionInterface for Action {
         let record = state.actions.get_mut(&id).unwrap();
         record.set_state(transition_to);
         record.set_state_payload(payload);
-        let finished = state
-            .actions_queue
-            .front()
-            .map(|front| *front == id)
-            .unwrap_or(false);
-        if finished && state_finished {
-            state.actions_queue.pop_front();
+        // Actions can finish out of order when the engine skips deferred ones.
+        if state_finished {
+            state.actions_queue.retain(|queued| *queued != id);
         }
         Ok(())
     }
 }
+
+struct Actions {
+    state: SyncState,
+}
+
+impl ActionsInterface for Actions {
+    fn export(
+        &self,
+        after: Option<Uuid>,
+        limit: u32,
+        _: Option<SpanContext>,
+    ) -> Result<Iter<ActionRecord>> {
+        let state = self.state.lock().unwrap();
+        let after = after.map(|id| id.to_string()).unwrap_or_default();
+        let mut ids: Vec<&String> = state.actions.keys().filter(|id| **id > after).collect();
+        ids.sort();
+        let records: Vec<Result<ActionRecord>> = ids
+            .into_iter()
+            .take(limit as usize)
+            .map(|id| Ok(state.actions[id].clone()))
+            .collect();
+        Ok(Iter::new(records.into_iter()))
+    }
+
+    fn finished(&self, _: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        panic!("TODO: MockStore::actions::finished")
+    }
+
+    fn queue(&self, _: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        let state = self.state.lock().unwrap();
+        let queue: Vec<Result<ActionListItem>> = state
+            .actions_queue
+            .iter()
+            .filter_map(|id| state.actions.get(id))
+            .map(|action| {
+                Ok(ActionListItem {
+                    kind: action.kind.clone(),
+                    id: action.id,
+                    state: action.state().clone(),
+                })
+            })
+            .collect();
+        Ok(Iter::new(queue.into_iter()))
+    }
+
+    fn prune(&self, _: u32, _: u32, _: Option<SpanContext>) -> Result<()> {
+        panic!("TODO: MockStore::actions::prune")
+    }
+
+    fn prune_payloads(&self, _: DateTime<Utc>, _: u32, _: Option<SpanContext>) -> Result<()> {
+        panic!("TODO: MockStore::actions::prune_payloads")
+    }
+
+    fn unresponsive(
+        &self,
+        heartbeat_before: DateTime<Utc>,
+        _: Option<SpanContext>,
+    ) -> Result<Iter<ActionRecord>> {
+        let state = self.state.lock().unwrap();
+        let records: Vec<Result<ActionRecord>> = state
+            .actions_queue
+            .iter()
+            .filter(|id| match state.actions_heartbeat.get(*id) {
+                Some(heartbeat) => *heartbeat < heartbeat_before,
+                None => false,
+            })
+            .filter_map(|id| state.actions.get(id))
+            .filter(|record| !record.state().is_finished())
+            .map(|record| Ok(record.clone()))
+            .collect();
+        Ok(Iter::new(records.into_iter()))
+    }
+}
+
+struct Events {
+    state: SyncState,
+}
+
+impl EventsInterface for Events {
+    fn insert(&self, event: AgentEvent, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.events.push(event);
+        Ok(())
+    }
+
+    fn prune(&self, before: DateTime<Utc>, _: u32, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.events.retain(|event| event.timestamp >= before);
+        Ok(())
+    }
+
+    fn recent(&self, _: Option<SpanContext>) -> Result<Iter<AgentEvent>> {
+        let state = self.state.lock().unwrap();
+        let events: Vec<Result<AgentEvent>> = state
+            .events
+            .iter()
+            .rev()
+            .take(100)
+            .cloned()
+            .map(Ok)
+            .collect();
+        Ok(Iter::new(events.into_iter()))
+    }
+}
@@ -1,8 +1,12 @@
+use std::sync::Arc;
+
 use slog::Logger;
 
 use replicante_util_tracing::MaybeTracer;
 
 use crate::config::Agent as Config;
+use crate::metrics::SdkMetrics;
+use crate::store::audit::AuditLog;
 use crate::store::interface::StoreImpl;
 use crate::store::Store;
 use crate::Result;
@@ -12,8 +16,24 @@ pub mod mock;
 mod sqlite3;
 
 /// Instantiate a new storage backend based on the given configuration.
-pub fn backend_factory(config: &Config, logger: Logger, tracer: MaybeTracer) -> Result<Store> {
-    let inner = self::sqlite3::Store::new(logger.clone(), config.db.clone(), tracer)?;
+pub fn backend_factory(
+    config: &Config,
+    logger: Logger,
+    tracer: MaybeTracer,
+    metrics: Arc<SdkMetrics>,
+) -> Result<Store> {
+    let max_records = config.actions.max_records;
+    let path = config.db.clone();
+    let inner = self::sqlite3::Store::new(logger.clone(), path, max_records, tracer, metrics)?;
     let inner = StoreImpl::new(inner);
-    Ok(Store { inner, logger })
+    let audit = config
+        .actions
+        .audit_log
+        .clone()
+        .map(|audit| Arc::new(AuditLog::new(audit, logger.clone())));
+    Ok(Store {
+        audit,
+        inner,
+        logger,
+    })
 }
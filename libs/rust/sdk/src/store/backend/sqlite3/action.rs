@@ -1,5 +1,7 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
+use chrono::DateTime;
 use chrono::TimeZone;
 use chrono::Utc;
 use failure::Fail;
@@ -10,6 +12,8 @@ use rusqlite::params;
 use rusqlite::Row;
 use rusqlite::NO_PARAMS;
 use serde_json::Value as Json;
+use sha2::Digest;
+use sha2::Sha256;
 use uuid::Uuid;
 
 use replicante_util_tracing::MaybeTracer;
@@ -18,15 +22,59 @@ use crate::actions::ActionHistoryItem;
 use crate::actions::ActionRecord;
 use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
-use crate::metrics::SQLITE_OPS_COUNT;
-use crate::metrics::SQLITE_OPS_DURATION;
-use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::metrics::SdkMetrics;
 use crate::store::interface::ActionInterface;
 use crate::store::Iter;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
 
+const ACTION_CLEAR_HEARTBEAT: &str = "action.clear_heartbeat";
+const ACTION_CLEAR_HEARTBEAT_SQL: &str = r#"
+UPDATE actions
+SET last_heartbeat_ts = NULL
+WHERE id = ?1;
+"#;
+const ACTION_COUNT: &str = "action.count";
+const ACTION_COUNT_SQL: &str = r#"
+SELECT COUNT(*)
+FROM actions;
+"#;
+const ACTION_DEDUP: &str = "action.dedup";
+const ACTION_DEDUP_SQL: &str = r#"
+SELECT
+    agent_version,
+    args,
+    created_ts,
+    finished_ts,
+    headers,
+    id,
+    kind,
+    requester,
+    scheduled_ts,
+    state,
+    state_payload,
+    timeout_override
+FROM actions
+WHERE
+    kind = ?1
+    AND args_hash = ?2
+    AND finished_ts IS NULL
+    AND created_ts >= ?3
+ORDER BY scheduled_ts DESC, ROWID DESC
+LIMIT 1;
+"#;
+const ACTION_EVICT: &str = "action.evict";
+const ACTION_EVICT_SQL: &str = r#"
+DELETE FROM actions
+WHERE id IN (
+    SELECT id
+    FROM actions
+    WHERE finished_ts IS NOT NULL
+    ORDER BY finished_ts ASC, ROWID ASC
+    LIMIT ?1
+);
+"#;
 const ACTION_GET: &str = "action.get";
 const ACTION_GET_SQL: &str = r#"
 SELECT
@@ -40,7 +88,8 @@ SELECT
     requester,
     scheduled_ts,
     state,
-    state_payload
+    state_payload,
+    timeout_override
 FROM actions
 WHERE id = ?;
 "#;
@@ -55,6 +104,12 @@ FROM actions_history
 WHERE action_id = ?
 ORDER BY time DESC, ROWID DESC;
 "#;
+const ACTION_HEARTBEAT: &str = "action.heartbeat";
+const ACTION_HEARTBEAT_SQL: &str = r#"
+UPDATE actions
+SET last_heartbeat_ts = ?1
+WHERE id = ?2;
+"#;
 const ACTION_INSERT: &str = "action.insert";
 const ACTION_INSERT_SQL: &str = r#"
 INSERT INTO actions (
@@ -67,9 +122,12 @@ INSERT INTO actions (
     requester,
     scheduled_ts,
     state,
-    state_payload
+    state_payload,
+    timeout_override,
+    args_hash,
+    finished_ts
 )
-VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);
 "#;
 const ACTION_INSERT_HISTORY: &str = "action.insert.history";
 const ACTION_INSERT_HISTORY_SQL: &str = r#"
@@ -81,6 +139,18 @@ INSERT INTO actions_history (
 )
 VALUES (?1, ?2, ?3, ?4);
 "#;
+const ACTION_INVOKED: &str = "action.invoked";
+const ACTION_INVOKED_SQL: &str = r#"
+SELECT invoked
+FROM actions
+WHERE id = ?;
+"#;
+const ACTION_MARK_INVOKED: &str = "action.mark_invoked";
+const ACTION_MARK_INVOKED_SQL: &str = r#"
+UPDATE actions
+SET invoked = ?1
+WHERE id = ?2;
+"#;
 const ACTION_NEXT: &str = "action.next";
 const ACTION_NEXT_SQL: &str = r#"
 SELECT
@@ -94,7 +164,8 @@ SELECT
     requester,
     scheduled_ts,
     state,
-    state_payload
+    state_payload,
+    timeout_override
 FROM actions
 WHERE finished_ts IS NULL
 ORDER BY scheduled_ts ASC, ROWID ASC
@@ -143,8 +214,16 @@ macro_rules! decode_or_return {
     };
 }
 
+/// Hash action arguments to look up identical actions.
+///
+/// JSON objects are serialised with sorted keys so equal arguments have equal hashes.
+fn args_hash(args: &Json) -> String {
+    let args = args.to_string();
+    format!("{:x}", Sha256::digest(args.as_bytes()))
+}
+
 /// Parse a SQLite result row into a full ActionRecord.
-fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
+pub(super) fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
     let id: String = decode_or_return!(row.get("id"), op);
     let id = decode_or_return!(Uuid::from_str(&id), op);
     let agent_version: String = decode_or_return!(row.get("agent_version"), op);
@@ -168,6 +247,8 @@ fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
         None => None,
         Some(payload) => decode_or_return!(serde_json::from_str(&payload), op),
     };
+    let timeout_override: Option<i64> = decode_or_return!(row.get("timeout_override"), op);
+    let timeout_override = timeout_override.map(|timeout| timeout as u64);
     Ok(ActionRecord::inflate(
         agent_version,
         args,
@@ -180,17 +261,106 @@ fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
         scheduled_ts,
         state,
         state_payload,
+        timeout_override,
     ))
 }
 
 pub struct Action<'a, 'b: 'a> {
     inner: &'a rusqlite::Transaction<'b>,
+    max_records: Option<u32>,
+    metrics: Arc<SdkMetrics>,
     tracer: MaybeTracer,
 }
 
 impl<'a, 'b: 'a> Action<'a, 'b> {
-    pub fn new(inner: &'a rusqlite::Transaction<'b>, tracer: MaybeTracer) -> Action<'a, 'b> {
-        Action { inner, tracer }
+    pub fn new(
+        inner: &'a rusqlite::Transaction<'b>,
+        max_records: Option<u32>,
+        tracer: MaybeTracer,
+        metrics: Arc<SdkMetrics>,
+    ) -> Action<'a, 'b> {
+        Action {
+            inner,
+            max_records,
+            metrics,
+            tracer,
+        }
+    }
+
+    /// Delete the oldest finished actions to make room for a new action.
+    ///
+    /// Unfinished actions are never deleted so if there are not enough finished
+    /// actions to delete the new action is rejected.
+    fn make_room(&self, max_records: u32, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.delete", opts);
+            span.tag("sql", ACTION_EVICT_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let count: i64 = self
+            .inner
+            .query_row(ACTION_COUNT_SQL, NO_PARAMS, |row| row.get(0))
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_COUNT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        timer.observe_duration();
+        let excess = count - i64::from(max_records) + 1;
+        if excess <= 0 {
+            return Ok(());
+        }
+
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["DELETE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_EVICT_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_EVICT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
+                error
+            })?;
+        let evicted = statement
+            .execute(params![excess])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_EVICT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
+                error
+            })?;
+        if (evicted as i64) < excess {
+            return Err(ErrorKind::ActionsLimitReached(max_records).into());
+        }
+        Ok(())
     }
 
     fn record_transition(
@@ -209,8 +379,13 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
             span.tag("sql", ACTION_INSERT_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["INSERT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["INSERT"])
             .start_timer();
         let mut statement = self
@@ -218,7 +393,10 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
             .prepare_cached(ACTION_INSERT_HISTORY_SQL)
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_INSERT_HISTORY))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
                 error
             })?;
         statement
@@ -230,7 +408,10 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
             ])
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_INSERT_HISTORY))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
                 error
             })?;
         Ok(())
@@ -238,6 +419,116 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
 }
 
 impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
+    fn duplicate(
+        &self,
+        action: &ActionRecord,
+        created_after: DateTime<Utc>,
+        span: Option<SpanContext>,
+    ) -> Result<Option<ActionRecord>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTION_DEDUP_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_DEDUP_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_DEDUP))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        let mut rows = statement
+            .query(params![
+                action.kind,
+                args_hash(action.args()),
+                created_after.timestamp(),
+            ])
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_DEDUP))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        let row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_DEDUP))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        timer.observe_duration();
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        parse_action(row, ACTION_DEDUP).map(Some)
+    }
+
+    fn clear_heartbeat(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.update", opts);
+            span.tag("sql", ACTION_CLEAR_HEARTBEAT_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["UPDATE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["UPDATE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_CLEAR_HEARTBEAT_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_CLEAR_HEARTBEAT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        statement
+            .execute(params![action.id.to_string()])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_CLEAR_HEARTBEAT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        Ok(())
+    }
+
     fn get(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -248,8 +539,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             span.tag("sql", ACTION_GET_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
-        let timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["SELECT"])
             .start_timer();
         let mut statement = self
@@ -257,21 +553,30 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .prepare_cached(ACTION_GET_SQL)
             .with_context(|_| ErrorKind::PersistentRead(ACTION_GET))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         let mut rows = statement
             .query(params![id])
             .with_context(|_| ErrorKind::PersistentRead(ACTION_GET))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         let row = rows
             .next()
             .with_context(|_| ErrorKind::PersistentRead(ACTION_GET))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         timer.observe_duration();
@@ -282,6 +587,49 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         parse_action(row, ACTION_GET).map(Some)
     }
 
+    fn heartbeat(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.update", opts);
+            span.tag("sql", ACTION_HEARTBEAT_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["UPDATE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["UPDATE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_HEARTBEAT_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_HEARTBEAT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        statement
+            .execute(params![Utc::now().timestamp(), action.id.to_string()])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_HEARTBEAT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        Ok(())
+    }
+
     fn history(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionHistoryItem>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -292,8 +640,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             span.tag("sql", ACTION_GET_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["SELECT"])
             .start_timer();
         let mut statement = self
@@ -301,7 +654,10 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .prepare_cached(ACTION_GET_HISTORY_SQL)
             .with_context(|_| ErrorKind::PersistentRead(ACTION_GET_HISTORY))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         let mut results = Vec::new();
@@ -352,6 +708,12 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             span.tag("sql", ACTION_INSERT_SQL);
             span.auto_finish()
         });
+        if let Some(max_records) = self.max_records {
+            self.make_room(
+                max_records,
+                span.as_ref().map(|span| span.context().clone()),
+            )?;
+        }
         let action_id = action.id.to_string();
         let args = serde_json::to_string(&action.args())
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_INSERT))?;
@@ -370,8 +732,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                     .map_err(Error::from)
             })
             .transpose()?;
-        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["INSERT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["INSERT"])
             .start_timer();
         let mut statement = self
@@ -379,7 +746,10 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .prepare_cached(ACTION_INSERT_SQL)
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_INSERT))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
                 error
             })?;
         let result = statement.execute(params![
@@ -393,6 +763,9 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             action.scheduled_ts.timestamp(),
             &state,
             &state_payload,
+            action.timeout_override.map(|timeout| timeout as i64),
+            args_hash(action.args()),
+            action.finished_ts.map(|ts| ts.timestamp()),
         ]);
         match result {
             Ok(_) => (),
@@ -404,7 +777,10 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                 return Err(error.into());
             }
             Err(error) => {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
                 let error = error.context(ErrorKind::PersistentWrite(ACTION_INSERT));
                 return Err(error.into());
             }
@@ -418,6 +794,97 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         Ok(())
     }
 
+    fn invoked(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<bool> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTION_INVOKED_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_INVOKED_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_INVOKED))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        let invoked = statement
+            .query_row(params![action.id.to_string()], |row| row.get("invoked"))
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_INVOKED))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        Ok(invoked)
+    }
+
+    fn mark_invoked(
+        &self,
+        action: &ActionRecord,
+        invoked: bool,
+        span: Option<SpanContext>,
+    ) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.update", opts);
+            span.tag("sql", ACTION_MARK_INVOKED_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["UPDATE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["UPDATE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_MARK_INVOKED_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_MARK_INVOKED))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        statement
+            .execute(params![invoked, action.id.to_string()])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_MARK_INVOKED))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
+                error
+            })?;
+        Ok(())
+    }
+
     fn next(&self, span: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -428,8 +895,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             span.tag("sql", ACTION_NEXT_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
-        let timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["SELECT"])
             .start_timer();
         let mut statement = self
@@ -437,21 +909,30 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .prepare_cached(ACTION_NEXT_SQL)
             .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         let mut rows = statement
             .query(NO_PARAMS)
             .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         let row = rows
             .next()
             .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         timer.observe_duration();
@@ -493,8 +974,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                     .map_err(Error::from)
             })
             .transpose()?;
-        SQLITE_OPS_COUNT.with_label_values(&["UPDATE"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["UPDATE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["UPDATE"])
             .start_timer();
         let mut statement = self
@@ -502,14 +988,20 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .prepare_cached(ACTION_TRANSITION_SQL)
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_TRANSITION))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
                 error
             })?;
         statement
             .execute(params![state, state_payload, finished_ts, action_id])
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_TRANSITION))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["UPDATE"])
+                    .inc();
                 error
             })?;
         self.record_transition(
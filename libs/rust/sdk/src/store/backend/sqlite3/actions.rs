@@ -1,5 +1,8 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
+use chrono::DateTime;
+use chrono::Utc;
 use failure::ResultExt;
 use opentracingrust::SpanContext;
 use opentracingrust::StartOptions;
@@ -10,17 +13,37 @@ use uuid::Uuid;
 
 use replicante_util_tracing::MaybeTracer;
 
+use super::action::parse_action;
 use crate::actions::ActionListItem;
+use crate::actions::ActionRecord;
 use crate::actions::ActionState;
-use crate::metrics::SQLITE_OPS_COUNT;
-use crate::metrics::SQLITE_OPS_DURATION;
-use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::metrics::SdkMetrics;
 use crate::store::interface::ActionsInterface;
 use crate::store::Iter;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
 
+const ACTIONS_EXPORT: &str = "action.export";
+const ACTIONS_EXPORT_SQL: &str = r#"
+SELECT
+    agent_version,
+    args,
+    created_ts,
+    finished_ts,
+    headers,
+    id,
+    kind,
+    requester,
+    scheduled_ts,
+    state,
+    state_payload,
+    timeout_override
+FROM actions
+WHERE id > ?1
+ORDER BY id ASC
+LIMIT ?2;
+"#;
 const ACTIONS_FINISHED: &str = "action.finished";
 const ACTIONS_FINISHED_SQL: &str = r#"
 SELECT
@@ -59,6 +82,50 @@ WHERE id IN (
 );
 "#;
 
+const ACTIONS_PRUNE_PAYLOADS: &str = "action.prune_payloads";
+const ACTIONS_PRUNE_PAYLOADS_SQL: &str = r#"
+UPDATE actions
+SET state_payload = NULL
+WHERE id IN (
+    SELECT id
+    FROM actions
+    WHERE finished_ts < ?1 AND state_payload IS NOT NULL
+    -- Limit result as a form of blast radius containment in case of bugs.
+    LIMIT ?2
+);
+"#;
+const ACTIONS_PRUNE_HISTORY_PAYLOADS_SQL: &str = r#"
+UPDATE actions_history
+SET state_payload = NULL
+WHERE id IN (
+    SELECT actions_history.id
+    FROM actions_history
+    JOIN actions ON actions.id = actions_history.action_id
+    WHERE actions.finished_ts < ?1 AND actions_history.state_payload IS NOT NULL
+    -- Limit result as a form of blast radius containment in case of bugs.
+    LIMIT ?2
+);
+"#;
+const ACTIONS_UNRESPONSIVE: &str = "action.unresponsive";
+const ACTIONS_UNRESPONSIVE_SQL: &str = r#"
+SELECT
+    agent_version,
+    args,
+    created_ts,
+    finished_ts,
+    headers,
+    id,
+    kind,
+    requester,
+    scheduled_ts,
+    state,
+    state_payload,
+    timeout_override
+FROM actions
+WHERE finished_ts IS NULL AND last_heartbeat_ts < ?1
+ORDER BY scheduled_ts ASC, ROWID ASC;
+"#;
+
 /// Helper macro to avoid writing the same match every time.
 macro_rules! decode_or_continue {
     ($decode:expr, $res:ident, $op:expr $(,)?) => {
@@ -100,16 +167,78 @@ fn parse_actions_list(statement: &mut Statement, op: &'static str) -> Result<Ite
 
 pub struct Actions<'a, 'b: 'a> {
     inner: &'a rusqlite::Transaction<'b>,
+    metrics: Arc<SdkMetrics>,
     tracer: MaybeTracer,
 }
 
 impl<'a, 'b: 'a> Actions<'a, 'b> {
-    pub fn new(inner: &'a rusqlite::Transaction<'b>, tracer: MaybeTracer) -> Actions<'a, 'b> {
-        Actions { inner, tracer }
+    pub fn new(
+        inner: &'a rusqlite::Transaction<'b>,
+        tracer: MaybeTracer,
+        metrics: Arc<SdkMetrics>,
+    ) -> Actions<'a, 'b> {
+        Actions {
+            inner,
+            metrics,
+            tracer,
+        }
     }
 }
 
 impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
+    fn export(
+        &self,
+        after: Option<Uuid>,
+        limit: u32,
+        span: Option<SpanContext>,
+    ) -> Result<Iter<ActionRecord>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTIONS_EXPORT_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_EXPORT_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_EXPORT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        // UUIDs in their string form sort after the empty string.
+        let after = after.map(|id| id.to_string()).unwrap_or_default();
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(params![after, limit])
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_EXPORT))?;
+        let mut maybe_row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_EXPORT))?;
+        while let Some(row) = maybe_row {
+            results.push(parse_action(row, ACTIONS_EXPORT));
+            maybe_row = rows
+                .next()
+                .with_context(|_| ErrorKind::PersistentRead(ACTIONS_EXPORT))?;
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+
     fn finished(&self, span: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -120,8 +249,13 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             span.tag("sql", ACTIONS_FINISHED_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["SELECT"])
             .start_timer();
         let mut statement = self
@@ -129,11 +263,17 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             .prepare_cached(ACTIONS_FINISHED_SQL)
             .with_context(|_| ErrorKind::PersistentRead(ACTIONS_FINISHED))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         parse_actions_list(&mut statement, ACTIONS_FINISHED).map_err(|error| {
-            SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+            self.metrics
+                .sqlite_op_errors_count
+                .with_label_values(&["SELECT"])
+                .inc();
             error
         })
     }
@@ -148,8 +288,13 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             span.tag("sql", ACTIONS_QUEUE_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["SELECT"])
             .start_timer();
         let mut statement = self
@@ -157,11 +302,17 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             .prepare_cached(ACTIONS_QUEUE_SQL)
             .with_context(|_| ErrorKind::PersistentRead(ACTIONS_QUEUE))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
                 error
             })?;
         parse_actions_list(&mut statement, ACTIONS_QUEUE).map_err(|error| {
-            SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+            self.metrics
+                .sqlite_op_errors_count
+                .with_label_values(&["SELECT"])
+                .inc();
             error
         })
     }
@@ -176,8 +327,13 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             span.tag("sql", ACTIONS_PRUNE_SQL);
             span.auto_finish()
         });
-        SQLITE_OPS_COUNT.with_label_values(&["DELETE"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["DELETE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["DELETE"])
             .start_timer();
         let mut statement = self
@@ -185,16 +341,126 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
             .prepare_cached(ACTIONS_PRUNE_SQL)
             .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_PRUNE))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
                 error
             })?;
         statement
             .execute(params![limit, keep])
             .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_PRUNE))
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
                 error
             })?;
         Ok(())
     }
+
+    fn prune_payloads(
+        &self,
+        finished_before: DateTime<Utc>,
+        limit: u32,
+        span: Option<SpanContext>,
+    ) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.update", opts);
+            span.tag("sql", ACTIONS_PRUNE_PAYLOADS_SQL);
+            span.auto_finish()
+        });
+        let finished_before = finished_before.timestamp();
+        for sql in &[
+            ACTIONS_PRUNE_PAYLOADS_SQL,
+            ACTIONS_PRUNE_HISTORY_PAYLOADS_SQL,
+        ] {
+            self.metrics
+                .sqlite_ops_count
+                .with_label_values(&["UPDATE"])
+                .inc();
+            let _timer = self
+                .metrics
+                .sqlite_ops_duration
+                .with_label_values(&["UPDATE"])
+                .start_timer();
+            let mut statement = self
+                .inner
+                .prepare_cached(sql)
+                .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_PRUNE_PAYLOADS))
+                .map_err(|error| {
+                    self.metrics
+                        .sqlite_op_errors_count
+                        .with_label_values(&["UPDATE"])
+                        .inc();
+                    error
+                })?;
+            statement
+                .execute(params![finished_before, limit])
+                .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_PRUNE_PAYLOADS))
+                .map_err(|error| {
+                    self.metrics
+                        .sqlite_op_errors_count
+                        .with_label_values(&["UPDATE"])
+                        .inc();
+                    error
+                })?;
+        }
+        Ok(())
+    }
+
+    fn unresponsive(
+        &self,
+        heartbeat_before: DateTime<Utc>,
+        span: Option<SpanContext>,
+    ) -> Result<Iter<ActionRecord>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTIONS_UNRESPONSIVE_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_UNRESPONSIVE_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_UNRESPONSIVE))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(params![heartbeat_before.timestamp()])
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_UNRESPONSIVE))?;
+        let mut maybe_row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_UNRESPONSIVE))?;
+        while let Some(row) = maybe_row {
+            results.push(parse_action(row, ACTIONS_UNRESPONSIVE));
+            maybe_row = rows
+                .next()
+                .with_context(|_| ErrorKind::PersistentRead(ACTIONS_UNRESPONSIVE))?;
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
 }
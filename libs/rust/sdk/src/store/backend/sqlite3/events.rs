@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use failure::ResultExt;
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use rusqlite::params;
+use rusqlite::NO_PARAMS;
+
+use replicante_util_tracing::MaybeTracer;
+
+use crate::metrics::SdkMetrics;
+use crate::store::interface::EventsInterface;
+use crate::store::AgentEvent;
+use crate::store::Iter;
+use crate::Error;
+use crate::ErrorKind;
+use crate::Result;
+
+const EVENTS_INSERT: &str = "events.insert";
+const EVENTS_INSERT_SQL: &str = r#"
+INSERT INTO events (kind, detail, timestamp)
+VALUES (?1, ?2, ?3);
+"#;
+const EVENTS_PRUNE: &str = "events.prune";
+const EVENTS_PRUNE_SQL: &str = r#"
+DELETE FROM events
+WHERE id IN (
+    SELECT id
+    FROM events
+    WHERE timestamp < ?1
+    -- Limit result as a form of blast radius containment in case of bugs.
+    LIMIT ?2
+);
+"#;
+const EVENTS_RECENT: &str = "events.recent";
+const EVENTS_RECENT_SQL: &str = r#"
+SELECT
+    kind, detail, timestamp
+FROM events
+ORDER BY timestamp DESC, id DESC
+-- Limit result as a form of blast radius containment from bugs or overload.
+LIMIT 100;
+"#;
+
+/// Helper macro to avoid writing the same match every time.
+macro_rules! decode_or_continue {
+    ($decode:expr, $res:ident, $op:expr $(,)?) => {
+        match $decode {
+            Ok(r) => r,
+            Err(error) => {
+                let error = Err(error)
+                    .with_context(|_| ErrorKind::PersistentRead($op))
+                    .map_err(Error::from);
+                $res.push(error);
+                continue;
+            }
+        }
+    };
+}
+
+pub struct Events<'a, 'b: 'a> {
+    inner: &'a rusqlite::Transaction<'b>,
+    metrics: Arc<SdkMetrics>,
+    tracer: MaybeTracer,
+}
+
+impl<'a, 'b: 'a> Events<'a, 'b> {
+    pub fn new(
+        inner: &'a rusqlite::Transaction<'b>,
+        tracer: MaybeTracer,
+        metrics: Arc<SdkMetrics>,
+    ) -> Events<'a, 'b> {
+        Events {
+            inner,
+            metrics,
+            tracer,
+        }
+    }
+}
+
+impl<'a, 'b: 'a> EventsInterface for Events<'a, 'b> {
+    fn insert(&self, event: AgentEvent, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", EVENTS_INSERT_SQL);
+            span.auto_finish()
+        });
+        let detail = event
+            .detail
+            .map(|detail| {
+                serde_json::to_string(&detail)
+                    .with_context(|_| ErrorKind::PersistentWrite(EVENTS_INSERT))
+                    .map_err(Error::from)
+            })
+            .transpose()?;
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["INSERT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_INSERT_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_INSERT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
+                error
+            })?;
+        statement
+            .execute(params![event.kind, detail, event.timestamp.timestamp()])
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_INSERT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["INSERT"])
+                    .inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn prune(&self, before: DateTime<Utc>, limit: u32, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.delete", opts);
+            span.tag("sql", EVENTS_PRUNE_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["DELETE"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_PRUNE_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PRUNE))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
+                error
+            })?;
+        statement
+            .execute(params![before.timestamp(), limit])
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PRUNE))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["DELETE"])
+                    .inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn recent(&self, span: Option<SpanContext>) -> Result<Iter<AgentEvent>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", EVENTS_RECENT_SQL);
+            span.auto_finish()
+        });
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["SELECT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_RECENT_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_RECENT))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["SELECT"])
+                    .inc();
+                error
+            })?;
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(NO_PARAMS)
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_RECENT))?;
+        let mut maybe_row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_RECENT))?;
+        while let Some(row) = maybe_row {
+            let kind: String = decode_or_continue!(row.get("kind"), results, EVENTS_RECENT);
+            let detail: Option<String> =
+                decode_or_continue!(row.get("detail"), results, EVENTS_RECENT);
+            let detail = match detail {
+                None => None,
+                Some(detail) => {
+                    decode_or_continue!(serde_json::from_str(&detail), results, EVENTS_RECENT)
+                }
+            };
+            let timestamp: i64 = decode_or_continue!(row.get("timestamp"), results, EVENTS_RECENT);
+            let timestamp = Utc.timestamp(timestamp, 0);
+            results.push(Ok(AgentEvent {
+                detail,
+                kind,
+                timestamp,
+            }));
+            maybe_row = rows
+                .next()
+                .with_context(|_| ErrorKind::PersistentRead(EVENTS_RECENT))?;
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+}
@@ -0,0 +1,2 @@
+-- Persisted marker to detect replays of non-idempotent actions.
+ALTER TABLE actions ADD COLUMN invoked INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- SQLite can't DROP COLUMNs and re-creating the table would cascade to actions_history.
+-- The column defaults to NULL so leaving it in place is harmless.
+SELECT 1;
@@ -0,0 +1,2 @@
+-- Per-invocation override of the action timeout, in seconds.
+ALTER TABLE actions ADD COLUMN timeout_override INTEGER DEFAULT NULL;
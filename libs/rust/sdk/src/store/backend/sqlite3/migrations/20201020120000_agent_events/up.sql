@@ -0,0 +1,10 @@
+-- Agent lifecycle events (process start, shutdown, ...).
+CREATE TABLE IF NOT EXISTS events(
+  -- INTEGER PRIMARY KEY is an alias for ROWID (which is more efficient then AUTOINCREMENT).
+  -- https://www.sqlite.org/autoinc.html
+  id INTEGER PRIMARY KEY NOT NULL,
+  kind TEXT NOT NULL,
+  detail TEXT,
+  timestamp INTEGER NOT NULL
+);
+CREATE INDEX events_timestamp ON events(timestamp);
@@ -0,0 +1,4 @@
+-- Hash of the action arguments, used to de-duplicate scheduled actions.
+-- Actions stored before this migration have no hash and are never considered duplicates.
+ALTER TABLE actions ADD COLUMN args_hash TEXT DEFAULT NULL;
+CREATE INDEX actions_kind_args_hash ON actions(kind, args_hash);
@@ -0,0 +1,2 @@
+-- Time of the last heartbeat from the action executor, used to detect stuck invocations.
+ALTER TABLE actions ADD COLUMN last_heartbeat_ts INTEGER DEFAULT NULL;
@@ -1,53 +1,73 @@
+use std::sync::Arc;
+
 use failure::ResultExt;
 use failure::SyncFailure;
 use migrant_lib::Config;
 use migrant_lib::Migrator;
 use migrant_lib::Settings;
+use rusqlite::NO_PARAMS;
 use slog::debug;
 use slog::info;
 use slog::Logger;
 
 use replicante_util_tracing::MaybeTracer;
 
-use crate::metrics::SQLITE_CONNECTION_ERRORS;
-use crate::metrics::SQLITE_OPS_COUNT;
-use crate::metrics::SQLITE_OPS_DURATION;
-use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::metrics::SdkMetrics;
 use crate::store::interface::ActionImpl;
 use crate::store::interface::ActionsImpl;
 use crate::store::interface::ConnectionImpl;
 use crate::store::interface::ConnectionInterface;
+use crate::store::interface::EventsImpl;
 use crate::store::interface::StoreInterface;
 use crate::store::interface::TransactionImpl;
 use crate::store::interface::TransactionInterface;
+use crate::store::MaintenanceReport;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
 
 mod action;
 mod actions;
+mod events;
+
+const STORE_SIZE: &str = "store.size";
+const STORE_VACUUM: &str = "store.vacuum";
 
 struct Connection {
     connection: rusqlite::Connection,
+    max_records: Option<u32>,
+    metrics: Arc<SdkMetrics>,
     tracer: MaybeTracer,
 }
 
 impl Connection {
-    fn new(path: &str, tracer: MaybeTracer) -> Result<Connection> {
+    fn new(
+        path: &str,
+        max_records: Option<u32>,
+        tracer: MaybeTracer,
+        metrics: Arc<SdkMetrics>,
+    ) -> Result<Connection> {
         let connection = rusqlite::Connection::open_with_flags(path, Default::default())
             .with_context(|_| ErrorKind::PersistentPool)?;
         // Ensure foreign keys are checked.
         connection
             .execute_batch("PRAGMA foreign_keys=1;")
             .with_context(|_| ErrorKind::PersistentPool)?;
-        Ok(Connection { connection, tracer })
+        Ok(Connection {
+            connection,
+            max_records,
+            metrics,
+            tracer,
+        })
     }
 }
 
 impl ConnectionInterface for Connection {
     fn transaction(&mut self) -> Result<TransactionImpl> {
-        SQLITE_OPS_COUNT.with_label_values(&["BEGIN"]).inc();
-        let timer = SQLITE_OPS_DURATION
+        let metrics = Arc::clone(&self.metrics);
+        metrics.sqlite_ops_count.with_label_values(&["BEGIN"]).inc();
+        let timer = metrics
+            .sqlite_ops_duration
             .with_label_values(&["BEGIN"])
             .start_timer();
         let inner = self
@@ -55,43 +75,123 @@ impl ConnectionInterface for Connection {
             .transaction()
             .with_context(|_| ErrorKind::PersistentNoConnection)
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["BEGIN"]).inc();
+                metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["BEGIN"])
+                    .inc();
                 error
             })?;
         timer.observe_duration();
         let inner = Some(inner);
+        let max_records = self.max_records;
         let tracer = self.tracer.clone();
-        Ok(TransactionImpl::new(Transaction { inner, tracer }))
+        Ok(TransactionImpl::new(Transaction {
+            inner,
+            max_records,
+            metrics,
+            tracer,
+        }))
     }
 }
 
 /// SQLite3 backed store.
 pub struct Store {
     logger: Logger,
+    max_records: Option<u32>,
+    metrics: Arc<SdkMetrics>,
     path: String,
     tracer: MaybeTracer,
 }
 
 impl Store {
-    pub fn new(logger: Logger, path: String, tracer: MaybeTracer) -> Result<Store> {
+    pub fn new(
+        logger: Logger,
+        path: String,
+        max_records: Option<u32>,
+        tracer: MaybeTracer,
+        metrics: Arc<SdkMetrics>,
+    ) -> Result<Store> {
         Ok(Store {
             logger,
+            max_records,
+            metrics,
             path,
             tracer,
         })
     }
+
+    /// Open a connection to the DB outside of the transaction interface.
+    fn connection_raw(&self) -> Result<rusqlite::Connection> {
+        let metrics = Arc::clone(&self.metrics);
+        Connection::new(&self.path, self.max_records, self.tracer.clone(), metrics)
+            .map(|connection| connection.connection)
+            .map_err(|error| {
+                self.metrics.sqlite_connection_errors.inc();
+                error
+            })
+    }
+}
+
+/// Compute the size (in bytes) of the DB from its page count and size.
+fn database_size(connection: &rusqlite::Connection) -> Result<u64> {
+    let pages: i64 = connection
+        .query_row("PRAGMA page_count;", NO_PARAMS, |row| row.get(0))
+        .with_context(|_| ErrorKind::PersistentRead(STORE_SIZE))?;
+    let page_size: i64 = connection
+        .query_row("PRAGMA page_size;", NO_PARAMS, |row| row.get(0))
+        .with_context(|_| ErrorKind::PersistentRead(STORE_SIZE))?;
+    Ok((pages * page_size) as u64)
 }
 
 impl StoreInterface for Store {
     fn connection(&self) -> Result<ConnectionImpl> {
         let tracer = self.tracer.clone();
-        let connection = Connection::new(&self.path, tracer).map_err(|error| {
-            SQLITE_CONNECTION_ERRORS.inc();
-            error
-        })?;
+        let metrics = Arc::clone(&self.metrics);
+        let connection =
+            Connection::new(&self.path, self.max_records, tracer, metrics).map_err(|error| {
+                self.metrics.sqlite_connection_errors.inc();
+                error
+            })?;
         Ok(ConnectionImpl::new(connection))
     }
 
+    fn maintenance(&self) -> Result<MaintenanceReport> {
+        let connection = self.connection_raw()?;
+        let size_before = database_size(&connection)?;
+        // VACUUM waits for (and blocks) writes from other connections.
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["VACUUM"])
+            .inc();
+        let timer = self
+            .metrics
+            .sqlite_ops_duration
+            .with_label_values(&["VACUUM"])
+            .start_timer();
+        connection
+            .execute_batch("VACUUM;")
+            .with_context(|_| ErrorKind::PersistentWrite(STORE_VACUUM))
+            .map_err(|error| {
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["VACUUM"])
+                    .inc();
+                error
+            })?;
+        timer.observe_duration();
+        let size_after = database_size(&connection)?;
+        info!(
+            self.logger,
+            "Agent DB maintenance complete";
+            "size_before" => size_before,
+            "size_after" => size_after,
+        );
+        Ok(MaintenanceReport {
+            size_before,
+            size_after,
+        })
+    }
+
     fn migrate(&self) -> Result<()> {
         debug!(self.logger, "Initialising migrations engine");
         let path = std::env::current_dir()
@@ -121,7 +221,14 @@ impl StoreInterface for Store {
             };
         }
         config
-            .use_migrations(&[make_migration!("20190728220141_initialise")])
+            .use_migrations(&[
+                make_migration!("20190728220141_initialise"),
+                make_migration!("20200610190000_actions_invoked"),
+                make_migration!("20201016120000_actions_timeout_override"),
+                make_migration!("20201020120000_agent_events"),
+                make_migration!("20201022120000_actions_args_hash"),
+                make_migration!("20201030120000_actions_heartbeat"),
+            ])
             .map_err(SyncFailure::new)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
 
@@ -145,6 +252,8 @@ impl StoreInterface for Store {
 /// Wrap all operations in a SQLite3 transaction.
 struct Transaction<'a> {
     inner: Option<rusqlite::Transaction<'a>>,
+    max_records: Option<u32>,
+    metrics: Arc<SdkMetrics>,
     tracer: MaybeTracer,
 }
 
@@ -159,19 +268,34 @@ impl<'a> Transaction<'a> {
 impl<'a> TransactionInterface for Transaction<'a> {
     fn action(&mut self) -> ActionImpl {
         let inner = self.tx();
-        let inner = self::action::Action::new(inner, self.tracer.clone());
+        let metrics = Arc::clone(&self.metrics);
+        let inner =
+            self::action::Action::new(inner, self.max_records, self.tracer.clone(), metrics);
         ActionImpl::new(inner)
     }
 
     fn actions(&mut self) -> ActionsImpl {
         let inner = self.tx();
-        let inner = self::actions::Actions::new(inner, self.tracer.clone());
+        let metrics = Arc::clone(&self.metrics);
+        let inner = self::actions::Actions::new(inner, self.tracer.clone(), metrics);
         ActionsImpl::new(inner)
     }
 
+    fn events(&mut self) -> EventsImpl {
+        let inner = self.tx();
+        let metrics = Arc::clone(&self.metrics);
+        let inner = self::events::Events::new(inner, self.tracer.clone(), metrics);
+        EventsImpl::new(inner)
+    }
+
     fn commit(&mut self) -> Result<()> {
-        SQLITE_OPS_COUNT.with_label_values(&["COMMIT"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["COMMIT"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["COMMIT"])
             .start_timer();
         self.inner
@@ -180,14 +304,22 @@ impl<'a> TransactionInterface for Transaction<'a> {
             .commit()
             .with_context(|_| ErrorKind::PersistentCommit)
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT.with_label_values(&["COMMIT"]).inc();
+                self.metrics
+                    .sqlite_op_errors_count
+                    .with_label_values(&["COMMIT"])
+                    .inc();
                 Error::from(error)
             })
     }
 
     fn rollback(&mut self) -> Result<()> {
-        SQLITE_OPS_COUNT.with_label_values(&["ROLLBACK"]).inc();
-        let _timer = SQLITE_OPS_DURATION
+        self.metrics
+            .sqlite_ops_count
+            .with_label_values(&["ROLLBACK"])
+            .inc();
+        let _timer = self
+            .metrics
+            .sqlite_ops_duration
             .with_label_values(&["ROLLBACK"])
             .start_timer();
         self.inner
@@ -196,10 +328,342 @@ impl<'a> TransactionInterface for Transaction<'a> {
             .rollback()
             .with_context(|_| ErrorKind::PersistentCommit)
             .map_err(|error| {
-                SQLITE_OP_ERRORS_COUNT
+                self.metrics
+                    .sqlite_op_errors_count
                     .with_label_values(&["ROLLBACK"])
                     .inc();
                 Error::from(error)
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use chrono::Duration;
+    use chrono::Utc;
+    use failure::Fail;
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use replicante_util_tracing::MaybeTracer;
+
+    use super::Store;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRecordView;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::store::interface::StoreImpl;
+    use crate::store::interface::StoreInterface;
+    use crate::AgentContext;
+
+    fn temp_store(context: &AgentContext, max_records: Option<u32>) -> (String, Store) {
+        let path = std::env::temp_dir().join(format!("repliagent-{}.db", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let tracer = MaybeTracer::new(context.tracer.clone());
+        let metrics = Arc::clone(&context.sdk_metrics);
+        let store = Store::new(
+            context.logger.clone(),
+            path.clone(),
+            max_records,
+            tracer,
+            metrics,
+        );
+        let store = store.unwrap();
+        (path, store)
+    }
+
+    fn migrated(context: &AgentContext, store: Store) -> crate::store::Store {
+        let mut store = crate::store::Store {
+            audit: None,
+            logger: context.logger.clone(),
+            inner: StoreImpl::new(store),
+        };
+        store.migrate().unwrap();
+        store
+    }
+
+    fn insert(store: &crate::store::Store, finished: bool) -> crate::Result<String> {
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let id = record.id.to_string();
+        store.with_transaction(|tx| {
+            tx.action().insert(record.clone(), None)?;
+            if finished {
+                tx.action()
+                    .transition(&record, ActionState::Done, None, None)?;
+            }
+            Ok(())
+        })?;
+        Ok(id)
+    }
+
+    #[test]
+    fn max_records_evicts_oldest_finished() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, Some(3));
+        let store = migrated(&context, store);
+        let first = insert(&store, true).unwrap();
+        let second = insert(&store, true).unwrap();
+        let running = insert(&store, false).unwrap();
+
+        // Finished actions are evicted oldest first.
+        let fourth = insert(&store, false).unwrap();
+        let fifth = insert(&store, false).unwrap();
+        let exists = |id: &str| {
+            store
+                .with_transaction(|tx| tx.action().get(id, None))
+                .unwrap()
+                .is_some()
+        };
+        assert!(!exists(&first));
+        assert!(!exists(&second));
+        assert!(exists(&running));
+        assert!(exists(&fourth));
+        assert!(exists(&fifth));
+
+        // Unfinished actions are never evicted.
+        let error = insert(&store, false).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.name().unwrap(), "ActionsLimitReached");
+        assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn duplicate_within_window_is_coalesced() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, None);
+        let store = migrated(&context, store);
+        let new = |args| ActionRecord::new("test", None, None, args, ActionRequester::AgentApi);
+        let dedup = |record: ActionRecord, window| {
+            store.with_transaction(|tx| tx.action().insert_dedup(record, window, None))
+        };
+        let original = new(json!({"a": 1, "b": 2}));
+        assert!(dedup(original.clone(), Some(60)).unwrap().is_none());
+
+        // Identical actions are coalesced while the original is pending.
+        let duplicate = dedup(new(json!({"b": 2, "a": 1})), Some(60)).unwrap();
+        assert_eq!(duplicate.map(|record| record.id), Some(original.id));
+
+        // But not once it finished, so failed actions can be retried.
+        store
+            .with_transaction(|tx| {
+                tx.action()
+                    .transition(&original, ActionState::Failed, None, None)
+            })
+            .unwrap();
+        let retry = new(json!({"a": 1, "b": 2}));
+        let retried = dedup(retry.clone(), Some(60)).unwrap();
+        assert!(retried.is_none());
+        let duplicate = dedup(new(json!({"a": 1, "b": 2})), Some(60)).unwrap();
+        assert_eq!(duplicate.map(|record| record.id), Some(retry.id));
+
+        // Actions with different arguments or without a window are never coalesced.
+        let different = dedup(new(json!({"a": 2, "b": 2})), Some(60)).unwrap();
+        let no_window = dedup(new(json!({"a": 1, "b": 2})), None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(different.is_none());
+        assert!(no_window.is_none());
+    }
+
+    #[test]
+    fn export_and_import_into_fresh_store() {
+        let context = AgentContext::mock();
+        let (source_path, source) = temp_store(&context, None);
+        let source = migrated(&context, source);
+        let mut ids = Vec::new();
+        for index in 0..5 {
+            ids.push(insert(&source, index % 2 == 0).unwrap());
+        }
+        ids.sort();
+
+        // Export in pages smaller than the number of records.
+        let export = |store: &crate::store::Store| {
+            let mut records = Vec::new();
+            let mut after = None;
+            loop {
+                let page: Vec<ActionRecord> = store
+                    .with_transaction(|tx| tx.actions().export(after, 2, None)?.collect())
+                    .unwrap();
+                if page.is_empty() {
+                    break;
+                }
+                after = page.last().map(|record| record.id);
+                records.extend(page);
+            }
+            records
+        };
+        let exported = export(&source);
+        let exported_ids: Vec<String> = exported.iter().map(|r| r.id.to_string()).collect();
+        assert_eq!(exported_ids, ids);
+
+        let (target_path, target) = temp_store(&context, None);
+        let target = migrated(&context, target);
+        let lines: Vec<String> = exported
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        target
+            .with_transaction(|tx| {
+                for line in &lines {
+                    let record: ActionRecord = serde_json::from_str(line).unwrap();
+                    tx.action().insert(record, None)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        let imported = export(&target);
+        std::fs::remove_file(&source_path).unwrap();
+        std::fs::remove_file(&target_path).unwrap();
+        assert_eq!(imported, exported);
+        assert!(imported.iter().any(|record| record.finished_ts.is_some()));
+    }
+
+    #[test]
+    fn lifecycle_events_recorded_and_pruned() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, None);
+        let store = migrated(&context, store);
+        store
+            .with_transaction(|tx| tx.record_event("agent.started", json!({"test": true})))
+            .unwrap();
+        let events: Vec<_> = store
+            .with_transaction(|tx| tx.events().recent(None)?.collect())
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, "agent.started");
+        assert_eq!(events[0].detail, Some(json!({"test": true})));
+
+        // Events recorded before the cut off are pruned.
+        let events: Vec<_> = store
+            .with_transaction(|tx| {
+                tx.events()
+                    .prune(Utc::now() + Duration::hours(1), 10, None)?;
+                tx.events().recent(None)?.collect()
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn maintenance_reclaims_space() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, None);
+
+        // Grow the DB and free the pages to create something to reclaim.
+        let connection = store.connection_raw().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE filler(data BLOB);
+                INSERT INTO filler VALUES (zeroblob(1048576));
+                DELETE FROM filler;",
+            )
+            .unwrap();
+        let pages: i64 = connection
+            .query_row("PRAGMA freelist_count;", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(pages > 0);
+        drop(connection);
+
+        let report = store.maintenance();
+        std::fs::remove_file(&path).unwrap();
+        let report = report.unwrap();
+        assert!(report.size_after < report.size_before);
+        assert_eq!(report.reclaimed(), report.size_before - report.size_after);
+    }
+
+    #[test]
+    fn unresponsive_actions_have_stale_heartbeats() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, None);
+        let store = migrated(&context, store);
+        // Actions that never recorded a heartbeat are not considered unresponsive.
+        insert(&store, false).unwrap();
+        let running = insert(&store, false).unwrap();
+        let finished = insert(&store, true).unwrap();
+        let invoked = insert(&store, false).unwrap();
+        store
+            .with_transaction(|tx| {
+                for id in &[&running, &finished, &invoked] {
+                    let record = tx.action().get(id, None)?.unwrap();
+                    tx.action().heartbeat(&record, None)?;
+                }
+                // Actions whose invocation is over are not considered unresponsive.
+                let record = tx.action().get(&invoked, None)?.unwrap();
+                tx.action().clear_heartbeat(&record, None)
+            })
+            .unwrap();
+
+        let fresh: Vec<_> = store
+            .with_transaction(|tx| {
+                let before = Utc::now() - Duration::hours(1);
+                tx.actions().unresponsive(before, None)?.collect()
+            })
+            .unwrap();
+        let stale: Vec<_> = store
+            .with_transaction(|tx| {
+                let before = Utc::now() + Duration::hours(1);
+                tx.actions().unresponsive(before, None)?.collect()
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(fresh.is_empty());
+        let stale: Vec<String> = stale
+            .into_iter()
+            .map(|record| record.id.to_string())
+            .collect();
+        assert_eq!(stale, vec![running]);
+    }
+
+    #[test]
+    fn prune_payloads_keeps_records() {
+        let context = AgentContext::mock();
+        let (path, store) = temp_store(&context, None);
+        let store = migrated(&context, store);
+
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let id = record.id.to_string();
+        store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                let payload = json!({"result": "large"});
+                tx.action()
+                    .transition(&record, ActionState::Done, payload, None)
+            })
+            .unwrap();
+
+        // Actions finished after the payload window keep their payloads.
+        let within_window = Utc::now() - Duration::hours(1);
+        let record = store
+            .with_transaction(|tx| {
+                tx.actions().prune_payloads(within_window, 10, None)?;
+                tx.action().get(&id, None)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(*record.state_payload(), Some(json!({"result": "large"})));
+
+        // Actions finished before the payload window lose payloads but keep the record.
+        let past_window = Utc::now() + Duration::hours(1);
+        let (record, history) = store
+            .with_transaction(|tx| {
+                tx.actions().prune_payloads(past_window, 10, None)?;
+                let record = tx.action().get(&id, None)?;
+                let history: Vec<_> = tx.action().history(&id, None)?.collect();
+                Ok((record, history))
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let record = record.expect("action record was removed");
+        assert_eq!(*record.state(), ActionState::Done);
+        assert_eq!(*record.state_payload(), None);
+        assert!(!history.is_empty());
+        for item in history {
+            assert_eq!(item.unwrap().state_payload, None);
+        }
+    }
+}
@@ -2,14 +2,19 @@ use std::ops::Deref;
 use std::ops::DerefMut;
 use std::sync::Arc;
 
+use chrono::DateTime;
+use chrono::Utc;
 use opentracingrust::SpanContext;
 use serde_json::Value as Json;
+use uuid::Uuid;
 
 use super::Iter;
 use crate::actions::ActionHistoryItem;
 use crate::actions::ActionListItem;
 use crate::actions::ActionRecord;
 use crate::actions::ActionState;
+use crate::store::AgentEvent;
+use crate::store::MaintenanceReport;
 use crate::Result;
 
 // Macro definition to generate an interface trait with a wrapping wrapper
@@ -126,6 +131,9 @@ arc_interface! {
         /// Request a new connection to the store.
         fn connection(&self) -> Result<ConnectionImpl>;
 
+        /// Compact the store to reclaim unused space.
+        fn maintenance(&self) -> Result<MaintenanceReport>;
+
         /// Perform database initialisation and applies migrations.
         fn migrate(&self) -> Result<()>;
     }
@@ -154,9 +162,25 @@ box_interface! {
     trait ActionInterface,
 
     interface {
+        /// Find an action with the same kind and arguments as the given action.
+        ///
+        /// Only actions that are not finished and were created after `created_after` are returned.
+        fn duplicate(
+            &self,
+            action: &ActionRecord,
+            created_after: DateTime<Utc>,
+            span: Option<SpanContext>,
+        ) -> Result<Option<ActionRecord>>;
+
+        /// Clear the heartbeat of an action once its invocation is over.
+        fn clear_heartbeat(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<()>;
+
         /// Fetch an action record by ID.
         fn get(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>>;
 
+        /// Record that the executor of the action is responsive.
+        fn heartbeat(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<()>;
+
         /// Fetch an action record's transition history.
         fn history(
             &self,
@@ -167,6 +191,17 @@ box_interface! {
         /// Persist a NEW action to the store.
         fn insert(&self, action: ActionRecord, span: Option<SpanContext>) -> Result<()>;
 
+        /// Check if the action was invoked without the outcome being persisted.
+        fn invoked(&self, action: &ActionRecord, span: Option<SpanContext>) -> Result<bool>;
+
+        /// Set or clear the persisted invocation marker for the action.
+        fn mark_invoked(
+            &self,
+            action: &ActionRecord,
+            invoked: bool,
+            span: Option<SpanContext>,
+        ) -> Result<()>;
+
         /// Fetch the next RUNNING or NEW action.
         fn next(&self, span: Option<SpanContext>) -> Result<Option<ActionRecord>>;
 
@@ -191,6 +226,14 @@ box_interface! {
     trait ActionsInterface,
 
     interface {
+        /// Iterate over up to `limit` action records with IDs after `after`, ordered by ID.
+        fn export(
+            &self,
+            after: Option<Uuid>,
+            limit: u32,
+            span: Option<SpanContext>,
+        ) -> Result<Iter<ActionRecord>>;
+
         /// Iterate over the most recent 100 finished actions, newest action first.
         fn finished(&self, span: Option<SpanContext>) -> Result<Iter<ActionListItem>>;
 
@@ -199,6 +242,42 @@ box_interface! {
 
         /// Prune finished historic actions to prevent endless DB growth.
         fn prune(&self, keep: u32, limit: u32, span: Option<SpanContext>) -> Result<()>;
+
+        /// Clear state payloads of actions that finished before the given time.
+        fn prune_payloads(
+            &self,
+            finished_before: DateTime<Utc>,
+            limit: u32,
+            span: Option<SpanContext>,
+        ) -> Result<()>;
+
+        /// Iterate over unfinished actions with a heartbeat older than `heartbeat_before`.
+        fn unresponsive(
+            &self,
+            heartbeat_before: DateTime<Utc>,
+            span: Option<SpanContext>,
+        ) -> Result<Iter<ActionRecord>>;
+    }
+}
+
+box_interface! {
+    lifetime 'a,
+
+    /// Dynamic dispatch all operations to a backend-specific implementation.
+    struct EventsImpl,
+
+    /// Interface to agent lifecycle events in the store.
+    trait EventsInterface,
+
+    interface {
+        /// Persist a lifecycle event to the store.
+        fn insert(&self, event: AgentEvent, span: Option<SpanContext>) -> Result<()>;
+
+        /// Prune events recorded before the given time.
+        fn prune(&self, before: DateTime<Utc>, limit: u32, span: Option<SpanContext>) -> Result<()>;
+
+        /// Iterate over the most recent 100 events, newest event first.
+        fn recent(&self, span: Option<SpanContext>) -> Result<Iter<AgentEvent>>;
     }
 }
 
@@ -218,6 +297,9 @@ box_interface! {
         /// Access the actions query interface.
         fn actions(&mut self) -> ActionsImpl;
 
+        /// Access the lifecycle events query interface.
+        fn events(&mut self) -> EventsImpl;
+
         /// Commit and invalidate the transaction.
         fn commit(&mut self) -> Result<()>;
 
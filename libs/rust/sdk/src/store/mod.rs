@@ -1,31 +1,58 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
 use opentracingrust::SpanContext;
+use serde_derive::Serialize;
+use serde_json::json;
 use serde_json::Value as Json;
+use slog::warn;
 use slog::Logger;
+use uuid::Uuid;
 
 use replicante_util_failure::capture_fail;
 use replicante_util_failure::failure_info;
 
+mod audit;
 mod backend;
 mod interface;
 
 pub use self::backend::backend_factory;
 
+use self::audit::AuditEntry;
+use self::audit::AuditLog;
 use self::interface::StoreImpl;
 use self::interface::TransactionImpl;
 use crate::actions::ensure_transition_allowed;
+use crate::actions::is_finished;
 use crate::actions::ActionHistoryItem;
 use crate::actions::ActionListItem;
 use crate::actions::ActionRecord;
 use crate::actions::ActionRecordView;
+use crate::actions::ActionRequester;
 use crate::actions::ActionState;
+use crate::config::MigrationsConfig;
+use crate::ErrorKind;
 use crate::Result;
 
 /// Single Action query interface.
 pub struct Action<'a> {
+    audit: &'a RefCell<Vec<AuditEntry>>,
     inner: self::interface::ActionImpl<'a>,
 }
 
 impl<'a> Action<'a> {
+    /// Clear the heartbeat of an action once its invocation is over.
+    pub fn clear_heartbeat<S>(&self, record: &dyn ActionRecordView, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.clear_heartbeat(record.inner(), span.into())
+    }
+
     /// Fetch an action record by ID.
     pub fn get<S>(&self, id: &str, span: S) -> Result<Option<ActionRecord>>
     where
@@ -34,6 +61,18 @@ impl<'a> Action<'a> {
         self.inner.get(id, span.into())
     }
 
+    /// Record that the executor of the action is responsive.
+    ///
+    /// The engine records a heartbeat when it starts invoking an action and clears it once
+    /// the outcome is stored; actions are failed if their heartbeat is older than
+    /// `actions.heartbeat_timeout` seconds and the engine is not invoking them.
+    pub fn heartbeat<S>(&self, record: &dyn ActionRecordView, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.heartbeat(record.inner(), span.into())
+    }
+
     /// Fetch an action record's transition history.
     pub fn history<S>(&self, id: &str, span: S) -> Result<Iter<ActionHistoryItem>>
     where
@@ -43,11 +82,63 @@ impl<'a> Action<'a> {
     }
 
     /// Persist a NEW action to the store.
+    ///
+    /// Finished actions can also be inserted, such as when importing backups.
     pub fn insert<S>(&self, action: ActionRecord, span: S) -> Result<()>
     where
         S: Into<Option<SpanContext>>,
     {
-        self.inner.insert(action, span.into())
+        let state = ActionRecordView::raw_state(&action).clone();
+        let entry = AuditEntry::new(&action, None, state);
+        self.inner.insert(action, span.into())?;
+        self.audit.borrow_mut().push(entry);
+        Ok(())
+    }
+
+    /// Persist a NEW action to the store unless an identical action exists.
+    ///
+    /// An existing action is identical if it has the same kind and arguments,
+    /// it is pending or running and it was created less than `window` seconds ago.
+    /// Finished actions are never matched so failed actions can be retried.
+    /// If an identical action is found it is returned and nothing is persisted.
+    /// De-duplication is skipped if `window` is `None`.
+    pub fn insert_dedup<S>(
+        &self,
+        action: ActionRecord,
+        window: Option<u64>,
+        span: S,
+    ) -> Result<Option<ActionRecord>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let span = span.into();
+        if let Some(window) = window {
+            let created_after = Utc::now() - chrono::Duration::seconds(window as i64);
+            let duplicate = self.inner.duplicate(&action, created_after, span.clone())?;
+            if duplicate.is_some() {
+                return Ok(duplicate);
+            }
+        }
+        self.insert(action, span)?;
+        Ok(None)
+    }
+
+    /// Check if the action was invoked without the outcome being persisted.
+    ///
+    /// Used to protect non-idempotent actions from being invoked again after a crash.
+    pub fn invoked<S>(&self, action: &ActionRecord, span: S) -> Result<bool>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.invoked(action, span.into())
+    }
+
+    /// Set or clear the persisted invocation marker for the action.
+    pub fn mark_invoked<S>(&self, action: &ActionRecord, invoked: bool, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.mark_invoked(action, invoked, span.into())
     }
 
     /// Fetch the next RUNNING or NEW action.
@@ -58,6 +149,59 @@ impl<'a> Action<'a> {
         self.inner.next(span.into())
     }
 
+    /// Persist a NEW copy of a finished action to run it again.
+    ///
+    /// The copy has a new ID and the kind, arguments and headers of the original action.
+    /// Returns `None` if the original action does not exist.
+    pub fn replay<S>(
+        &self,
+        id: &str,
+        requester: ActionRequester,
+        span: S,
+    ) -> Result<Option<ActionRecord>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let span = span.into();
+        let original = match self.get(id, span.clone())? {
+            None => return Ok(None),
+            Some(original) => original,
+        };
+        if !is_finished(ActionRecordView::raw_state(&original)) {
+            return Err(ErrorKind::ActionNotFinished(id.to_string()).into());
+        }
+        let mut record = ActionRecord::new(
+            original.kind.clone(),
+            None,
+            None,
+            original.args().clone(),
+            requester,
+        );
+        record.headers = original.headers.clone();
+        record.timeout_override = original.timeout_override;
+        self.insert(record.clone(), span)?;
+        Ok(Some(record))
+    }
+
+    /// Report the current phase of a multi-stage action.
+    ///
+    /// The phase is stored as the `phase` attribute of the action's `state_payload`,
+    /// preserving any other attributes, and the action is transitioned to `Running`.
+    ///
+    /// The `state_payload` of the action must be a JSON object (or unset).
+    pub fn phase<S>(&self, record: &dyn ActionRecordView, phase: &str, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let mut payload = match record.state_payload() {
+            None | Some(Json::Null) => serde_json::Map::new(),
+            Some(Json::Object(payload)) => payload.clone(),
+            Some(_) => return Err(ErrorKind::ActionEncode.into()),
+        };
+        payload.insert("phase".into(), json!(phase));
+        self.transition(record, ActionState::Running, Json::Object(payload), span)
+    }
+
     /// Transition the action to a new state.
     ///
     /// # Allowed transitions
@@ -89,8 +233,11 @@ impl<'a> Action<'a> {
         let record = record.inner();
         let state = ActionRecordView::raw_state(record);
         ensure_transition_allowed(state, &transition_to);
+        let entry = AuditEntry::new(record, Some(state.clone()), transition_to.clone());
         self.inner
-            .transition(record, transition_to, payload, span.into())
+            .transition(record, transition_to, payload, span.into())?;
+        self.audit.borrow_mut().push(entry);
+        Ok(())
     }
 }
 
@@ -100,6 +247,17 @@ pub struct Actions<'a> {
 }
 
 impl<'a> Actions<'a> {
+    /// Iterate over all action records, in pages of `limit` records, for backups.
+    ///
+    /// Records are ordered by ID: pass the ID of the last record in a page as
+    /// `after` to fetch the next page.
+    pub fn export<S>(&self, after: Option<Uuid>, limit: u32, span: S) -> Result<Iter<ActionRecord>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.export(after, limit, span.into())
+    }
+
     /// Iterate over the most recent 100 finished actions.
     pub fn finished<S>(&self, span: S) -> Result<Iter<ActionListItem>>
     where
@@ -116,6 +274,26 @@ impl<'a> Actions<'a> {
         self.inner.queue(span.into())
     }
 
+    /// Number of actions the engine will execute before the given NEW action.
+    ///
+    /// Returns `None` for actions that are not NEW or are not found in the queue.
+    pub fn queue_position<S>(&self, id: &str, span: S) -> Result<Option<u32>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        // The queue is sorted in the order the engine executes actions.
+        for (position, action) in self.queue(span)?.enumerate() {
+            let action = action?;
+            if action.id.to_string() == id {
+                if action.state != ActionState::New {
+                    return Ok(None);
+                }
+                return Ok(Some(position as u32));
+            }
+        }
+        Ok(None)
+    }
+
     /// Prune finished historic actions to prevent endless DB growth.
     pub fn prune<S>(&self, keep: u32, limit: u32, span: S) -> Result<()>
     where
@@ -123,6 +301,112 @@ impl<'a> Actions<'a> {
     {
         self.inner.prune(keep, limit, span.into())
     }
+
+    /// Clear state payloads of actions, and their history, that finished before the given time.
+    ///
+    /// Action records are kept so they remain available for audit until pruned.
+    pub fn prune_payloads<S>(
+        &self,
+        finished_before: DateTime<Utc>,
+        limit: u32,
+        span: S,
+    ) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner
+            .prune_payloads(finished_before, limit, span.into())
+    }
+
+    /// Iterate over unfinished actions with a heartbeat older than `heartbeat_before`.
+    ///
+    /// Actions that are not being invoked have no heartbeat and are not returned.
+    pub fn unresponsive<S>(
+        &self,
+        heartbeat_before: DateTime<Utc>,
+        span: S,
+    ) -> Result<Iter<ActionRecord>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.unresponsive(heartbeat_before, span.into())
+    }
+}
+
+/// Agent lifecycle event, such as the process starting or stopping.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AgentEvent {
+    /// Additional event-specific information.
+    pub detail: Option<Json>,
+
+    /// Kind of event, for example `agent.started`.
+    pub kind: String,
+
+    /// Time the event was recorded.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl AgentEvent {
+    /// Create a new event recorded now.
+    pub fn new<K, D>(kind: K, detail: D) -> AgentEvent
+    where
+        K: Into<String>,
+        D: Into<Option<Json>>,
+    {
+        AgentEvent {
+            detail: detail.into(),
+            kind: kind.into(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Agent lifecycle events query interface.
+pub struct Events<'a> {
+    inner: self::interface::EventsImpl<'a>,
+}
+
+impl<'a> Events<'a> {
+    /// Persist a lifecycle event to the store.
+    pub fn insert<S>(&self, event: AgentEvent, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.insert(event, span.into())
+    }
+
+    /// Prune events recorded before the given time to prevent endless DB growth.
+    pub fn prune<S>(&self, before: DateTime<Utc>, limit: u32, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.prune(before, limit, span.into())
+    }
+
+    /// Iterate over the most recent 100 events, newest event first.
+    pub fn recent<S>(&self, span: S) -> Result<Iter<AgentEvent>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.recent(span.into())
+    }
+}
+
+/// Outcome of a store maintenance run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaintenanceReport {
+    /// Size (in bytes) of the store before maintenance.
+    pub size_before: u64,
+
+    /// Size (in bytes) of the store after maintenance.
+    pub size_after: u64,
+}
+
+impl MaintenanceReport {
+    /// Number of bytes reclaimed by the maintenance.
+    pub fn reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
 }
 
 /// Iterator over store results.
@@ -147,6 +431,7 @@ impl<T> Iterator for Iter<T> {
 /// Interface to the agent's persistent storage.
 #[derive(Clone)]
 pub struct Store {
+    audit: Option<Arc<AuditLog>>,
     logger: Logger,
     inner: StoreImpl,
 }
@@ -160,12 +445,50 @@ impl Store {
         self.inner.migrate()
     }
 
+    /// Apply migrations, retrying failed attempts with exponential backoff.
+    ///
+    /// Agents sharing a store may race to migrate it: the losers fail while the
+    /// store is locked and find the migrations already applied when they retry.
+    pub fn migrate_with_retries(&mut self, config: &MigrationsConfig) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.migrate() {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            if attempt >= config.retries {
+                return Err(error);
+            }
+            let delay = config.retry_interval.saturating_mul(1 << attempt.min(16));
+            warn!(
+                self.logger,
+                "Agent DB migrations failed, retrying";
+                "attempt" => attempt + 1,
+                "delay" => delay,
+                failure_info(&error),
+            );
+            thread::sleep(Duration::from_secs(delay));
+            attempt += 1;
+        }
+    }
+
+    /// Compact the store to reclaim unused space.
+    ///
+    /// Maintenance uses its own connection and must not be run from within a transaction.
+    pub fn maintenance(&self) -> Result<MaintenanceReport> {
+        self.inner.maintenance()
+    }
+
     #[cfg(any(test, feature = "with_test_support"))]
     pub fn mock() -> Store {
         let inner = self::backend::mock::MockStore::new();
         let inner = StoreImpl::new(inner);
         let logger = Logger::root(slog::Discard, slog::o!());
-        Store { inner, logger }
+        Store {
+            audit: None,
+            inner,
+            logger,
+        }
     }
 
     pub fn with_transaction<F, T>(&self, block: F) -> Result<T>
@@ -174,7 +497,10 @@ impl Store {
     {
         let mut connection = self.inner.connection()?;
         let tx = connection.transaction()?;
-        let mut tx = Transaction { inner: tx };
+        let mut tx = Transaction {
+            audit: RefCell::new(Vec::new()),
+            inner: tx,
+        };
         match block(&mut tx) {
             Err(error) => {
                 if let Err(error) = tx.rollback() {
@@ -188,7 +514,11 @@ impl Store {
                 Err(error)
             }
             Ok(rv) => {
+                let entries = tx.audit.replace(Vec::new());
                 tx.commit()?;
+                if let Some(audit) = self.audit.as_ref() {
+                    audit.record(entries);
+                }
                 Ok(rv)
             }
         }
@@ -196,7 +526,11 @@ impl Store {
 }
 
 /// Interface to transactional operations on the store.
+///
+/// Action state transitions are recorded in the audit log, if configured,
+/// once the transaction is committed.
 pub struct Transaction<'a> {
+    audit: RefCell<Vec<AuditEntry>>,
     inner: TransactionImpl<'a>,
 }
 
@@ -204,7 +538,8 @@ impl<'a> Transaction<'a> {
     /// Access single action query interface.
     pub fn action(&mut self) -> Action {
         let inner = self.inner.action();
-        Action { inner }
+        let audit = &self.audit;
+        Action { audit, inner }
     }
 
     /// Access the actions query interface.
@@ -213,6 +548,22 @@ impl<'a> Transaction<'a> {
         Actions { inner }
     }
 
+    /// Access the lifecycle events query interface.
+    pub fn events(&mut self) -> Events {
+        let inner = self.inner.events();
+        Events { inner }
+    }
+
+    /// Record an agent lifecycle event.
+    pub fn record_event<K, D>(&mut self, kind: K, detail: D) -> Result<()>
+    where
+        K: Into<String>,
+        D: Into<Option<Json>>,
+    {
+        let event = AgentEvent::new(kind, detail);
+        self.events().insert(event, None)
+    }
+
     /// Commit and consume the transaction.
     pub fn commit(mut self) -> Result<()> {
         self.inner.commit()
@@ -226,12 +577,193 @@ impl<'a> Transaction<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use failure::Fail;
     use serde_json::json;
+    use serde_json::Value as Json;
+    use slog::Logger;
+    use uuid::Uuid;
 
+    use super::audit::AuditLog;
+    use super::backend::mock::MockStore;
+    use super::interface::StoreImpl;
     use super::Store;
     use crate::actions::ActionRecord;
+    use crate::actions::ActionRecordView;
     use crate::actions::ActionRequester;
     use crate::actions::ActionState;
+    use crate::config::AuditLogConfig;
+    use crate::config::MigrationsConfig;
+
+    fn conflicting_store(conflicts: u32) -> Store {
+        let inner = StoreImpl::new(MockStore::with_migrate_conflicts(conflicts));
+        let logger = Logger::root(slog::Discard, slog::o!());
+        Store {
+            audit: None,
+            inner,
+            logger,
+        }
+    }
+
+    #[test]
+    fn audit_log_line_per_transition() {
+        let path = std::env::temp_dir().join(format!("repliagent-audit-{}.log", Uuid::new_v4()));
+        let config = AuditLogConfig {
+            keep: 1,
+            max_size: 1024 * 1024,
+            path: path.to_str().unwrap().to_string(),
+        };
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let store = Store {
+            audit: Some(Arc::new(AuditLog::new(config, logger.clone()))),
+            inner: StoreImpl::new(MockStore::new()),
+            logger,
+        };
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        store
+            .with_transaction(|tx| tx.action().insert(record.clone(), None))
+            .unwrap();
+        store
+            .with_transaction(|tx| {
+                tx.action()
+                    .transition(&record, ActionState::Running, None, None)
+            })
+            .unwrap();
+        // Transitions in rolled back transactions are not logged.
+        let result: crate::Result<()> = store.with_transaction(|tx| {
+            tx.action()
+                .transition(&record, ActionState::Failed, None, None)?;
+            Err("test".into())
+        });
+        assert!(result.is_err());
+
+        let audit = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<Json> = audit
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["action_id"], json!(record.id));
+        assert_eq!(lines[0]["from"], Json::Null);
+        assert_eq!(lines[0]["to"], json!("NEW"));
+        assert_eq!(lines[1]["from"], json!("NEW"));
+        assert_eq!(lines[1]["to"], json!("RUNNING"));
+    }
+
+    #[test]
+    fn migrate_conflict_resolves_on_retry() {
+        let config = MigrationsConfig {
+            retries: 3,
+            retry_interval: 0,
+        };
+        let mut store = conflicting_store(2);
+        store.migrate_with_retries(&config).unwrap();
+    }
+
+    #[test]
+    fn migrate_retries_are_bounded() {
+        let config = MigrationsConfig {
+            retries: 1,
+            retry_interval: 0,
+        };
+        let mut store = conflicting_store(2);
+        assert!(store.migrate_with_retries(&config).is_err());
+    }
+
+    #[test]
+    fn phase_updates_payload() {
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let id = record.id.to_string();
+        let store = Store::mock();
+        let record = store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                tx.action().phase(&record, "validate", None)?;
+                tx.action().get(&id, None)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(*record.state(), ActionState::Running);
+        assert_eq!(*record.state_payload(), Some(json!({"phase": "validate"})));
+
+        let record = store
+            .with_transaction(|tx| {
+                let payload = json!({"phase": "validate", "progress": 50});
+                tx.action()
+                    .transition(&record, ActionState::Running, payload, None)?;
+                let record = tx.action().get(&id, None)?.unwrap();
+                tx.action().phase(&record, "execute", None)?;
+                tx.action().get(&id, None)
+            })
+            .unwrap()
+            .unwrap();
+        let payload = json!({"phase": "execute", "progress": 50});
+        assert_eq!(*record.state_payload(), Some(payload));
+    }
+
+    #[test]
+    fn phase_requires_object_payload() {
+        let mut record =
+            ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        record.set_state_payload(Some(json!("not an object")));
+        let store = Store::mock();
+        let result = store.with_transaction(|tx| {
+            tx.action().insert(record.clone(), None)?;
+            tx.action().phase(&record, "validate", None)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn replay_copies_finished_action() {
+        let mut record = ActionRecord::new(
+            "test",
+            None,
+            None,
+            json!({"a": 1}),
+            ActionRequester::AgentApi,
+        );
+        record.headers.insert("x-test".into(), "value".into());
+        let store = Store::mock();
+        let replay = store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                tx.action()
+                    .transition(&record, ActionState::Failed, None, None)?;
+                tx.action()
+                    .replay(&record.id.to_string(), ActionRequester::AgentApi, None)
+            })
+            .unwrap()
+            .unwrap();
+        assert_ne!(replay.id, record.id);
+        assert_eq!(replay.kind, record.kind);
+        assert_eq!(replay.args(), record.args());
+        assert_eq!(replay.headers, record.headers);
+        assert_eq!(*replay.state(), ActionState::New);
+
+        let stored = store
+            .with_transaction(|tx| tx.action().get(&replay.id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.args(), &json!({"a": 1}));
+    }
+
+    #[test]
+    fn replay_requires_finished_action() {
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let store = Store::mock();
+        let error = store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                tx.action()
+                    .replay(&record.id.to_string(), ActionRequester::AgentApi, None)
+            })
+            .unwrap_err();
+        assert_eq!(error.name().unwrap(), "ActionNotFinished");
+    }
 
     #[test]
     #[should_panic(expected = "actions are not allowed to transition from Running to New")]
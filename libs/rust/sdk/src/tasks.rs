@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde_derive::Serialize;
+
+use crate::Result;
+
+/// Outcome of the most recent run of a background task.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum TaskOutcome {
+    #[serde(rename = "failed")]
+    Failed,
+
+    #[serde(rename = "success")]
+    Success,
+}
+
+/// Schedule and most recent run of a background task.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TaskStatus {
+    /// Time, in seconds, between runs of the task.
+    pub interval: u64,
+
+    /// Error message of the most recent run, if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_status: Option<TaskOutcome>,
+    pub name: String,
+}
+
+/// Track background tasks the agent runs periodically and the outcome of their last run.
+///
+/// Tasks are registered when their thread starts and record every run so operators
+/// can spot stuck or misconfigured tasks.
+#[derive(Clone, Debug, Default)]
+pub struct BackgroundTasks {
+    tasks: Arc<Mutex<BTreeMap<String, TaskStatus>>>,
+}
+
+impl BackgroundTasks {
+    /// Track a task running every `interval`, replacing any task with the same name.
+    pub fn register<S>(&self, name: S, interval: Duration)
+    where
+        S: Into<String>,
+    {
+        let name = name.into();
+        let status = TaskStatus {
+            interval: interval.as_secs(),
+            last_error: None,
+            last_run: None,
+            last_status: None,
+            name: name.clone(),
+        };
+        let mut tasks = self.tasks.lock().expect("BackgroundTasks lock poisoned");
+        tasks.insert(name, status);
+    }
+
+    /// Record the result of a task run.
+    ///
+    /// Results for tasks that were not registered are ignored.
+    pub fn record<T>(&self, name: &str, result: &Result<T>) {
+        let mut tasks = self.tasks.lock().expect("BackgroundTasks lock poisoned");
+        let task = match tasks.get_mut(name) {
+            None => return,
+            Some(task) => task,
+        };
+        task.last_run = Some(Utc::now());
+        match result {
+            Ok(_) => {
+                task.last_error = None;
+                task.last_status = Some(TaskOutcome::Success);
+            }
+            Err(error) => {
+                task.last_error = Some(error.to_string());
+                task.last_status = Some(TaskOutcome::Failed);
+            }
+        }
+    }
+
+    /// Snapshot of the registered tasks, sorted by name.
+    pub fn tasks(&self) -> Vec<TaskStatus> {
+        let tasks = self.tasks.lock().expect("BackgroundTasks lock poisoned");
+        tasks.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BackgroundTasks;
+    use super::TaskOutcome;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    #[test]
+    fn records_last_run() {
+        let tasks = BackgroundTasks::default();
+        tasks.register("test.task", Duration::from_secs(10));
+        let failed: Result<()> = Err(ErrorKind::FreeForm("boom".into()).into());
+        tasks.record("test.task", &failed);
+        let task = &tasks.tasks()[0];
+        assert_eq!(task.last_status, Some(TaskOutcome::Failed));
+        assert_eq!(task.last_error, Some("boom".into()));
+
+        tasks.record("test.task", &Ok(()));
+        let task = &tasks.tasks()[0];
+        assert_eq!(task.last_status, Some(TaskOutcome::Success));
+        assert_eq!(task.last_error, None);
+    }
+
+    #[test]
+    fn unregistered_tasks_ignored() {
+        let tasks = BackgroundTasks::default();
+        tasks.record("test.task", &Ok(()));
+        assert!(tasks.tasks().is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use opentracingrust::Span;
 
 use replicante_models_agent::info::AgentInfo;
@@ -5,13 +7,18 @@ use replicante_models_agent::info::AgentVersion;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 
+use super::actions::ActionAuthorizer;
+use super::actions::AllowAll;
 use super::Agent;
+use super::DatastoreExtras;
 use super::ErrorKind;
 use super::Result;
 
 /// An implementation of Agent to be used for tests.
 pub struct MockAgent {
+    pub action_authorizer: Arc<dyn ActionAuthorizer>,
     pub agent_info: ::std::result::Result<AgentInfo, String>,
+    pub datastore_extras: ::std::result::Result<DatastoreExtras, String>,
     pub datastore_info: ::std::result::Result<DatastoreInfo, String>,
     pub shards: ::std::result::Result<Shards, String>,
 }
@@ -28,7 +35,9 @@ impl MockAgent {
         ));
         let shards = Ok(Shards::new(vec![]));
         MockAgent {
+            action_authorizer: Arc::new(AllowAll),
             agent_info,
+            datastore_extras: Ok(DatastoreExtras::new()),
             datastore_info,
             shards,
         }
@@ -36,12 +45,22 @@ impl MockAgent {
 }
 
 impl Agent for MockAgent {
+    fn action_authorizer(&self) -> Arc<dyn ActionAuthorizer> {
+        Arc::clone(&self.action_authorizer)
+    }
+
     fn agent_info(&self, _: &mut Span) -> Result<AgentInfo> {
         self.agent_info
             .clone()
             .map_err(|error| ErrorKind::FreeForm(error).into())
     }
 
+    fn datastore_extras(&self, _: &mut Span) -> Result<DatastoreExtras> {
+        self.datastore_extras
+            .clone()
+            .map_err(|error| ErrorKind::FreeForm(error).into())
+    }
+
     fn datastore_info(&self, _: &mut Span) -> Result<DatastoreInfo> {
         self.datastore_info
             .clone()
@@ -1,15 +1,22 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use opentracingrust::Span;
+use serde_json::Value as Json;
 
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 
 use crate::actions::Action;
+use crate::actions::ActionAuthorizer;
 use crate::actions::ActionHook;
+use crate::actions::AllowAll;
 use crate::Result;
 
+/// Additional, datastore specific, information merged into `DatastoreInfo` responses.
+pub type DatastoreExtras = BTreeMap<String, Json>;
+
 /// Trait to share common agent code and features.
 ///
 /// Agents should be implemented as structs that implement `BaseAgent`.
@@ -20,6 +27,29 @@ pub trait Agent: Send + Sync {
     /// Fetches the datastore information.
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo>;
 
+    /// Fetches additional datastore specific information to report alongside `DatastoreInfo`.
+    ///
+    /// Extras are optional: failing to fetch them does not fail datastore info requests.
+    fn datastore_extras(&self, _: &mut Span) -> Result<DatastoreExtras> {
+        Ok(DatastoreExtras::new())
+    }
+
+    /// Probe the datastore node to check if it is healthy.
+    ///
+    /// By default a node is healthy if its datastore information can be fetched.
+    /// Agents should override this with a cheaper, datastore specific, probe.
+    fn health(&self, span: &mut Span) -> Result<()> {
+        self.datastore_info(span).map(|_| ())
+    }
+
+    /// Prepare the agent to serve requests, such as by opening datastore connections.
+    ///
+    /// Called at startup, if warmup is enabled, before the agent reports ready.
+    /// By default the datastore node is probed once with `Agent::health`.
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        self.health(span)
+    }
+
     /// Fetches all shards and details on the managed datastore node.
     fn shards(&self, span: &mut Span) -> Result<Shards>;
 
@@ -33,4 +63,11 @@ pub trait Agent: Send + Sync {
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         Vec::new()
     }
+
+    /// Policy to authorize actions before they are scheduled.
+    ///
+    /// By default all actions are allowed.
+    fn action_authorizer(&self) -> Arc<dyn ActionAuthorizer> {
+        Arc::new(AllowAll)
+    }
 }
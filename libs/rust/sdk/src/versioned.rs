@@ -13,10 +13,14 @@ use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
 
 use crate::actions::Action;
+use crate::actions::ActionAuthorizer;
 use crate::actions::ActionHook;
 use crate::Agent;
 use crate::AgentContext;
+use crate::Backoff;
+use crate::DatastoreExtras;
 use crate::Error;
+use crate::ErrorKind;
 use crate::Result;
 
 /// Information about an Agent that is active.
@@ -77,6 +81,10 @@ pub trait AgentFactory: Send + Sync {
 /// agent that can fail fast and be replaced as soon as possible (especially useful if the
 /// datastore is down and a default agent cannot be defined).
 ///
+/// To avoid hammering a datastore that is down, failures to fetch datastore information
+/// start a backoff (see `Backoff`) during which `datastore_info` fails without contacting
+/// the datastore or remaking the agent.
+///
 /// If the agent is replaced by the version check a new request for data is issued
 /// even if that may result in an unnecessary call to the database.
 ///
@@ -105,6 +113,7 @@ where
     Factory: AgentFactory + 'static,
 {
     active: RwLock<ActiveAgent>,
+    backoff: Backoff,
     context: AgentContext,
     factory: Factory,
 }
@@ -129,8 +138,11 @@ where
 {
     pub fn new(context: AgentContext, factory: Factory) -> VersionedAgent<Factory> {
         let active = RwLock::new(factory.make());
+        let gauge = context.sdk_metrics.datastore_reconnect_backoff.clone();
+        let backoff = Backoff::new(context.config.reconnect.clone(), gauge);
         VersionedAgent {
             active,
+            backoff,
             context,
             factory,
         }
@@ -156,10 +168,18 @@ where
             let info = active.agent.datastore_info(span);
             match info {
                 Err(error) => {
-                    warn!(self.context.logger, "Failed to detect version"; failure_info(&error));
+                    let delay = self.backoff.failure();
+                    warn!(
+                        self.context.logger, "Failed to detect version";
+                        "reconnect_in" => ?delay,
+                        failure_info(&error),
+                    );
                     (self.factory.should_remake_on_error(&active, &error), None)
                 }
-                Ok(info) => (self.factory.should_remake(&active, &info), Some(info)),
+                Ok(info) => {
+                    self.backoff.success();
+                    (self.factory.should_remake(&active, &info), Some(info))
+                }
             }
         };
         // Remake the agent if needed.
@@ -183,6 +203,12 @@ where
     }
 
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
+        // Don't reach out to the datastore while backing off after failures.
+        if let Some(remaining) = self.backoff.remaining() {
+            span.tag("reconnect.backoff", true);
+            let remaining = remaining.as_secs() + 1;
+            return Err(ErrorKind::DatastoreBackoff(remaining).into());
+        }
         // If validation returns a version we can reuse that in the response.
         if let Some(info) = self.validate_version(span) {
             return Ok(info);
@@ -196,15 +222,35 @@ where
         active.agent.datastore_info(span)
     }
 
+    fn datastore_extras(&self, span: &mut Span) -> Result<DatastoreExtras> {
+        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        active.agent.datastore_extras(span)
+    }
+
+    fn health(&self, span: &mut Span) -> Result<()> {
+        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        active.agent.health(span)
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
         let active = self.active.read().expect("ActiveAgent lock was poisoned");
         active.agent.shards(span)
     }
 
+    fn warmup(&self, span: &mut Span) -> Result<()> {
+        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        active.agent.warmup(span)
+    }
+
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         let active = self.active.read().expect("ActiveAgent lock was poisoned");
         active.agent.action_hooks()
     }
+
+    fn action_authorizer(&self) -> Arc<dyn ActionAuthorizer> {
+        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        active.agent.action_authorizer()
+    }
 }
 
 #[cfg(test)]
@@ -314,6 +360,33 @@ mod tests {
         assert_eq!(2, *factory.made.lock().unwrap());
     }
 
+    #[test]
+    fn datastore_info_backs_off_after_errors() {
+        let mut mocked = MockAgent::new();
+        mocked.datastore_info = Err("test".into());
+        let mocked = Arc::new(mocked);
+        let factory = Arc::new(MockFactory {
+            agent: Arc::new(WrappedMockAgent(Arc::clone(&mocked))),
+            made: Mutex::new(0),
+            remake: false,
+            remake_on_error: true,
+        });
+        let context = AgentContext::mock();
+        let agent = VersionedAgent::new(context.clone(), WrappedMockFactory(Arc::clone(&factory)));
+        assert!(agent
+            .datastore_info(&mut context.tracer.span("TEST"))
+            .is_err());
+        assert_eq!(2, *factory.made.lock().unwrap());
+        let error = agent
+            .datastore_info(&mut context.tracer.span("TEST"))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "datastore connection failed, reconnecting in 1 seconds"
+        );
+        assert_eq!(2, *factory.made.lock().unwrap());
+    }
+
     #[test]
     fn validate_version_info_error_no_change() {
         let mut mocked = MockAgent::new();
@@ -0,0 +1,126 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use failure::ResultExt;
+use humthreads::Builder;
+use slog::info;
+use slog::warn;
+
+use replicante_util_failure::failure_info;
+
+use crate::Agent;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Track if the agent completed its startup warmup and is ready to serve requests.
+#[derive(Clone, Debug)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn new(ready: bool) -> Readiness {
+        Readiness(Arc::new(AtomicBool::new(ready)))
+    }
+
+    /// Check if the agent is ready.
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Mark the agent as ready.
+    pub fn mark_ready(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Warm up the agent in a background thread, if enabled.
+///
+/// Like the update checker, the thread is not tracked to avoid shutdown delays.
+pub fn spawn(agent: Arc<dyn Agent>, context: AgentContext) -> Result<()> {
+    if !context.config.warmup.enabled {
+        return Ok(());
+    }
+    Builder::new("r:b:warmup")
+        .full_name("replicante:base:warmup")
+        .spawn(move |scope| {
+            let _activity = scope.scoped_activity("warming up the agent");
+            warmup(agent.as_ref(), &context);
+        })
+        .with_context(|_| ErrorKind::ThreadSpawn("warmup"))?;
+    Ok(())
+}
+
+/// Run `Agent::warmup`, with retries, and mark the agent as ready once done.
+///
+/// Warmup is an optimisation so the agent is marked ready even if all attempts fail.
+fn warmup(agent: &dyn Agent, context: &AgentContext) {
+    let config = &context.config.warmup;
+    let retry_interval = Duration::from_secs(config.retry_interval);
+    for attempt in 0..=config.retries {
+        let mut span = context.tracer.span("warmup").auto_finish();
+        match agent.warmup(&mut span) {
+            Ok(()) => {
+                info!(context.logger, "Agent warmup completed"; "attempt" => attempt);
+                context.readiness.mark_ready();
+                return;
+            }
+            Err(error) => warn!(
+                context.logger,
+                "Agent warmup failed";
+                "attempt" => attempt,
+                failure_info(&error),
+            ),
+        };
+        if attempt < config.retries {
+            thread::sleep(retry_interval);
+        }
+    }
+    warn!(
+        context.logger,
+        "Giving up on agent warmup, reporting ready anyway";
+        "retries" => config.retries,
+    );
+    context.readiness.mark_ready();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::warmup;
+    use crate::config::Agent as Config;
+    use crate::testing::MockAgent;
+    use crate::AgentContext;
+
+    fn context() -> AgentContext {
+        let mut config = Config::mock();
+        config.warmup.enabled = true;
+        config.warmup.retries = 2;
+        config.warmup.retry_interval = 0;
+        AgentContext::mock_with_config(config)
+    }
+
+    #[test]
+    fn not_ready_until_warmup_completes() {
+        let context = context();
+        assert!(!context.readiness.is_ready());
+        warmup(&MockAgent::new(), &context);
+        assert!(context.readiness.is_ready());
+    }
+
+    #[test]
+    fn ready_after_failed_retries() {
+        let context = context();
+        let mut agent = MockAgent::new();
+        agent.datastore_info = Err("test".into());
+        warmup(&agent, &context);
+        assert!(context.readiness.is_ready());
+    }
+
+    #[test]
+    fn ready_without_warmup() {
+        let context = AgentContext::mock();
+        assert!(context.readiness.is_ready());
+    }
+}